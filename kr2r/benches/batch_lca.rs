@@ -0,0 +1,66 @@
+//! 合成的 2M 节点分类树上比较 batch_lca 与逐对调用 lca
+//!
+//! 运行: `cargo bench -p kr2r --bench batch_lca`
+//!
+//! 每次 1M 对节点. `pool_5000` 中节点取自 5000 个 taxon, 接近一批 read 实际命中的 taxon 数;
+//! `uniform` 中节点在整棵树上均匀抽取, 几乎没有重复
+//!
+//! 单核虚拟机、workspace 的 release 配置下: `pool_5000` 逐对 lca 约 240-270 ms, batch_lca 约 125 ms;
+//! `uniform` 两者都约 770 ms, 此时 batch_lca 直接逐对查 path_cache, 不复制路径
+
+mod common;
+
+use common::synthetic_taxonomy;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+const NODES: usize = 2_000_000;
+const PAIRS: usize = 1_000_000;
+
+fn random_pairs(rng: &mut SmallRng, pool: &[u32]) -> Vec<(u32, u32)> {
+    (0..PAIRS)
+        .map(|_| {
+            (
+                pool[rng.gen_range(0..pool.len())],
+                pool[rng.gen_range(0..pool.len())],
+            )
+        })
+        .collect()
+}
+
+fn bench_batch_lca(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut taxo = synthetic_taxonomy(&mut rng, NODES);
+    taxo.build_path_cache();
+
+    let all: Vec<u32> = (1..=NODES as u32).collect();
+    let pool: Vec<u32> = (0..5000).map(|_| rng.gen_range(1..=NODES as u32)).collect();
+    let cases = [
+        ("pool_5000", random_pairs(&mut rng, &pool)),
+        ("uniform", random_pairs(&mut rng, &all)),
+    ];
+
+    let mut group = c.benchmark_group("batch_lca");
+    group.sample_size(10);
+    for (name, pairs) in &cases {
+        let scalar: Vec<u32> = pairs.iter().map(|&(a, b)| taxo.lca(a, b)).collect();
+        assert_eq!(taxo.batch_lca(pairs), scalar);
+
+        group.bench_with_input(BenchmarkId::new("lca", name), pairs, |b, pairs| {
+            b.iter(|| {
+                pairs
+                    .iter()
+                    .map(|&(a, b)| taxo.lca(a, b))
+                    .collect::<Vec<u32>>()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("batch_lca", name), pairs, |b, pairs| {
+            b.iter(|| taxo.batch_lca(pairs))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_batch_lca);
+criterion_main!(benches);
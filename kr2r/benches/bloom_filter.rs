@@ -0,0 +1,62 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use kr2r::bloom::{bloom_key, BloomFilter};
+use kr2r::compact_hash::{CHTable, Compact, HashConfig, Page};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+const CAPACITY: usize = 1 << 24;
+const VALUE_BITS: usize = 16;
+const QUERIES: usize = 1 << 20;
+
+/// 装载率 70% 的内存哈希表和对应的 Bloom filter
+fn build_table(rng: &mut SmallRng) -> (CHTable, BloomFilter) {
+    let config = HashConfig::new(CAPACITY, VALUE_BITS, 0, 1, CAPACITY);
+    let bloom = BloomFilter::with_capacity(CAPACITY, 0.01);
+    let mut data = vec![0u32; CAPACITY];
+    for _ in 0..CAPACITY * 7 / 10 {
+        let (index, compacted) = config.compact(rng.gen());
+        let mut idx = index;
+        while data[idx] != 0 {
+            idx = (idx + 1) % CAPACITY;
+        }
+        data[idx] = u32::combined(compacted, 1, VALUE_BITS);
+        bloom.insert(bloom_key(index, compacted));
+    }
+    let pages = vec![Page::new(0, CAPACITY, data)];
+    (CHTable { config, pages }, bloom)
+}
+
+/// 大部分 read 不在数据库中 (如宿主 read), 随机的 minimizer 几乎都查不到
+fn bench_mostly_unclassified(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(1);
+    let (table, bloom) = build_table(&mut rng);
+    let queries: Vec<(usize, u32)> = (0..QUERIES)
+        .map(|_| table.config.compact(rng.gen()))
+        .collect();
+
+    let mut group = c.benchmark_group("mostly_unclassified");
+    group.throughput(Throughput::Elements(QUERIES as u64));
+    group.bench_function("hash_table", |b| {
+        b.iter(|| {
+            black_box(&queries)
+                .iter()
+                .filter(|&&(index, compacted)| table.get_from_page(index, compacted, 0) != 0)
+                .count()
+        })
+    });
+    group.bench_function("bloom_filter_then_hash_table", |b| {
+        b.iter(|| {
+            black_box(&queries)
+                .iter()
+                .filter(|&&(index, compacted)| {
+                    bloom.contains(bloom_key(index, compacted))
+                        && table.get_from_page(index, compacted, 0) != 0
+                })
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_mostly_unclassified);
+criterion_main!(benches);
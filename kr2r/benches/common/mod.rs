@@ -0,0 +1,32 @@
+//! 多个 bench 共用的合成数据
+
+use kr2r::taxonomy::{Taxonomy, TaxonomyNode};
+use rand::rngs::SmallRng;
+use rand::Rng;
+
+/// BFS 顺序的合成分类树: 每个节点有 0 到 3 个子节点, 平均深度与 NCBI taxonomy 相近
+pub fn synthetic_taxonomy(rng: &mut SmallRng, nodes: usize) -> Taxonomy {
+    let mut taxo = Taxonomy::default();
+    // 0 号为哨兵节点, 1 号为根节点
+    taxo.nodes.push(TaxonomyNode::default());
+    let mut next_child = 2u64;
+    let mut id = 1u64;
+    let mut parents = vec![0u64, 0];
+    while (id as usize) < nodes + 1 {
+        let remaining = nodes as u64 + 1 - next_child.min(nodes as u64 + 1);
+        // 前面的节点至少有一个子节点, 避免整棵树过早结束
+        let min = if next_child <= id + 1 { 1 } else { 0 };
+        let child_count = rng.gen_range(min..=3).min(remaining);
+        taxo.nodes.push(TaxonomyNode {
+            parent_id: parents[id as usize],
+            first_child: next_child,
+            child_count,
+            external_id: id,
+            ..Default::default()
+        });
+        parents.extend(std::iter::repeat_n(id, child_count as usize));
+        next_child += child_count;
+        id += 1;
+    }
+    taxo
+}
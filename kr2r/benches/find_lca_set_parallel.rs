@@ -0,0 +1,54 @@
+//! 合成的 2M 节点分类树上比较逐个计算 lca_of_set 与 find_lca_set_parallel
+//!
+//! 运行: `cargo bench -p kr2r --bench find_lca_set_parallel`, 并行度由 `RAYON_NUM_THREADS` 控制
+//!
+//! 每次 200k 个集合, 每个集合 1 到 20 个节点, 节点取自 5000 个 taxon
+//!
+//! 单核虚拟机、workspace 的 release 配置下两者都约 260 ms, 即 rayon 调度没有明显开销;
+//! 多核时集合之间相互独立, 预期随线程数近似线性加速 (尚未在多核机器上测量)
+
+mod common;
+
+use common::synthetic_taxonomy;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+const NODES: usize = 2_000_000;
+const SETS: usize = 200_000;
+
+fn bench_find_lca_set(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut taxo = synthetic_taxonomy(&mut rng, NODES);
+    taxo.build_path_cache();
+
+    let pool: Vec<u32> = (0..5000).map(|_| rng.gen_range(1..=NODES as u32)).collect();
+    let hit_sets: Vec<Vec<u32>> = (0..SETS)
+        .map(|_| {
+            let len = rng.gen_range(1..=20);
+            (0..len)
+                .map(|_| pool[rng.gen_range(0..pool.len())])
+                .collect()
+        })
+        .collect();
+    let serial: Vec<u32> = hit_sets.iter().map(|ids| taxo.lca_of_set(ids)).collect();
+    assert_eq!(taxo.find_lca_set_parallel(&hit_sets), serial);
+
+    let mut group = c.benchmark_group("find_lca_set");
+    group.sample_size(10);
+    group.bench_function("serial", |b| {
+        b.iter(|| {
+            hit_sets
+                .iter()
+                .map(|ids| taxo.lca_of_set(ids))
+                .collect::<Vec<u32>>()
+        })
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| taxo.find_lca_set_parallel(&hit_sets))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_lca_set);
+criterion_main!(benches);
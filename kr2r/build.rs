@@ -0,0 +1,29 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 把构建时的 git commit 和时间写入环境变量, 供 classify 的元数据使用
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    // 可复现构建可以用 SOURCE_DATE_EPOCH 固定构建时间
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+
+    println!("cargo:rustc-env=KR2R_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=KR2R_BUILD_TIMESTAMP={}", timestamp);
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
@@ -1,13 +1,21 @@
 // 使用时需要引用模块路径
+use crate::classify::AmbiguousPolicy;
+use crate::compact_hash::Slot;
+use crate::metadata::METADATA_FILE;
+use crate::rank_split::classified_output_paths;
+use crate::sam::OutputFormat;
 use crate::utils::expand_spaced_seed_mask;
+use crate::Kr2Result;
 use crate::{construct_seed_template, parse_binary};
 use clap::Parser;
-use seqkmer::Meros;
+use seqkmer::{Compression, Meros, QualityEncoding, DEFAULT_DUST_THRESHOLD};
 use seqkmer::{
     BITS_PER_CHAR, DEFAULT_KMER_LENGTH, DEFAULT_MINIMIZER_LENGTH, DEFAULT_MINIMIZER_SPACES,
     DEFAULT_TOGGLE_MASK,
 };
-use std::path::PathBuf;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 
 pub const U32MAXPLUS: u64 = u32::MAX as u64;
 pub const ONEGB: u64 = 1073741824;
@@ -30,6 +38,22 @@ pub struct Build {
     #[clap(short, long, value_parser = clap::value_parser!(u8).range(0..31), default_value_t = 0)]
     pub requested_bits_for_taxid: u8,
 
+    /// Only insert minimizers that occur at least this many times in the sequences of the same taxid (1 keeps all)
+    #[clap(long, default_value_t = 1)]
+    pub min_kmer_count: usize,
+
+    /// Also build a Bloom filter of all minimizers in the hash table and write it to $db/database.k2bloom
+    #[clap(long, action)]
+    pub bloom_filter: bool,
+
+    /// False positive rate of the Bloom filter built by --bloom-filter
+    #[clap(long, default_value_t = 0.01, value_parser = parse_fpr)]
+    pub bloom_fpr: f64,
+
+    /// Skip k-mer windows containing N or any other non-ACGT base; `false` hashes them as A.
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub mask_n_bases: bool,
+
     /// Number of threads
     #[clap(short = 'p', long, default_value_t = num_cpus::get())]
     pub threads: usize,
@@ -55,6 +79,10 @@ pub struct ClassifyArgs {
     #[arg(long = "db", required = true)]
     pub database: PathBuf,
 
+    /// Another database directory to query together with --db (repeatable); all of them must share taxo.k2d and opts.k2d
+    #[clap(long = "k2d-dir")]
+    pub k2d_dirs: Vec<PathBuf>,
+
     /// chunk directory
     #[clap(long)]
     pub chunk_dir: PathBuf,
@@ -67,10 +95,15 @@ pub struct ClassifyArgs {
     #[clap(short = 'P', long = "paired-end-processing", action)]
     pub paired_end_processing: bool,
 
-    /// Process pairs with mates in the same file.
+    /// Process pairs with mates in the same file (interleaved FASTQ, R1 and R2 alternating).
+    /// Requires -P.
     #[clap(short = 'S', long = "single-file-pairs", action)]
     pub single_file_pairs: bool,
 
+    /// Input is interleaved paired-end FASTQ, same as -P -S.
+    #[clap(long, action)]
+    pub interleaved: bool,
+
     /// Minimum quality score for FASTQ data.
     #[clap(
         short = 'Q',
@@ -80,6 +113,52 @@ pub struct ClassifyArgs {
     )]
     pub minimum_quality_score: i32,
 
+    /// Input compression: auto, gzip, zstd or none.
+    /// `auto` detects gzip and zstd from the file content.
+    #[clap(long, default_value_t = Compression::Auto)]
+    pub compression: Compression,
+
+    /// Skip k-mer windows containing N or any other non-ACGT base; `false` hashes them as A.
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub mask_n_bases: bool,
+
+    /// Mask low-complexity regions (homopolymers, short tandem repeats) with DUST before extracting minimizers.
+    #[clap(long, action)]
+    pub low_complexity_mask: bool,
+
+    /// DUST score threshold used by --low-complexity-mask, higher values mask less.
+    #[clap(long, default_value_t = DEFAULT_DUST_THRESHOLD)]
+    pub dust_threshold: f64,
+
+    /// Skip reads shorter than this many bases (each mate is checked for paired reads).
+    #[clap(long, default_value_t = 0)]
+    pub min_length: usize,
+
+    /// Skip reads longer than this many bases (each mate is checked for paired reads).
+    #[clap(long)]
+    pub max_length: Option<usize>,
+
+    /// Trim the 3' end of FASTQ reads from the first window whose mean Phred quality is below this value.
+    /// Trimming happens before --min-length filtering, each mate is trimmed independently.
+    #[clap(long)]
+    pub trim_quality: Option<u8>,
+
+    /// Window size in bases used by --trim-quality.
+    #[clap(long, default_value_t = 4)]
+    pub trim_window: usize,
+
+    /// Quality encoding of FASTQ input: phred33 or phred64.
+    #[clap(long, default_value_t = QualityEncoding::Phred33)]
+    pub quality_encoding: QualityEncoding,
+
+    /// Warn about and skip FASTQ records whose sequence and quality lengths differ instead of aborting.
+    #[clap(long, action)]
+    pub lenient_fastq: bool,
+
+    /// Skip hash table lookups for minimizers rejected by the database Bloom filter ($db/database.k2bloom).
+    #[clap(long, action)]
+    pub use_bloom_filter: bool,
+
     /// The number of threads to use.
     #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
     pub num_threads: usize,
@@ -87,6 +166,10 @@ pub struct ClassifyArgs {
     #[clap(long, default_value_t = BATCH_SIZE)]
     pub batch_size: usize,
 
+    /// Limit memory usage (e.g. 8G), overrides --batch-size with a value computed from this limit
+    #[clap(long, value_parser = parse_size)]
+    pub memory_limit: Option<usize>,
+
     /// Confidence score threshold
     #[clap(
         short = 'T',
@@ -105,11 +188,23 @@ pub struct ClassifyArgs {
     )]
     pub minimum_hit_groups: usize,
 
+    /// Minimum fraction of minimizers that must hit the called taxon's subtree, 0 <= f <= 1
+    #[clap(long, value_parser = parse_fraction, default_value_t = 0.0)]
+    pub min_hit_fraction: f64,
+
+    /// How to call reads whose top-scoring taxa tie: lca, unclassified or report-all (one output line per taxon)
+    #[clap(long = "ambiguous-taxid-policy", value_enum, default_value_t = AmbiguousPolicy::LCA)]
+    pub ambiguous_policy: AmbiguousPolicy,
+
     /// Enables use of a Kraken 2 compatible shared database.
     #[clap(long, default_value_t = false)]
     pub kraken_db_type: bool,
 
-    /// In comb. w/ -R, provide minimizer information in report
+    /// Memory-map the hash table files instead of reading them into RAM, pages are loaded on demand by the OS.
+    #[clap(long, action)]
+    pub mmap_db: bool,
+
+    /// In comb. w/ -R, provide minimizer information in report; the distinct column is a HyperLogLog estimate of the distinct minimizers hit
     #[clap(short = 'K', long, value_parser, default_value_t = false)]
     pub report_kmer_data: bool,
 
@@ -117,15 +212,231 @@ pub struct ClassifyArgs {
     #[clap(short = 'z', long, value_parser, default_value_t = false)]
     pub report_zero_counts: bool,
 
+    /// Count distinct minimizers per taxon exactly (KrakenUniq style) and add them to the report, implies -K
+    #[clap(long, action, conflicts_with = "approx_unique_kmers")]
+    pub count_unique_kmers: bool,
+
+    /// Like --count-unique-kmers, but estimate the counts with HyperLogLog to bound memory on large databases
+    #[clap(long, action)]
+    pub approx_unique_kmers: bool,
+
+    /// Relative standard error of --approx-unique-kmers, default is about 0.004
+    #[clap(long, value_parser = parse_error_rate, requires = "approx_unique_kmers")]
+    pub unique_kmers_error_rate: Option<f64>,
+
     /// output file contains all unclassified sequence
     #[clap(long, value_parser, default_value_t = false)]
     pub full_output: bool,
 
+    /// Write a TSV of `read_id, minimizer, taxid` for every minimizer hit to this file
+    #[clap(long)]
+    pub kmer_hits_file: Option<PathBuf>,
+
+    /// Maximum number of minimizer hits written per read with --kmer-hits-file
+    #[clap(long, default_value_t = 100)]
+    pub max_hits_per_read: usize,
+
+    /// Overwrite existing output files in the output directory
+    #[clap(long, default_value_t = false)]
+    pub force_overwrite: bool,
+
+    /// Random seed for breaking ties between equally scored taxa (default: use their LCA)
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// Split input reads into one FASTA/FASTQ file per taxon at this rank (e.g. phylum), requires --output-dir
+    #[clap(long)]
+    pub split_by_rank: Option<String>,
+
+    /// Write classified reads to one file per taxon, `<dir>/<taxid>.fastq` (`<taxid>_1.fastq` and `<taxid>_2.fastq` for paired reads, `.fasta` for FASTA input), requires --output-dir
+    #[clap(long)]
+    pub split_by_taxon: Option<PathBuf>,
+
+    /// Write classified reads to this file, paired reads go to `<path>_1.fastq` and `<path>_2.fastq`, requires --output-dir
+    #[clap(long)]
+    pub classified_out: Option<PathBuf>,
+
+    /// Write unclassified reads to this file, paired reads go to `<path>_1.fastq` and `<path>_2.fastq`, requires --output-dir
+    #[clap(long)]
+    pub unclassified_out: Option<PathBuf>,
+
+    /// Write a BIOM v1 (JSON) table of read counts, one row per taxon and one column per sample
+    #[clap(long)]
+    pub biom_output: Option<PathBuf>,
+
+    /// Append the confidence of each call (fraction of k-mers hitting its clade) as a sixth output column
+    #[clap(long, action)]
+    pub include_confidence: bool,
+
+    /// Write a TSV histogram (bin_start, count) of per-read confidence to this file
+    #[clap(long)]
+    pub confidence_histogram: Option<PathBuf>,
+
+    /// Bin width of --confidence-histogram
+    #[clap(long, value_parser = parse_bin_width, default_value_t = 0.01)]
+    pub hist_bin_width: f64,
+
+    /// Append the database that contributed the most hits to each read (1 = --db, then --k2d-dir in order) as the last output column
+    #[clap(long, action)]
+    pub top_db_column: bool,
+
+    /// Output format: kraken, or sam to also write `output_{i}.sam` with the taxid (XT) and confidence (XC) of each read, requires --output-dir
+    #[clap(long, value_enum, default_value_t = OutputFormat::Kraken)]
+    pub output_format: OutputFormat,
+
+    /// Write a JSON record of the run (version, arguments, input and database size and modification time, read counts, platform),
+    /// defaults to `<output-dir>/metadata.json` when --output-dir is set
+    #[clap(long)]
+    pub metadata_output: Option<PathBuf>,
+
+    /// Also record the SHA-256 of every input file and database in the run metadata, which reads all of them again
+    #[clap(long, action)]
+    pub metadata_checksums: bool,
+
+    /// Classify the samples of a TSV manifest (`sample_id`, `r1_path`, optional `r2_path`) in one run,
+    /// outputs of each sample go to `<output-dir>/<sample_id>/`, requires --output-dir
+    #[clap(long, conflicts_with = "input_files", requires = "kraken_output_dir")]
+    pub manifest: Option<PathBuf>,
+
+    /// Number of samples resolved at the same time, each sample keeps its reads in memory while it is resolved
+    #[clap(long, default_value_t = 1)]
+    pub parallel_samples: usize,
+
     /// A list of input file paths (FASTA/FASTQ) to be processed by the classify program.
     // #[clap(short = 'F', long = "files")]
     pub input_files: Vec<String>,
 }
 
+/// 元数据文件的路径, 没有指定 --metadata-output 时为 `<output-dir>/metadata.json`
+pub fn metadata_output_path(args: &ClassifyArgs) -> Option<PathBuf> {
+    args.metadata_output.clone().or_else(|| {
+        args.kraken_output_dir
+            .as_ref()
+            .map(|dir| dir.join(METADATA_FILE))
+    })
+}
+
+/// 统一双端参数的含义: `--interleaved` 等同于 `-P -S`, 单独的 `-S` 没有意义, 直接报错
+/// 之后 reader 和各个 writer 都只需判断 `single_file_pairs`
+pub fn resolve_pairing(
+    paired_end_processing: &mut bool,
+    single_file_pairs: &mut bool,
+    interleaved: bool,
+) -> Kr2Result<()> {
+    if interleaved {
+        *paired_end_processing = true;
+        *single_file_pairs = true;
+    }
+    if *single_file_pairs && !*paired_end_processing {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--single-file-pairs requires --paired-end-processing, use --interleaved for interleaved input",
+        ).into());
+    }
+    Ok(())
+}
+
+/// 序号为 samples 的样本在输出目录中生成的 output_{i}.txt 和 output_{i}.kreport2, 以及所有样本合计的 output.kreport2
+pub fn sample_output_paths(output_dir: &Path, samples: RangeInclusive<usize>) -> Vec<PathBuf> {
+    std::iter::once(output_dir.join("output.kreport2"))
+        .chain(samples.flat_map(|i| {
+            [
+                output_dir.join(format!("output_{}.txt", i)),
+                output_dir.join(format!("output_{}.kreport2", i)),
+            ]
+        }))
+        .collect()
+}
+
+/// 第 i 个样本在输出目录中生成的所有文件: output_{i}.txt, output_{i}.kreport2,
+/// 以及按参数生成的 output_{i}.sam 和 output_{i}_{rank} 目录
+pub fn sample_output_files(args: &ClassifyArgs, output_dir: &Path, i: usize) -> Vec<PathBuf> {
+    let mut outputs = vec![
+        output_dir.join(format!("output_{}.txt", i)),
+        output_dir.join(format!("output_{}.kreport2", i)),
+    ];
+    if args.output_format == OutputFormat::Sam {
+        outputs.push(output_dir.join(format!("output_{}.sam", i)));
+    }
+    if let Some(rank) = &args.split_by_rank {
+        outputs.push(output_dir.join(format!("output_{}_{}", i, rank)));
+    }
+    outputs
+}
+
+/// 与 splitr 的样本划分一致, 按样本分组的输入文件, 以及双端 read 是否交错在同一个文件中
+/// 两个文件的双端样本每两个输入文件为一个样本, 其余情况每个输入文件为一个样本
+pub fn sample_inputs(args: &ClassifyArgs) -> (std::slice::Chunks<'_, String>, bool) {
+    let chunk = if args.paired_end_processing && !args.single_file_pairs {
+        2
+    } else {
+        1
+    };
+    (args.input_files.chunks(chunk), args.single_file_pairs)
+}
+
+/// 每个样本的 output_{i}.txt 都写入 --output-dir, 没有时只能写到 stdout, 不能并行处理多个样本
+pub fn check_parallel_samples(parallel_samples: usize, output_dir: Option<&Path>) -> Kr2Result<()> {
+    if parallel_samples > 1 && output_dir.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--parallel-samples greater than 1 requires --output-dir, otherwise the outputs of different samples would be interleaved on stdout",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// 输出文件已存在时返回的错误, 列出所有冲突的路径
+pub fn output_conflict_error(conflicts: &[PathBuf]) -> io::Error {
+    let paths: Vec<String> = conflicts
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!(
+            "output files already exist, use --force-overwrite to replace them:\n{}",
+            paths.join("\n")
+        ),
+    )
+}
+
+/// 列出 classify 将要写入但已经存在的输出文件
+pub fn check_output_conflicts(args: &ClassifyArgs) -> Vec<PathBuf> {
+    let mut outputs: Vec<PathBuf> = [&args.classified_out, &args.unclassified_out]
+        .into_iter()
+        .flatten()
+        .flat_map(|path| classified_output_paths(path, args.paired_end_processing))
+        .chain(args.biom_output.clone())
+        .chain(args.confidence_histogram.clone())
+        .chain(args.kmer_hits_file.clone())
+        .chain(metadata_output_path(args))
+        .collect();
+    // 每个 taxon 的文件名要分类之后才知道, 目录非空就视为冲突
+    if let Some(dir) = &args.split_by_taxon {
+        if dir
+            .read_dir()
+            .is_ok_and(|mut entries| entries.next().is_some())
+        {
+            outputs.push(dir.clone());
+        }
+    }
+    let output_dir = match &args.kraken_output_dir {
+        Some(dir) => dir,
+        None => return outputs.into_iter().filter(|path| path.exists()).collect(),
+    };
+
+    let sample_count = sample_inputs(args).0.len();
+
+    outputs.push(output_dir.join("output.kreport2"));
+    for i in 1..=sample_count {
+        outputs.extend(sample_output_files(args, output_dir, i));
+    }
+
+    outputs.into_iter().filter(|path| path.exists()).collect()
+}
+
 #[derive(Parser, Debug, Clone, Copy)]
 #[clap(version, about = "k-mer")]
 pub struct KLMTArgs {
@@ -153,6 +464,37 @@ pub struct KLMTArgs {
 }
 
 impl KLMTArgs {
+    /// 检查 k-mer 参数之间的约束, 错误信息包含违反的约束和实际的值
+    pub fn validate(&self) -> Result<(), String> {
+        if self.l_mer < 1 {
+            return Err(format!("l-mer must be at least 1 (got l = {})", self.l_mer));
+        }
+        if self.k_mer < self.l_mer as u64 {
+            return Err(format!(
+                "k-mer must not be shorter than l-mer (got k = {}, l = {})",
+                self.k_mer, self.l_mer
+            ));
+        }
+        if self.minimizer_spaces as usize > self.l_mer as usize / 4 {
+            return Err(format!(
+                "minimizer spaces must be at most l-mer / 4 (got spaces = {}, l = {}, max = {})",
+                self.minimizer_spaces,
+                self.l_mer,
+                self.l_mer / 4
+            ));
+        }
+        // 默认的 toggle mask 总是按 l-mer 长度截断, 只检查用户指定的值
+        let mask_bits = self.l_mer as u32 * BITS_PER_CHAR as u32;
+        let significant_bits = u64::BITS - self.toggle_mask.leading_zeros();
+        if self.toggle_mask != DEFAULT_TOGGLE_MASK && significant_bits > mask_bits {
+            return Err(format!(
+                "toggle mask must fit in l-mer * {} bits (got mask = {:#x} with {} significant bits, l = {} allows {})",
+                BITS_PER_CHAR, self.toggle_mask, significant_bits, self.l_mer, mask_bits
+            ));
+        }
+        Ok(())
+    }
+
     pub fn as_meros(&self) -> Meros {
         let seed = construct_seed_template(self.l_mer as usize, self.minimizer_spaces as usize);
         let space_seed_mask = parse_binary(&seed).unwrap();
@@ -183,3 +525,158 @@ pub fn parse_size(s: &str) -> Result<usize, String> {
         _ => Err("Invalid size suffix. Use 'G', 'M', or 'K'".to_string()),
     }
 }
+
+/// --batch-size 的单位是 annotate 一次读入的 Slot<u64> 个数, 每个 Slot 占用的字节数, 用于 --memory-limit
+pub const BATCH_SLOT_BYTES: usize = std::mem::size_of::<Slot<u64>>();
+
+/// 在 memory_limit 字节内, 每个线程可以同时处理的 batch 大小 (Slot 个数), 至少为 1
+pub fn compute_safe_batch_size(
+    memory_limit: usize,
+    threads: usize,
+    bytes_per_slot: usize,
+) -> usize {
+    let per_batch = threads.max(1).saturating_mul(bytes_per_slot.max(1));
+    (memory_limit / per_batch).max(1)
+}
+
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    let fraction: f64 = s.parse().map_err(|_| "Invalid number".to_string())?;
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err("Fraction must be between 0 and 1".to_string());
+    }
+    Ok(fraction)
+}
+
+/// 直方图的区间宽度, 0 < w <= 1
+pub fn parse_bin_width(s: &str) -> Result<f64, String> {
+    let width = parse_fraction(s)?;
+    if width == 0.0 {
+        return Err("Bin width must be greater than 0".to_string());
+    }
+    Ok(width)
+}
+
+/// HyperLogLog 的相对误差, 0 < e < 1
+pub fn parse_error_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|_| "Invalid number".to_string())?;
+    if !(rate > 0.0 && rate < 1.0) {
+        return Err("Error rate must be between 0 and 1".to_string());
+    }
+    Ok(rate)
+}
+
+/// Bloom filter 的假阳性率, 0 < p < 1
+pub fn parse_fpr(s: &str) -> Result<f64, String> {
+    let fpr = parse_fraction(s)?;
+    if fpr == 0.0 || fpr == 1.0 {
+        return Err("False positive rate must be between 0 and 1 (exclusive)".to_string());
+    }
+    Ok(fpr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    fn classify_args(output_dir: &Path, extra: &[&str]) -> ClassifyArgs {
+        let output_dir = output_dir.to_string_lossy().to_string();
+        let mut argv = vec![
+            "classify",
+            "--db",
+            "db",
+            "--chunk-dir",
+            "chunk",
+            "--output-dir",
+            &output_dir,
+        ];
+        argv.extend_from_slice(extra);
+        argv.extend_from_slice(&["a.fq", "b.fq"]);
+        ClassifyArgs::parse_from(argv)
+    }
+
+    #[test]
+    fn safe_batch_size_counts_slots() {
+        assert_eq!(BATCH_SLOT_BYTES, 16);
+        let limit = parse_size("1G").unwrap();
+        assert_eq!(compute_safe_batch_size(limit, 4, BATCH_SLOT_BYTES), 1 << 24);
+        assert_eq!(compute_safe_batch_size(limit, 0, BATCH_SLOT_BYTES), 1 << 26);
+        assert_eq!(compute_safe_batch_size(10, 4, BATCH_SLOT_BYTES), 1);
+    }
+
+    #[test]
+    fn no_conflicts_in_empty_output_dir() {
+        let dir = test_dir("no_conflicts");
+        let args = classify_args(&dir, &["--split-by-rank", "genus"]);
+        assert!(check_output_conflicts(&args).is_empty());
+    }
+
+    #[test]
+    fn existing_outputs_are_reported() {
+        let dir = test_dir("existing_outputs");
+        let hits = dir.join("hits.tsv");
+        let hits_arg = hits.to_string_lossy().to_string();
+        let args = classify_args(
+            &dir,
+            &["--kmer-hits-file", &hits_arg, "--split-by-rank", "genus"],
+        );
+        std::fs::write(dir.join("output_2.txt"), "").unwrap();
+        std::fs::write(&hits, "").unwrap();
+        std::fs::create_dir(dir.join("output_1_genus")).unwrap();
+        // 不会生成的文件不算冲突
+        std::fs::write(dir.join("output_3.txt"), "").unwrap();
+
+        let mut conflicts = check_output_conflicts(&args);
+        conflicts.sort();
+        let mut expected = vec![hits, dir.join("output_1_genus"), dir.join("output_2.txt")];
+        expected.sort();
+        assert_eq!(conflicts, expected);
+    }
+
+    #[test]
+    fn sample_output_files_follow_arguments() {
+        let dir = test_dir("sample_output_files");
+        let args = classify_args(&dir, &[]);
+        assert_eq!(
+            sample_output_files(&args, &dir, 2),
+            vec![dir.join("output_2.txt"), dir.join("output_2.kreport2")]
+        );
+        let args = classify_args(
+            &dir,
+            &["--output-format", "sam", "--split-by-rank", "genus"],
+        );
+        assert_eq!(
+            sample_output_files(&args, &dir, 1),
+            vec![
+                dir.join("output_1.txt"),
+                dir.join("output_1.kreport2"),
+                dir.join("output_1.sam"),
+                dir.join("output_1_genus"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parallel_samples_require_output_dir() {
+        let dir = test_dir("parallel_samples");
+        check_parallel_samples(1, None).unwrap();
+        check_parallel_samples(4, Some(&dir)).unwrap();
+        let err = check_parallel_samples(4, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn interleaved_implies_paired_single_file() {
+        let (mut paired, mut single) = (false, false);
+        resolve_pairing(&mut paired, &mut single, true).unwrap();
+        assert!(paired && single);
+
+        let (mut paired, mut single) = (false, true);
+        let err = resolve_pairing(&mut paired, &mut single, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let (mut paired, mut single) = (true, false);
+        resolve_pairing(&mut paired, &mut single, false).unwrap();
+        assert!(paired && !single);
+    }
+}
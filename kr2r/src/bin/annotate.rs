@@ -1,5 +1,5 @@
 use clap::Parser;
-use kr2r::compact_hash::{CHTable, Compact, HashConfig, Row, Slot};
+use kr2r::compact_hash::{CHTable, Compact, HashConfig, HashLookup, MmapDatabase, Row, Slot};
 use kr2r::utils::{find_and_sort_files, open_file};
 // use std::collections::HashMap;
 use rayon::prelude::*;
@@ -32,6 +32,10 @@ pub struct Args {
     #[clap(long, default_value_t = false)]
     pub kraken_db_type: bool,
 
+    /// Memory-map the hash table files instead of reading them into RAM, pages are loaded on demand by the OS.
+    #[clap(long, action)]
+    pub mmap_db: bool,
+
     /// chunk directory
     #[clap(long)]
     pub chunk_dir: PathBuf,
@@ -89,16 +93,17 @@ fn write_to_file(
     Ok(())
 }
 
-fn process_batch<R>(
+fn process_batch<R, T>(
     reader: &mut R,
     hash_config: &HashConfig,
-    chtm: &CHTable,
+    chtm: &T,
     chunk_dir: PathBuf,
     batch_size: usize,
     page_index: usize,
 ) -> std::io::Result<()>
 where
     R: Read + Send,
+    T: HashLookup,
 {
     let slot_size = std::mem::size_of::<Slot<u64>>();
     let row_size = std::mem::size_of::<Row>();
@@ -136,7 +141,9 @@ where
                     let seq_id = slot.get_seq_id() as u32;
                     let left = slot.value.left(value_bits) as u32;
                     let high = u32::combined(left, taxid, value_bits);
-                    let row = Row::new(high, seq_id, kmer_id as u32);
+                    let minimizer = hash_config
+                        .minimizer_key(page_index * hash_config.hash_capacity + indx, compacted);
+                    let row = Row::new(high, seq_id, kmer_id as u32, minimizer);
                     // let value = slot.to_b(high);
                     // let value_bytes = value.to_le_bytes(); // 将u64转换为[u8; 8]
                     let value_bytes = row.as_slice(row_size);
@@ -201,28 +208,43 @@ fn process_chunk_file<P: AsRef<Path>>(
 
     let config = HashConfig::from_hash_header(&args.database.join("hash_config.k2d"))?;
     let parition = hash_files.len();
-    let chtm = if args.kraken_db_type {
-        CHTable::from_pair(
-            config,
-            &hash_files[page_index],
-            &hash_files[(page_index + 1) % parition],
-        )?
+    let page_file = &hash_files[page_index];
+    let next_file = &hash_files[(page_index + 1) % parition];
+    if args.mmap_db {
+        let chtm = if args.kraken_db_type {
+            MmapDatabase::from_pair(config, page_file, next_file)?
+        } else {
+            MmapDatabase::from(config, page_file)?
+        };
+        println!("map table took: {:?}", start.elapsed());
+        process_batch(
+            &mut reader,
+            &config,
+            &chtm,
+            args.chunk_dir.clone(),
+            args.batch_size,
+            page_index,
+        )?;
     } else {
-        CHTable::from(config, &hash_files[page_index])?
-    };
+        let chtm = if args.kraken_db_type {
+            CHTable::from_pair(config, page_file, next_file)?
+        } else {
+            CHTable::from(config, page_file)?
+        };
 
-    // 计算持续时间
-    let duration = start.elapsed();
-    // 打印运行时间
-    println!("load table took: {:?}", duration);
-    process_batch(
-        &mut reader,
-        &config,
-        &chtm,
-        args.chunk_dir.clone(),
-        args.batch_size,
-        page_index,
-    )?;
+        // 计算持续时间
+        let duration = start.elapsed();
+        // 打印运行时间
+        println!("load table took: {:?}", duration);
+        process_batch(
+            &mut reader,
+            &config,
+            &chtm,
+            args.chunk_dir.clone(),
+            args.batch_size,
+            page_index,
+        )?;
+    }
 
     Ok(())
 }
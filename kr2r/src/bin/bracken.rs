@@ -0,0 +1,199 @@
+use clap::Parser;
+use kr2r::bracken::{
+    estimate_abundance, parse_level, read_kraken_report, write_bracken_report, KmerDistribution,
+};
+use kr2r::classify::resolve_tree;
+use kr2r::compact_hash::{CHTable, HashConfig};
+use kr2r::taxonomy::Taxonomy;
+use kr2r::utils::{find_and_sort_files, find_library_fna_files, read_id_to_taxon_map};
+use kr2r::IndexOptions;
+use rayon::prelude::*;
+use seqkmer::{FastaReader, Meros, MinimizerIterator, Reader};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "bracken",
+    long_about = "Re-estimate abundance from a Kraken 2 style report in the same way as Bracken"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files
+    #[arg(long = "db", required = true)]
+    pub database: PathBuf,
+
+    /// Kraken 2 style report to re-estimate (e.g. output_1.kreport2)
+    #[clap(short = 'i', long)]
+    pub report: Option<PathBuf>,
+
+    /// Bracken report file to write, default is stdout
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Read length used to simulate reads and to pick the kmer distribution file
+    #[clap(short = 'r', long, default_value_t = 100)]
+    pub read_length: usize,
+
+    /// Taxonomic level to estimate: D, P, C, O, F, G or S
+    #[clap(short = 'l', long, value_parser = parse_level, default_value = "S")]
+    pub level: String,
+
+    /// Taxa at the level with fewer reads than this are dropped
+    #[clap(short = 't', long, default_value_t = 10)]
+    pub threshold: u64,
+
+    /// Build the kmer distribution by classifying every read of --read-length simulated from the library (slow)
+    #[clap(long, action)]
+    pub build_bracken_db: bool,
+
+    /// Kmer distribution file, default is `database{read_length}mers.kmer_distrib` in the database directory
+    #[clap(long)]
+    pub kmer_distrib: Option<PathBuf>,
+
+    /// The number of threads to use.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+}
+
+/// 与 classify 相同的方式对一条模拟 read 分类, 返回内部 taxid, 0 表示未分类
+fn classify_read(
+    read: &[u8],
+    meros: &Meros,
+    hash_config: &HashConfig,
+    chtable: &CHTable,
+    taxonomy: &Taxonomy,
+) -> u32 {
+    let chunk_size = hash_config.hash_capacity;
+    let mut hit_counts: HashMap<u32, u64> = HashMap::new();
+    for (_, hash_key) in MinimizerIterator::from_seq(read, meros) {
+        let (idx, compacted) = hash_config.compact(hash_key);
+        let taxid = chtable.get_from_page(idx % chunk_size, compacted, idx / chunk_size + 1);
+        if taxid > 0 {
+            *hit_counts.entry(taxid).or_insert(0) += 1;
+        }
+    }
+    if hit_counts.is_empty() {
+        return 0;
+    }
+    resolve_tree(&hit_counts, taxonomy, 0)
+}
+
+/// 从每条参考序列的每个位置截取 read_length 长的 read 并分类, 统计每个基因组的 read 被分到哪些 taxon
+fn build_distribution(args: &Args, taxonomy: &Taxonomy, path: &Path) -> Result<KmerDistribution> {
+    let meros = IndexOptions::read_index_options(args.database.join("opts.k2d"))?.as_meros();
+    let hash_config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let hash_files = find_and_sort_files(&args.database, "hash", ".k2d")?;
+    let chtable = CHTable::from_hash_files(hash_config, hash_files)?;
+    let id_to_taxon_map = read_id_to_taxon_map(args.database.join("seqid2taxid.map"))?;
+
+    let mut distrib = KmerDistribution::new();
+    for fna_file in find_library_fna_files(&args.database) {
+        println!("simulate reads from {:?}", fna_file);
+        let mut reader = FastaReader::from_path(fna_file, 1)?;
+        while let Some(seqs) = reader.next()? {
+            for seq in seqs {
+                let Some(&genome_taxid) = id_to_taxon_map.get(&seq.header.id) else {
+                    continue;
+                };
+                let bases: Vec<u8> = seq
+                    .body
+                    .get_seq1()
+                    .iter()
+                    .copied()
+                    .filter(|&b| b != b'\n' && b != b'\r')
+                    .collect();
+                if bases.len() < args.read_length {
+                    continue;
+                }
+                let calls = bases
+                    .par_windows(args.read_length)
+                    .map(|read| classify_read(read, &meros, &hash_config, &chtable, taxonomy))
+                    .filter(|&call| call != 0)
+                    .fold(HashMap::new, |mut calls: HashMap<u64, u64>, call| {
+                        *calls
+                            .entry(taxonomy.nodes[call as usize].external_id)
+                            .or_insert(0) += 1;
+                        calls
+                    })
+                    .reduce(HashMap::new, |mut a, b| {
+                        for (taxid, count) in b {
+                            *a.entry(taxid).or_insert(0) += count;
+                        }
+                        a
+                    });
+                let total = (bases.len() - args.read_length + 1) as u64;
+                distrib.add_genome(genome_taxid, &calls, total);
+            }
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    distrib.write_to(&mut writer)?;
+    writer.flush()?;
+    println!("kmer distribution written to {:?}", path);
+    Ok(distrib)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    if !args.build_bracken_db && args.report.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "nothing to do, use --build-bracken-db and/or --report",
+        ));
+    }
+    if args.read_length == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--read-length must be positive",
+        ));
+    }
+
+    let start = Instant::now();
+    let taxonomy = Taxonomy::from_file(args.database.join("taxo.k2d"))?;
+    let distrib_path = args.kmer_distrib.clone().unwrap_or_else(|| {
+        args.database
+            .join(format!("database{}mers.kmer_distrib", args.read_length))
+    });
+
+    let distrib = if args.build_bracken_db {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(args.num_threads)
+            .build()
+            .map_err(Error::other)?;
+        pool.install(|| build_distribution(&args, &taxonomy, &distrib_path))?
+    } else {
+        KmerDistribution::from_file(&distrib_path)?
+    };
+
+    if let Some(report) = &args.report {
+        let direct_reads = read_kraken_report(report, &taxonomy)?;
+        let entries = estimate_abundance(
+            &taxonomy,
+            &direct_reads,
+            &distrib,
+            &args.level,
+            args.threshold,
+        );
+        let mut writer: Box<dyn Write> = match &args.output {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(std::io::stdout()),
+        };
+        write_bracken_report(&mut writer, &entries, &args.level)?;
+        writer.flush()?;
+    }
+
+    eprintln!("bracken took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
@@ -1,16 +1,20 @@
 // 使用时需要引用模块路径
 use clap::Parser;
 use kr2r::args::{parse_size, Build};
+use kr2r::bloom::{BloomFilter, BLOOM_FILE};
 use kr2r::compact_hash::HashConfig;
 use kr2r::db::{
     convert_fna_to_k2_format, generate_taxonomy, get_bits_for_taxid, process_k2file,
-    write_config_to_file,
+    write_config_to_file, KmerFilterStats,
 };
 use kr2r::utils::{
     create_partition_files, create_partition_writers, find_library_fna_files, get_file_limit,
     read_id_to_taxon_map,
 };
+use kr2r::verify::write_checksum_file;
 use kr2r::IndexOptions;
+use seqkmer::Meros;
+use std::collections::HashSet;
 use std::fs::remove_file;
 use std::time::Instant;
 
@@ -34,12 +38,16 @@ pub struct Args {
 }
 
 pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::error::Error>> {
+    args.build.klmt.validate()?;
     let file_num_limit = get_file_limit();
-    let meros = args.build.klmt.as_meros();
+    let meros = Meros {
+        mask_n_bases: args.build.mask_n_bases,
+        ..args.build.klmt.as_meros()
+    };
 
     let id_to_taxon_map_filename = args.build.database.join("seqid2taxid.map");
 
-    let id_to_taxon_map = read_id_to_taxon_map(&id_to_taxon_map_filename)?;
+    let mut id_to_taxon_map = read_id_to_taxon_map(&id_to_taxon_map_filename)?;
 
     let k2d_dir = &args.build.database;
 
@@ -47,10 +55,10 @@ pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::erro
 
     let ncbi_taxonomy_directory = &args.build.database.join("taxonomy");
 
-    let taxonomy = generate_taxonomy(
+    let mut taxonomy = generate_taxonomy(
         &ncbi_taxonomy_directory,
         &taxonomy_filename,
-        &id_to_taxon_map,
+        &mut id_to_taxon_map,
     )?;
 
     let value_bits = get_bits_for_taxid(
@@ -94,18 +102,30 @@ pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::erro
     let hash_filename = k2d_dir.join("hash_config.k2d");
     let partition = chunk_files.len();
     let mut size: u64 = 0;
+    let mut nodes_with_kmers = HashSet::new();
+    let mut filter_stats = KmerFilterStats::default();
+    // 哈希表的格子数是写入的 minimizer 数的上限
+    let bloom = args
+        .build
+        .bloom_filter
+        .then(|| BloomFilter::with_capacity(capacity, args.build.bloom_fpr));
 
     for i in 1..=partition {
         // 计算持续时间
-        let count = process_k2file(
+        let (count, taxids, stats) = process_k2file(
             hash_config,
             &k2d_dir,
             &chunk_files[i - 1],
             &taxonomy,
             chunk_size,
             i,
+            args.build.min_kmer_count,
+            bloom.as_ref(),
         )?;
         size += count as u64;
+        filter_stats.total += stats.total;
+        filter_stats.removed += stats.removed;
+        nodes_with_kmers.extend(taxids);
         let duration = start.elapsed();
         println!(
             "process chunk file {:?}/{:}: duration: {:?}",
@@ -113,6 +133,21 @@ pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::erro
         );
     }
 
+    if args.build.min_kmer_count > 1 {
+        println!(
+            "--min-kmer-count {} removed {} of {} minimizers ({:.2}%)",
+            args.build.min_kmer_count,
+            filter_stats.removed,
+            filter_stats.total,
+            100.0 * filter_stats.removed as f64 / filter_stats.total.max(1) as f64
+        );
+    }
+
+    // 所有分页写完后才知道哪些节点有 k-mer, 重新写入带 godparent 的 taxonomy
+    // 旧格式读取时会重新推导 godparent, 这里用带版本号的格式保留它们
+    taxonomy.assign_godparents(&nodes_with_kmers);
+    taxonomy.write_to_disk_versioned(&taxonomy_filename)?;
+
     write_config_to_file(
         &hash_filename,
         partition as u64,
@@ -135,6 +170,21 @@ pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::erro
     for chunk_file in chunk_files {
         remove_file(chunk_file)?;
     }
+
+    if let Some(bloom) = &bloom {
+        let bloom_file = k2d_dir.join(BLOOM_FILE);
+        bloom.write_to_file(&bloom_file)?;
+        println!(
+            "bloom filter written to {:?} ({} bits, {} hashes, false positive rate {})",
+            bloom_file,
+            bloom.num_bits(),
+            bloom.num_hashes(),
+            args.build.bloom_fpr
+        );
+    }
+
+    let checksum_file = write_checksum_file(k2d_dir)?;
+    println!("checksums written to {:?}", checksum_file);
     Ok(())
 }
 
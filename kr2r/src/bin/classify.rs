@@ -1,12 +1,17 @@
 use clap::Parser;
-use kr2r::classify::process_hitgroup;
+use kr2r::args::{output_conflict_error, parse_fraction, resolve_pairing, sample_output_paths};
+use kr2r::classify::{process_hitgroup, AmbiguousPolicy};
 use kr2r::compact_hash::{CHTable, Compact, HashConfig, Row};
-use kr2r::readcounts::{TaxonCounters, TaxonCountersDash};
+use kr2r::readcounts::{KmerCounting, ReadCounter, TaxonCounters, TaxonCountersDash};
 use kr2r::report::report_kraken_style;
 use kr2r::taxonomy::Taxonomy;
 use kr2r::utils::{create_sample_file, find_and_sort_files, get_lastest_file_index};
 use kr2r::{HitGroup, IndexOptions};
-use seqkmer::{read_parallel, Base, FastxReader, Meros, MinimizerIterator, OptionPair, Reader};
+use seqkmer::{
+    read_parallel, Base, Compression, DustReader, FastxReader, LengthFilter, Meros,
+    MinimizerIterator, OptionPair, QualityEncoding, QualityTrimmer, ReadOptions, Reader,
+    TrimStrategy, DEFAULT_DUST_THRESHOLD,
+};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufWriter, Write};
@@ -33,10 +38,15 @@ pub struct Args {
     #[clap(short = 'P', long = "paired-end-processing", action)]
     pub paired_end_processing: bool,
 
-    /// Process pairs with mates in the same file.
+    /// Process pairs with mates in the same file (interleaved FASTQ, R1 and R2 alternating).
+    /// Requires -P.
     #[clap(short = 'S', long = "single-file-pairs", action)]
     pub single_file_pairs: bool,
 
+    /// Input is interleaved paired-end FASTQ, same as -P -S.
+    #[clap(long, action)]
+    pub interleaved: bool,
+
     /// Minimum quality score for FASTQ data.
     #[clap(
         short = 'Q',
@@ -46,6 +56,48 @@ pub struct Args {
     )]
     pub minimum_quality_score: i32,
 
+    /// Input compression: auto, gzip, zstd or none.
+    /// `auto` detects gzip and zstd from the file content.
+    #[clap(long, default_value_t = Compression::Auto)]
+    pub compression: Compression,
+
+    /// Skip k-mer windows containing N or any other non-ACGT base; `false` hashes them as A.
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub mask_n_bases: bool,
+
+    /// Mask low-complexity regions (homopolymers, short tandem repeats) with DUST before extracting minimizers.
+    #[clap(long, action)]
+    pub low_complexity_mask: bool,
+
+    /// DUST score threshold used by --low-complexity-mask, higher values mask less.
+    #[clap(long, default_value_t = DEFAULT_DUST_THRESHOLD)]
+    pub dust_threshold: f64,
+
+    /// Skip reads shorter than this many bases (each mate is checked for paired reads).
+    #[clap(long, default_value_t = 0)]
+    pub min_length: usize,
+
+    /// Skip reads longer than this many bases (each mate is checked for paired reads).
+    #[clap(long)]
+    pub max_length: Option<usize>,
+
+    /// Trim the 3' end of FASTQ reads from the first window whose mean Phred quality is below this value.
+    /// Trimming happens before --min-length filtering, each mate is trimmed independently.
+    #[clap(long)]
+    pub trim_quality: Option<u8>,
+
+    /// Window size in bases used by --trim-quality.
+    #[clap(long, default_value_t = 4)]
+    pub trim_window: usize,
+
+    /// Quality encoding of FASTQ input: phred33 or phred64.
+    #[clap(long, default_value_t = QualityEncoding::Phred33)]
+    pub quality_encoding: QualityEncoding,
+
+    /// Warn about and skip FASTQ records whose sequence and quality lengths differ instead of aborting.
+    #[clap(long, action)]
+    pub lenient_fastq: bool,
+
     /// Confidence score threshold.
     #[clap(
         short = 'T',
@@ -55,7 +107,7 @@ pub struct Args {
     )]
     pub confidence_threshold: f64,
 
-    /// In comb. w/ -R, provide minimizer information in report
+    /// In comb. w/ -R, provide minimizer information in report; the distinct column is a HyperLogLog estimate of the distinct minimizers hit
     #[clap(short = 'K', long, value_parser, default_value_t = false)]
     pub report_kmer_data: bool,
 
@@ -72,6 +124,14 @@ pub struct Args {
     )]
     pub minimum_hit_groups: usize,
 
+    /// Minimum fraction of minimizers that must hit the called taxon's subtree, 0 <= f <= 1
+    #[clap(long, value_parser = parse_fraction, default_value_t = 0.0)]
+    pub min_hit_fraction: f64,
+
+    /// How to call reads whose top-scoring taxa tie: lca, unclassified or report-all (one output line per taxon)
+    #[clap(long = "ambiguous-taxid-policy", value_enum, default_value_t = AmbiguousPolicy::LCA)]
+    pub ambiguous_policy: AmbiguousPolicy,
+
     /// The number of threads to use.
     #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
     pub num_threads: usize,
@@ -80,6 +140,10 @@ pub struct Args {
     #[clap(long = "output-dir", value_parser)]
     pub kraken_output_dir: Option<PathBuf>,
 
+    /// Overwrite existing output files in the output directory
+    #[clap(long, default_value_t = false)]
+    pub force_overwrite: bool,
+
     /// A list of input file paths (FASTA/FASTQ) to be processed by the classify program.
     // #[clap(short = 'F', long = "files")]
     pub input_files: Vec<String>,
@@ -94,8 +158,7 @@ fn process_seq(
 ) -> usize {
     let chunk_size = hash_config.hash_capacity;
     let value_bits = hash_config.value_bits;
-    let data: Vec<(usize, u64)> = m_iter.collect();
-    for (sort, hash_key) in data {
+    for (sort, hash_key) in m_iter.by_ref() {
         let (idx, compacted) = hash_config.compact(hash_key);
         let partition_index = idx / chunk_size;
         let index = idx % chunk_size;
@@ -103,7 +166,8 @@ fn process_seq(
         let taxid = chtable.get_from_page(index, compacted, partition_index + 1);
         if taxid > 0 {
             let high = u32::combined(compacted, taxid, value_bits);
-            let row = Row::new(high, 0, sort as u32 + 1 + offset as u32);
+            let minimizer = hash_config.minimizer_key(idx, compacted);
+            let row = Row::new(high, 0, sort as u32 + 1 + offset as u32, minimizer);
             rows.push(row);
         }
     }
@@ -134,7 +198,11 @@ fn process_record(
         classify_counter,
         required_score,
         args.minimum_hit_groups,
+        args.min_hit_fraction,
         hash_config.value_mask,
+        args.ambiguous_policy,
+        None,
+        KmerCounting::default(),
     );
 
     hit_data.3.iter().for_each(|(key, value)| {
@@ -144,10 +212,16 @@ fn process_record(
             .merge(value)
             .unwrap();
     });
-    format!(
-        "{}\t{}\t{}\t{}\t{}\n",
-        hit_data.0, id, hit_data.1, seq_len_str, hit_data.2
-    )
+    hit_data
+        .1
+        .iter()
+        .map(|ext_call| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                hit_data.0, id, ext_call, seq_len_str, hit_data.2
+            )
+        })
+        .collect()
 }
 
 fn process_fastx_file<R>(
@@ -177,7 +251,7 @@ where
     let seq_counter = AtomicUsize::new(0);
     let classify_counter = AtomicUsize::new(0);
 
-    let _ = read_parallel(
+    read_parallel(
         reader,
         args.num_threads,
         &meros,
@@ -206,12 +280,9 @@ where
                     .expect("Failed to write date to file");
             }
         },
-    );
+    )?;
 
-    let mut sample_taxon_counts: HashMap<
-        u64,
-        kr2r::readcounts::ReadCounts<hyperloglogplus::HyperLogLogPlus<u64, kr2r::KBuildHasher>>,
-    > = HashMap::new();
+    let mut sample_taxon_counts: HashMap<u64, ReadCounter> = HashMap::new();
     cur_taxon_counts.iter().for_each(|entry| {
         total_taxon_counts
             .entry(*entry.key())
@@ -268,6 +339,7 @@ fn process_files(
         let mut total_taxon_counts = TaxonCounters::new();
         let mut total_seqs: usize = 0;
         let mut total_unclassified: usize = 0;
+        let mut total_filtered: usize = 0;
         for file_pair in files {
             file_index += 1;
 
@@ -276,7 +348,22 @@ fn process_files(
 
             let score = args.minimum_quality_score;
             let paths = OptionPair::from_slice(file_pair);
-            let mut reader = FastxReader::from_paths(paths, file_index, score)?;
+            let options = ReadOptions {
+                compression: args.compression,
+                interleaved: args.single_file_pairs,
+                quality_encoding: args.quality_encoding,
+                trimmer: args.trim_quality.map(|quality| {
+                    QualityTrimmer::new(TrimStrategy::SlidingWindow(args.trim_window), quality, 0)
+                }),
+                lenient: args.lenient_fastq,
+            };
+            let mut reader: Box<dyn Reader + Send> = Box::new(FastxReader::from_paths_with(
+                paths, file_index, score, &options,
+            )?);
+            if args.low_complexity_mask {
+                reader = Box::new(DustReader::new(reader, args.dust_threshold));
+            }
+            let mut reader = LengthFilter::new(reader, args.min_length, args.max_length);
             // let mut reader = create_reader(file_pair, file_index, score)?;
             let (thread_sequences, thread_unclassified) = process_fastx_file(
                 &args,
@@ -290,6 +377,10 @@ fn process_files(
             )?;
             total_seqs += thread_sequences;
             total_unclassified += thread_unclassified;
+            total_filtered += reader.filtered();
+        }
+        if total_filtered > 0 {
+            eprintln!("{} reads skipped by length filter", total_filtered);
         }
         if let Some(output) = &args.kraken_output_dir {
             let filename = output.join("output.kreport2");
@@ -319,7 +410,12 @@ fn process_files(
     Ok(())
 }
 
-pub fn run(args: Args) -> Result<()> {
+pub fn run(mut args: Args) -> Result<()> {
+    resolve_pairing(
+        &mut args.paired_end_processing,
+        &mut args.single_file_pairs,
+        args.interleaved,
+    )?;
     let options_filename = &args.database.join("opts.k2d");
     let idx_opts = IndexOptions::read_index_options(options_filename)?;
 
@@ -331,6 +427,29 @@ pub fn run(args: Args) -> Result<()> {
         ));
     }
 
+    if let (Some(output_dir), false) = (&args.kraken_output_dir, args.force_overwrite) {
+        let sample_count = if args.paired_end_processing && !args.single_file_pairs {
+            args.input_files.len() / 2
+        } else {
+            args.input_files.len()
+        };
+        // 样本序号接着 sample_file.map 中已有的序号
+        let map_file = output_dir.join("sample_file.map");
+        let first = if map_file.exists() {
+            get_lastest_file_index(&map_file)? + 1
+        } else {
+            1
+        };
+        let samples = first..=first + sample_count - 1;
+        let conflicts: Vec<PathBuf> = sample_output_paths(output_dir, samples)
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(output_conflict_error(&conflicts));
+        }
+    }
+
     let taxonomy_filename = args.database.join("taxo.k2d");
     let taxo = Taxonomy::from_file(taxonomy_filename)?;
 
@@ -342,7 +461,10 @@ pub fn run(args: Args) -> Result<()> {
     }
     println!("classify start...");
     let start = Instant::now();
-    let meros = idx_opts.as_meros();
+    let meros = Meros {
+        mask_n_bases: args.mask_n_bases,
+        ..idx_opts.as_meros()
+    };
     let hash_files = find_and_sort_files(&args.database, "hash", ".k2d")?;
     let chtable = CHTable::from_hash_files(hash_config, hash_files)?;
 
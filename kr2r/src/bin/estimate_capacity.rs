@@ -1,8 +1,11 @@
 use clap::{error::ErrorKind, Error, Parser};
 use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
-use kr2r::args::KLMTArgs;
-use kr2r::utils::{find_library_fna_files, format_bytes, open_file};
-use kr2r::KBuildHasher;
+use kr2r::args::{parse_error_rate, KLMTArgs};
+use kr2r::db::get_bits_for_taxid;
+use kr2r::readcounts::{self, DEFAULT_HLL_PRECISION};
+use kr2r::taxonomy::NCBITaxonomy;
+use kr2r::utils::{find_library_fna_files, format_bytes, open_file, read_id_to_taxon_map};
+use kr2r::{IndexOptions, KBuildHasher, Kr2Result};
 
 use seqkmer::{read_parallel, FastaReader};
 use serde_json;
@@ -43,6 +46,26 @@ pub struct Args {
     /// Number of threads
     #[clap(short = 'p', long, default_value_t = 10)]
     pub threads: usize,
+
+    /// Relative standard error of the HyperLogLog minimizer count, default is about 0.004
+    #[clap(long, value_parser = parse_error_rate)]
+    pub error_rate: Option<f64>,
+
+    /// Bit storage requested for taxid, 0 uses the minimum needed by the taxonomy
+    #[clap(long = "taxid-bits", value_parser = clap::value_parser!(u8).range(0..31), default_value_t = 0)]
+    pub requested_bits_for_taxid: u8,
+
+    /// Do not read the taxonomy to estimate the size of taxo.k2d
+    #[clap(long, action)]
+    pub skip_taxonomy: bool,
+
+    /// Also write the estimate as JSON to this file
+    #[clap(long)]
+    pub json: Option<PathBuf>,
+}
+
+fn hll_precision(error_rate: Option<f64>) -> u8 {
+    error_rate.map_or(DEFAULT_HLL_PRECISION, readcounts::hll_precision)
 }
 
 const RANGE_SECTIONS: u64 = 1024;
@@ -64,8 +87,14 @@ fn process_sequence<P: AsRef<Path>>(
     // hllp: &mut HyperLogLogPlus<u64, KBuildHasher>,
     args: Args,
 ) -> HyperLogLogPlus<u64, KBuildHasher> {
-    // 构建预期的 JSON 文件路径
-    let json_path = build_output_path(fna_file, &format!("hllp_{}.json", args.n));
+    let precision = hll_precision(args.error_rate);
+    // 构建预期的 JSON 文件路径, 非默认精度的缓存单独保存
+    let extension = if precision == DEFAULT_HLL_PRECISION {
+        format!("hllp_{}.json", args.n)
+    } else {
+        format!("hllp_{}_p{}.json", args.n, precision)
+    };
+    let json_path = build_output_path(fna_file, &extension);
     // 检查是否存在 JSON 文件
     if args.cache && Path::new(&json_path).exists() {
         // 如果存在，从文件读取并反序列化
@@ -81,7 +110,7 @@ fn process_sequence<P: AsRef<Path>>(
     let meros = args.klmt.as_meros();
 
     let mut hllp: HyperLogLogPlus<u64, _> =
-        HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
+        HyperLogLogPlus::new(precision, KBuildHasher::default()).unwrap();
 
     let mut reader = FastaReader::from_path(fna_file, 1).unwrap();
     let range_n = args.n as u64;
@@ -129,22 +158,50 @@ fn process_sequence<P: AsRef<Path>>(
     hllp
 }
 
-pub fn run(args: Args) -> usize {
-    let meros = args.klmt.as_meros();
+/// 按 build 的方式从 seqid2taxid.map 和 taxonomy 目录生成分类树, 返回 (节点数, taxo.k2d 的字节数)
+/// 缺少 seqid2taxid.map 或 nodes.dmp 时返回 None, 文件存在但无法解析时返回错误
+fn estimate_taxonomy(database: &Path) -> Kr2Result<Option<(usize, usize)>> {
+    let id_map_file = database.join("seqid2taxid.map");
+    let taxonomy_dir = database.join("taxonomy");
+    if !id_map_file.exists() || !taxonomy_dir.join("nodes.dmp").exists() {
+        return Ok(None);
+    }
+    let id_to_taxon_map = read_id_to_taxon_map(id_map_file)?;
+    let mut ncbi = NCBITaxonomy::from_ncbi(
+        taxonomy_dir.join("nodes.dmp"),
+        taxonomy_dir.join("names.dmp"),
+    )?;
+    let merged_file = taxonomy_dir.join("merged.dmp");
+    if merged_file.exists() {
+        ncbi.load_merged(merged_file)?;
+    }
+    for taxid in id_to_taxon_map.values() {
+        ncbi.mark_node(*taxid);
+    }
+    let taxonomy = ncbi.convert_to_kraken_taxonomy();
+    let mut buffer = Vec::new();
+    taxonomy.write_to_writer(&mut buffer)?;
+    Ok(Some((taxonomy.node_count(), buffer.len())))
+}
 
-    if meros.k_mer < meros.l_mer {
-        let err = Error::raw(ErrorKind::ValueValidation, "k cannot be less than l");
+pub fn run(args: Args) -> Kr2Result<usize> {
+    if let Err(msg) = args.klmt.validate() {
+        let err = Error::raw(ErrorKind::ValueValidation, msg);
         err.exit();
     }
 
+    let precision = hll_precision(args.error_rate);
+    if let Some(error_rate) = args.error_rate {
+        readcounts::warn_if_hll_clamped(error_rate, precision);
+    }
     let mut hllp: HyperLogLogPlus<u64, KBuildHasher> =
-        HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
+        HyperLogLogPlus::new(precision, KBuildHasher::default()).unwrap();
 
     let source: PathBuf = args.database.clone();
     let fna_files = if source.is_file() {
         vec![source.clone()]
     } else {
-        find_library_fna_files(args.database)
+        find_library_fna_files(args.database.clone())
     };
 
     if fna_files.is_empty() {
@@ -156,7 +213,7 @@ pub fn run(args: Args) -> usize {
     for fna_file in fna_files {
         let args_clone = Args {
             database: source.clone(),
-            ..args
+            ..args.clone()
         };
         let local_hllp = process_sequence(&fna_file, args_clone);
         if let Err(e) = hllp.merge(&local_hllp) {
@@ -166,17 +223,86 @@ pub fn run(args: Args) -> usize {
 
     let hllp_count = (hllp.count() * RANGE_SECTIONS as f64 / args.n as f64).round() as u64;
     let required_capacity = (hllp_count + 8192) as f64 / args.load_factor;
-    println!(
-        "estimate count: {:?}, required capacity: {:?}, Estimated hash table requirement: {:}",
-        hllp_count,
-        required_capacity.ceil(),
-        format_bytes(required_capacity * 4f64)
-    );
-    required_capacity.ceil() as usize
+    let capacity = required_capacity.ceil() as usize;
+    let hash_table_bytes = capacity * std::mem::size_of::<u32>();
+
+    let taxonomy = if args.skip_taxonomy || !source.is_dir() {
+        None
+    } else {
+        estimate_taxonomy(&source)?
+    };
+    let taxid_bits = match taxonomy {
+        Some((node_count, _)) => Some(
+            get_bits_for_taxid(args.requested_bits_for_taxid as usize, node_count as f64)
+                .unwrap_or_else(|msg| Error::raw(ErrorKind::ValueValidation, msg + "\n").exit()),
+        ),
+        None if args.requested_bits_for_taxid > 0 => Some(args.requested_bits_for_taxid as usize),
+        None => None,
+    };
+    let taxonomy_bytes = taxonomy.map_or(0, |(_, bytes)| bytes);
+    let disk_bytes = hash_table_bytes + taxonomy_bytes + std::mem::size_of::<IndexOptions>();
+    // classify 需要同时载入哈希表和分类树
+    let memory_bytes = hash_table_bytes + taxonomy_bytes;
+
+    let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+    let rows = [
+        ("estimated minimizers", hllp_count.to_string()),
+        (
+            "minimizer count error",
+            format!("{:.4}", readcounts::hll_error(precision)),
+        ),
+        ("load factor", args.load_factor.to_string()),
+        ("hash table capacity", capacity.to_string()),
+        (
+            "bits per cell (taxid + minimizer)",
+            or_unknown(taxid_bits.map(|bits| format!("32 ({} + {})", bits, 32 - bits))),
+        ),
+        ("hash table size", format_bytes(hash_table_bytes as f64)),
+        (
+            "taxonomy nodes",
+            or_unknown(taxonomy.map(|(nodes, _)| nodes.to_string())),
+        ),
+        (
+            "taxonomy file size",
+            or_unknown(taxonomy.map(|(_, bytes)| format_bytes(bytes as f64))),
+        ),
+        ("total disk usage", format_bytes(disk_bytes as f64)),
+        ("classify memory", format_bytes(memory_bytes as f64)),
+    ];
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, value) in &rows {
+        println!("{:<width$}  {}", name, value, width = width);
+    }
+
+    if let Some(path) = &args.json {
+        let estimate = serde_json::json!({
+            "estimated_minimizers": hllp_count,
+            "hll_precision": precision,
+            "load_factor": args.load_factor,
+            "hash_table_capacity": capacity,
+            "taxid_bits": taxid_bits,
+            "hash_table_bytes": hash_table_bytes,
+            "taxonomy_nodes": taxonomy.map(|(nodes, _)| nodes),
+            "taxonomy_bytes": taxonomy.map(|(_, bytes)| bytes),
+            "total_disk_bytes": disk_bytes,
+            "classify_memory_bytes": memory_bytes,
+        });
+        let written = File::create(path).and_then(|mut file| {
+            serde_json::to_writer_pretty(&mut file, &estimate)?;
+            writeln!(file)
+        });
+        if let Err(e) = written {
+            eprintln!("Failed to write {:?}: {}", path, e);
+        }
+    }
+
+    Ok(capacity)
 }
 
 #[allow(dead_code)]
 fn main() {
     let args = Args::parse();
-    run(args);
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
 }
@@ -0,0 +1,49 @@
+use clap::Parser;
+use kr2r::krona::{parse_kraken_report, write_krona_html};
+use kr2r::utils::open_file;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Result, Write};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "krona",
+    long_about = "Write a self-contained Krona style HTML chart from a Kraken 2 style report"
+)]
+pub struct Args {
+    /// Kraken 2 style report (e.g. output.kreport2)
+    #[clap(short = 'i', long, required = true)]
+    pub report: PathBuf,
+
+    /// HTML file to write
+    #[clap(short, long, required = true)]
+    pub output: PathBuf,
+
+    /// Dataset name shown in the chart, default is the report file name
+    #[clap(long)]
+    pub name: Option<String>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let root = parse_kraken_report(BufReader::new(open_file(&args.report)?))?;
+    let name = args.name.clone().unwrap_or_else(|| {
+        args.report
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_string())
+    });
+
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    write_krona_html(&mut writer, &root, &name)?;
+    writer.flush()?;
+    println!("krona chart written to {:?}", args.output);
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
@@ -1,19 +1,41 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 mod annotate;
+mod bracken;
 mod build_k2_db;
 mod classify;
 mod estimate_capacity;
 mod hashshard;
+mod krona;
 mod merge_fna;
+mod report;
 mod resolve;
 // mod seqid2taxid;
 mod splitr;
+mod verify;
 
-use kr2r::args::ClassifyArgs;
-use kr2r::args::{parse_size, Build};
-use kr2r::utils::find_and_sort_files;
+use kr2r::args::{
+    check_output_conflicts, check_parallel_samples, metadata_output_path, output_conflict_error,
+    resolve_pairing, sample_inputs, sample_output_files, ClassifyArgs,
+};
+use kr2r::args::{compute_safe_batch_size, parse_size, Build, BATCH_SLOT_BYTES};
+use kr2r::config::{apply_config_file, default_config, find_config_arg};
+use kr2r::manifest::{
+    read_manifest, write_manifest_summary, ManifestSample, SampleStats, MANIFEST_SUMMARY_FILE,
+};
+use kr2r::metadata::{arguments_json, RunMetadata};
+use kr2r::multi_db::MultiDatabaseClassifier;
+use kr2r::rank_split::{
+    classified_output_paths, rank_group, rank_output_paths, read_kraken_calls,
+    split_sample_by_call, split_sample_by_rank, split_sample_by_taxon, taxon_writers,
+    NO_RANK_GROUP, UNCLASSIFIED_GROUP,
+};
+use kr2r::sam::{read_sam_calls, write_sam_header, write_sample_sam, OutputFormat};
+use kr2r::taxonomy::Taxonomy;
+use kr2r::utils::{find_and_sort_files, get_file_limit, utc_now};
 // use std::io::Result;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 #[derive(Parser, Debug, Clone)]
@@ -52,6 +74,10 @@ struct BuildArgs {
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    /// Read option defaults from a TOML file, options on the command line take precedence
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
     #[clap(subcommand)]
     cmd: Commands,
 }
@@ -62,7 +88,19 @@ impl From<ClassifyArgs> for splitr::Args {
             database: item.database,
             paired_end_processing: item.paired_end_processing,
             single_file_pairs: item.single_file_pairs,
+            interleaved: item.interleaved,
             minimum_quality_score: item.minimum_quality_score,
+            compression: item.compression,
+            mask_n_bases: item.mask_n_bases,
+            low_complexity_mask: item.low_complexity_mask,
+            dust_threshold: item.dust_threshold,
+            min_length: item.min_length,
+            max_length: item.max_length,
+            trim_quality: item.trim_quality,
+            trim_window: item.trim_window,
+            quality_encoding: item.quality_encoding,
+            lenient_fastq: item.lenient_fastq,
+            use_bloom_filter: item.use_bloom_filter,
             num_threads: item.num_threads,
             chunk_dir: item.chunk_dir,
             input_files: item.input_files,
@@ -77,6 +115,7 @@ impl From<ClassifyArgs> for annotate::Args {
             chunk_dir: item.chunk_dir,
             batch_size: item.batch_size,
             kraken_db_type: item.kraken_db_type,
+            mmap_db: item.mmap_db,
         }
     }
 }
@@ -89,10 +128,24 @@ impl From<ClassifyArgs> for resolve::Args {
             batch_size: item.batch_size,
             confidence_threshold: item.confidence_threshold,
             minimum_hit_groups: item.minimum_hit_groups,
+            min_hit_fraction: item.min_hit_fraction,
+            ambiguous_policy: item.ambiguous_policy,
             kraken_output_dir: item.kraken_output_dir,
             report_kmer_data: item.report_kmer_data,
             report_zero_counts: item.report_zero_counts,
+            count_unique_kmers: item.count_unique_kmers,
+            approx_unique_kmers: item.approx_unique_kmers,
+            unique_kmers_error_rate: item.unique_kmers_error_rate,
             full_output: item.full_output,
+            seed: item.seed,
+            kmer_hits_file: item.kmer_hits_file,
+            max_hits_per_read: item.max_hits_per_read,
+            biom_output: item.biom_output,
+            include_confidence: item.include_confidence,
+            confidence_histogram: item.confidence_histogram,
+            hist_bin_width: item.hist_bin_width,
+            top_db_column: item.top_db_column,
+            parallel_samples: item.parallel_samples,
         }
     }
 }
@@ -106,6 +159,11 @@ impl From<BuildArgs> for estimate_capacity::Args {
             n: item.max_n,
             load_factor: item.load_factor,
             threads: item.build.threads,
+            error_rate: None,
+            requested_bits_for_taxid: item.build.requested_bits_for_taxid,
+            // build 之后会生成真正的 taxo.k2d, 不需要提前估计
+            skip_taxonomy: true,
+            json: None,
         }
     }
 }
@@ -137,6 +195,262 @@ impl From<BuildArgs> for merge_fna::Args {
     }
 }
 
+/// 按 --split-by-rank 把每个样本的 read 拆分到 `output_{i}_{rank}` 目录下
+fn split_outputs_by_rank(
+    args: &ClassifyArgs,
+    rank: &str,
+    taxonomy: &Taxonomy,
+) -> std::io::Result<()> {
+    let output_dir = args.kraken_output_dir.as_ref().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--split-by-rank requires --output-dir",
+        )
+    })?;
+    if !taxonomy.known_ranks().iter().any(|known| known == rank) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("rank `{}` not found in taxonomy", rank),
+        ));
+    }
+
+    let (samples, interleaved) = sample_inputs(args);
+    for (i, input_files) in samples.enumerate() {
+        let calls = read_kraken_calls(output_dir.join(format!("output_{}.txt", i + 1)), taxonomy)?;
+        let rank_taxids: Vec<u32> = calls
+            .keys()
+            .map(|seq_id| rank_group(&calls, seq_id, taxonomy, rank))
+            .filter(|&group| group != UNCLASSIFIED_GROUP && group != NO_RANK_GROUP)
+            .collect::<HashSet<u32>>()
+            .into_iter()
+            .collect();
+
+        let split_dir = output_dir.join(format!("output_{}_{}", i + 1, rank));
+        std::fs::create_dir_all(&split_dir)?;
+        let paths = rank_output_paths(&split_dir, &rank_taxids, taxonomy);
+        split_sample_by_rank(
+            input_files,
+            interleaved,
+            args.compression,
+            &calls,
+            &paths,
+            taxonomy,
+            rank,
+        )?;
+    }
+    Ok(())
+}
+
+/// 依次用 --db 和每个 --k2d-dir 运行 splitr 和 annotate, 再合并各数据库的命中
+/// 返回每个样本被长度过滤跳过的 read 数, 各数据库读到的 read 相同, 取第一个数据库的结果
+fn annotate_with_databases(
+    args: &ClassifyArgs,
+    taxonomy: &Taxonomy,
+) -> std::io::Result<Vec<usize>> {
+    let databases: Vec<PathBuf> = std::iter::once(args.database.clone())
+        .chain(args.k2d_dirs.iter().cloned())
+        .collect();
+    let classifier = MultiDatabaseClassifier::new(databases)?;
+    let mut filtered = Vec::new();
+    for (index, database) in classifier.databases.iter().enumerate() {
+        println!("query database {}: {:?}", index + 1, database);
+        let chunk_dir = MultiDatabaseClassifier::chunk_dir(&args.chunk_dir, index);
+        if chunk_dir.exists() {
+            std::fs::remove_dir_all(&chunk_dir)?;
+        }
+        std::fs::create_dir_all(&chunk_dir)?;
+
+        let mut db_args = args.clone();
+        db_args.database = database.clone();
+        db_args.chunk_dir = chunk_dir;
+        let db_filtered = splitr::run(splitr::Args::from(db_args.clone()))?;
+        if index == 0 {
+            filtered = db_filtered;
+        }
+        annotate::run(annotate::Args::from(db_args))?;
+    }
+    classifier.merge_annotations(&args.chunk_dir, taxonomy)?;
+    Ok(filtered)
+}
+
+/// 按 --classified-out/--unclassified-out 把所有样本的 read 写入分类和未分类两组文件
+fn write_classified_outputs(args: &ClassifyArgs, taxonomy: &Taxonomy) -> std::io::Result<()> {
+    let output_dir = args.kraken_output_dir.as_ref().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--classified-out and --unclassified-out require --output-dir",
+        )
+    })?;
+    let create =
+        |path: &Option<PathBuf>| -> std::io::Result<Vec<std::io::BufWriter<std::fs::File>>> {
+            path.iter()
+                .flat_map(|path| classified_output_paths(path, args.paired_end_processing))
+                .map(|path| Ok(std::io::BufWriter::new(std::fs::File::create(path)?)))
+                .collect()
+        };
+    let mut classified = create(&args.classified_out)?;
+    let mut unclassified = create(&args.unclassified_out)?;
+
+    let (samples, interleaved) = sample_inputs(args);
+    for (i, input_files) in samples.enumerate() {
+        let calls = read_kraken_calls(output_dir.join(format!("output_{}.txt", i + 1)), taxonomy)?;
+        split_sample_by_call(
+            input_files,
+            interleaved,
+            args.compression,
+            &calls,
+            taxonomy,
+            &mut classified,
+            &mut unclassified,
+        )?;
+    }
+    for writer in classified.iter_mut().chain(unclassified.iter_mut()) {
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// 按 --split-by-taxon 把所有样本中已分类的 read 写入每个 taxon 各自的文件
+fn write_taxon_outputs(
+    args: &ClassifyArgs,
+    dir: &Path,
+    taxonomy: &Taxonomy,
+) -> std::io::Result<()> {
+    let output_dir = args.kraken_output_dir.as_ref().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--split-by-taxon requires --output-dir",
+        )
+    })?;
+    std::fs::create_dir_all(dir)?;
+    // 给输入文件和其他输出留出一半的文件描述符
+    let max_open = (get_file_limit() / 2).max(2);
+    let mut writers = taxon_writers(dir, args.paired_end_processing, taxonomy, max_open);
+
+    let (samples, interleaved) = sample_inputs(args);
+    for (i, input_files) in samples.enumerate() {
+        let calls = read_kraken_calls(output_dir.join(format!("output_{}.txt", i + 1)), taxonomy)?;
+        split_sample_by_taxon(
+            input_files,
+            interleaved,
+            args.compression,
+            &calls,
+            &mut writers,
+        )?;
+    }
+    for writer in writers.iter_mut() {
+        writer.flush()?;
+    }
+    println!(
+        "reads of {} taxa written to {:?}",
+        writers[0].taxon_count(),
+        dir
+    );
+    Ok(())
+}
+
+/// 按 --output-format sam 为每个样本写出 `output_{i}.sam`
+fn write_sam_outputs(args: &ClassifyArgs, taxonomy: &Taxonomy) -> std::io::Result<()> {
+    let output_dir = args.kraken_output_dir.as_ref().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--output-format sam requires --output-dir",
+        )
+    })?;
+    let command_line: Vec<String> = std::env::args().collect();
+
+    let (samples, interleaved) = sample_inputs(args);
+    for (i, input_files) in samples.enumerate() {
+        let calls = read_sam_calls(output_dir.join(format!("output_{}.txt", i + 1)), taxonomy)?;
+        let file = std::fs::File::create(output_dir.join(format!("output_{}.sam", i + 1)))?;
+        let mut writer = std::io::BufWriter::new(file);
+        write_sam_header(&mut writer, &command_line.join(" "), &args.database)?;
+        write_sample_sam(
+            &mut writer,
+            input_files,
+            interleaved,
+            args.compression,
+            &calls,
+        )?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// 用 --manifest 中的样本替换输入文件, 所有样本都有 r2 时自动开启双端模式
+/// 各样本的输出按 sample_id 放在 --output-dir 下, 配置文件中的 manifest 不经过 clap 的检查, 这里再确认一次
+fn apply_manifest(args: &mut ClassifyArgs, samples: &[ManifestSample]) -> std::io::Result<()> {
+    if args.kraken_output_dir.is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--manifest requires --output-dir",
+        ));
+    }
+    let paired = samples.iter().all(|sample| sample.r2.is_some());
+    if paired && args.single_file_pairs {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--single-file-pairs can not be used with a manifest that has r2_path",
+        ));
+    }
+    if !paired && args.paired_end_processing && !args.single_file_pairs {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "paired-end processing requires r2_path for every sample in the manifest",
+        ));
+    }
+    args.paired_end_processing |= paired;
+    args.input_files = samples
+        .iter()
+        .flat_map(ManifestSample::input_files)
+        .collect();
+    Ok(())
+}
+
+/// --manifest 模式下各样本输出所在的目录, 以及汇总表
+fn manifest_output_paths(output_dir: &Path, samples: &[ManifestSample]) -> Vec<PathBuf> {
+    samples
+        .iter()
+        .map(|sample| sample.output_dir(output_dir))
+        .chain(std::iter::once(output_dir.join(MANIFEST_SUMMARY_FILE)))
+        .collect()
+}
+
+/// 把第 i 个样本生成的 `output_{i}.*` 和 `output_{i}_{rank}` 移动到 `<output-dir>/<sample_id>/`, 并写出汇总表
+fn write_manifest_outputs(
+    args: &ClassifyArgs,
+    samples: &[ManifestSample],
+    stats: &[SampleStats],
+) -> std::io::Result<()> {
+    let output_dir = args.kraken_output_dir.as_ref().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--manifest requires --output-dir",
+        )
+    })?;
+    for (i, sample) in samples.iter().enumerate() {
+        let sample_dir = sample.output_dir(output_dir);
+        std::fs::create_dir_all(&sample_dir)?;
+        let prefix = format!("output_{}", i + 1);
+        for path in sample_output_files(args, output_dir, i + 1) {
+            if !path.exists() {
+                continue;
+            }
+            let name = path.file_name().unwrap().to_string_lossy();
+            let target = sample_dir.join(name.replacen(&prefix, "output", 1));
+            if target.is_dir() {
+                std::fs::remove_dir_all(&target)?;
+            }
+            std::fs::rename(&path, target)?;
+        }
+    }
+
+    let file = std::fs::File::create(output_dir.join(MANIFEST_SUMMARY_FILE))?;
+    let mut writer = std::io::BufWriter::new(file);
+    write_manifest_summary(&mut writer, samples, stats)?;
+    writer.flush()
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     Estimate(estimate_capacity::Args),
@@ -146,29 +460,45 @@ enum Commands {
     Splitr(splitr::Args),
     Annotate(annotate::Args),
     Resolve(resolve::Args),
-    Classify(ClassifyArgs),
+    Classify(Box<ClassifyArgs>),
     Direct(classify::Args),
     MergeFna(merge_fna::Args),
+    Report(report::Args),
+    Bracken(bracken::Args),
+    Krona(krona::Args),
+    Verify(verify::Args),
+    /// Print a TOML config file with the default value of every option
+    GenerateConfig,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let mut command = Args::command();
+    if let Some(path) = find_config_arg(std::env::args_os()) {
+        command = apply_config_file(command, &path)?;
+    }
+    let matches = command.get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
     match args.cmd {
+        Commands::GenerateConfig => {
+            print!("{}", default_config(&Args::command(), &["generate-config"]));
+        }
         Commands::MergeFna(cmd_args) => {
             merge_fna::run(cmd_args)?;
         }
         Commands::Estimate(cmd_args) => {
-            estimate_capacity::run(cmd_args);
+            estimate_capacity::run(cmd_args)?;
         }
         // Commands::Seqid2taxid(cmd_args) => {
         //     seqid2taxid::run(cmd_args)?;
         // }
         Commands::Build(cmd_args) => {
+            // 在耗时的 merge_fna 之前检查 k-mer 参数
+            cmd_args.build.klmt.validate()?;
             let fna_args = merge_fna::Args::from(cmd_args.clone());
             merge_fna::run(fna_args)?;
             let ec_args = estimate_capacity::Args::from(cmd_args.clone());
-            let required_capacity = estimate_capacity::run(ec_args);
+            let required_capacity = estimate_capacity::run(ec_args)?;
 
             let build_args = build_k2_db::Args::from(cmd_args.clone());
             build_k2_db::run(build_args, required_capacity)?;
@@ -186,7 +516,51 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             resolve::run(cmd_args)?;
         }
         Commands::Classify(cmd_args) => {
+            let mut cmd_args = *cmd_args;
             let start = Instant::now();
+            let started_at = utc_now();
+            resolve_pairing(
+                &mut cmd_args.paired_end_processing,
+                &mut cmd_args.single_file_pairs,
+                cmd_args.interleaved,
+            )?;
+            check_parallel_samples(
+                cmd_args.parallel_samples,
+                cmd_args.kraken_output_dir.as_deref(),
+            )?;
+
+            if let Some(memory_limit) = cmd_args.memory_limit {
+                cmd_args.batch_size =
+                    compute_safe_batch_size(memory_limit, cmd_args.num_threads, BATCH_SLOT_BYTES);
+                eprintln!(
+                    "batch size set to {} by --memory-limit",
+                    cmd_args.batch_size
+                );
+            }
+
+            let samples = match &cmd_args.manifest {
+                Some(path) => {
+                    let samples = read_manifest(path)?;
+                    apply_manifest(&mut cmd_args, &samples)?;
+                    println!("{} samples read from manifest", samples.len());
+                    samples
+                }
+                None => Vec::new(),
+            };
+
+            if !cmd_args.force_overwrite {
+                let mut conflicts = check_output_conflicts(&cmd_args);
+                if let Some(output_dir) = &cmd_args.kraken_output_dir {
+                    conflicts.extend(
+                        manifest_output_paths(output_dir, &samples)
+                            .into_iter()
+                            .filter(|path| path.exists()),
+                    );
+                }
+                if !conflicts.is_empty() {
+                    return Err(Box::new(output_conflict_error(&conflicts)));
+                }
+            }
 
             let splitr_args = splitr::Args::from(cmd_args.clone());
             let chunk_files = find_and_sort_files(&splitr_args.chunk_dir, "sample", ".k2")?;
@@ -201,18 +575,84 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     ),
                 )));
             }
-            splitr::run(splitr_args)?;
-            let annotate_args = annotate::Args::from(cmd_args.clone());
-            annotate::run(annotate_args)?;
+            // 合并多个数据库的命中和按 read 拆分输出都要用到分类树, 只读取一次
+            let taxonomy = Taxonomy::from_file(cmd_args.database.join("taxo.k2d"))?;
+            let filtered = if cmd_args.k2d_dirs.is_empty() {
+                let filtered = splitr::run(splitr_args)?;
+                let annotate_args = annotate::Args::from(cmd_args.clone());
+                annotate::run(annotate_args)?;
+                filtered
+            } else {
+                annotate_with_databases(&cmd_args, &taxonomy)?
+            };
             let resolve_args = resolve::Args::from(cmd_args.clone());
-            resolve::run(resolve_args)?;
+            let mut sample_stats = resolve::run(resolve_args)?;
+            for (stats, filtered) in sample_stats.iter_mut().zip(&filtered) {
+                stats.filtered = *filtered;
+            }
+            let total_filtered: usize = filtered.iter().sum();
+            if total_filtered > 0 {
+                eprintln!("{} reads skipped by length filter", total_filtered);
+            }
+            if let Some(rank) = &cmd_args.split_by_rank {
+                split_outputs_by_rank(&cmd_args, rank, &taxonomy)?;
+            }
+            if cmd_args.classified_out.is_some() || cmd_args.unclassified_out.is_some() {
+                write_classified_outputs(&cmd_args, &taxonomy)?;
+            }
+            if let Some(dir) = &cmd_args.split_by_taxon {
+                write_taxon_outputs(&cmd_args, dir, &taxonomy)?;
+            }
+            if cmd_args.output_format == OutputFormat::Sam {
+                write_sam_outputs(&cmd_args, &taxonomy)?;
+            }
+            if !samples.is_empty() {
+                write_manifest_outputs(&cmd_args, &samples, &sample_stats)?;
+            }
 
             let duration = start.elapsed();
             println!("Classify took: {:?}", duration);
+
+            if let Some(path) = metadata_output_path(&cmd_args) {
+                let arguments = arguments_json(
+                    Args::command().find_subcommand("classify").unwrap(),
+                    matches.subcommand_matches("classify").unwrap(),
+                );
+                let databases: Vec<PathBuf> = std::iter::once(cmd_args.database.clone())
+                    .chain(cmd_args.k2d_dirs.iter().cloned())
+                    .collect();
+                let metadata = RunMetadata {
+                    command_line: std::env::args().collect(),
+                    arguments,
+                    input_files: &cmd_args.input_files,
+                    databases: &databases,
+                    taxonomy: cmd_args.database.join("taxo.k2d"),
+                    sample_stats: &sample_stats,
+                    started_at,
+                    wall_clock: duration,
+                    checksums: cmd_args.metadata_checksums,
+                };
+                let mut writer = std::io::BufWriter::new(std::fs::File::create(&path)?);
+                metadata.write(&mut writer)?;
+                writer.flush()?;
+                println!("run metadata written to {:?}", path);
+            }
         }
         Commands::Direct(cmd_args) => {
             classify::run(cmd_args)?;
         }
+        Commands::Report(cmd_args) => {
+            report::run(cmd_args)?;
+        }
+        Commands::Bracken(cmd_args) => {
+            bracken::run(cmd_args)?;
+        }
+        Commands::Krona(cmd_args) => {
+            krona::run(cmd_args)?;
+        }
+        Commands::Verify(cmd_args) => {
+            verify::run(cmd_args)?;
+        }
     }
 
     Ok(())
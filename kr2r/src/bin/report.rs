@@ -0,0 +1,120 @@
+use clap::Parser;
+use kr2r::classify::primary_call;
+use kr2r::readcounts::TaxonCounters;
+use kr2r::report::report_kraken_style_with_min_reads;
+use kr2r::taxonomy::Taxonomy;
+use kr2r::utils::open_file;
+use std::io::{BufRead, BufReader, Result};
+use std::path::PathBuf;
+use std::time::Instant;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "report",
+    long_about = "Write a Kraken 2 style report from existing classify output without classifying again"
+)]
+pub struct Args {
+    /// Per-read classification output of classify or resolve (output_{i}.txt),
+    /// reads without minimizers are only counted as unclassified if it was written with --full-output
+    #[clap(long, required = true)]
+    pub classify_output: PathBuf,
+
+    /// Taxonomy file of the database used for classification (taxo.k2d)
+    #[clap(long, required = true)]
+    pub taxonomy: PathBuf,
+
+    /// Report file to write
+    #[clap(short, long, required = true)]
+    pub output: PathBuf,
+
+    /// Report taxa w/ 0 count
+    #[clap(short = 'z', long, value_parser, default_value_t = false)]
+    pub report_zero_counts: bool,
+
+    /// Omit taxa whose clade has fewer reads than this
+    #[clap(long, default_value_t = 0)]
+    pub min_reads: usize,
+}
+
+/// 统计每个 taxon 直接分到的 read 数, 返回 (计数, read 总数, 未分类 read 数)
+/// --ambiguous-taxid-policy report-all 输出的同一条 read 的多行结果是连续的, 计入它们的主要分类结果 (见 primary_call)
+fn count_calls(args: &Args, taxonomy: &Taxonomy) -> Result<(TaxonCounters, u64, u64)> {
+    let reader = BufReader::new(open_file(&args.classify_output)?);
+    let mut call_counters = TaxonCounters::new();
+    let mut total_seqs = 0;
+    let mut total_unclassified = 0;
+
+    let mut count = |calls: &[u32]| {
+        total_seqs += 1;
+        let call = primary_call(taxonomy, calls);
+        if call == 0 {
+            total_unclassified += 1;
+        } else {
+            call_counters
+                .entry(call as u64)
+                .or_default()
+                .increment_read_count();
+        }
+    };
+
+    let mut seq_id = String::new();
+    let mut calls: Vec<u32> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let mut fields = line.split('\t');
+        let (Some(_), Some(id), Some(taxid)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        let call = taxid
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|taxid| taxonomy.get_internal_id_opt(taxid))
+            .unwrap_or(0);
+        if id != seq_id {
+            if !calls.is_empty() {
+                count(&calls);
+            }
+            calls.clear();
+            seq_id = id.to_string();
+        }
+        calls.push(call);
+    }
+    if !calls.is_empty() {
+        count(&calls);
+    }
+    Ok((call_counters, total_seqs, total_unclassified))
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let start = Instant::now();
+    let taxonomy = Taxonomy::from_file(&args.taxonomy)?;
+    let (call_counters, total_seqs, total_unclassified) = count_calls(&args, &taxonomy)?;
+
+    report_kraken_style_with_min_reads(
+        &args.output,
+        args.report_zero_counts,
+        false,
+        &taxonomy,
+        &call_counters,
+        total_seqs,
+        total_unclassified,
+        args.min_reads as u64,
+    )?;
+
+    println!(
+        "{} reads, {} unclassified, report written to {:?}",
+        total_seqs, total_unclassified, args.output
+    );
+    println!("report took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
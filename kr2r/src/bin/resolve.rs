@@ -1,8 +1,15 @@
 use clap::Parser;
 use dashmap::{DashMap, DashSet};
-use kr2r::classify::process_hitgroup;
+use kr2r::args::{check_parallel_samples, parse_bin_width, parse_error_rate, parse_fraction};
+use kr2r::biom::{write_biom, BiomSample};
+use kr2r::classify::{collect_kmer_hits, process_hitgroup, AmbiguousPolicy, ConfidenceHistogram};
 use kr2r::compact_hash::{HashConfig, Row};
-use kr2r::readcounts::{TaxonCounters, TaxonCountersDash};
+use kr2r::manifest::SampleStats;
+use kr2r::multi_db::read_top_db_map;
+use kr2r::readcounts::{
+    hll_precision, warn_if_hll_clamped, KmerCounting, ReadCounter, TaxonCounters,
+    TaxonCountersDash, DEFAULT_HLL_PRECISION,
+};
 use kr2r::report::report_kraken_style;
 use kr2r::taxonomy::Taxonomy;
 use kr2r::utils::{find_and_sort_files, open_file};
@@ -77,7 +84,7 @@ pub struct Args {
     )]
     pub confidence_threshold: f64,
 
-    /// In comb. w/ -R, provide minimizer information in report
+    /// In comb. w/ -R, provide minimizer information in report; the distinct column is a HyperLogLog estimate of the distinct minimizers hit
     #[clap(short = 'K', long, value_parser, default_value_t = false)]
     pub report_kmer_data: bool,
 
@@ -85,6 +92,18 @@ pub struct Args {
     #[clap(short = 'z', long, value_parser, default_value_t = false)]
     pub report_zero_counts: bool,
 
+    /// Count distinct minimizers per taxon exactly (KrakenUniq style) and add them to the report, implies -K
+    #[clap(long, action, conflicts_with = "approx_unique_kmers")]
+    pub count_unique_kmers: bool,
+
+    /// Like --count-unique-kmers, but estimate the counts with HyperLogLog to bound memory on large databases
+    #[clap(long, action)]
+    pub approx_unique_kmers: bool,
+
+    /// Relative standard error of --approx-unique-kmers, default is about 0.004
+    #[clap(long, value_parser = parse_error_rate, requires = "approx_unique_kmers")]
+    pub unique_kmers_error_rate: Option<f64>,
+
     /// The minimum number of hit groups needed for a call.
     #[clap(
         short = 'g',
@@ -94,22 +113,95 @@ pub struct Args {
     )]
     pub minimum_hit_groups: usize,
 
+    /// Minimum fraction of minimizers that must hit the called taxon's subtree, 0 <= f <= 1
+    #[clap(long, value_parser = parse_fraction, default_value_t = 0.0)]
+    pub min_hit_fraction: f64,
+
+    /// How to call reads whose top-scoring taxa tie: lca, unclassified or report-all (one output line per taxon)
+    #[clap(long = "ambiguous-taxid-policy", value_enum, default_value_t = AmbiguousPolicy::LCA)]
+    pub ambiguous_policy: AmbiguousPolicy,
+
     #[clap(long, default_value_t = BATCH_SIZE)]
     pub batch_size: usize,
 
     /// File path for outputting normal Kraken output.
     #[clap(long = "output-dir", value_parser)]
     pub kraken_output_dir: Option<PathBuf>,
+
+    /// Write a TSV of `read_id, minimizer, taxid` for every minimizer hit to this file
+    #[clap(long)]
+    pub kmer_hits_file: Option<PathBuf>,
+
+    /// Maximum number of minimizer hits written per read with --kmer-hits-file
+    #[clap(long, default_value_t = 100)]
+    pub max_hits_per_read: usize,
+
+    /// Random seed for breaking ties between equally scored taxa (default: use their LCA)
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// Write a BIOM v1 (JSON) table of read counts, one row per taxon and one column per sample
+    #[clap(long)]
+    pub biom_output: Option<PathBuf>,
+
+    /// Append the confidence of each call (fraction of k-mers hitting its clade) as a sixth output column
+    #[clap(long, action)]
+    pub include_confidence: bool,
+
+    /// Write a TSV histogram (bin_start, count) of per-read confidence to this file
+    #[clap(long)]
+    pub confidence_histogram: Option<PathBuf>,
+
+    /// Bin width of --confidence-histogram
+    #[clap(long, value_parser = parse_bin_width, default_value_t = 0.01)]
+    pub hist_bin_width: f64,
+
+    /// Append the database that contributed the most hits to each read (1 = --db, then --k2d-dir in order) as the last output column
+    #[clap(long, action)]
+    pub top_db_column: bool,
+
+    /// Number of samples resolved at the same time, each sample keeps its reads in memory while it is resolved, values above 1 require --output-dir
+    #[clap(long, default_value_t = 1)]
+    pub parallel_samples: usize,
 }
 
+/// 从 chunk 目录的 sample_file.map 读取每个样本的输入文件名, 用作 BIOM 的列 id
+fn read_sample_names(chunk_dir: &Path) -> HashMap<usize, String> {
+    let Ok(file) = open_file(chunk_dir.join("sample_file.map")) else {
+        return HashMap::new();
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .filter_map(|line| {
+            let (index, files) = line.split_once('\t')?;
+            let names: Vec<String> = files
+                .split(',')
+                .map(|file| {
+                    Path::new(file.trim()).file_name().map_or_else(
+                        || file.to_string(),
+                        |name| name.to_string_lossy().to_string(),
+                    )
+                })
+                .collect();
+            Some((index.trim().parse().ok()?, names.join(",")))
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 fn process_batch<P: AsRef<Path>>(
     sample_file: P,
     args: &Args,
     taxonomy: &Taxonomy,
     id_map: &DashMap<u32, (String, String, usize, Option<usize>)>,
     writer: &Mutex<Box<dyn Write + Send>>,
-    value_mask: usize,
+    hits_writer: Option<&Mutex<BufWriter<File>>>,
+    histogram: Option<&ConfidenceHistogram>,
+    top_db: Option<&HashMap<u32, usize>>,
+    hash_config: &HashConfig,
 ) -> Result<(TaxonCountersDash, usize, DashSet<u32>)> {
+    let value_mask = hash_config.value_mask;
     let file = open_file(sample_file)?;
     let mut reader = BufReader::new(file);
     let size = std::mem::size_of::<Row>();
@@ -119,6 +211,7 @@ fn process_batch<P: AsRef<Path>>(
     let hit_seq_id_set = DashSet::new();
     let confidence_threshold = args.confidence_threshold;
     let minimum_hit_groups = args.minimum_hit_groups;
+    let kmer_counting = args.kmer_counting();
 
     while let Ok(bytes_read) = reader.read(&mut batch_buffer) {
         if bytes_read == 0 {
@@ -158,7 +251,12 @@ fn process_batch<P: AsRef<Path>>(
                 &classify_counter,
                 hits.required_score(confidence_threshold),
                 minimum_hit_groups,
+                args.min_hit_fraction,
                 value_mask,
+                args.ambiguous_policy,
+                // 每条序列独立派生种子, 并行处理时结果也可复现
+                args.seed.map(|seed| seed.wrapping_add(k as u64)),
+                kmer_counting,
             );
             // let (counts, cur_counts, hit_groups) = count_values(&rows, value_mask, item.2);
             // let hit_string = add_hitlist_string(&rows, value_mask, item.2, item.3, taxonomy);
@@ -189,12 +287,49 @@ fn process_batch<P: AsRef<Path>>(
             //     "U"
             // };
             // 使用锁来同步写入
-            let output_line = format!(
-                "{}\t{}\t{}\t{}\t{}\n",
-                hit_data.0, dna_id, hit_data.1, item.1, hit_data.2
-            );
+            if let Some(histogram) = histogram {
+                // 报告多个结果时取其中最高的置信度, 每条 read 只计一次
+                histogram.add(hit_data.4.iter().copied().fold(0.0, f64::max));
+            }
+            let output_line: String = hit_data
+                .1
+                .iter()
+                .zip(&hit_data.4)
+                .map(|(ext_call, confidence)| {
+                    let mut line = format!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        hit_data.0, dna_id, ext_call, item.1, hit_data.2
+                    );
+                    if args.include_confidence {
+                        line.push_str(&format!("\t{:.4}", confidence));
+                    }
+                    if let Some(top_db) = top_db {
+                        // 只用一个数据库分类时没有 top_db 文件, 有命中的 read 都来自 --db
+                        line.push_str(&format!("\t{}", top_db.get(&k).copied().unwrap_or(1)));
+                    }
+                    line.push('\n');
+                    line
+                })
+                .collect();
             let mut file = writer.lock().unwrap();
             file.write_all(output_line.as_bytes()).unwrap();
+
+            if let Some(hits_writer) = hits_writer {
+                let records = collect_kmer_hits(
+                    &hits,
+                    &dna_id,
+                    taxonomy,
+                    hash_config.value_bits,
+                    value_mask,
+                    args.max_hits_per_read,
+                );
+                let lines: String = records
+                    .iter()
+                    .map(|record| format!("{}\n", record))
+                    .collect();
+                let mut file = hits_writer.lock().unwrap();
+                file.write_all(lines.as_bytes()).unwrap();
+            }
         }
     });
     Ok((
@@ -204,7 +339,33 @@ fn process_batch<P: AsRef<Path>>(
     ))
 }
 
-pub fn run(args: Args) -> Result<()> {
+/// 返回每个样本的 read 统计, 顺序与 chunk 目录中的样本一致
+impl Args {
+    /// 统计每个 taxon 的 distinct k-mer 的方式, 没有指定 --count-unique-kmers 或 --approx-unique-kmers 时为 -K 的默认方式
+    fn kmer_counting(&self) -> KmerCounting {
+        if self.count_unique_kmers {
+            KmerCounting::Exact
+        } else if self.approx_unique_kmers {
+            KmerCounting::Approx(
+                self.unique_kmers_error_rate
+                    .map_or(DEFAULT_HLL_PRECISION, hll_precision),
+            )
+        } else {
+            KmerCounting::default()
+        }
+    }
+}
+
+pub fn run(mut args: Args) -> Result<Vec<SampleStats>> {
+    check_parallel_samples(args.parallel_samples, args.kraken_output_dir.as_deref())?;
+    if let (Some(error_rate), KmerCounting::Approx(precision)) =
+        (args.unique_kmers_error_rate, args.kmer_counting())
+    {
+        warn_if_hll_clamped(error_rate, precision);
+    }
+    // 统计 distinct k-mer 时报告中总是输出 k-mer 列
+    args.report_kmer_data |= args.count_unique_kmers || args.approx_unique_kmers;
+
     let k2d_dir = &args.database;
     let taxonomy_filename = k2d_dir.join("taxo.k2d");
     let taxo = Taxonomy::from_file(taxonomy_filename)?;
@@ -214,7 +375,23 @@ pub fn run(args: Args) -> Result<()> {
 
     let partition = sample_files.len();
     let hash_config = HashConfig::from_hash_header(&args.database.join("hash_config.k2d"))?;
-    let value_mask = hash_config.value_mask;
+
+    let hits_writer = match &args.kmer_hits_file {
+        Some(path) => {
+            let mut file = BufWriter::new(File::create(path)?);
+            writeln!(file, "read_id\tminimizer\ttaxid")?;
+            Some(Mutex::new(file))
+        }
+        None => None,
+    };
+
+    let histogram = args
+        .confidence_histogram
+        .as_ref()
+        .map(|_| ConfidenceHistogram::new(args.hist_bin_width));
+
+    let sample_names = read_sample_names(&args.chunk_dir);
+    let mut biom_samples = Vec::new();
 
     let mut total_taxon_counts = TaxonCounters::new();
     let mut total_seqs = 0;
@@ -224,7 +401,7 @@ pub fn run(args: Args) -> Result<()> {
     let start = Instant::now();
     println!("resolve start...");
 
-    for i in 0..partition {
+    let resolve_sample = |i: usize| -> Result<(HashMap<u64, ReadCounter>, SampleStats)> {
         let sample_file = &sample_files[i];
         let sample_id_map = read_id_to_seq_map(&sample_id_files[i])?;
         let thread_sequences = sample_id_map.len();
@@ -237,43 +414,62 @@ pub fn run(args: Args) -> Result<()> {
             None => Box::new(io::stdout()) as Box<dyn Write + Send>,
         };
         let writer = Mutex::new(writer);
+        let top_db = if args.top_db_column {
+            let path = args.chunk_dir.join(format!("top_db_{}.map", i + 1));
+            Some(if path.exists() {
+                read_top_db_map(path)?
+            } else {
+                HashMap::new()
+            })
+        } else {
+            None
+        };
         let (thread_taxon_counts, thread_classified, hit_seq_set) = process_batch::<&PathBuf>(
             sample_file,
             &args,
             &taxo,
             &sample_id_map,
             &writer,
-            value_mask,
+            hits_writer.as_ref(),
+            histogram.as_ref(),
+            top_db.as_ref(),
+            &hash_config,
         )?;
 
-        if args.full_output {
+        if args.full_output || histogram.is_some() {
             sample_id_map
                 .iter()
                 .filter(|item| !hit_seq_set.contains(item.key()))
                 .for_each(|item| {
+                    // 没有 minimizer 命中的 read 置信度为 0
+                    if let Some(histogram) = &histogram {
+                        histogram.add(0.0);
+                    }
+                    if !args.full_output {
+                        return;
+                    }
                     let dna_id = trim_pair_info(&item.0);
                     let output_line = format!(
-                        "U\t{}\t0\t{}\t{}\n",
+                        "U\t{}\t0\t{}\t{}{}{}\n",
                         dna_id,
                         item.1,
-                        if item.3.is_none() { "" } else { " |:| " }
+                        if item.3.is_none() { "" } else { " |:| " },
+                        if args.include_confidence {
+                            "\t0.0000"
+                        } else {
+                            ""
+                        },
+                        if args.top_db_column { "\t0" } else { "" }
                     );
 
                     let mut file = writer.lock().unwrap();
                     file.write_all(output_line.as_bytes()).unwrap();
                 });
         }
+        writer.into_inner().unwrap().flush()?;
 
-        let mut sample_taxon_counts: HashMap<
-            u64,
-            kr2r::readcounts::ReadCounts<hyperloglogplus::HyperLogLogPlus<u64, kr2r::KBuildHasher>>,
-        > = HashMap::new();
+        let mut sample_taxon_counts: HashMap<u64, ReadCounter> = HashMap::new();
         thread_taxon_counts.iter().for_each(|entry| {
-            total_taxon_counts
-                .entry(*entry.key())
-                .or_default()
-                .merge(&entry.value())
-                .unwrap();
             sample_taxon_counts
                 .entry(*entry.key())
                 .or_default()
@@ -293,8 +489,64 @@ pub fn run(args: Args) -> Result<()> {
             )?;
         }
 
-        total_seqs += thread_sequences;
-        total_unclassified += thread_sequences - thread_classified;
+        let stats = SampleStats {
+            sequences: thread_sequences,
+            classified: thread_classified,
+            ..Default::default()
+        };
+        Ok((sample_taxon_counts, stats))
+    };
+
+    // 每次同时处理 parallel_samples 个样本, 每个样本内部仍然按 read 并行
+    let sample_indices: Vec<usize> = (0..partition).collect();
+    let mut all_stats = Vec::with_capacity(partition);
+    for indices in sample_indices.chunks(args.parallel_samples.max(1)) {
+        let results = indices
+            .par_iter()
+            .map(|&i| resolve_sample(i))
+            .collect::<Result<Vec<_>>>()?;
+        for (i, (sample_taxon_counts, stats)) in indices.iter().zip(results) {
+            sample_taxon_counts.iter().for_each(|(taxid, counter)| {
+                total_taxon_counts
+                    .entry(*taxid)
+                    .or_default()
+                    .merge(counter)
+                    .unwrap();
+            });
+
+            if args.biom_output.is_some() {
+                biom_samples.push(BiomSample {
+                    id: sample_names
+                        .get(&(i + 1))
+                        .cloned()
+                        .unwrap_or_else(|| format!("sample_{}", i + 1)),
+                    counts: sample_taxon_counts
+                        .iter()
+                        .map(|(&taxid, counter)| (taxid, counter.read_count()))
+                        .collect(),
+                });
+            }
+
+            total_seqs += stats.sequences;
+            total_unclassified += stats.unclassified();
+            all_stats.push(stats);
+        }
+    }
+
+    if let Some(path) = &args.biom_output {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_biom(&mut writer, &taxo, &biom_samples)?;
+        writer.flush()?;
+    }
+
+    if let Some(hits_writer) = hits_writer {
+        hits_writer.into_inner().unwrap().flush()?;
+    }
+
+    if let (Some(path), Some(histogram)) = (&args.confidence_histogram, &histogram) {
+        let mut writer = BufWriter::new(File::create(path)?);
+        histogram.write_to(&mut writer)?;
+        writer.flush()?;
     }
 
     if let Some(output) = &args.kraken_output_dir {
@@ -315,7 +567,7 @@ pub fn run(args: Args) -> Result<()> {
     // 打印运行时间
     println!("resolve took: {:?}", duration);
 
-    Ok(())
+    Ok(all_stats)
 }
 
 #[allow(dead_code)]
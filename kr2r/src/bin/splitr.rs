@@ -1,11 +1,17 @@
 use clap::Parser;
+use kr2r::args::resolve_pairing;
+use kr2r::bloom::{bloom_key, BloomFilter, BLOOM_FILE};
 use kr2r::compact_hash::{HashConfig, Slot};
 use kr2r::utils::{
     create_partition_files, create_partition_writers, create_sample_file, get_file_limit,
     get_lastest_file_index,
 };
 use kr2r::IndexOptions;
-use seqkmer::{read_parallel, FastxReader, Meros, MinimizerIterator, OptionPair, Reader};
+use seqkmer::{
+    read_parallel, Compression, DustReader, FastxReader, LengthFilter, Meros, MinimizerIterator,
+    OptionPair, QualityEncoding, QualityTrimmer, ReadOptions, Reader, TrimStrategy,
+    DEFAULT_DUST_THRESHOLD,
+};
 use std::fs;
 use std::io::{BufWriter, Write};
 use std::io::{Error, ErrorKind, Result};
@@ -33,10 +39,15 @@ pub struct Args {
     #[clap(short = 'P', long = "paired-end-processing", action)]
     pub paired_end_processing: bool,
 
-    /// Process pairs with mates in the same file.
+    /// Process pairs with mates in the same file (interleaved FASTQ, R1 and R2 alternating).
+    /// Requires -P.
     #[clap(short = 'S', long = "single-file-pairs", action)]
     pub single_file_pairs: bool,
 
+    /// Input is interleaved paired-end FASTQ, same as -P -S.
+    #[clap(long, action)]
+    pub interleaved: bool,
+
     /// Minimum quality score for FASTQ data.
     #[clap(
         short = 'Q',
@@ -46,6 +57,52 @@ pub struct Args {
     )]
     pub minimum_quality_score: i32,
 
+    /// Input compression: auto, gzip, zstd or none.
+    /// `auto` detects gzip and zstd from the file content.
+    #[clap(long, default_value_t = Compression::Auto)]
+    pub compression: Compression,
+
+    /// Skip k-mer windows containing N or any other non-ACGT base; `false` hashes them as A.
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub mask_n_bases: bool,
+
+    /// Mask low-complexity regions (homopolymers, short tandem repeats) with DUST before extracting minimizers.
+    #[clap(long, action)]
+    pub low_complexity_mask: bool,
+
+    /// DUST score threshold used by --low-complexity-mask, higher values mask less.
+    #[clap(long, default_value_t = DEFAULT_DUST_THRESHOLD)]
+    pub dust_threshold: f64,
+
+    /// Skip reads shorter than this many bases (each mate is checked for paired reads).
+    #[clap(long, default_value_t = 0)]
+    pub min_length: usize,
+
+    /// Skip reads longer than this many bases (each mate is checked for paired reads).
+    #[clap(long)]
+    pub max_length: Option<usize>,
+
+    /// Trim the 3' end of FASTQ reads from the first window whose mean Phred quality is below this value.
+    /// Trimming happens before --min-length filtering, each mate is trimmed independently.
+    #[clap(long)]
+    pub trim_quality: Option<u8>,
+
+    /// Window size in bases used by --trim-quality.
+    #[clap(long, default_value_t = 4)]
+    pub trim_window: usize,
+
+    /// Quality encoding of FASTQ input: phred33 or phred64.
+    #[clap(long, default_value_t = QualityEncoding::Phred33)]
+    pub quality_encoding: QualityEncoding,
+
+    /// Warn about and skip FASTQ records whose sequence and quality lengths differ instead of aborting.
+    #[clap(long, action)]
+    pub lenient_fastq: bool,
+
+    /// Skip hash table lookups for minimizers rejected by the database Bloom filter ($db/database.k2bloom).
+    #[clap(long, action)]
+    pub use_bloom_filter: bool,
+
     /// The number of threads to use.
     #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
     pub num_threads: usize,
@@ -93,7 +150,7 @@ fn init_chunk_writers(
     writers
 }
 
-/// 处理record
+/// 处理record, bloom 不为空时跳过一定不在数据库中的 minimizer
 fn process_record(
     k2_slot_list: &mut Vec<(usize, Slot<u64>)>,
     marker: &mut MinimizerIterator,
@@ -101,9 +158,16 @@ fn process_record(
     chunk_size: usize,
     seq_id: u64,
     idx_bits: usize,
+    bloom: Option<&BloomFilter>,
 ) {
     let offset = k2_slot_list.len();
     for (sort, hash_key) in marker {
+        if let Some(bloom) = bloom {
+            let (index, compacted) = hash_config.compact(hash_key);
+            if !bloom.contains(bloom_key(index, compacted)) {
+                continue;
+            }
+        }
         let mut slot = hash_config.slot_u64(hash_key, seq_id);
         let seq_sort = sort + offset;
         let partition_index = slot.idx / chunk_size;
@@ -130,10 +194,12 @@ fn write_data_to_file(
     sample_writer.write_all(k2_map.as_bytes()).unwrap();
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_fastx_file<R>(
     args: &Args,
     meros: Meros,
     hash_config: HashConfig,
+    bloom: Option<&BloomFilter>,
     file_index: usize,
     reader: &mut R,
     writers: &mut Vec<BufWriter<fs::File>>,
@@ -168,6 +234,7 @@ where
                         chunk_size,
                         seq_id,
                         idx_bits,
+                        bloom,
                     );
                 });
                 k2_slot_list.extend_from_slice(&init);
@@ -186,12 +253,14 @@ where
             }
         },
     )
-    .expect("failed");
-
-    Ok(())
 }
 
-fn convert(args: Args, meros: Meros, hash_config: HashConfig) -> Result<()> {
+fn convert(
+    args: Args,
+    meros: Meros,
+    hash_config: HashConfig,
+    bloom: Option<&BloomFilter>,
+) -> Result<Vec<usize>> {
     let partition = hash_config.partition;
     let mut writers: Vec<BufWriter<fs::File>> =
         init_chunk_writers(&args, partition, hash_config.hash_capacity);
@@ -200,6 +269,7 @@ fn convert(args: Args, meros: Meros, hash_config: HashConfig) -> Result<()> {
     let mut file_writer = create_sample_file(&file_path);
     // 如果文件内容为空，则默认最大值为0
     let mut file_index = get_lastest_file_index(&file_path)?;
+    let mut filtered = Vec::new();
 
     let mut process_files = |files: Vec<&[String]>| -> Result<()> {
         let file_bits = (((files.len() + file_index) as f64).log2().ceil() as usize).max(1);
@@ -222,17 +292,40 @@ fn convert(args: Args, meros: Meros, hash_config: HashConfig) -> Result<()> {
 
             let score = args.minimum_quality_score;
             let paths = OptionPair::from_slice(file_pair);
-            let mut reader = FastxReader::from_paths(paths, file_index, score)?;
+            let options = ReadOptions {
+                compression: args.compression,
+                interleaved: args.single_file_pairs,
+                quality_encoding: args.quality_encoding,
+                trimmer: args.trim_quality.map(|quality| {
+                    QualityTrimmer::new(TrimStrategy::SlidingWindow(args.trim_window), quality, 0)
+                }),
+                lenient: args.lenient_fastq,
+            };
+            let mut reader: Box<dyn Reader + Send> = Box::new(FastxReader::from_paths_with(
+                paths, file_index, score, &options,
+            )?);
+            if args.low_complexity_mask {
+                reader = Box::new(DustReader::new(reader, args.dust_threshold));
+            }
+            let mut reader = LengthFilter::new(reader, args.min_length, args.max_length);
             process_fastx_file(
                 &args,
                 meros,
                 hash_config,
+                bloom,
                 file_index,
                 &mut reader,
                 &mut writers,
                 &mut sample_writer,
-            )
-            .expect("process fastx file error");
+            )?;
+            if reader.filtered() > 0 {
+                eprintln!(
+                    "{}: {} reads skipped by length filter",
+                    file_pair.join(","),
+                    reader.filtered()
+                );
+            }
+            filtered.push(reader.filtered());
         }
         Ok(())
     };
@@ -246,11 +339,17 @@ fn convert(args: Args, meros: Meros, hash_config: HashConfig) -> Result<()> {
         process_files(files)?;
     }
 
-    Ok(())
+    Ok(filtered)
 }
 
-pub fn run(args: Args) -> Result<()> {
+/// 返回每个样本被长度过滤跳过的 read 数
+pub fn run(mut args: Args) -> Result<Vec<usize>> {
     // let args = Args::parse();
+    resolve_pairing(
+        &mut args.paired_end_processing,
+        &mut args.single_file_pairs,
+        args.interleaved,
+    )?;
     let options_filename = &args.database.join("opts.k2d");
     let idx_opts = IndexOptions::read_index_options(options_filename)?;
 
@@ -273,13 +372,32 @@ pub fn run(args: Args) -> Result<()> {
         panic!("Exceeds File Number Limit");
     }
 
-    let meros = idx_opts.as_meros();
+    let bloom = if args.use_bloom_filter {
+        let bloom_file = args.database.join(BLOOM_FILE);
+        if !bloom_file.exists() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "{:?} not found, build the database with --bloom-filter",
+                    bloom_file
+                ),
+            ));
+        }
+        Some(BloomFilter::from_file(&bloom_file)?)
+    } else {
+        None
+    };
+
+    let meros = Meros {
+        mask_n_bases: args.mask_n_bases,
+        ..idx_opts.as_meros()
+    };
     let start = Instant::now();
-    convert(args, meros, hash_config)?;
+    let filtered = convert(args, meros, hash_config, bloom.as_ref())?;
     let duration = start.elapsed();
     println!("splitr took: {:?}", duration);
 
-    Ok(())
+    Ok(filtered)
 }
 
 #[allow(dead_code)]
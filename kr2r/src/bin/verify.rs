@@ -0,0 +1,103 @@
+use clap::Parser;
+use kr2r::taxonomy::Taxonomy;
+use kr2r::verify::{verify_checksums, verify_hash_tables, HashTableReport, CHECKSUM_FILE};
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::time::Instant;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "verify",
+    long_about = "Check a database for truncated or corrupted files"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files
+    #[arg(long = "db", required = true)]
+    pub k2d_dir: PathBuf,
+
+    /// Skip the SHA-256 check against database.sha256 even if it exists
+    #[clap(long, action)]
+    pub skip_checksum: bool,
+}
+
+/// 打印一项检查的结果, 返回问题数
+fn print_issues(name: &str, issues: &[String]) -> usize {
+    for issue in issues {
+        println!("  [{}] {}", name, issue);
+    }
+    issues.len()
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let start = Instant::now();
+    let mut summary: Vec<(&str, usize)> = Vec::new();
+
+    let taxonomy_file = args.k2d_dir.join("taxo.k2d");
+    let node_count = match Taxonomy::from_file(&taxonomy_file) {
+        Ok(taxonomy) => {
+            let issues = taxonomy.validate().unwrap_or_else(|e| vec![e.to_string()]);
+            summary.push(("taxonomy", print_issues("taxonomy", &issues)));
+            Some(taxonomy.node_count())
+        }
+        Err(e) => {
+            let issues = [format!("{:?}: {}", taxonomy_file, e)];
+            summary.push(("taxonomy", print_issues("taxonomy", &issues)));
+            None
+        }
+    };
+
+    // 分类树无法读取时无法判断 taxid 是否存在, 只检查文件大小
+    let report = verify_hash_tables(&args.k2d_dir, node_count.unwrap_or(usize::MAX))
+        .unwrap_or_else(|e| HashTableReport {
+            issues: vec![format!("{:?}: {}", args.k2d_dir.join("hash_config.k2d"), e)],
+            ..Default::default()
+        });
+    summary.push(("hash table", print_issues("hash table", &report.issues)));
+    let missing: Vec<String> = report
+        .missing_taxids
+        .iter()
+        .map(|taxid| format!("internal taxid {} is not in the taxonomy", taxid))
+        .collect();
+    summary.push(("missing taxids", print_issues("missing taxid", &missing)));
+
+    let checksum_file = args.k2d_dir.join(CHECKSUM_FILE);
+    if args.skip_checksum {
+        println!("checksum: skipped");
+    } else if checksum_file.exists() {
+        let issues = verify_checksums(&args.k2d_dir, &checksum_file)?;
+        summary.push(("checksum", print_issues("checksum", &issues)));
+    } else {
+        println!("checksum: {:?} not found, skipped", checksum_file);
+    }
+
+    let total: usize = summary.iter().map(|(_, count)| count).sum();
+    let counts: Vec<String> = summary
+        .iter()
+        .map(|(name, count)| format!("{}: {}", name, count))
+        .collect();
+    println!(
+        "{}: {} issues ({}), {} occupied hash cells",
+        if total == 0 { "PASS" } else { "FAIL" },
+        total,
+        counts.join(", "),
+        report.occupied_cells
+    );
+    println!("verify took: {:?}", start.elapsed());
+
+    if total > 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("database {:?} failed verification", args.k2d_dir),
+        ));
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
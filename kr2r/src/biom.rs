@@ -0,0 +1,111 @@
+use crate::taxonomy::Taxonomy;
+use crate::utils::utc_now;
+use crate::Kr2Result;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+
+pub const BIOM_FORMAT: &str = "Biological Observation Matrix 1.0.0";
+pub const BIOM_FORMAT_URL: &str =
+    "http://biom-format.org/documentation/format_versions/biom-1.0.html";
+
+/// lineage 中保留的 rank 及其前缀, 与 mpa 风格报告一致
+const LINEAGE_RANKS: [(&str, &str); 8] = [
+    ("superkingdom", "d__"),
+    ("kingdom", "k__"),
+    ("phylum", "p__"),
+    ("class", "c__"),
+    ("order", "o__"),
+    ("family", "f__"),
+    ("genus", "g__"),
+    ("species", "s__"),
+];
+
+/// 一个样本中每个 taxon 直接分到的 read 数, 键为内部 taxid
+#[derive(Debug, Clone, Default)]
+pub struct BiomSample {
+    pub id: String,
+    pub counts: HashMap<u64, u64>,
+}
+
+/// 从根到该节点的标准 rank 学名, 如 `["d__Viruses", ..., "s__Human immunodeficiency virus 1"]`
+fn lineage(taxonomy: &Taxonomy, internal_id: u32) -> Vec<String> {
+    let mut names: Vec<String> = taxonomy
+        .ancestors(internal_id)
+        .filter_map(|id| {
+            let rank = taxonomy.get_rank(id)?;
+            let (_, prefix) = LINEAGE_RANKS.iter().find(|(r, _)| *r == rank)?;
+            Some(format!("{}{}", prefix, taxonomy.get_name(id)?))
+        })
+        .collect();
+    names.reverse();
+    names
+}
+
+/// 生成 BIOM v1 (JSON) 格式的 OTU 表
+/// 行 (rows) 为 taxon, id 为外部 taxid, metadata.taxonomy 为 lineage; 列 (columns) 为样本, 顺序与 samples 一致
+/// matrix 为稀疏格式, data 中每一项为 `[行号, 列号, read 数]`, shape 为 `[行数, 列数]`
+/// 计数为每个 taxon 直接分到的 read 数, 不累加子树, 未分类的 read 不计入
+pub fn biom_table(taxonomy: &Taxonomy, samples: &[BiomSample]) -> Value {
+    let taxids: Vec<u64> = samples
+        .iter()
+        .flat_map(|sample| {
+            sample
+                .counts
+                .iter()
+                .filter(|(&taxid, &count)| taxid != 0 && count > 0)
+                .map(|(&taxid, _)| taxid)
+        })
+        .collect::<BTreeSet<u64>>()
+        .into_iter()
+        .collect();
+
+    let rows: Vec<Value> = taxids
+        .iter()
+        .map(|&taxid| {
+            json!({
+                "id": taxonomy.nodes[taxid as usize].external_id.to_string(),
+                "metadata": { "taxonomy": lineage(taxonomy, taxid as u32) },
+            })
+        })
+        .collect();
+    let columns: Vec<Value> = samples
+        .iter()
+        .map(|sample| json!({ "id": sample.id, "metadata": null }))
+        .collect();
+
+    let mut data = Vec::new();
+    for (row, taxid) in taxids.iter().enumerate() {
+        for (column, sample) in samples.iter().enumerate() {
+            match sample.counts.get(taxid) {
+                Some(&count) if count > 0 => data.push(json!([row, column, count])),
+                _ => {}
+            }
+        }
+    }
+
+    json!({
+        "id": null,
+        "format": BIOM_FORMAT,
+        "format_url": BIOM_FORMAT_URL,
+        "type": "OTU table",
+        "generated_by": format!("kun_peng {}", env!("CARGO_PKG_VERSION")),
+        "date": utc_now(),
+        "rows": rows,
+        "columns": columns,
+        "matrix_type": "sparse",
+        "matrix_element_type": "int",
+        "shape": [taxids.len(), samples.len()],
+        "data": data,
+    })
+}
+
+/// 把 BIOM v1 表写为 JSON
+pub fn write_biom<W: Write>(
+    writer: W,
+    taxonomy: &Taxonomy,
+    samples: &[BiomSample],
+) -> Kr2Result<()> {
+    serde_json::to_writer(writer, &biom_table(taxonomy, samples))?;
+    Ok(())
+}
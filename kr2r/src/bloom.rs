@@ -0,0 +1,173 @@
+use crate::fmix64;
+use crate::utils::open_file;
+use crate::Kr2Result;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 数据库目录中的 Bloom filter 文件
+pub const BLOOM_FILE: &str = "database.k2bloom";
+
+const BLOOM_MAGIC: &[u8; 8] = b"K2BLOOM1";
+/// 每个块 8 个 u64, 即一条 64 字节的 cache line
+const BLOCK_WORDS: usize = 8;
+const BLOCK_BITS: u64 = (BLOCK_WORDS * 64) as u64;
+
+/// 哈希表格子的 Bloom filter 键: minimizer 在整个哈希表中的位置和压缩后的 key
+/// 与 `HashConfig::compact` 的结果一一对应, 位置超过 32 位时高位会被丢弃, 只会增加假阳性
+pub fn bloom_key(index: usize, compacted: u32) -> u64 {
+    (index as u64) << 32 | compacted as u64
+}
+
+/// 分块 Bloom filter, 一个键的所有位都在同一个 64 字节的块中, 每次查询只访问一条 cache line
+/// 可以在多个线程中同时插入
+pub struct BloomFilter {
+    words: Vec<AtomicU64>,
+    num_blocks: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// 按预计的键数和假阳性率确定大小: m = -n ln(p) / (ln 2)^2, k = m / n * ln 2
+    pub fn with_capacity(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(1.0);
+        let num_blocks = (num_bits / BLOCK_BITS as f64).ceil() as u64;
+        let num_hashes = ((num_bits / n) * ln2).round().clamp(1.0, 16.0) as u32;
+        Self::new(num_blocks, num_hashes)
+    }
+
+    fn new(num_blocks: u64, num_hashes: u32) -> Self {
+        let words = (0..num_blocks as usize * BLOCK_WORDS)
+            .map(|_| AtomicU64::new(0))
+            .collect();
+        Self {
+            words,
+            num_blocks,
+            num_hashes,
+        }
+    }
+
+    pub fn num_bits(&self) -> u64 {
+        self.num_blocks * BLOCK_BITS
+    }
+
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    /// 键所在块的第一个 word 和块内各位置的双重哈希参数
+    fn probe(&self, key: u64) -> (usize, u64, u64) {
+        let h = fmix64(key);
+        let block = (h % self.num_blocks) as usize * BLOCK_WORDS;
+        let h2 = fmix64(h ^ 0x9e37_79b9_7f4a_7c15);
+        (block, h2 & 0xffff_ffff, (h2 >> 32) | 1)
+    }
+
+    pub fn insert(&self, key: u64) {
+        let (block, h1, h2) = self.probe(key);
+        for i in 0..self.num_hashes as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % BLOCK_BITS;
+            self.words[block + (bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// 返回 false 时键一定不在集合中
+    pub fn contains(&self, key: u64) -> bool {
+        let (block, h1, h2) = self.probe(key);
+        (0..self.num_hashes as u64).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % BLOCK_BITS;
+            self.words[block + (bit / 64) as usize].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0
+        })
+    }
+
+    /// 文件格式: 8 字节 magic `K2BLOOM1`, 块数 (u64), 哈希函数个数 (u64), 之后是所有 word, 均为小端序
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Kr2Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(BLOOM_MAGIC)?;
+        writer.write_u64::<LittleEndian>(self.num_blocks)?;
+        writer.write_u64::<LittleEndian>(self.num_hashes as u64)?;
+        for word in &self.words {
+            writer.write_u64::<LittleEndian>(word.load(Ordering::Relaxed))?;
+        }
+        Ok(writer.flush()?)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Kr2Result<Self> {
+        let file = open_file(&path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != BLOOM_MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?} is not a Bloom filter file", path.as_ref()),
+            )
+            .into());
+        }
+        let num_blocks = reader.read_u64::<LittleEndian>()?;
+        let num_hashes = reader.read_u64::<LittleEndian>()? as u32;
+        if num_blocks == 0 || num_hashes == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?}: invalid Bloom filter header", path.as_ref()),
+            )
+            .into());
+        }
+        // 先按文件大小检查块数, 避免损坏的文件头导致分配过大的内存
+        let data_len = num_blocks.checked_mul(BLOCK_BITS / 8);
+        if data_len.is_none_or(|len| len > file_len - 24) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{:?}: Bloom filter header claims {} blocks but the file has {} bytes",
+                    path.as_ref(),
+                    num_blocks,
+                    file_len
+                ),
+            )
+            .into());
+        }
+        let filter = Self::new(num_blocks, num_hashes);
+        for word in &filter.words {
+            word.store(reader.read_u64::<LittleEndian>()?, Ordering::Relaxed);
+        }
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn file_round_trip() {
+        let path = test_dir("bloom_round_trip").join(BLOOM_FILE);
+        let filter = BloomFilter::with_capacity(1000, 0.01);
+        (0..1000).for_each(|key| filter.insert(key));
+        filter.write_to_file(&path).unwrap();
+        let loaded = BloomFilter::from_file(&path).unwrap();
+        assert_eq!(loaded.num_bits(), filter.num_bits());
+        assert_eq!(loaded.num_hashes(), filter.num_hashes());
+        assert!((0..1000).all(|key| loaded.contains(key)));
+    }
+
+    #[test]
+    fn from_file_rejects_block_count_larger_than_file() {
+        let path = test_dir("bloom_huge_blocks").join(BLOOM_FILE);
+        let mut data = BLOOM_MAGIC.to_vec();
+        data.extend_from_slice(&(u64::MAX / 2).to_le_bytes());
+        data.extend_from_slice(&3u64.to_le_bytes());
+        data.extend_from_slice(&[0; 64]);
+        std::fs::write(&path, data).unwrap();
+        let err = BloomFilter::from_file(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
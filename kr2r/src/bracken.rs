@@ -0,0 +1,284 @@
+use crate::report::get_clade_counts;
+use crate::taxonomy::Taxonomy;
+use crate::utils::open_file;
+use crate::Kr2Result;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::path::Path;
+
+/// Bracken 的分类层级代码与对应的 rank
+pub const BRACKEN_LEVELS: [(&str, &str); 7] = [
+    ("D", "superkingdom"),
+    ("P", "phylum"),
+    ("C", "class"),
+    ("O", "order"),
+    ("F", "family"),
+    ("G", "genus"),
+    ("S", "species"),
+];
+
+/// 把 Bracken 的层级代码 (如 `S`) 转换为对应的 rank
+pub fn parse_level(s: &str) -> std::result::Result<String, String> {
+    BRACKEN_LEVELS
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(s))
+        .map(|(_, rank)| rank.to_string())
+        .ok_or_else(|| format!("level must be one of D, P, C, O, F, G, S (got {})", s))
+}
+
+fn level_code(rank: &str) -> &str {
+    BRACKEN_LEVELS
+        .iter()
+        .find(|(_, level_rank)| *level_rank == rank)
+        .map_or(rank, |(code, _)| code)
+}
+
+/// Bracken 的 kmer_distrib 数据: 从每个基因组模拟的 read 被分类到哪些 taxon
+/// taxid 均为外部 taxid
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KmerDistribution {
+    /// 分类结果 taxid -> (基因组 taxid -> 分类到该 taxid 的 read 数)
+    pub mapped: BTreeMap<u64, BTreeMap<u64, u64>>,
+    /// 基因组 taxid -> 模拟的 read 总数
+    pub totals: BTreeMap<u64, u64>,
+}
+
+impl KmerDistribution {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一个基因组的模拟结果, calls 为分类结果 taxid 到 read 数的映射, 同一 taxid 的多条序列会累加
+    pub fn add_genome(&mut self, genome_taxid: u64, calls: &HashMap<u64, u64>, total: u64) {
+        *self.totals.entry(genome_taxid).or_insert(0) += total;
+        for (&mapped, &count) in calls {
+            *self
+                .mapped
+                .entry(mapped)
+                .or_default()
+                .entry(genome_taxid)
+                .or_insert(0) += count;
+        }
+    }
+
+    /// 以 Bracken 的格式写出: `mapped_taxid\tgenome_taxid:reads_mapped:total_reads ...`
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Kr2Result<()> {
+        writeln!(
+            writer,
+            "mapped_taxid\tgenome_taxids:kmers_mapped:total_genome_kmers"
+        )?;
+        for (mapped, genomes) in &self.mapped {
+            let entries: Vec<String> = genomes
+                .iter()
+                .map(|(genome, count)| {
+                    format!(
+                        "{}:{}:{}",
+                        genome,
+                        count,
+                        self.totals.get(genome).unwrap_or(&0)
+                    )
+                })
+                .collect();
+            writeln!(writer, "{}\t{}", mapped, entries.join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// 读取 Bracken 格式的 kmer_distrib 文件
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Kr2Result<Self> {
+        let path = path.as_ref();
+        let reader = BufReader::new(open_file(path)?);
+        let invalid = |line: &str| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?}: invalid kmer distribution line `{}`", path, line),
+            )
+        };
+
+        let mut distrib = Self::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with("mapped_taxid") {
+                continue;
+            }
+            let (mapped, entries) = line.split_once('\t').ok_or_else(|| invalid(&line))?;
+            let mapped: u64 = mapped.trim().parse().map_err(|_| invalid(&line))?;
+            for entry in entries.split_whitespace() {
+                let fields: Vec<u64> = entry
+                    .split(':')
+                    .map(|field| field.parse())
+                    .collect::<std::result::Result<_, _>>()
+                    .map_err(|_| invalid(&line))?;
+                let [genome, count, total] = fields[..] else {
+                    return Err(invalid(&line).into());
+                };
+                distrib
+                    .mapped
+                    .entry(mapped)
+                    .or_default()
+                    .insert(genome, count);
+                distrib.totals.insert(genome, total);
+            }
+        }
+        Ok(distrib)
+    }
+}
+
+/// 读取 Kraken 2 风格报告中每个 taxon 直接分到的 read 数, 键为内部 taxid
+/// 兼容带 -K 的 minimizer 列, 不在分类树中的 taxid 被忽略
+pub fn read_kraken_report<P: AsRef<Path>>(
+    path: P,
+    taxonomy: &Taxonomy,
+) -> Kr2Result<HashMap<u64, u64>> {
+    let path = path.as_ref();
+    let reader = BufReader::new(open_file(path)?);
+    let mut direct_reads = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 6 {
+            continue;
+        }
+        let parse = |field: &str| {
+            field.trim().parse::<u64>().map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{:?}: invalid report line `{}`", path, line),
+                )
+            })
+        };
+        let reads = parse(fields[2])?;
+        let taxid = parse(fields[fields.len() - 2])?;
+        if let Some(internal_id) = taxonomy.get_internal_id_opt(taxid) {
+            if internal_id != 0 && reads > 0 {
+                direct_reads.insert(internal_id as u64, reads);
+            }
+        }
+    }
+    Ok(direct_reads)
+}
+
+/// Bracken 报告的一行
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrackenEntry {
+    pub name: String,
+    /// 外部 taxid
+    pub taxid: u64,
+    /// 原报告中该 taxon 子树的 read 数
+    pub kraken_reads: u64,
+    /// 从上层 taxon 重新分配得到的 read 数
+    pub added_reads: u64,
+    pub new_reads: u64,
+    pub fraction: f64,
+}
+
+/// 按 Bracken 的方法估计 rank 层级上每个 taxon 的 read 数
+/// rank 层级以上的 taxon 直接分到的 read 按 P(分类到该 taxon | 基因组) * 基因组所在 rank 节点的 read 数的比例分配给下层的 rank 节点,
+/// read 数少于 threshold 的 rank 节点不参与分配也不输出; direct_reads 的键为内部 taxid
+pub fn estimate_abundance(
+    taxonomy: &Taxonomy,
+    direct_reads: &HashMap<u64, u64>,
+    distrib: &KmerDistribution,
+    rank: &str,
+    threshold: u64,
+) -> Vec<BrackenEntry> {
+    let clade_reads = get_clade_counts(taxonomy, direct_reads);
+    let kept: HashMap<u32, u64> = clade_reads
+        .iter()
+        .filter(|(&taxid, &reads)| {
+            reads >= threshold.max(1) && taxonomy.get_rank(taxid as u32) == Some(rank)
+        })
+        .map(|(&taxid, &reads)| (taxid as u32, reads))
+        .collect();
+
+    // 每个基因组所在的 rank 节点
+    let genome_levels: HashMap<u64, u32> = distrib
+        .totals
+        .keys()
+        .filter_map(|&genome| {
+            let internal_id = taxonomy.get_internal_id_opt(genome)?;
+            let level = taxonomy.node_at_rank(internal_id, rank)?;
+            kept.contains_key(&level).then_some((genome, level))
+        })
+        .collect();
+
+    let mut added: HashMap<u32, f64> = HashMap::new();
+    for (&taxid, &reads) in direct_reads {
+        let taxid = taxid as u32;
+        // rank 层级及以下的 read 已经计入对应 rank 节点的子树
+        if taxonomy.node_at_rank(taxid, rank).is_some() {
+            continue;
+        }
+        let Some(genomes) = distrib
+            .mapped
+            .get(&taxonomy.nodes[taxid as usize].external_id)
+        else {
+            continue;
+        };
+        let mut weights: HashMap<u32, f64> = HashMap::new();
+        for (genome, &count) in genomes {
+            let (Some(&level), Some(&total)) =
+                (genome_levels.get(genome), distrib.totals.get(genome))
+            else {
+                continue;
+            };
+            if total > 0 {
+                *weights.entry(level).or_insert(0.0) +=
+                    count as f64 / total as f64 * kept[&level] as f64;
+            }
+        }
+        let sum: f64 = weights.values().sum();
+        if sum > 0.0 {
+            for (level, weight) in weights {
+                *added.entry(level).or_insert(0.0) += reads as f64 * weight / sum;
+            }
+        }
+    }
+
+    let mut entries: Vec<BrackenEntry> = kept
+        .iter()
+        .map(|(&taxid, &kraken_reads)| {
+            let added_reads = added.get(&taxid).map_or(0, |reads| reads.round() as u64);
+            BrackenEntry {
+                name: taxonomy.get_name(taxid).unwrap_or("").to_string(),
+                taxid: taxonomy.nodes[taxid as usize].external_id,
+                kraken_reads,
+                added_reads,
+                new_reads: kraken_reads + added_reads,
+                fraction: 0.0,
+            }
+        })
+        .collect();
+    let total: u64 = entries.iter().map(|entry| entry.new_reads).sum();
+    for entry in entries.iter_mut() {
+        entry.fraction = entry.new_reads as f64 / total.max(1) as f64;
+    }
+    entries.sort_by(|a, b| b.new_reads.cmp(&a.new_reads).then(a.taxid.cmp(&b.taxid)));
+    entries
+}
+
+/// 以 Bracken 的格式写出估计结果
+pub fn write_bracken_report<W: Write>(
+    writer: &mut W,
+    entries: &[BrackenEntry],
+    rank: &str,
+) -> Kr2Result<()> {
+    writeln!(
+        writer,
+        "name\ttaxonomy_id\ttaxonomy_lvl\tkraken_assigned_reads\tadded_reads\tnew_est_reads\tfraction_total_reads"
+    )?;
+    for entry in entries {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.5}",
+            entry.name,
+            entry.taxid,
+            level_code(rank),
+            entry.kraken_reads,
+            entry.added_reads,
+            entry.new_reads,
+            entry.fraction
+        )?;
+    }
+    Ok(())
+}
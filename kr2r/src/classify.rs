@@ -1,9 +1,16 @@
 use crate::compact_hash::Compact;
-use crate::readcounts::TaxonCounters;
+use crate::readcounts::{KmerCounting, ReadCounter, TaxonCounters};
+use crate::report::subtree_hits;
 use crate::taxonomy::Taxonomy;
-use crate::HitGroup;
+use crate::Kr2Result;
+use crate::{HitGroup, KmerHitRecord};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use seqkmer::SpaceDist;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::io::Write;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 // fn generate_hit_string(
@@ -61,13 +68,9 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 //         .join(" ")
 // }
 
-// &HashMap<u32, u64>,
-pub fn resolve_tree(
-    hit_counts: &HashMap<u32, u64>,
-    taxonomy: &Taxonomy,
-    required_score: u64,
-) -> u32 {
-    let mut max_taxon = 0u32;
+/// 得分最高的所有 taxon, 得分为 taxon 及其所有祖先的命中数之和
+fn max_score_candidates(hit_counts: &HashMap<u32, u64>, taxonomy: &Taxonomy) -> Vec<u32> {
+    let mut candidates = Vec::new();
     let mut max_score = 0;
 
     for (&taxon, _) in hit_counts {
@@ -81,20 +84,27 @@ pub fn resolve_tree(
 
         if score > max_score {
             max_score = score;
-            max_taxon = taxon;
+            candidates.clear();
+            candidates.push(taxon);
         } else if score == max_score {
-            max_taxon = taxonomy.lca(max_taxon, taxon);
+            candidates.push(taxon);
         }
     }
 
-    max_score = *hit_counts.get(&max_taxon).unwrap_or(&0);
+    candidates
+}
+
+/// 从 max_taxon 开始向上回溯, 直到子树命中数达到 required_score
+fn climb_to_required_score(
+    hit_counts: &HashMap<u32, u64>,
+    taxonomy: &Taxonomy,
+    mut max_taxon: u32,
+    required_score: u64,
+) -> u32 {
+    let mut max_score = *hit_counts.get(&max_taxon).unwrap_or(&0);
 
     while max_taxon != 0 && max_score < required_score {
-        max_score = hit_counts
-            .iter()
-            .filter(|(&taxon, _)| taxonomy.is_a_ancestor_of_b(max_taxon, taxon))
-            .map(|(_, &count)| count)
-            .sum();
+        max_score = subtree_hits(taxonomy, hit_counts, max_taxon);
 
         if max_score >= required_score {
             break;
@@ -105,6 +115,88 @@ pub fn resolve_tree(
     max_taxon
 }
 
+/// 从得分相同的候选 taxon 中随机选择一个, 候选为空时返回 0
+pub fn break_tie<R: Rng>(candidates: &[u32], rng: &mut R) -> u32 {
+    candidates.choose(rng).copied().unwrap_or(0)
+}
+
+/// 多个 taxon 得分相同时的处理方式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmbiguousPolicy {
+    /// 取它们的 LCA, 指定了 --seed 时改为用该种子随机选择一个
+    LCA,
+    /// 视为未分类
+    Unclassified,
+    /// 每个候选 taxon 各输出一行
+    ReportAll,
+}
+
+/// 按 policy 处理得分相同的候选 taxon, 返回分类结果, 0 表示未分类
+pub fn resolve_ambiguous(
+    candidates: &[u32],
+    policy: AmbiguousPolicy,
+    taxonomy: &Taxonomy,
+) -> Vec<u32> {
+    match (policy, candidates) {
+        (_, []) => vec![0],
+        (_, [taxon]) => vec![*taxon],
+        (AmbiguousPolicy::LCA, _) => vec![taxonomy.lca_of_set(candidates)],
+        (AmbiguousPolicy::Unclassified, _) => vec![0],
+        (AmbiguousPolicy::ReportAll, _) => {
+            let mut taxa = candidates.to_vec();
+            taxa.sort_unstable();
+            taxa.dedup();
+            taxa
+        }
+    }
+}
+
+/// 一条 read 的主要分类结果: --ambiguous-taxid-policy report-all 时一条 read 在 output_{i}.txt 中有多行结果,
+/// 读取分类结果的地方 (报告, --split-by-rank, --split-by-taxon, SAM 等) 都以这些结果的 LCA 作为该 read 的分类, 0 表示未分类
+pub fn primary_call(taxonomy: &Taxonomy, calls: &[u32]) -> u32 {
+    taxonomy.lca_of_set(calls)
+}
+
+/// 按 policy 得到所有分类结果, 每个结果都向上回溯直到达到 required_score
+fn resolve_calls(
+    hit_counts: &HashMap<u32, u64>,
+    taxonomy: &Taxonomy,
+    required_score: u64,
+    policy: AmbiguousPolicy,
+    tie_seed: Option<u64>,
+) -> Vec<u32> {
+    let mut candidates = max_score_candidates(hit_counts, taxonomy);
+    // HashMap 的遍历顺序不固定, 排序后结果才可复现
+    candidates.sort_unstable();
+    let resolved = match (policy, tie_seed) {
+        (AmbiguousPolicy::LCA, Some(seed)) => {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            vec![break_tie(&candidates, &mut rng)]
+        }
+        _ => resolve_ambiguous(&candidates, policy, taxonomy),
+    };
+
+    let mut calls: Vec<u32> = resolved
+        .into_iter()
+        .map(|taxon| climb_to_required_score(hit_counts, taxonomy, taxon, required_score))
+        .collect();
+    calls.sort_unstable();
+    calls.dedup();
+    calls
+}
+
+// &HashMap<u32, u64>,
+pub fn resolve_tree(
+    hit_counts: &HashMap<u32, u64>,
+    taxonomy: &Taxonomy,
+    required_score: u64,
+) -> u32 {
+    // 得分相同时取它们的 LCA
+    let max_taxon = taxonomy.lca_of_set(&max_score_candidates(hit_counts, taxonomy));
+
+    climb_to_required_score(hit_counts, taxonomy, max_taxon, required_score)
+}
+
 // pub fn add_hitlist_string(
 //     rows: &Vec<Row>,
 //     value_mask: usize,
@@ -162,6 +254,7 @@ fn stat_hits<'a>(
     value_mask: usize,
     taxonomy: &Taxonomy,
     cur_taxon_counts: &mut TaxonCounters,
+    kmer_counting: KmerCounting,
 ) -> String {
     let mut space_dist = hits.range.apply(|range| SpaceDist::new(*range));
     for row in &hits.rows {
@@ -172,8 +265,8 @@ fn stat_hits<'a>(
 
         cur_taxon_counts
             .entry(key as u64)
-            .or_default()
-            .add_kmer(value as u64);
+            .or_insert_with(|| ReadCounter::with_counting(kmer_counting))
+            .add_kmer(row.minimizer);
 
         let ext_code = taxonomy.nodes[key as usize].external_id;
         let pos = row.kmer_id as usize;
@@ -184,18 +277,103 @@ fn stat_hits<'a>(
     space_dist.reduce_str(" |:| ", |str| str.to_string())
 }
 
+/// 按 k-mer 位置顺序列出 read 的 minimizer 命中, 最多 max_hits 条
+pub fn collect_kmer_hits(
+    hits: &HitGroup,
+    read_id: &str,
+    taxonomy: &Taxonomy,
+    value_bits: usize,
+    value_mask: usize,
+    max_hits: usize,
+) -> Vec<KmerHitRecord> {
+    hits.rows
+        .iter()
+        .take(max_hits)
+        .map(|row| KmerHitRecord {
+            read_id: read_id.to_string(),
+            minimizer: row.value.left(value_bits) as u64,
+            taxid: taxonomy.nodes[row.value.right(value_mask) as usize].external_id as u32,
+        })
+        .collect()
+}
+
+/// 命中分类结果所在子树的 k-mer 占全部 k-mer 的比例是否达到 threshold
+pub fn passes_hit_fraction(hits: usize, total_kmers: usize, threshold: f64) -> bool {
+    if threshold <= 0.0 {
+        return true;
+    }
+    if total_kmers == 0 {
+        return false;
+    }
+    hits as f64 / total_kmers as f64 >= threshold
+}
+
+/// 分类结果的置信度: 命中该 taxon 子树的 k-mer 占全部 k-mer 的比例, 与 --confidence-threshold 的含义一致
+pub fn call_confidence(subtree_hits: usize, total_kmers: usize) -> f64 {
+    if total_kmers == 0 {
+        return 0.0;
+    }
+    subtree_hits as f64 / total_kmers as f64
+}
+
+/// 置信度直方图, 第 i 个区间为 `[i * bin_width, (i + 1) * bin_width)`, 置信度为 1 的 read 计入最后一个区间
+pub struct ConfidenceHistogram {
+    bin_width: f64,
+    bins: Vec<AtomicUsize>,
+}
+
+impl ConfidenceHistogram {
+    pub fn new(bin_width: f64) -> Self {
+        let n_bins = ((1.0 / bin_width).ceil() as usize).max(1);
+        Self {
+            bin_width,
+            bins: (0..n_bins).map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    pub fn add(&self, confidence: f64) {
+        let index = ((confidence / self.bin_width).floor() as usize).min(self.bins.len() - 1);
+        self.bins[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 以 `bin_start\tcount` 的 TSV 格式写出所有区间, 包括计数为 0 的区间
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Kr2Result<()> {
+        writeln!(writer, "bin_start\tcount")?;
+        for (i, bin) in self.bins.iter().enumerate() {
+            writeln!(
+                writer,
+                "{:.4}\t{}",
+                i as f64 * self.bin_width,
+                bin.load(Ordering::Relaxed)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// 每条 read 的 taxon 命中计数, 在同一线程的 read 之间复用
+    static HIT_COUNTS: RefCell<HashMap<u32, u64>> = RefCell::new(HashMap::new());
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn process_hitgroup(
     hits: &HitGroup,
     taxonomy: &Taxonomy,
     classify_counter: &AtomicUsize,
     required_score: u64,
     minimum_hit_groups: usize,
+    min_hit_fraction: f64,
     value_mask: usize,
-) -> (String, u64, String, TaxonCounters) {
+    ambiguous_policy: AmbiguousPolicy,
+    tie_seed: Option<u64>,
+    kmer_counting: KmerCounting,
+) -> (String, Vec<u64>, String, TaxonCounters, Vec<f64>) {
     // let value_mask = hash_config.value_mask;
 
     let mut cur_taxon_counts = TaxonCounters::new();
-    let mut counts = HashMap::new();
+    let mut counts = HIT_COUNTS.with(|buf| buf.take());
+    counts.clear();
     let hit_groups = hits.capacity();
     let hit_string = stat_hits(
         hits,
@@ -203,6 +381,7 @@ pub fn process_hitgroup(
         value_mask,
         taxonomy,
         &mut cur_taxon_counts,
+        kmer_counting,
     );
 
     // cur_counts.iter().for_each(|(key, value)| {
@@ -213,23 +392,157 @@ pub fn process_hitgroup(
     //         .unwrap();
     // });
 
-    let mut call = resolve_tree(&counts, taxonomy, required_score);
-    if call > 0 && hit_groups < minimum_hit_groups {
-        call = 0;
+    let mut calls = resolve_calls(
+        &counts,
+        taxonomy,
+        required_score,
+        ambiguous_policy,
+        tie_seed,
+    );
+    if hit_groups < minimum_hit_groups {
+        calls.clear();
     };
-
-    let ext_call = taxonomy.nodes[call as usize].external_id;
-    let clasify = if call > 0 {
+    calls.retain(|&call| {
+        call != 0
+            && passes_hit_fraction(
+                subtree_hits(taxonomy, &counts, call) as usize,
+                hits.capacity(),
+                min_hit_fraction,
+            )
+    });
+
+    let clasify = if calls.is_empty() {
+        calls.push(0);
+        "U"
+    } else {
         classify_counter.fetch_add(1, Ordering::SeqCst);
+        // 报告多个结果时, read 只计入主要分类结果以免重复计数
         cur_taxon_counts
-            .entry(call as u64)
+            .entry(primary_call(taxonomy, &calls) as u64)
             .or_default()
             .increment_read_count();
 
         "C"
-    } else {
-        "U"
     };
 
-    (clasify.to_owned(), ext_call, hit_string, cur_taxon_counts)
+    let ext_calls = calls
+        .iter()
+        .map(|&call| taxonomy.nodes[call as usize].external_id)
+        .collect();
+    let confidences = calls
+        .iter()
+        .map(|&call| match call {
+            0 => 0.0,
+            _ => call_confidence(
+                subtree_hits(taxonomy, &counts, call) as usize,
+                hits.capacity(),
+            ),
+        })
+        .collect();
+    HIT_COUNTS.with(|buf| *buf.borrow_mut() = counts);
+    (
+        clasify.to_owned(),
+        ext_calls,
+        hit_string,
+        cur_taxon_counts,
+        confidences,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact_hash::Row;
+    use crate::taxonomy::test_taxonomy;
+    use seqkmer::OptionPair;
+
+    /// 1 ─┬─ 2 ─┬─ 4
+    ///    │     └─ 5
+    ///    └─ 3
+    fn taxonomy() -> Taxonomy {
+        test_taxonomy(&[(0, 1), (1, 10), (1, 20), (2, 100), (2, 101)])
+    }
+
+    #[test]
+    fn same_seed_breaks_ties_the_same_way() {
+        let candidates: Vec<u32> = (1..100).collect();
+        let pick = |seed| {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            (0..20)
+                .map(|_| break_tie(&candidates, &mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pick(7), pick(7));
+        assert_ne!(pick(7), pick(8));
+        assert_eq!(break_tie(&[], &mut SmallRng::seed_from_u64(7)), 0);
+    }
+
+    /// 同一哈希表格子值 (压缩 key 和 taxid 相同) 的不同 minimizer 分别计数
+    #[test]
+    fn unique_kmers_count_minimizers_not_cells() {
+        let taxo = taxonomy();
+        let value_bits = 16;
+        let value = u32::combined(7, 4, value_bits);
+        let rows = [(1, 100), (2, 200), (3, 100), (4, 300)]
+            .map(|(kmer_id, minimizer)| Row::new(value, 0, kmer_id, minimizer));
+        let hits = HitGroup::new(rows.to_vec(), OptionPair::Single((0, 10)));
+        for counting in [KmerCounting::Exact, KmerCounting::Approx(16)] {
+            let (_, _, _, mut counters, _) = process_hitgroup(
+                &hits,
+                &taxo,
+                &AtomicUsize::new(0),
+                0,
+                0,
+                0.0,
+                (1 << value_bits) - 1,
+                AmbiguousPolicy::LCA,
+                None,
+                counting,
+            );
+            let counter = counters.get_mut(&4).unwrap();
+            assert_eq!(counter.read_count(), 1);
+            assert_eq!(counter.kmer_count(), 4);
+            assert_eq!(counter.distinct_kmer_count(), 3);
+        }
+    }
+
+    /// --kmer-hits-file 每行为 `read_id, 压缩键, 外部 taxid`, 按 --max-hits-per-read 截断
+    #[test]
+    fn kmer_hit_table_is_truncated_per_read() {
+        let taxo = taxonomy();
+        let value_bits = 16;
+        let rows = [(7, 4), (8, 5), (9, 3)]
+            .map(|(key, taxid)| Row::new(u32::combined(key, taxid, value_bits), 0, key, 0));
+        let hits = HitGroup::new(rows.to_vec(), OptionPair::Single((0, 10)));
+        let value_mask = (1 << value_bits) - 1;
+        let lines = |max_hits| {
+            collect_kmer_hits(&hits, "read1", &taxo, value_bits, value_mask, max_hits)
+                .iter()
+                .map(|record| record.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lines(100),
+            vec!["read1\t7\t100", "read1\t8\t101", "read1\t9\t20"]
+        );
+        assert_eq!(lines(2), vec!["read1\t7\t100", "read1\t8\t101"]);
+        assert!(lines(0).is_empty());
+    }
+
+    #[test]
+    fn ties_use_lca_without_seed() {
+        let taxo = taxonomy();
+        let hit_counts = HashMap::from([(4, 3), (5, 3)]);
+        let lca = resolve_calls(&hit_counts, &taxo, 0, AmbiguousPolicy::LCA, None);
+        assert_eq!(lca, vec![2]);
+
+        for seed in 0..10 {
+            let calls = resolve_calls(&hit_counts, &taxo, 0, AmbiguousPolicy::LCA, Some(seed));
+            assert_eq!(
+                calls,
+                resolve_calls(&hit_counts, &taxo, 0, AmbiguousPolicy::LCA, Some(seed))
+            );
+            assert!(calls == vec![4] || calls == vec![5]);
+        }
+    }
 }
@@ -1,7 +1,11 @@
+use crate::Kr2Result;
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+#[cfg(unix)]
+use memmap2::Advice;
+use memmap2::Mmap;
 use std::cmp::Ordering as CmpOrdering;
 use std::fs::OpenOptions;
-use std::io::{Read, Result};
+use std::io::{Error, ErrorKind, Read, Result};
 use std::path::Path;
 
 /// 1101010101 => left: 11010, right: 10101;
@@ -72,20 +76,24 @@ impl Compact for u64 {
     }
 }
 
-#[repr(C)]
+/// annotate 输出的一个 minimizer 命中, 按 4 字节对齐紧密排列, 文件中每条 20 字节
+#[repr(C, packed(4))]
 #[derive(PartialEq, Clone, Copy, Eq, Debug)]
 pub struct Row {
     pub value: u32,
     pub seq_id: u32,
     pub kmer_id: u32,
+    /// minimizer 在哈希表中的位置和压缩 key 组成的键, 见 HashConfig::minimizer_key
+    pub minimizer: u64,
 }
 
 impl Row {
-    pub fn new(value: u32, seq_id: u32, kmer_id: u32) -> Self {
+    pub fn new(value: u32, seq_id: u32, kmer_id: u32, minimizer: u64) -> Self {
         Self {
             value,
             seq_id,
             kmer_id,
+            minimizer,
         }
     }
     #[inline]
@@ -216,7 +224,7 @@ impl HashConfig {
         }
     }
 
-    pub fn from_hash_header<P: AsRef<Path>>(filename: P) -> Result<Self> {
+    pub fn from_hash_header<P: AsRef<Path>>(filename: P) -> Kr2Result<Self> {
         let mut file = OpenOptions::new().read(true).open(&filename)?;
         let partition = file.read_u64::<LittleEndian>()? as usize;
         let hash_capacity = file.read_u64::<LittleEndian>()? as usize;
@@ -260,6 +268,12 @@ impl HashConfig {
         (self.index(hash_key), hash_key.left(self.value_bits) as u32)
     }
 
+    /// 由 compact 的结果组成的 minimizer 键, 用于统计 distinct minimizer
+    /// 压缩 key 只有 32 - value_bits 位, 位置在高位, 不同的 minimizer 在同一个数据库中的键一定不同
+    pub fn minimizer_key(&self, index: usize, compacted: u32) -> u64 {
+        (index as u64) << (32 - self.value_bits) | compacted as u64
+    }
+
     pub fn slot(&self, hash_key: u64, taxid: u32) -> Slot<u32> {
         let idx = self.index(hash_key);
         Slot::<u32>::new(idx, u32::hash_value(hash_key, self.value_bits, taxid))
@@ -379,30 +393,53 @@ impl Page {
         value_mask: usize,
     ) -> u32 {
         // let compacted_key = value.left(value_bits) as u32;
-        let mut idx = index;
-        if idx > self.size {
-            return u32::default();
-        }
+        find_cell(
+            |idx| self.data.get(idx).copied(),
+            self.size,
+            index,
+            compacted_key,
+            value_bits,
+            value_mask,
+        )
+    }
+}
 
-        loop {
-            if let Some(cell) = self.data.get(idx) {
-                if cell.right(value_mask) == u32::default()
-                    || cell.left(value_bits) == compacted_key
-                {
-                    return cell.right(value_mask);
-                }
+/// 从 index 开始线性探测, 返回第一个空格子或 key 相同的格子中的 taxid, get 返回第 idx 个格子
+fn find_cell<F: Fn(usize) -> Option<u32>>(
+    get: F,
+    size: usize,
+    index: usize,
+    compacted_key: u32,
+    value_bits: usize,
+    value_mask: usize,
+) -> u32 {
+    let mut idx = index;
+    if idx > size {
+        return u32::default();
+    }
+
+    loop {
+        if let Some(cell) = get(idx) {
+            if cell.right(value_mask) == u32::default() || cell.left(value_bits) == compacted_key {
+                return cell.right(value_mask);
+            }
 
-                idx = idx + 1;
-                if idx >= self.size {
-                    break;
-                }
-            } else {
-                // 如果get(idx)失败，返回默认值
-                return u32::default();
+            idx = idx + 1;
+            if idx >= size {
+                break;
             }
+        } else {
+            // 如果get(idx)失败，返回默认值
+            return u32::default();
         }
-        u32::default()
     }
+    u32::default()
+}
+
+/// 按分页查询 taxid 的哈希表, 由读入内存的 CHTable 和内存映射的 MmapDatabase 实现
+pub trait HashLookup: Sync {
+    /// 在第 page_index 个分页中从 indx 开始查找压缩 key 为 compacted 的格子, 返回其 taxid, 找不到时返回 0
+    fn get_from_page(&self, indx: usize, compacted: u32, page_index: usize) -> u32;
 }
 
 #[allow(unused)]
@@ -416,7 +453,7 @@ impl CHTable {
         config: HashConfig,
         chunk_file1: P,
         chunk_file2: P,
-    ) -> Result<CHTable> {
+    ) -> Kr2Result<CHTable> {
         let mut page = read_page_from_file(chunk_file1)?;
         let next_page = if page.data.last().map_or(false, |&x| x == 0) {
             read_first_block_from_file(chunk_file2)?
@@ -434,7 +471,7 @@ impl CHTable {
     pub fn from_hash_files<P: AsRef<Path> + Debug>(
         config: HashConfig,
         hash_files: Vec<P>,
-    ) -> Result<CHTable> {
+    ) -> Kr2Result<CHTable> {
         let mut pages = vec![Page::default(); hash_files.len() + 1];
         for hash_file in hash_files {
             let mut page = read_page_from_file(&hash_file)?;
@@ -453,7 +490,7 @@ impl CHTable {
         Ok(chtm)
     }
 
-    pub fn from<P: AsRef<Path> + Debug>(config: HashConfig, chunk_file1: P) -> Result<CHTable> {
+    pub fn from<P: AsRef<Path> + Debug>(config: HashConfig, chunk_file1: P) -> Kr2Result<CHTable> {
         let mut page = read_page_from_file(&chunk_file1)?;
         let next_page = if page.data.last().map_or(false, |&x| x == 0) {
             read_first_block_from_file(&chunk_file1)?
@@ -480,4 +517,200 @@ impl CHTable {
             0
         }
     }
+
+    /// minimizer hash 所在的分页和分页内位置, 与 classify 的划分相同: 返回 (位置, 压缩 key, 分页序号)
+    fn locate(&self, hash_key: u64) -> (usize, u32, usize) {
+        let (idx, compacted) = self.config.compact(hash_key);
+        let chunk_size = self.config.hash_capacity;
+        (idx % chunk_size, compacted, idx / chunk_size + 1)
+    }
+
+    #[inline]
+    fn prefetch(&self, hash_key: u64) {
+        let (index, _, page_index) = self.locate(hash_key);
+        if let Some(cell) = self
+            .pages
+            .get(page_index)
+            .and_then(|page| page.data.get(index))
+        {
+            prefetch_cell(cell);
+        }
+    }
+
+    /// 批量查询 minimizer hash 的 taxid, 结果写入 results 的对应位置, 找不到时为 0
+    /// 每批 DEFAULT_PREFETCH_BATCH 个, 查询当前批之前先预取下一批的格子, 让内存访问和查询重叠
+    pub fn lookup_batch(&self, minimizers: &[u64], results: &mut [u32]) {
+        self.lookup_batch_with(minimizers, results, DEFAULT_PREFETCH_BATCH)
+    }
+
+    /// 与 lookup_batch 相同, 每批 batch_size 个
+    pub fn lookup_batch_with(&self, minimizers: &[u64], results: &mut [u32], batch_size: usize) {
+        assert_eq!(
+            minimizers.len(),
+            results.len(),
+            "lookup_batch: results must have the same length as minimizers"
+        );
+        let batch_size = batch_size.max(1);
+        let mut batches = minimizers.chunks(batch_size);
+        let mut next = batches.next();
+        if let Some(batch) = next {
+            batch.iter().for_each(|&hash_key| self.prefetch(hash_key));
+        }
+        for results in results.chunks_mut(batch_size) {
+            let Some(batch) = next else {
+                break;
+            };
+            next = batches.next();
+            if let Some(following) = next {
+                following
+                    .iter()
+                    .for_each(|&hash_key| self.prefetch(hash_key));
+            }
+            for (&hash_key, result) in batch.iter().zip(results.iter_mut()) {
+                let (index, compacted, page_index) = self.locate(hash_key);
+                *result = self.get_from_page(index, compacted, page_index);
+            }
+        }
+    }
+}
+
+/// lookup_batch 每批预取的格子数
+pub const DEFAULT_PREFETCH_BATCH: usize = 16;
+
+/// 提示 CPU 把格子所在的 cache line 读入 L1, 其他架构上不做任何事
+#[inline(always)]
+fn prefetch_cell(cell: &u32) {
+    // 安全性: 预取只是提示, 地址来自有效的引用, 不会读写内存
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch::<_MM_HINT_T0>(cell as *const u32 as *const i8);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = cell;
+}
+
+impl HashLookup for CHTable {
+    fn get_from_page(&self, indx: usize, compacted: u32, page_index: usize) -> u32 {
+        CHTable::get_from_page(self, indx, compacted, page_index)
+    }
+}
+
+/// 哈希表分页文件头: 分页序号和容量, 各 8 字节
+const PAGE_HEADER_SIZE: usize = 16;
+
+/// 内存映射的哈希表分页文件, 返回文件头中的分页序号 (从 1 开始) 和容量
+fn map_page_file<P: AsRef<Path> + Debug>(filename: P) -> Result<(Mmap, usize, usize)> {
+    let file = std::fs::File::open(&filename)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    #[cfg(unix)]
+    mmap.advise(Advice::Random)?;
+    if mmap.len() < PAGE_HEADER_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{:?}: truncated hash table header", filename),
+        ));
+    }
+    let index = LittleEndian::read_u64(&mmap[0..8]) as usize;
+    let capacity = LittleEndian::read_u64(&mmap[8..16]) as usize;
+    if mmap.len() < PAGE_HEADER_SIZE + capacity * std::mem::size_of::<u32>() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{:?}: {} bytes, too small for {} cells",
+                filename,
+                mmap.len(),
+                capacity
+            ),
+        ));
+    }
+    Ok((mmap, index, capacity))
+}
+
+/// 分页文件中的格子, 映射的起始地址按页对齐, 文件头之后的数据按 u32 对齐
+fn page_cells(mmap: &Mmap, capacity: usize) -> &[u32] {
+    unsafe { std::slice::from_raw_parts(mmap[PAGE_HEADER_SIZE..].as_ptr() as *const u32, capacity) }
+}
+
+/// 内存映射的哈希表分页, 不需要先把整个分页读入内存, 由操作系统的 page cache 按需加载
+/// 查询结果与 CHTable::from / CHTable::from_pair 读入的分页相同
+pub struct MmapDatabase {
+    pub config: HashConfig,
+    /// 分页在 CHTable::pages 中的位置, 即文件头中的分页序号减 1
+    page_index: usize,
+    page: Mmap,
+    capacity: usize,
+    /// 分页最后一个格子为空时, 继续探测的下一块格子所在的文件和格子数 (到第一个空格子为止)
+    next_block: Option<(Mmap, usize)>,
+}
+
+impl MmapDatabase {
+    fn map<P: AsRef<Path> + Debug>(config: HashConfig, page_file: P, next_file: P) -> Result<Self> {
+        let (page, index, capacity) = map_page_file(&page_file)?;
+        if index == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?}: invalid page index 0", page_file),
+            ));
+        }
+        let cells = page_cells(&page, capacity);
+        let next_block = if cells.last() == Some(&0) {
+            let (next, _, next_capacity) = map_page_file(&next_file)?;
+            let next_cells = page_cells(&next, next_capacity);
+            let size = match next_cells.iter().position(|&x| x == 0) {
+                Some(pos) => pos + 1,
+                None => {
+                    eprintln!("Warning: No zero value found in the data, using full capacity.");
+                    next_capacity
+                }
+            };
+            Some((next, size))
+        } else {
+            None
+        };
+        Ok(Self {
+            config,
+            page_index: index - 1,
+            page,
+            capacity,
+            next_block,
+        })
+    }
+
+    pub fn from<P: AsRef<Path> + Debug>(config: HashConfig, chunk_file1: P) -> Kr2Result<Self> {
+        Ok(Self::map(config, &chunk_file1, &chunk_file1)?)
+    }
+
+    pub fn from_pair<P: AsRef<Path> + Debug>(
+        config: HashConfig,
+        chunk_file1: P,
+        chunk_file2: P,
+    ) -> Kr2Result<Self> {
+        Ok(Self::map(config, chunk_file1, chunk_file2)?)
+    }
+
+    fn cell(&self, idx: usize) -> Option<u32> {
+        if idx < self.capacity {
+            return Some(page_cells(&self.page, self.capacity)[idx]);
+        }
+        let (next, size) = self.next_block.as_ref()?;
+        page_cells(next, *size).get(idx - self.capacity).copied()
+    }
+}
+
+impl HashLookup for MmapDatabase {
+    fn get_from_page(&self, indx: usize, compacted: u32, page_index: usize) -> u32 {
+        if page_index != self.page_index {
+            return 0;
+        }
+        let size = self.capacity + self.next_block.as_ref().map_or(0, |(_, size)| *size);
+        find_cell(
+            |idx| self.cell(idx),
+            size,
+            indx,
+            compacted,
+            self.config.value_bits,
+            self.config.value_mask,
+        )
+    }
 }
@@ -0,0 +1,163 @@
+use clap::{ArgAction, Command};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// 在 clap 解析之前从命令行中找出 `--config <path>` 或 `--config=<path>`
+pub fn find_config_arg<I: IntoIterator<Item = OsString>>(args: I) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// 把 TOML 值转换为 clap 的参数默认值, 数组对应可以出现多次的参数
+fn to_default_values(key: &str, value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::String(s) => Ok(vec![s.clone()]),
+        Value::Integer(i) => Ok(vec![i.to_string()]),
+        Value::Float(f) => Ok(vec![f.to_string()]),
+        Value::Boolean(b) => Ok(vec![b.to_string()]),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                Value::Array(_) | Value::Table(_) => Err(format!(
+                    "config key `{}`: nested values are not supported",
+                    key
+                )),
+                value => to_default_values(key, value).map(|mut v| v.remove(0)),
+            })
+            .collect(),
+        _ => Err(format!(
+            "config key `{}`: unsupported value type {}",
+            key,
+            value.type_str()
+        )),
+    }
+}
+
+/// 把 table 中的键值设置为 subcommand 对应参数的默认值, strict 为 true 时不认识的键报错
+fn apply_table(
+    mut cmd: Command,
+    table: &Table,
+    section: &str,
+    strict: bool,
+) -> Result<Command, String> {
+    for (key, value) in table {
+        if value.is_table() {
+            continue;
+        }
+        if !cmd.get_arguments().any(|arg| arg.get_id() == key.as_str()) {
+            if strict {
+                return Err(format!("unknown config key `{}` in [{}]", key, section));
+            }
+            continue;
+        }
+        let values = to_default_values(key, value)?;
+        cmd = cmd.mut_arg(key.as_str(), |arg| {
+            arg.required(false).default_values(values)
+        });
+    }
+    Ok(cmd)
+}
+
+/// 读取 TOML 配置文件, 把其中的值作为 cmd 各个 subcommand 参数的默认值, 命令行参数仍然优先
+/// 顶层的键作用于所有拥有该参数的 subcommand, `[classify]` 这样的 table 只作用于对应的 subcommand 并覆盖顶层的值
+/// 键名与参数的字段名一致, 如 `confidence_threshold = 0.1`, `num_threads = 32`
+pub fn apply_config_file(cmd: Command, path: &Path) -> Result<Command, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read config file {:?}: {}", path, e))?;
+    let table: Table = content
+        .parse()
+        .map_err(|e| format!("failed to parse config file {:?}: {}", path, e))?;
+    apply_config(cmd, &table)
+}
+
+pub fn apply_config(mut cmd: Command, table: &Table) -> Result<Command, String> {
+    for key in table.keys().filter(|key| !table[key.as_str()].is_table()) {
+        let known = cmd
+            .get_subcommands()
+            .any(|sub| sub.get_arguments().any(|arg| arg.get_id() == key.as_str()));
+        if !known {
+            return Err(format!("unknown config key `{}`", key));
+        }
+    }
+    for (section, value) in table {
+        if value.is_table() && cmd.find_subcommand(section).is_none() {
+            return Err(format!("unknown config section [{}]", section));
+        }
+    }
+
+    let names: Vec<String> = cmd
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .collect();
+    for name in names {
+        let mut sub = cmd.find_subcommand(&name).unwrap().clone();
+        sub = apply_table(sub, table, &name, false)?;
+        if let Some(section) = table.get(&name).and_then(Value::as_table) {
+            sub = apply_table(sub, section, &name, true)?;
+        }
+        cmd = cmd.mut_subcommand(&name, |_| sub);
+    }
+    Ok(cmd)
+}
+
+/// 把参数默认值格式化为 TOML 值, 数字和布尔值不加引号
+fn format_toml_value(value: &str) -> String {
+    if value == "true" || value == "false" || value.parse::<i64>().is_ok() {
+        return value.to_string();
+    }
+    if value.parse::<f64>().is_ok() && value.contains('.') {
+        return value.to_string();
+    }
+    Value::String(value.to_string()).to_string()
+}
+
+/// 生成包含每个 subcommand 所有参数默认值的 TOML 配置, 没有默认值的参数以注释列出
+pub fn default_config(cmd: &Command, skip: &[&str]) -> String {
+    let mut out = String::from(
+        "# Keys outside any table apply to every subcommand that has the option.\n\
+         # Values in a [subcommand] table apply to that subcommand only.\n\
+         # Options given on the command line override the config file.\n",
+    );
+    for sub in cmd.get_subcommands() {
+        if skip.contains(&sub.get_name()) {
+            continue;
+        }
+        out.push_str(&format!("\n[{}]\n", sub.get_name()));
+        for arg in sub.get_arguments() {
+            let id = arg.get_id().as_str();
+            if id == "help" || id == "version" || id == "config" {
+                continue;
+            }
+            let mut defaults: Vec<String> = arg
+                .get_default_values()
+                .iter()
+                .map(|value| format_toml_value(&value.to_string_lossy()))
+                .collect();
+            // 开关参数的默认值在 clap build 时才设置
+            if defaults.is_empty() && matches!(arg.get_action(), ArgAction::SetTrue) {
+                defaults.push("false".to_string());
+            }
+            let multiple = matches!(arg.get_action(), ArgAction::Append)
+                || arg.get_num_args().is_some_and(|n| n.max_values() > 1);
+            match defaults.as_slice() {
+                [] => out.push_str(&format!("# {} =\n", id)),
+                [value] if !multiple => out.push_str(&format!("{} = {}\n", id, value)),
+                values => out.push_str(&format!("{} = [{}]\n", id, values.join(", "))),
+            }
+        }
+    }
+    out
+}
@@ -1,5 +1,7 @@
 // 使用时需要引用模块路径
+use crate::bloom::{bloom_key, BloomFilter};
 use crate::compact_hash::{Compact, HashConfig, Slot};
+use crate::Kr2Result;
 // use crate::mmscanner::MinimizerScanner;
 use crate::taxonomy::{NCBITaxonomy, Taxonomy};
 use seqkmer::Meros;
@@ -8,7 +10,7 @@ use crate::utils::open_file;
 use byteorder::{LittleEndian, WriteBytesExt};
 use rayon::prelude::*;
 use seqkmer::{read_parallel, FastaReader};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Result as IOResult, Write};
 use std::path::{Path, PathBuf};
@@ -87,7 +89,7 @@ pub fn write_config_to_file(
     size: u64,
     key_bits: u64,
     value_bits: u64,
-) -> IOResult<()> {
+) -> Kr2Result<()> {
     // 打开文件用于写入
     let file = File::create(file_path)?;
     let mut writer = BufWriter::new(file);
@@ -101,6 +103,45 @@ pub fn write_config_to_file(
     Ok(())
 }
 
+/// 按批读取 chunk 文件中的格子
+fn for_each_cell_batch<F>(chunk_file: &PathBuf, mut f: F) -> IOResult<()>
+where
+    F: FnMut(&[Slot<u32>]),
+{
+    let file = open_file(chunk_file)?;
+    let mut reader = BufReader::new(file);
+
+    let cell_size = std::mem::size_of::<Slot<u32>>();
+    let batch_buffer_size = cell_size * BATCH_SIZE;
+    let mut batch_buffer = vec![0u8; batch_buffer_size];
+
+    while let Ok(bytes_read) = reader.read(&mut batch_buffer) {
+        if bytes_read == 0 {
+            break;
+        } // 文件末尾
+
+        // 处理读取的数据批次
+        let cells_in_batch = bytes_read / cell_size;
+
+        let cells = unsafe {
+            std::slice::from_raw_parts(batch_buffer.as_ptr() as *const Slot<u32>, cells_in_batch)
+        };
+        f(cells);
+    }
+    Ok(())
+}
+
+/// --min-kmer-count 过滤的统计, 按 (minimizer, taxid) 去重计数
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KmerFilterStats {
+    pub total: usize,
+    pub removed: usize,
+}
+
+/// 把 chunk 文件写入第 page_index 个哈希表分页, 返回写入的非空格子数、分页中出现的内部 taxid 和过滤统计
+/// min_kmer_count > 1 时先统计每个 (minimizer, taxid) 在该 taxid 的序列中出现的次数, 次数不足的不写入哈希表
+/// bloom 不为空时把写入的 minimizer 同时加入 Bloom filter
+#[allow(clippy::too_many_arguments)]
 pub fn process_k2file(
     config: HashConfig,
     database: &PathBuf,
@@ -108,7 +149,9 @@ pub fn process_k2file(
     taxonomy: &Taxonomy,
     page_size: usize,
     page_index: usize,
-) -> IOResult<usize> {
+    min_kmer_count: usize,
+    bloom: Option<&BloomFilter>,
+) -> Kr2Result<(usize, HashSet<u32>, KmerFilterStats)> {
     let total_counter = AtomicUsize::new(0);
 
     let value_mask = config.value_mask;
@@ -122,47 +165,70 @@ pub fn process_k2file(
 
     let page: Vec<AtomicU32> = (0..capacity).map(|_| AtomicU32::new(0)).collect();
 
-    let file = open_file(&chunk_file)?;
-    let mut reader = BufReader::new(file);
-
-    let cell_size = std::mem::size_of::<Slot<u32>>();
-    let batch_buffer_size = cell_size * BATCH_SIZE;
-    let mut batch_buffer = vec![0u8; batch_buffer_size];
-
-    while let Ok(bytes_read) = reader.read(&mut batch_buffer) {
-        if bytes_read == 0 {
-            break;
-        } // 文件末尾
-
-        // 处理读取的数据批次
-        let cells_in_batch = bytes_read / cell_size;
+    // 同一分页的格子都在同一个 chunk 文件中, 格子的 idx 和 value 一起确定了 minimizer 和 taxid
+    let mut kmer_counts: HashMap<(usize, u32), usize> = HashMap::new();
+    if min_kmer_count > 1 {
+        for_each_cell_batch(chunk_file, |cells| {
+            for item in cells {
+                *kmer_counts.entry((item.idx, item.value)).or_insert(0) += 1;
+            }
+        })?;
+    }
+    let keep = |item: &Slot<u32>| {
+        min_kmer_count <= 1 || kmer_counts[&(item.idx, item.value)] >= min_kmer_count
+    };
 
-        let cells = unsafe {
-            std::slice::from_raw_parts(batch_buffer.as_ptr() as *const Slot<u32>, cells_in_batch)
-        };
-        cells.par_iter().for_each(|item| {
+    for_each_cell_batch(chunk_file, |cells| {
+        cells.par_iter().filter(|item| keep(item)).for_each(|item| {
             set_page_cell(taxonomy, &page, item, capacity, value_bits, value_mask);
+            if let Some(bloom) = bloom {
+                let compacted = item.value.left(value_bits);
+                bloom.insert(bloom_key(start_index + item.idx, compacted));
+            }
         });
         total_counter.fetch_add(cells.len(), Ordering::SeqCst);
-    }
+    })?;
+
+    let stats = KmerFilterStats {
+        total: kmer_counts.len(),
+        removed: kmer_counts
+            .values()
+            .filter(|&&count| count < min_kmer_count)
+            .count(),
+    };
 
     let size_count =
         write_hashtable_to_file(&page, &page_file, page_index as u64, capacity as u64)?;
-    Ok(size_count)
+    let taxids = page
+        .iter()
+        .map(|item| item.load(Ordering::Relaxed).right(value_mask).to_u32())
+        .filter(|&taxid| taxid != 0)
+        .collect();
+    Ok((size_count, taxids, stats))
 }
 
 /// 生成taxonomy树文件
 pub fn generate_taxonomy(
     ncbi_taxonomy_directory: &PathBuf,
     taxonomy_filename: &PathBuf,
-    id_map: &HashMap<String, u64>,
-) -> Result<Taxonomy, Box<dyn std::error::Error>> {
+    id_map: &mut HashMap<String, u64>,
+) -> Kr2Result<Taxonomy> {
     let nodes_filename = ncbi_taxonomy_directory.join("nodes.dmp");
     let names_filename = ncbi_taxonomy_directory.join("names.dmp");
+    let merged_filename = ncbi_taxonomy_directory.join("merged.dmp");
     let mut ncbi = NCBITaxonomy::from_ncbi(nodes_filename, names_filename)?;
+    let delnodes_filename = ncbi_taxonomy_directory.join("delnodes.dmp");
+    if merged_filename.exists() {
+        ncbi.load_merged(merged_filename)?;
+    }
+    if delnodes_filename.exists() {
+        ncbi.load_deleted(delnodes_filename)?;
+    }
 
-    for (_, id) in id_map.into_iter() {
+    // 已合并的旧 taxid 同时在 id_map 中替换为新 taxid, 使序列能找到对应的节点
+    for (_, id) in id_map.iter_mut() {
         ncbi.mark_node(*id);
+        *id = ncbi.current_taxid(*id);
     }
     let mut taxo = ncbi.convert_to_kraken_taxonomy();
     taxo.generate_external_to_internal_id_map();
@@ -265,8 +331,11 @@ pub fn convert_fna_to_k2_format<P: AsRef<Path>>(
             for record in seqs {
                 let header = &record.header;
                 record.body.apply_mut(|m_iter| {
-                    if let Some(ext_taxid) = id_to_taxon_map.get(&header.id) {
-                        let taxid = taxonomy.get_internal_id(*ext_taxid);
+                    // 不在分类树中的 taxid 没有对应的节点, 跳过这条序列
+                    if let Some(taxid) = id_to_taxon_map
+                        .get(&header.id)
+                        .and_then(|ext_taxid| taxonomy.get_internal_id_opt(*ext_taxid))
+                    {
                         let k2_cell: Vec<(usize, Slot<u32>)> = m_iter
                             .map(|(_, hash_key)| {
                                 let index: usize = hash_config.index(hash_key);
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// kr2r 的错误类型, 调用者可以按错误种类决定重试还是终止
+/// 可以通过 `?` 转换为 io::Error, 原始的 Kr2Error 保存在 io::Error 内部, 可用 get_ref 取回
+#[derive(Debug)]
+pub enum Kr2Error {
+    /// taxonomy 文件格式错误
+    MalformedTaxonomyFile {
+        path: PathBuf,
+        reason: String,
+    },
+    /// taxonomy 中不存在的外部 taxid
+    UnknownTaxId(u64),
+    /// 数据库文件的格式版本不受支持
+    DatabaseVersionMismatch {
+        found: u32,
+        expected: u32,
+    },
+    /// minimizer 不合法
+    InvalidMinimizer,
+    /// 序列 id 不在 seqid2taxid 映射中
+    SeqIdNotInMap(String),
+    Io(io::Error),
+}
+
+pub type Kr2Result<T> = std::result::Result<T, Kr2Error>;
+
+impl Kr2Error {
+    /// 对应的 io::ErrorKind, 与转换为 io::Error 时一致
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Kr2Error::Io(e) => e.kind(),
+            Kr2Error::UnknownTaxId(_) | Kr2Error::SeqIdNotInMap(_) => io::ErrorKind::NotFound,
+            _ => io::ErrorKind::InvalidData,
+        }
+    }
+
+    pub fn malformed_taxonomy<P: Into<PathBuf>, S: Into<String>>(path: P, reason: S) -> Self {
+        Kr2Error::MalformedTaxonomyFile {
+            path: path.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Kr2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kr2Error::MalformedTaxonomyFile { path, reason } => {
+                write!(f, "Malformed taxonomy file {:?}: {}", path, reason)
+            }
+            Kr2Error::UnknownTaxId(taxid) => write!(f, "taxid {} not found in taxonomy", taxid),
+            Kr2Error::DatabaseVersionMismatch { found, expected } => write!(
+                f,
+                "Unsupported database format version {} (supported up to {})",
+                found, expected
+            ),
+            Kr2Error::InvalidMinimizer => f.write_str("invalid minimizer"),
+            Kr2Error::SeqIdNotInMap(seq_id) => {
+                write!(f, "sequence id `{}` not found in seqid2taxid map", seq_id)
+            }
+            Kr2Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Kr2Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Kr2Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Kr2Error {
+    fn from(e: io::Error) -> Self {
+        Kr2Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Kr2Error {
+    fn from(e: serde_json::Error) -> Self {
+        Kr2Error::Io(e.into())
+    }
+}
+
+impl From<Kr2Error> for io::Error {
+    fn from(e: Kr2Error) -> Self {
+        match e {
+            Kr2Error::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
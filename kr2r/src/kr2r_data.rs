@@ -1,11 +1,12 @@
 use crate::compact_hash::Row;
 use crate::utils::open_file;
+use crate::Kr2Result;
 // use crate::{Meros, CURRENT_REVCOM_VERSION};
 use seqkmer::Meros;
 use seqkmer::OptionPair;
 use seqkmer::CURRENT_REVCOM_VERSION;
 use std::fs::File;
-use std::io::{Read, Result as IoResult, Write};
+use std::io::{Read, Write};
 use std::mem;
 use std::path::Path;
 
@@ -52,6 +53,22 @@ impl HitGroup {
     }
 }
 
+/// 一条 minimizer 命中记录, 用于导出每条 read 的命中明细
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KmerHitRecord {
+    pub read_id: String,
+    /// 哈希表中保存的 minimizer 压缩键
+    pub minimizer: u64,
+    /// 外部 taxid
+    pub taxid: u32,
+}
+
+impl std::fmt::Display for KmerHitRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\t{}\t{}", self.read_id, self.minimizer, self.taxid)
+    }
+}
+
 /// 顺序不能错
 #[repr(C)]
 #[derive(Debug)]
@@ -89,7 +106,7 @@ impl IndexOptions {
         }
     }
 
-    pub fn read_index_options<P: AsRef<Path>>(file_path: P) -> IoResult<Self> {
+    pub fn read_index_options<P: AsRef<Path>>(file_path: P) -> Kr2Result<Self> {
         let mut file = open_file(file_path)?;
         let mut buffer = vec![0; std::mem::size_of::<Self>()];
         file.read_exact(&mut buffer)?;
@@ -106,7 +123,7 @@ impl IndexOptions {
         Ok(idx_opts)
     }
 
-    pub fn write_to_file<P: AsRef<Path>>(&self, file_path: P) -> IoResult<()> {
+    pub fn write_to_file<P: AsRef<Path>>(&self, file_path: P) -> Kr2Result<()> {
         let mut file = File::create(file_path)?;
 
         // 将结构体转换为字节切片。这是不安全的操作，因为我们正在
@@ -0,0 +1,263 @@
+use crate::Kr2Result;
+use std::io::{BufRead, Error, ErrorKind, Result, Write};
+
+/// Krona 图中的一个节点, count 为子树的 read 数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KronaNode {
+    pub name: String,
+    pub taxid: u64,
+    pub rank: String,
+    pub count: u64,
+    pub children: Vec<KronaNode>,
+}
+
+impl KronaNode {
+    fn new(name: &str, taxid: u64, rank: &str, count: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            taxid,
+            rank: rank.to_string(),
+            count,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// 从 Kraken 2 风格报告构建 Krona 的分类树, 层级由学名前的缩进 (每层两个空格) 确定
+/// 未分类的 read 作为根节点下的 `Unclassified` 节点, 兼容带 -K 的 minimizer 列
+pub fn parse_kraken_report<R: BufRead>(reader: R) -> Kr2Result<KronaNode> {
+    let mut root = KronaNode::new("Root", 0, "no rank", 0);
+    let mut unclassified = 0;
+    // 当前路径上每一层的节点, stack[0] 为报告中的第一个根节点
+    let mut stack: Vec<KronaNode> = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 6 {
+            continue;
+        }
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid report line `{}`", line),
+            )
+        };
+        let count: u64 = fields[1].trim().parse().map_err(|_| invalid())?;
+        let taxid: u64 = fields[fields.len() - 2]
+            .trim()
+            .parse()
+            .map_err(|_| invalid())?;
+        let rank = fields[fields.len() - 3].trim();
+        let raw_name = fields[fields.len() - 1];
+        let name = raw_name.trim_start_matches(' ');
+        let depth = (raw_name.len() - name.len()) / 2;
+
+        if taxid == 0 && rank == "U" {
+            unclassified += count;
+            continue;
+        }
+        // 把比当前深度更深的节点挂到它们的父节点上
+        while stack.len() > depth {
+            let node = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => root.children.push(node),
+            }
+        }
+        if stack.len() < depth {
+            return Err(invalid().into());
+        }
+        stack.push(KronaNode::new(name, taxid, rank, count));
+    }
+    while let Some(node) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => root.children.push(node),
+        }
+    }
+
+    if unclassified > 0 {
+        root.children
+            .push(KronaNode::new("Unclassified", 0, "no rank", unclassified));
+    }
+    root.count = root.children.iter().map(|child| child.count).sum();
+    Ok(root)
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn write_node<W: Write>(writer: &mut W, node: &KronaNode, depth: usize) -> Result<()> {
+    let indent = "  ".repeat(depth);
+    writeln!(
+        writer,
+        "{}<node name=\"{}\">",
+        indent,
+        escape_xml(&node.name)
+    )?;
+    writeln!(
+        writer,
+        "{}  <magnitude><val>{}</val></magnitude>",
+        indent, node.count
+    )?;
+    writeln!(
+        writer,
+        "{}  <rank><val>{}</val></rank>",
+        indent,
+        escape_xml(&node.rank)
+    )?;
+    if node.taxid != 0 {
+        writeln!(
+            writer,
+            "{}  <taxid><val>{}</val></taxid>",
+            indent, node.taxid
+        )?;
+    }
+    for child in &node.children {
+        write_node(writer, child, depth + 1)?;
+    }
+    writeln!(writer, "{}</node>", indent)
+}
+
+/// 写出 Krona 格式的 XML 数据, 与 ktImportTaxonomy 生成的结构一致
+pub fn write_krona_xml<W: Write>(writer: &mut W, root: &KronaNode, dataset: &str) -> Kr2Result<()> {
+    writeln!(writer, "<krona collapse=\"true\" key=\"true\">")?;
+    writeln!(writer, "<attributes magnitude=\"magnitude\">")?;
+    writeln!(
+        writer,
+        "  <attribute display=\"Count\">magnitude</attribute>"
+    )?;
+    writeln!(writer, "  <attribute display=\"Rank\">rank</attribute>")?;
+    writeln!(writer, "  <attribute display=\"Tax ID\">taxid</attribute>")?;
+    writeln!(writer, "</attributes>")?;
+    writeln!(
+        writer,
+        "<datasets><dataset>{}</dataset></datasets>",
+        escape_xml(dataset)
+    )?;
+    write_node(writer, root, 0)?;
+    Ok(writeln!(writer, "</krona>")?)
+}
+
+/// 内嵌在 HTML 中的查看器, 读取页面中的 `<krona>` 数据并绘制可缩放的 sunburst 图, 不需要访问网络
+const KRONA_VIEWER_JS: &str = r#"(function () {
+  function parse(el) {
+    var node = { name: el.getAttribute('name'), count: 0, rank: '', taxid: '', children: [], parent: null };
+    for (var c = el.firstElementChild; c; c = c.nextElementSibling) {
+      var tag = c.tagName.toLowerCase();
+      if (tag === 'node') { var child = parse(c); child.parent = node; node.children.push(child); }
+      else if (tag === 'magnitude') node.count = Number(c.textContent.trim());
+      else if (tag === 'rank') node.rank = c.textContent.trim();
+      else if (tag === 'taxid') node.taxid = c.textContent.trim();
+    }
+    node.children.sort(function (a, b) { return b.count - a.count; });
+    return node;
+  }
+  var data = document.querySelector('krona');
+  var root = parse(data.querySelector('node'));
+  var total = root.count || 1;
+  var svg = document.getElementById('chart');
+  var info = document.getElementById('info');
+  var path = document.getElementById('path');
+  var NS = 'http://www.w3.org/2000/svg';
+  var size = 760, c = size / 2;
+
+  function depth(n) {
+    var d = 0;
+    n.children.forEach(function (child) { d = Math.max(d, depth(child)); });
+    return d + 1;
+  }
+  function point(r, a) { return (c + r * Math.sin(a)).toFixed(2) + ' ' + (c - r * Math.cos(a)).toFixed(2); }
+  function segment(r0, r1, a0, a1) {
+    if (a1 - a0 > 2 * Math.PI - 1e-4) a1 = a0 + 2 * Math.PI - 1e-4;
+    var large = a1 - a0 > Math.PI ? 1 : 0;
+    return 'M' + point(r1, a0) + 'A' + r1 + ' ' + r1 + ' 0 ' + large + ' 1 ' + point(r1, a1) +
+      'L' + point(r0, a1) + 'A' + r0 + ' ' + r0 + ' 0 ' + large + ' 0 ' + point(r0, a0) + 'Z';
+  }
+  function describe(n) {
+    var pct = (100 * n.count / total).toFixed(2);
+    return n.name + (n.rank ? ' (' + n.rank + ')' : '') + (n.taxid ? ', taxid ' + n.taxid : '') +
+      ': ' + n.count + ' reads, ' + pct + '%';
+  }
+  function lineage(n) {
+    var names = [];
+    for (var p = n; p; p = p.parent) names.unshift(p.name);
+    return names.join(' > ');
+  }
+  function draw(focus) {
+    while (svg.firstChild) svg.removeChild(svg.firstChild);
+    var ring = (c - 4) / depth(focus);
+    function layout(n, level, a0, a1) {
+      if (a1 - a0 < 0.002) return;
+      var el = document.createElementNS(NS, 'path');
+      el.setAttribute('d', segment(level === 0 ? 0 : level * ring, (level + 1) * ring, a0, a1));
+      var hue = ((a0 + a1) / 2 / (2 * Math.PI) * 360).toFixed(0);
+      el.setAttribute('fill', level === 0 ? '#eee' : 'hsl(' + hue + ',' + (70 - 5 * level) + '%,' + (45 + 6 * level) + '%)');
+      el.setAttribute('stroke', '#fff');
+      var title = document.createElementNS(NS, 'title');
+      title.textContent = describe(n);
+      el.appendChild(title);
+      el.addEventListener('mouseover', function () { info.textContent = describe(n); });
+      el.addEventListener('click', function () { draw(level === 0 && focus.parent ? focus.parent : n); });
+      svg.appendChild(el);
+      var a = a0;
+      n.children.forEach(function (child) {
+        var span = n.count ? (a1 - a0) * child.count / n.count : 0;
+        layout(child, level + 1, a, a + span);
+        a += span;
+      });
+    }
+    layout(focus, 0, 0, 2 * Math.PI);
+    path.textContent = lineage(focus);
+    info.textContent = describe(focus);
+  }
+  draw(root);
+})();
+"#;
+
+/// 写出可以直接在浏览器中打开的 Krona HTML, 数据与查看器都内嵌在文件中
+pub fn write_krona_html<W: Write>(
+    writer: &mut W,
+    root: &KronaNode,
+    dataset: &str,
+) -> Kr2Result<()> {
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html>")?;
+    writeln!(writer, "<head>")?;
+    writeln!(writer, "<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>{}</title>", escape_xml(dataset))?;
+    writeln!(
+        writer,
+        "<style>body{{font-family:sans-serif;margin:16px}}#path,#info{{margin:6px 0}}path{{cursor:pointer}}</style>"
+    )?;
+    writeln!(writer, "</head>")?;
+    writeln!(writer, "<body>")?;
+    writeln!(writer, "<h3>{}</h3>", escape_xml(dataset))?;
+    writeln!(writer, "<div id=\"path\"></div>")?;
+    writeln!(writer, "<div id=\"info\"></div>")?;
+    writeln!(
+        writer,
+        "<svg id=\"chart\" width=\"760\" height=\"760\" viewBox=\"0 0 760 760\"></svg>"
+    )?;
+    writeln!(writer, "<div style=\"display:none\">")?;
+    write_krona_xml(writer, root, dataset)?;
+    writeln!(writer, "</div>")?;
+    writeln!(writer, "<script>")?;
+    writer.write_all(KRONA_VIEWER_JS.as_bytes())?;
+    writeln!(writer, "</script>")?;
+    writeln!(writer, "</body>")?;
+    Ok(writeln!(writer, "</html>")?)
+}
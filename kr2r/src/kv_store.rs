@@ -101,7 +101,7 @@ impl Hasher for KHasher {
     }
 }
 
-#[derive(Default, Serialize, Deserialize, Debug)]
+#[derive(Default, Clone, Serialize, Deserialize, Debug)]
 pub struct KBuildHasher;
 
 impl BuildHasher for KBuildHasher {
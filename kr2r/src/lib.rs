@@ -1,15 +1,28 @@
+mod error;
 mod kr2r_data;
 mod kv_store;
+pub mod rank_split;
 pub mod readcounts;
 pub mod report;
+pub mod sam;
 pub mod taxonomy;
 pub mod utils;
 
 pub mod db;
+pub use error::{Kr2Error, Kr2Result};
 pub use kr2r_data::*;
 pub use kv_store::*;
 pub use readcounts::TaxonCounts;
 
 pub mod args;
+pub mod biom;
+pub mod bloom;
+pub mod bracken;
 pub mod classify;
 pub mod compact_hash;
+pub mod config;
+pub mod krona;
+pub mod manifest;
+pub mod metadata;
+pub mod multi_db;
+pub mod verify;
@@ -0,0 +1,258 @@
+use crate::utils::open_file;
+use crate::Kr2Result;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// 汇总表的文件名, 写在 --output-dir 下
+pub const MANIFEST_SUMMARY_FILE: &str = "manifest_summary.tsv";
+
+/// --manifest 中的一个样本, 单端样本没有 r2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestSample {
+    pub sample_id: String,
+    pub r1: PathBuf,
+    pub r2: Option<PathBuf>,
+}
+
+impl ManifestSample {
+    /// 传给 splitr 的输入文件, 与命令行上的顺序一致
+    pub fn input_files(&self) -> Vec<String> {
+        std::iter::once(&self.r1)
+            .chain(self.r2.as_ref())
+            .map(|path| path.to_string_lossy().to_string())
+            .collect()
+    }
+
+    /// 该样本的输出目录 `<output_dir>/<sample_id>`
+    pub fn output_dir(&self, output_dir: &Path) -> PathBuf {
+        output_dir.join(&self.sample_id)
+    }
+}
+
+/// 一个样本的 read 统计, 由 resolve 按样本顺序返回
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SampleStats {
+    pub sequences: usize,
+    pub classified: usize,
+    /// 被 --min-length/--max-length 跳过的 read, 不计入 sequences
+    pub filtered: usize,
+}
+
+impl SampleStats {
+    pub fn unclassified(&self) -> usize {
+        self.sequences - self.classified
+    }
+}
+
+/// sample_id 会用作目录名, 不能为空, 也不能包含路径分隔符
+fn validate_sample_id(sample_id: &str) -> std::result::Result<(), String> {
+    if sample_id.is_empty() || sample_id == "." || sample_id == ".." {
+        return Err(format!("invalid sample_id `{}`", sample_id));
+    }
+    if sample_id.contains(['/', '\\']) {
+        return Err(format!(
+            "sample_id `{}` must not contain a path separator",
+            sample_id
+        ));
+    }
+    Ok(())
+}
+
+/// 读取 `sample_id<TAB>r1_path[<TAB>r2_path]` 格式的样本清单
+/// 第一行为 `sample_id` 开头时视为表头, 空行和 `#` 开头的行被忽略
+/// 所有样本必须同为单端或同为双端, sample_id 不能重复
+pub fn read_manifest<P: AsRef<Path>>(path: P) -> Kr2Result<Vec<ManifestSample>> {
+    let path = path.as_ref();
+    let reader = BufReader::new(open_file(path)?);
+    let invalid = |line_no: usize, reason: String| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}:{}: {}", path.display(), line_no, reason),
+        )
+    };
+
+    let mut samples: Vec<ManifestSample> = Vec::new();
+    let mut seen = HashSet::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = i + 1;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        if samples.is_empty() && fields[0] == "sample_id" {
+            continue;
+        }
+        if !(2..=3).contains(&fields.len()) || fields[1].is_empty() {
+            return Err(invalid(
+                line_no,
+                format!("expected 2 or 3 tab-separated columns, got `{}`", line),
+            )
+            .into());
+        }
+        let sample_id = fields[0].to_string();
+        validate_sample_id(&sample_id).map_err(|reason| invalid(line_no, reason))?;
+        if !seen.insert(sample_id.clone()) {
+            return Err(invalid(line_no, format!("duplicate sample_id `{}`", sample_id)).into());
+        }
+        let r2 = fields
+            .get(2)
+            .filter(|field| !field.is_empty())
+            .map(PathBuf::from);
+        if let Some(first) = samples.first() {
+            if first.r2.is_some() != r2.is_some() {
+                return Err(invalid(
+                    line_no,
+                    "single-end and paired-end samples can not be mixed in one manifest"
+                        .to_string(),
+                )
+                .into());
+            }
+        }
+        samples.push(ManifestSample {
+            sample_id,
+            r1: PathBuf::from(fields[1]),
+            r2,
+        });
+    }
+
+    if samples.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: no samples in manifest", path.display()),
+        )
+        .into());
+    }
+    Ok(samples)
+}
+
+/// 以 TSV 格式写出每个样本的 read 统计
+pub fn write_manifest_summary<W: Write>(
+    writer: &mut W,
+    samples: &[ManifestSample],
+    stats: &[SampleStats],
+) -> Kr2Result<()> {
+    writeln!(
+        writer,
+        "sample_id\ttotal_reads\tclassified\tunclassified\tclassified_pct\tfiltered"
+    )?;
+    for (sample, stats) in samples.iter().zip(stats) {
+        let pct = if stats.sequences == 0 {
+            0.0
+        } else {
+            stats.classified as f64 * 100.0 / stats.sequences as f64
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{:.2}\t{}",
+            sample.sample_id,
+            stats.sequences,
+            stats.classified,
+            stats.unclassified(),
+            pct,
+            stats.filtered
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    fn manifest_error(name: &str, content: &str) -> String {
+        let path = test_dir(name).join("manifest.tsv");
+        std::fs::write(&path, content).unwrap();
+        let err = read_manifest(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        err.to_string()
+    }
+
+    #[test]
+    fn read_paired_manifest_with_header_and_comments() {
+        let path = test_dir("manifest_paired").join("manifest.tsv");
+        std::fs::write(
+            &path,
+            "sample_id\tr1_path\tr2_path\n# comment\n\nA\ta_1.fq\ta_2.fq\r\nB\tb_1.fq\tb_2.fq\n",
+        )
+        .unwrap();
+        let samples = read_manifest(&path).unwrap();
+        assert_eq!(
+            samples,
+            vec![
+                ManifestSample {
+                    sample_id: "A".to_string(),
+                    r1: PathBuf::from("a_1.fq"),
+                    r2: Some(PathBuf::from("a_2.fq")),
+                },
+                ManifestSample {
+                    sample_id: "B".to_string(),
+                    r1: PathBuf::from("b_1.fq"),
+                    r2: Some(PathBuf::from("b_2.fq")),
+                },
+            ]
+        );
+        assert_eq!(samples[0].input_files(), vec!["a_1.fq", "a_2.fq"]);
+        assert_eq!(samples[1].output_dir(Path::new("out")), Path::new("out/B"));
+    }
+
+    #[test]
+    fn read_single_end_manifest() {
+        let path = test_dir("manifest_single").join("manifest.tsv");
+        std::fs::write(&path, "A\ta.fq\nB\tb.fq\t\n").unwrap();
+        let samples = read_manifest(&path).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert!(samples.iter().all(|sample| sample.r2.is_none()));
+        assert_eq!(samples[1].input_files(), vec!["b.fq"]);
+    }
+
+    #[test]
+    fn invalid_manifests_are_rejected() {
+        assert!(manifest_error("manifest_dup", "A\ta.fq\nA\tb.fq\n").contains(":2: duplicate"));
+        assert!(
+            manifest_error("manifest_mixed", "A\ta_1.fq\ta_2.fq\nB\tb.fq\n")
+                .contains("can not be mixed")
+        );
+        assert!(manifest_error("manifest_sep", "a/b\ta.fq\n").contains("path separator"));
+        assert!(manifest_error("manifest_dots", "..\ta.fq\n").contains("invalid sample_id"));
+        assert!(manifest_error("manifest_columns", "A\n").contains("expected 2 or 3"));
+        assert!(
+            manifest_error("manifest_empty", "sample_id\tr1_path\n# none\n").contains("no samples")
+        );
+    }
+
+    #[test]
+    fn summary_has_filtered_column() {
+        let samples = [
+            ManifestSample {
+                sample_id: "A".to_string(),
+                r1: PathBuf::from("a.fq"),
+                r2: None,
+            },
+            ManifestSample {
+                sample_id: "B".to_string(),
+                r1: PathBuf::from("b.fq"),
+                r2: None,
+            },
+        ];
+        let stats = [
+            SampleStats {
+                sequences: 8,
+                classified: 6,
+                filtered: 2,
+            },
+            SampleStats::default(),
+        ];
+        let mut buf = Vec::new();
+        write_manifest_summary(&mut buf, &samples, &stats).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "sample_id\ttotal_reads\tclassified\tunclassified\tclassified_pct\tfiltered\n\
+             A\t8\t6\t2\t75.00\t2\n\
+             B\t0\t0\t0\t0.00\t0\n"
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use crate::manifest::SampleStats;
+use crate::utils::{format_utc, open_file, unix_secs};
+use crate::verify::{database_files, database_sha256, file_sha256};
+use crate::Kr2Result;
+use clap::{ArgAction, ArgMatches, Command};
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 没有指定 --metadata-output 时, 写在 --output-dir 下的元数据文件名
+pub const METADATA_FILE: &str = "metadata.json";
+
+/// 构建时由 build.rs 记录的 git commit
+pub const GIT_COMMIT: &str = env!("KR2R_GIT_COMMIT");
+
+/// 构建时间, ISO 8601 格式的 UTC 时间
+pub fn build_date() -> String {
+    format_utc(env!("KR2R_BUILD_TIMESTAMP").parse().unwrap_or(0))
+}
+
+/// CPU 型号, 只在 Linux 上从 /proc/cpuinfo 读取, 其他平台为 `unknown`
+pub fn cpu_name() -> String {
+    let model = open_file("/proc/cpuinfo").ok().and_then(|file| {
+        BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                (key.trim() == "model name").then(|| value.trim().to_string())
+            })
+    });
+    model.unwrap_or_else(|| "unknown".to_string())
+}
+
+/// 把参数值转换为 JSON, 布尔值和数字不加引号
+fn json_value(value: &str) -> Value {
+    if let Ok(b) = value.parse::<bool>() {
+        return Value::Bool(b);
+    }
+    if let Ok(i) = value.parse::<i64>() {
+        return Value::from(i);
+    }
+    if let Some(f) = value.parse::<f64>().ok().filter(|f| f.is_finite()) {
+        return Value::from(f);
+    }
+    Value::String(value.to_string())
+}
+
+/// subcommand 所有参数解析之后的值 (包括默认值), 键为参数的字段名, 可以出现多次的参数为数组
+pub fn arguments_json(cmd: &Command, matches: &ArgMatches) -> Value {
+    let mut arguments = Map::new();
+    for arg in cmd.get_arguments() {
+        let id = arg.get_id().as_str();
+        if id == "help" || id == "version" {
+            continue;
+        }
+        let values: Vec<Value> = match matches.try_get_raw(id) {
+            Ok(Some(values)) => values
+                .map(|value| json_value(&value.to_string_lossy()))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let multiple = matches!(arg.get_action(), ArgAction::Append)
+            || arg.get_num_args().is_some_and(|n| n.max_values() > 1);
+        let value = match values.len() {
+            _ if multiple => Value::Array(values),
+            0 => Value::Null,
+            _ => values.into_iter().next().unwrap(),
+        };
+        arguments.insert(id.to_string(), value);
+    }
+    Value::Object(arguments)
+}
+
+/// 输入文件或数据库的路径, files 的总大小和最后的修改时间, sha256 为 Some 时同时记录 SHA-256
+fn fingerprint(
+    path: &Path,
+    files: &[PathBuf],
+    sha256: Option<Kr2Result<String>>,
+) -> Kr2Result<Value> {
+    let (mut size, mut modified) = (0, 0);
+    for file in files {
+        let metadata = std::fs::metadata(file)?;
+        size += metadata.len();
+        modified = modified.max(unix_secs(metadata.modified()?));
+    }
+    let mut value = json!({
+        "path": path.display().to_string(),
+        "size": size,
+        "modified": format_utc(modified),
+    });
+    if let Some(sha256) = sha256 {
+        value["sha256"] = Value::String(sha256?);
+    }
+    Ok(value)
+}
+
+/// 一次 classify 运行的元数据, 记录复现分析所需的软件版本, 参数, 输入, 数据库和运行环境
+pub struct RunMetadata<'a> {
+    pub command_line: Vec<String>,
+    pub arguments: Value,
+    pub input_files: &'a [String],
+    pub databases: &'a [PathBuf],
+    pub taxonomy: PathBuf,
+    pub sample_stats: &'a [SampleStats],
+    pub started_at: String,
+    pub wall_clock: Duration,
+    /// 是否计算每个输入文件和数据库的 SHA-256, 需要重新读取全部文件
+    pub checksums: bool,
+}
+
+impl RunMetadata<'_> {
+    /// 生成 JSON, 输入文件和数据库记录大小和修改时间, checksums 为 true 时还会计算 SHA-256
+    pub fn to_json(&self) -> Kr2Result<Value> {
+        let inputs = self
+            .input_files
+            .iter()
+            .map(|path| {
+                let path = Path::new(path);
+                let sha256 = self.checksums.then(|| file_sha256(path));
+                fingerprint(path, &[path.to_path_buf()], sha256)
+            })
+            .collect::<Kr2Result<Vec<_>>>()?;
+        let databases = self
+            .databases
+            .iter()
+            .map(|path| {
+                let sha256 = self.checksums.then(|| database_sha256(path));
+                fingerprint(path, &database_files(path)?, sha256)
+            })
+            .collect::<Kr2Result<Vec<_>>>()?;
+        let total: usize = self.sample_stats.iter().map(|stats| stats.sequences).sum();
+        let classified: usize = self.sample_stats.iter().map(|stats| stats.classified).sum();
+        let filtered: usize = self.sample_stats.iter().map(|stats| stats.filtered).sum();
+
+        Ok(json!({
+            "software": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+                "git_commit": GIT_COMMIT,
+                "build_date": build_date(),
+            },
+            "command_line": self.command_line,
+            "arguments": self.arguments,
+            "inputs": inputs,
+            "databases": databases,
+            "taxonomy": self.taxonomy.display().to_string(),
+            "reads": {
+                "total": total,
+                "classified": classified,
+                "unclassified": total - classified,
+                "filtered": filtered,
+            },
+            "samples": self.sample_stats.iter().map(|stats| json!({
+                "total": stats.sequences,
+                "classified": stats.classified,
+                "unclassified": stats.unclassified(),
+                "filtered": stats.filtered,
+            })).collect::<Vec<_>>(),
+            "started_at": self.started_at,
+            "wall_clock_seconds": self.wall_clock.as_secs_f64(),
+            "platform": {
+                "os": std::env::consts::OS,
+                "arch": std::env::consts::ARCH,
+                "cpu": cpu_name(),
+            },
+        }))
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Kr2Result<()> {
+        serde_json::to_writer_pretty(&mut *writer, &self.to_json()?)?;
+        Ok(writeln!(writer)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn checksums_are_opt_in() {
+        let dir = test_dir("metadata_checksums");
+        let input = dir.join("reads.fa");
+        std::fs::write(&input, ">r1\nACGT\n").unwrap();
+        let db = dir.join("db");
+        std::fs::create_dir(&db).unwrap();
+        for name in ["taxo.k2d", "opts.k2d", "hash_config.k2d", "hash_1.k2d"] {
+            std::fs::write(db.join(name), "12345").unwrap();
+        }
+        let input_files = [input.to_string_lossy().to_string()];
+        let databases = [db.clone()];
+        let mut metadata = RunMetadata {
+            command_line: Vec::new(),
+            arguments: Value::Null,
+            input_files: &input_files,
+            databases: &databases,
+            taxonomy: db.join("taxo.k2d"),
+            sample_stats: &[],
+            started_at: String::new(),
+            wall_clock: Duration::ZERO,
+            checksums: false,
+        };
+
+        let json = metadata.to_json().unwrap();
+        assert_eq!(json["inputs"][0]["size"], 9);
+        assert_eq!(json["databases"][0]["size"], 20);
+        assert!(json["inputs"][0]["modified"].is_string());
+        assert!(json["inputs"][0].get("sha256").is_none());
+        assert!(json["databases"][0].get("sha256").is_none());
+
+        metadata.checksums = true;
+        let json = metadata.to_json().unwrap();
+        assert_eq!(json["inputs"][0]["sha256"], file_sha256(&input).unwrap());
+        assert_eq!(
+            json["databases"][0]["sha256"],
+            database_sha256(&db).unwrap()
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use crate::compact_hash::{Compact, HashConfig, Row};
+use crate::taxonomy::Taxonomy;
+use crate::utils::{find_and_sort_files, open_file};
+use crate::Kr2Result;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+
+const ROW_SIZE: usize = std::mem::size_of::<Row>();
+
+/// 每个排序段最多包含的命中数, 合并时的内存占用与段数而不是命中总数成正比
+const RUN_ROWS: usize = 1 << 22;
+
+/// 依次读取 annotate 输出的 sample_file_{i}.bin 或排序段中的命中
+struct RowReader {
+    reader: BufReader<File>,
+}
+
+impl RowReader {
+    fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(open_file(path)?),
+        })
+    }
+
+    /// 文件末尾不完整的命中被忽略
+    fn next_row(&mut self) -> Result<Option<Row>> {
+        let mut buf = [0u8; ROW_SIZE];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => {
+                let field =
+                    |i: usize| u32::from_ne_bytes(buf[i * 4..i * 4 + 4].try_into().unwrap());
+                let minimizer = u64::from_ne_bytes(buf[12..20].try_into().unwrap());
+                Ok(Some(Row::new(field(0), field(1), field(2), minimizer)))
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// 把 path 中的命中按 (seq_id, kmer_id) 排序, 每 run_rows 个写为一个排序段, 返回各段的路径
+fn write_sorted_runs(path: &Path, run_rows: usize) -> Result<Vec<PathBuf>> {
+    let mut reader = RowReader::open(path)?;
+    let mut runs = Vec::new();
+    let mut rows = Vec::new();
+    loop {
+        let row = reader.next_row()?;
+        rows.extend(row);
+        if rows.len() == run_rows || (row.is_none() && !rows.is_empty()) {
+            rows.sort_unstable_by_key(|row: &Row| (row.seq_id, row.kmer_id));
+            let run_path = path.with_extension(format!("run{}", runs.len()));
+            let mut writer = BufWriter::new(File::create(&run_path)?);
+            for row in &rows {
+                writer.write_all(row.as_slice(ROW_SIZE))?;
+            }
+            writer.flush()?;
+            runs.push(run_path);
+            rows.clear();
+        }
+        if row.is_none() {
+            return Ok(runs);
+        }
+    }
+}
+
+/// 同时使用多个数据库分类
+/// 每个数据库分别经过 splitr 和 annotate, 结果写在 chunk 目录下的 `db_{i}` 子目录中,
+/// 然后按 (seq_id, kmer_id) 归并所有数据库的命中, 多个数据库都命中同一个 minimizer 时取它们的 LCA
+pub struct MultiDatabaseClassifier {
+    pub databases: Vec<PathBuf>,
+    value_bits: usize,
+    value_mask: usize,
+}
+
+impl MultiDatabaseClassifier {
+    /// 所有数据库的 taxo.k2d 与 opts.k2d 必须完全相同, 这样才能共用分类树并得到相同的 minimizer
+    pub fn new(databases: Vec<PathBuf>) -> Kr2Result<Self> {
+        let Some(primary) = databases.first() else {
+            return Err(Error::new(ErrorKind::InvalidInput, "no database given").into());
+        };
+        let config = HashConfig::from_hash_header(primary.join("hash_config.k2d"))?;
+        for file_name in ["taxo.k2d", "opts.k2d"] {
+            let expected = fs::read(primary.join(file_name))?;
+            for database in &databases[1..] {
+                if fs::read(database.join(file_name))? != expected {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "{:?} differs from {:?}, all databases must share the same taxonomy and minimizer options",
+                            database.join(file_name),
+                            primary.join(file_name)
+                        ),
+                    ).into());
+                }
+            }
+        }
+        for database in &databases[1..] {
+            let other = HashConfig::from_hash_header(database.join("hash_config.k2d"))?;
+            if other.value_bits != config.value_bits {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "{:?} uses {} value bits, {:?} uses {}",
+                        database, other.value_bits, primary, config.value_bits
+                    ),
+                )
+                .into());
+            }
+        }
+        Ok(Self {
+            databases,
+            value_bits: config.value_bits,
+            value_mask: config.value_mask,
+        })
+    }
+
+    /// 第 index 个数据库的 chunk 子目录
+    pub fn chunk_dir(chunk_dir: &Path, index: usize) -> PathBuf {
+        chunk_dir.join(format!("db_{}", index))
+    }
+
+    /// 合并各数据库 chunk 子目录中 annotate 的结果, 写到 chunk_dir 中供 resolve 使用, 完成后删除子目录
+    /// 同时写出 `top_db_{i}.map`, 每行为 `seq_id\tdb`, db 为命中最多的数据库序号 (从 1 开始, 与 databases 的顺序一致)
+    pub fn merge_annotations(&self, chunk_dir: &Path, taxonomy: &Taxonomy) -> Kr2Result<()> {
+        let primary_dir = Self::chunk_dir(chunk_dir, 0);
+        for entry in fs::read_dir(&primary_dir)? {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            // seq_id 只与输入文件有关, 各数据库的 sample_id 映射都相同
+            if file_name == "sample_file.map"
+                || (file_name.starts_with("sample_id_") && file_name.ends_with(".map"))
+            {
+                fs::copy(&path, chunk_dir.join(file_name))?;
+            }
+        }
+
+        for sample_file in find_and_sort_files(&primary_dir, "sample_file", ".bin")? {
+            let file_name = sample_file
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            self.merge_sample(chunk_dir, &file_name, taxonomy, RUN_ROWS)?;
+        }
+
+        for index in 0..self.databases.len() {
+            fs::remove_dir_all(Self::chunk_dir(chunk_dir, index))?;
+        }
+        Ok(())
+    }
+
+    /// 合并各数据库中同一个样本的命中
+    /// 每个数据库的命中先排序为若干段, 再按 (seq_id, kmer_id) 多路归并, 同一个 seq_id 的命中是连续的
+    fn merge_sample(
+        &self,
+        chunk_dir: &Path,
+        file_name: &str,
+        taxonomy: &Taxonomy,
+        run_rows: usize,
+    ) -> Result<()> {
+        let databases = self.databases.len();
+        let mut readers = Vec::new();
+        for index in 0..databases {
+            let path = Self::chunk_dir(chunk_dir, index).join(file_name);
+            if !path.exists() {
+                continue;
+            }
+            for run in write_sorted_runs(&path, run_rows)? {
+                readers.push((RowReader::open(&run)?, index));
+            }
+        }
+
+        // 每个排序段当前的命中, 排序段按数据库的顺序排列, 同一个位置先取序号小的数据库
+        let mut heap = BinaryHeap::new();
+        for (i, (reader, _)) in readers.iter_mut().enumerate() {
+            if let Some(row) = reader.next_row()? {
+                heap.push(Reverse((
+                    row.seq_id,
+                    row.kmer_id,
+                    i,
+                    row.value,
+                    row.minimizer,
+                )));
+            }
+        }
+
+        let mut writer = BufWriter::new(File::create(chunk_dir.join(file_name))?);
+        let map_name = file_name
+            .replace("sample_file_", "top_db_")
+            .replace(".bin", ".map");
+        let mut top_writer = BufWriter::new(File::create(chunk_dir.join(map_name))?);
+        let mut write_row = |(seq_id, kmer_id, compacted, taxid, minimizer)| {
+            let row = Row::new(
+                u32::combined(compacted, taxid, self.value_bits),
+                seq_id,
+                kmer_id,
+                minimizer,
+            );
+            writer.write_all(row.as_slice(ROW_SIZE))
+        };
+        let mut write_top = |(seq_id, hits): (u32, Vec<usize>)| {
+            // 命中数相同时取序号较小的数据库
+            let top = (0..hits.len())
+                .max_by(|&a, &b| hits[a].cmp(&hits[b]).then(b.cmp(&a)))
+                .unwrap_or(0);
+            writeln!(top_writer, "{}\t{}", seq_id, top + 1)
+        };
+
+        // (seq_id, kmer_id, 压缩的 minimizer, taxid, minimizer 键)
+        // 各数据库的哈希表大小可以不同, 多个数据库命中同一个 minimizer 时使用序号最小的数据库的键
+        let mut current: Option<(u32, u32, u32, u32, u64)> = None;
+        // seq_id 和每个数据库的命中数
+        let mut db_hits: Option<(u32, Vec<usize>)> = None;
+        while let Some(Reverse((seq_id, kmer_id, i, value, minimizer))) = heap.pop() {
+            let (reader, index) = &mut readers[i];
+            let index = *index;
+            if let Some(row) = reader.next_row()? {
+                heap.push(Reverse((
+                    row.seq_id,
+                    row.kmer_id,
+                    i,
+                    row.value,
+                    row.minimizer,
+                )));
+            }
+
+            let taxid = value.right(self.value_mask);
+            match current.as_mut() {
+                Some(entry) if (entry.0, entry.1) == (seq_id, kmer_id) => {
+                    entry.3 = taxonomy.lca(entry.3, taxid);
+                }
+                _ => {
+                    let next = (
+                        seq_id,
+                        kmer_id,
+                        value.left(self.value_bits),
+                        taxid,
+                        minimizer,
+                    );
+                    if let Some(entry) = current.replace(next) {
+                        write_row(entry)?;
+                    }
+                }
+            }
+            match db_hits.as_mut() {
+                Some((id, hits)) if *id == seq_id => hits[index] += 1,
+                _ => {
+                    let mut hits = vec![0; databases];
+                    hits[index] += 1;
+                    if let Some(entry) = db_hits.replace((seq_id, hits)) {
+                        write_top(entry)?;
+                    }
+                }
+            }
+        }
+        if let Some(entry) = current {
+            write_row(entry)?;
+        }
+        if let Some(entry) = db_hits {
+            write_top(entry)?;
+        }
+        writer.flush()?;
+        top_writer.flush()
+    }
+}
+
+/// 读取 `top_db_{i}.map`, seq_id -> 命中最多的数据库序号
+pub fn read_top_db_map<P: AsRef<Path>>(path: P) -> Kr2Result<HashMap<u32, usize>> {
+    let mut content = String::new();
+    BufReader::new(open_file(path)?).read_to_string(&mut content)?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let (seq_id, db) = line.split_once('\t')?;
+            Some((seq_id.parse().ok()?, db.parse().ok()?))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxonomy::test_taxonomy;
+    use crate::utils::test_dir;
+
+    const VALUE_BITS: usize = 16;
+
+    /// minimizer 键与数据库有关, 为 db * 1000 + kmer_id
+    fn write_rows(path: &Path, db: u64, rows: &[(u32, u32, u32)]) {
+        let mut data = Vec::new();
+        for &(seq_id, kmer_id, taxid) in rows {
+            let value = u32::combined(kmer_id + 100, taxid, VALUE_BITS);
+            let minimizer = db * 1000 + kmer_id as u64;
+            data.extend_from_slice(Row::new(value, seq_id, kmer_id, minimizer).as_slice(ROW_SIZE));
+        }
+        fs::write(path, data).unwrap();
+    }
+
+    fn read_rows(path: &Path) -> Vec<(u32, u32, u32, u32, u64)> {
+        let mut reader = RowReader::open(path).unwrap();
+        let mut rows = Vec::new();
+        while let Some(row) = reader.next_row().unwrap() {
+            let compacted = row.value.left(VALUE_BITS);
+            let taxid = row.value.right((1 << VALUE_BITS) - 1);
+            rows.push((row.seq_id, row.kmer_id, compacted, taxid, row.minimizer));
+        }
+        rows
+    }
+
+    #[test]
+    fn merge_sample_streams_sorted_runs() {
+        // 1 ─┬─ 2 ─┬─ 4
+        //    │     └─ 5
+        //    └─ 3 ── 6
+        let taxonomy = test_taxonomy(&[(0, 1), (1, 2), (1, 3), (2, 4), (2, 5), (3, 6)]);
+        let dir = test_dir("multi_db_merge");
+        let classifier = MultiDatabaseClassifier {
+            databases: vec![PathBuf::from("a"), PathBuf::from("b")],
+            value_bits: VALUE_BITS,
+            value_mask: (1 << VALUE_BITS) - 1,
+        };
+        for index in 0..2 {
+            fs::create_dir_all(MultiDatabaseClassifier::chunk_dir(&dir, index)).unwrap();
+        }
+        let file_name = "sample_file_1.bin";
+        write_rows(
+            &MultiDatabaseClassifier::chunk_dir(&dir, 0).join(file_name),
+            0,
+            &[(2, 0, 4), (1, 1, 5), (1, 0, 4)],
+        );
+        write_rows(
+            &MultiDatabaseClassifier::chunk_dir(&dir, 1).join(file_name),
+            1,
+            &[(2, 2, 3), (1, 0, 5), (2, 1, 6)],
+        );
+
+        // 每段 2 个命中, 每个数据库各有 2 段; 两个数据库都命中时使用第一个数据库的 minimizer 键
+        classifier
+            .merge_sample(&dir, file_name, &taxonomy, 2)
+            .unwrap();
+        assert_eq!(
+            read_rows(&dir.join(file_name)),
+            [
+                (1, 0, 100, 2, 0),
+                (1, 1, 101, 5, 1),
+                (2, 0, 100, 4, 0),
+                (2, 1, 101, 6, 1001),
+                (2, 2, 102, 3, 1002),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("top_db_1.map")).unwrap(),
+            "1\t1\n2\t2\n"
+        );
+    }
+}
@@ -0,0 +1,560 @@
+use crate::classify::primary_call;
+use crate::taxonomy::Taxonomy;
+use crate::utils::open_file;
+use crate::Kr2Result;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+
+/// 未分类的 read 所在的分组
+pub const UNCLASSIFIED_GROUP: u32 = 0;
+/// 已分类, 但祖先中没有指定 rank 的 read 所在的分组
+pub const NO_RANK_GROUP: u32 = u32::MAX;
+
+/// 每个分组对应的输出文件路径 (不含扩展名), 分组键为指定 rank 上的内部 taxid
+/// 文件名为 `外部taxid_学名`, 另外包含 `unclassified` 和 `no_rank` 两个分组
+pub fn rank_output_paths(
+    output_dir: &Path,
+    rank_taxids: &[u32],
+    taxonomy: &Taxonomy,
+) -> HashMap<u32, PathBuf> {
+    let mut paths = HashMap::new();
+    for &taxid in rank_taxids {
+        let Some(node) = taxonomy.nodes.get(taxid as usize) else {
+            continue;
+        };
+        let name: String = taxonomy
+            .get_name(taxid)
+            .unwrap_or("")
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        paths.insert(
+            taxid,
+            output_dir.join(format!("{}_{}", node.external_id, name)),
+        );
+    }
+    paths.insert(UNCLASSIFIED_GROUP, output_dir.join("unclassified"));
+    paths.insert(NO_RANK_GROUP, output_dir.join("no_rank"));
+    paths
+}
+
+/// 读取 resolve 输出的 kraken 结果, 返回序列 id 到内部 taxid 的映射
+/// 同一条 read 有多行结果时取主要分类结果 (见 primary_call)
+pub fn read_kraken_calls<P: AsRef<Path>>(
+    path: P,
+    taxonomy: &Taxonomy,
+) -> Kr2Result<HashMap<String, u32>> {
+    let reader = BufReader::new(open_file(path)?);
+    let mut calls = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let mut fields = line.split('\t');
+        let (Some(_), Some(seq_id), Some(taxid)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let internal_id = taxid
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|taxid| taxonomy.get_internal_id_opt(taxid))
+            .unwrap_or(0);
+        calls
+            .entry(seq_id.to_string())
+            .and_modify(|call| *call = primary_call(taxonomy, &[*call, internal_id]))
+            .or_insert(internal_id);
+    }
+    Ok(calls)
+}
+
+/// read 所属的分组
+pub fn rank_group(
+    calls: &HashMap<String, u32>,
+    seq_id: &str,
+    taxonomy: &Taxonomy,
+    rank: &str,
+) -> u32 {
+    match calls.get(seq_id) {
+        None | Some(0) => UNCLASSIFIED_GROUP,
+        Some(&call) => taxonomy.node_at_rank(call, rank).unwrap_or(NO_RANK_GROUP),
+    }
+}
+
+/// 去掉 `>`/`@` 后的第一个字段, 并去掉双端序列的 /1 /2 后缀
+pub(crate) fn record_id(header: &[u8]) -> String {
+    let header = String::from_utf8_lossy(&header[1..]);
+    let id = header.split_whitespace().next().unwrap_or("");
+    seqkmer::trim_pair_info(id)
+}
+
+/// 读取下一条完整的记录 (含换行符), line 保存已经读入的下一条记录的首行, 返回记录和是否为 FASTQ
+pub(crate) fn next_record<R: BufRead + ?Sized>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+) -> Kr2Result<Option<(Vec<u8>, bool)>> {
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let fastq = line[0] == b'@';
+    let mut record = std::mem::take(line);
+    if fastq {
+        // FASTQ 记录固定为四行
+        for _ in 0..3 {
+            reader.read_until(b'\n', &mut record)?;
+        }
+        reader.read_until(b'\n', line)?;
+    } else {
+        loop {
+            reader.read_until(b'\n', line)?;
+            if line.is_empty() || line[0] == b'>' {
+                break;
+            }
+            record.append(line);
+        }
+    }
+    Ok(Some((record, fastq)))
+}
+
+/// --classified-out/--unclassified-out 的输出文件, 双端数据写入 `<path>_1.fastq` 和 `<path>_2.fastq`
+pub fn classified_output_paths(path: &Path, paired: bool) -> Vec<PathBuf> {
+    if paired {
+        (1..=2)
+            .map(|mate| PathBuf::from(format!("{}_{}.fastq", path.display(), mate)))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    }
+}
+
+/// 按分类结果把一个样本的 read 写入 classified 或 unclassified, 逐条读取逐条写出, 不把 read 保存在内存中
+/// header 末尾追加 ` kraken:taxid|<taxid>`, 其余内容原样复制
+/// writers 按 mate 排列, 为空时不写出对应的 read; interleaved 为 true 时单个文件中的记录依次属于 mate 1 和 mate 2
+pub fn split_sample_by_call<W: Write>(
+    input_files: &[String],
+    interleaved: bool,
+    compression: seqkmer::Compression,
+    calls: &HashMap<String, u32>,
+    taxonomy: &Taxonomy,
+    classified: &mut [W],
+    unclassified: &mut [W],
+) -> Kr2Result<()> {
+    for (file_index, input_file) in input_files.iter().enumerate() {
+        let mut reader = seqkmer::open_sequence_reader_with(input_file.as_ref(), compression)?;
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line)?;
+        let mut index = 0;
+        while let Some((record, _)) = next_record(&mut reader, &mut line)? {
+            let mate = if interleaved { index % 2 } else { file_index };
+            index += 1;
+
+            let header_end = record
+                .iter()
+                .position(|&b| b == b'\n')
+                .unwrap_or(record.len());
+            let header = &record[..header_end];
+            let call = calls.get(&record_id(header)).copied().unwrap_or(0);
+            let writers = if call == 0 {
+                &mut *unclassified
+            } else {
+                &mut *classified
+            };
+            let Some(writer) = writers.get_mut(mate) else {
+                continue;
+            };
+            let taxid = taxonomy
+                .nodes
+                .get(call as usize)
+                .map_or(0, |node| node.external_id);
+            writer.write_all(header.strip_suffix(b"\r").unwrap_or(header))?;
+            writeln!(writer, " kraken:taxid|{}", taxid)?;
+            if header_end < record.len() {
+                writer.write_all(&record[header_end + 1..])?;
+            }
+            if !record.ends_with(b"\n") {
+                writer.write_all(b"\n")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 把一个样本的输入文件按 read 的分组拆分写出, 记录原样复制, 保留 FASTQ 的质量值
+/// 双端数据的两个 mate 分别写入 `_1`, `_2` 后缀的文件, 扩展名按输入格式为 `.fa` 或 `.fq`
+/// interleaved 为 true 时单个文件中的记录依次属于 mate 1 和 mate 2
+pub fn split_sample_by_rank(
+    input_files: &[String],
+    interleaved: bool,
+    compression: seqkmer::Compression,
+    calls: &HashMap<String, u32>,
+    paths: &HashMap<u32, PathBuf>,
+    taxonomy: &Taxonomy,
+    rank: &str,
+) -> Kr2Result<()> {
+    let paired = interleaved || input_files.len() > 1;
+    let mut writers: HashMap<(u32, usize), BufWriter<File>> = HashMap::new();
+    for (file_index, input_file) in input_files.iter().enumerate() {
+        let mut reader = seqkmer::open_sequence_reader_with(input_file.as_ref(), compression)?;
+
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line)?;
+        let mut index = 0;
+        while let Some((record, fastq)) = next_record(&mut reader, &mut line)? {
+            let mate = if interleaved { index % 2 } else { file_index };
+            index += 1;
+
+            let header_end = record
+                .iter()
+                .position(|&b| b == b'\n')
+                .unwrap_or(record.len());
+            let group = rank_group(calls, &record_id(&record[..header_end]), taxonomy, rank);
+            let writer = match writers.entry((group, mate)) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    let stem = paths.get(&group).ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::NotFound,
+                            format!("no output path for taxid {}", group),
+                        )
+                    })?;
+                    let suffix = if paired {
+                        format!("_{}", mate + 1)
+                    } else {
+                        String::new()
+                    };
+                    let ext = if fastq { "fq" } else { "fa" };
+                    let filename = format!("{}{}.{}", stem.display(), suffix, ext);
+                    entry.insert(BufWriter::new(File::create(filename)?))
+                }
+            };
+            writer.write_all(&record)?;
+            if !record.ends_with(b"\n") {
+                writer.write_all(b"\n")?;
+            }
+        }
+    }
+
+    for writer in writers.values_mut() {
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// --split-by-taxon 的输出文件, 每个 taxon 一个 `<dir>/<taxid><suffix>.fastq`, FASTA 输入为 `.fasta`, 在第一次写入时打开
+/// 打开的文件数超过 max_open 时关闭最久没有写入的文件, 之后再写入时以追加方式重新打开
+pub struct TaxonWriters<'a> {
+    dir: PathBuf,
+    suffix: String,
+    taxonomy: &'a Taxonomy,
+    max_open: usize,
+    /// 键为 (内部 taxid, 是否为 FASTQ)
+    writers: HashMap<(u32, bool), (BufWriter<File>, u64)>,
+    created: HashSet<(u32, bool)>,
+    clock: u64,
+}
+
+impl<'a> TaxonWriters<'a> {
+    pub fn new(dir: &Path, suffix: &str, taxonomy: &'a Taxonomy, max_open: usize) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            suffix: suffix.to_string(),
+            taxonomy,
+            max_open: max_open.max(1),
+            writers: HashMap::new(),
+            created: HashSet::new(),
+            clock: 0,
+        }
+    }
+
+    /// 内部 taxid 对应的输出文件, 文件名使用外部 taxid, 扩展名与记录的格式一致
+    pub fn path(&self, taxid: u32, fastq: bool) -> PathBuf {
+        let external_id = self
+            .taxonomy
+            .nodes
+            .get(taxid as usize)
+            .map_or(0, |node| node.external_id);
+        let ext = if fastq { "fastq" } else { "fasta" };
+        self.dir
+            .join(format!("{}{}.{}", external_id, self.suffix, ext))
+    }
+
+    /// 至今写入过 read 的 taxon 数
+    pub fn taxon_count(&self) -> usize {
+        self.created
+            .iter()
+            .map(|&(taxid, _)| taxid)
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    fn writer(&mut self, taxid: u32, fastq: bool) -> Result<&mut BufWriter<File>> {
+        self.clock += 1;
+        let key = (taxid, fastq);
+        if !self.writers.contains_key(&key) {
+            if self.writers.len() >= self.max_open {
+                let oldest = self
+                    .writers
+                    .iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(&key, _)| key);
+                if let Some((mut writer, _)) = oldest.and_then(|key| self.writers.remove(&key)) {
+                    writer.flush()?;
+                }
+            }
+            let path = self.path(taxid, fastq);
+            // 本次运行第一次写入时清空旧文件, 被关闭后重新打开时追加
+            let file = if self.created.insert(key) {
+                File::create(path)?
+            } else {
+                OpenOptions::new().append(true).open(path)?
+            };
+            self.writers.insert(key, (BufWriter::new(file), 0));
+        }
+        let (writer, last_used) = self.writers.get_mut(&key).unwrap();
+        *last_used = self.clock;
+        Ok(writer)
+    }
+
+    /// 原样写出一条记录, 补全末尾的换行符, FASTQ 和 FASTA 记录写入不同的文件
+    pub fn write_record(&mut self, taxid: u32, record: &[u8], fastq: bool) -> Kr2Result<()> {
+        let writer = self.writer(taxid, fastq)?;
+        writer.write_all(record)?;
+        if !record.ends_with(b"\n") {
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Kr2Result<()> {
+        for (writer, _) in self.writers.values_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// --split-by-taxon 的输出, 双端数据的两个 mate 分别写入 `<taxid>_1.fastq` 和 `<taxid>_2.fastq` (FASTA 为 `.fasta`)
+/// max_open 为所有 mate 共用的打开文件数上限
+pub fn taxon_writers<'a>(
+    dir: &Path,
+    paired: bool,
+    taxonomy: &'a Taxonomy,
+    max_open: usize,
+) -> Vec<TaxonWriters<'a>> {
+    if paired {
+        (1..=2)
+            .map(|mate| TaxonWriters::new(dir, &format!("_{}", mate), taxonomy, max_open / 2))
+            .collect()
+    } else {
+        vec![TaxonWriters::new(dir, "", taxonomy, max_open)]
+    }
+}
+
+/// 把一个样本中已分类的 read 按分到的 taxon 写入各自的文件, 未分类的 read 不写出
+/// writers 按 mate 排列; interleaved 为 true 时单个文件中的记录依次属于 mate 1 和 mate 2
+pub fn split_sample_by_taxon(
+    input_files: &[String],
+    interleaved: bool,
+    compression: seqkmer::Compression,
+    calls: &HashMap<String, u32>,
+    writers: &mut [TaxonWriters],
+) -> Kr2Result<()> {
+    for (file_index, input_file) in input_files.iter().enumerate() {
+        let mut reader = seqkmer::open_sequence_reader_with(input_file.as_ref(), compression)?;
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line)?;
+        let mut index = 0;
+        while let Some((record, fastq)) = next_record(&mut reader, &mut line)? {
+            let mate = if interleaved { index % 2 } else { file_index };
+            index += 1;
+
+            let header_end = record
+                .iter()
+                .position(|&b| b == b'\n')
+                .unwrap_or(record.len());
+            let call = calls
+                .get(&record_id(&record[..header_end]))
+                .copied()
+                .unwrap_or(0);
+            if call == 0 {
+                continue;
+            }
+            if let Some(writer) = writers.get_mut(mate) {
+                writer.write_record(call, &record, fastq)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxonomy::test_taxonomy;
+    use crate::utils::test_dir;
+    use seqkmer::Compression;
+
+    /// 1 (no rank) ─┬─ 2 (genus) ─┬─ 4 (species)
+    ///              │             └─ 5 (species)
+    ///              └─ 3 (genus)
+    fn ranked_taxonomy() -> Taxonomy {
+        let mut taxo = test_taxonomy(&[(0, 1), (1, 10), (1, 20), (2, 100), (2, 101)]);
+        taxo.rank_data = b"no rank\0genus\0species\0".to_vec();
+        for (id, rank_offset) in [(1, 0), (2, 8), (3, 8), (4, 14), (5, 14)] {
+            taxo.nodes[id].rank_offset = rank_offset;
+        }
+        taxo
+    }
+
+    fn calls(pairs: &[(&str, u32)]) -> HashMap<String, u32> {
+        pairs
+            .iter()
+            .map(|&(seq_id, call)| (seq_id.to_string(), call))
+            .collect()
+    }
+
+    fn read(path: PathBuf) -> String {
+        std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("missing {}", path.display()))
+    }
+
+    #[test]
+    fn multi_line_reads_use_primary_call() {
+        let dir = test_dir("multi_line_calls");
+        let output = dir.join("output_1.txt");
+        std::fs::write(
+            &output,
+            "C\tr1\t100\t2\t100:1\nC\tr1\t101\t2\t101:1\nC\tr2\t20\t2\t20:1\nU\tr3\t0\t2\t0:1\n",
+        )
+        .unwrap();
+        let calls = read_kraken_calls(&output, &ranked_taxonomy()).unwrap();
+        assert_eq!(calls, self::calls(&[("r1", 2), ("r2", 3), ("r3", 0)]));
+    }
+
+    #[test]
+    fn split_interleaved_by_rank() {
+        let dir = test_dir("split_interleaved_by_rank");
+        let input = dir.join("reads.fq");
+        std::fs::write(
+            &input,
+            "@r1/1\nAC\n+\nII\n@r1/2\nGT\n+\nII\n@r2/1\nAA\n+\nII\n@r2/2\nTT\n+\nII\n",
+        )
+        .unwrap();
+        let taxonomy = ranked_taxonomy();
+        let calls = calls(&[("r1", 4), ("r2", 0)]);
+        let paths = rank_output_paths(&dir, &[2], &taxonomy);
+        let input_files = vec![input.to_string_lossy().to_string()];
+        split_sample_by_rank(
+            &input_files,
+            true,
+            Compression::Auto,
+            &calls,
+            &paths,
+            &taxonomy,
+            "genus",
+        )
+        .unwrap();
+
+        assert_eq!(read(dir.join("10__1.fq")), "@r1/1\nAC\n+\nII\n");
+        assert_eq!(read(dir.join("10__2.fq")), "@r1/2\nGT\n+\nII\n");
+        assert_eq!(read(dir.join("unclassified_1.fq")), "@r2/1\nAA\n+\nII\n");
+        assert_eq!(read(dir.join("unclassified_2.fq")), "@r2/2\nTT\n+\nII\n");
+    }
+
+    #[test]
+    fn split_compressed_input_by_rank() {
+        let dir = test_dir("split_compressed_by_rank");
+        // 扩展名不是 .zst, 由 --compression 指定格式
+        let input = dir.join("reads.fq.bin");
+        let reads = b"@r1\nAC\n+\nII\n@r2\nGT\n+\nII\n";
+        std::fs::write(&input, zstd::encode_all(&reads[..], 0).unwrap()).unwrap();
+        let taxonomy = ranked_taxonomy();
+        let calls = calls(&[("r1", 4), ("r2", 3)]);
+        let paths = rank_output_paths(&dir, &[2, 3], &taxonomy);
+        let input_files = vec![input.to_string_lossy().to_string()];
+        for compression in [Compression::Auto, Compression::Zstd] {
+            split_sample_by_rank(
+                &input_files,
+                false,
+                compression,
+                &calls,
+                &paths,
+                &taxonomy,
+                "genus",
+            )
+            .unwrap();
+            assert_eq!(read(dir.join("10_.fq")), "@r1\nAC\n+\nII\n");
+            assert_eq!(read(dir.join("20_.fq")), "@r2\nGT\n+\nII\n");
+        }
+    }
+
+    #[test]
+    fn split_single_end_by_rank() {
+        let dir = test_dir("split_single_end_by_rank");
+        let input = dir.join("reads.fa");
+        std::fs::write(&input, ">r1\nAC\nGT\n>r2\nAA\n>r3\nCC\n").unwrap();
+        let taxonomy = ranked_taxonomy();
+        let calls = calls(&[("r1", 5), ("r2", 1), ("r3", 3)]);
+        let paths = rank_output_paths(&dir, &[2, 3], &taxonomy);
+        let input_files = vec![input.to_string_lossy().to_string()];
+        split_sample_by_rank(
+            &input_files,
+            false,
+            Compression::Auto,
+            &calls,
+            &paths,
+            &taxonomy,
+            "genus",
+        )
+        .unwrap();
+
+        assert_eq!(read(dir.join("10_.fa")), ">r1\nAC\nGT\n");
+        assert_eq!(read(dir.join("20_.fa")), ">r3\nCC\n");
+        assert_eq!(read(dir.join("no_rank.fa")), ">r2\nAA\n");
+        assert!(!dir.join("unclassified.fa").exists());
+    }
+
+    #[test]
+    fn split_fasta_by_taxon() {
+        let dir = test_dir("split_fasta_by_taxon");
+        let input = dir.join("reads.fa");
+        std::fs::write(&input, ">r1\nAC\nGT\n>r2\nAA\n>r3\nCC\n>r4\nTT\n").unwrap();
+        let taxonomy = ranked_taxonomy();
+        let calls = calls(&[("r1", 4), ("r2", 0), ("r3", 3), ("r4", 4)]);
+        let input_files = vec![input.to_string_lossy().to_string()];
+        // 只允许打开一个文件, r4 写入时重新以追加方式打开
+        let mut writers = taxon_writers(&dir, false, &taxonomy, 1);
+        split_sample_by_taxon(&input_files, false, Compression::Auto, &calls, &mut writers)
+            .unwrap();
+        writers[0].flush().unwrap();
+
+        assert_eq!(writers[0].taxon_count(), 2);
+        assert_eq!(read(dir.join("100.fasta")), ">r1\nAC\nGT\n>r4\nTT\n");
+        assert_eq!(read(dir.join("20.fasta")), ">r3\nCC\n");
+        assert!(!dir.join("100.fastq").exists());
+        assert!(!dir.join("0.fasta").exists());
+    }
+
+    #[test]
+    fn split_interleaved_by_taxon() {
+        let dir = test_dir("split_interleaved_by_taxon");
+        let input = dir.join("reads.fq");
+        std::fs::write(
+            &input,
+            "@r1/1\nAC\n+\nII\n@r1/2\nGT\n+\nII\n@r2/1\nAA\n+\nII\n@r2/2\nTT\n+\nII\n",
+        )
+        .unwrap();
+        let taxonomy = ranked_taxonomy();
+        let calls = calls(&[("r1", 2), ("r2", 0)]);
+        let input_files = vec![input.to_string_lossy().to_string()];
+        let mut writers = taxon_writers(&dir, true, &taxonomy, 16);
+        split_sample_by_taxon(&input_files, true, Compression::Auto, &calls, &mut writers).unwrap();
+        for writer in writers.iter_mut() {
+            writer.flush().unwrap();
+        }
+
+        assert_eq!(read(dir.join("10_1.fastq")), "@r1/1\nAC\n+\nII\n");
+        assert_eq!(read(dir.join("10_2.fastq")), "@r1/2\nGT\n+\nII\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::KBuildHasher;
+use crate::{fmix64, KBuildHasher};
 use dashmap::DashMap;
 use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
 use std::collections::{HashMap, HashSet};
@@ -130,38 +130,183 @@ where
     }
 }
 
-// 定义 READCOUNTER 类型
-#[cfg(feature = "exact_counting")]
-pub type ReadCounter = ReadCounts<HashSet<u64>>;
+/// 默认的 HyperLogLog 精度, 误差约为 1.04 / sqrt(2^16)
+pub const DEFAULT_HLL_PRECISION: u8 = 16;
 
-#[cfg(feature = "exact_counting")]
-impl Default for ReadCounter {
+/// hyperloglogplus 支持的最大精度
+pub const MAX_HLL_PRECISION: u8 = 18;
+
+/// 满足误差要求的最小 HyperLogLog 精度, 受 hyperloglogplus 限制在 4..=MAX_HLL_PRECISION 之间
+/// 误差要求过小时精度被截断, 实际误差见 hll_error
+pub fn hll_precision(error_rate: f64) -> u8 {
+    ((1.04 / error_rate).powi(2).log2().ceil() as u8).clamp(4, MAX_HLL_PRECISION)
+}
+
+/// 精度为 precision 时 HyperLogLog 的相对标准误差
+pub fn hll_error(precision: u8) -> f64 {
+    1.04 / ((1u64 << precision) as f64).sqrt()
+}
+
+/// 误差要求无法满足时输出警告
+pub fn warn_if_hll_clamped(error_rate: f64, precision: u8) {
+    if hll_error(precision) > error_rate {
+        eprintln!(
+            "warning: error rate {} needs a HyperLogLog precision above the maximum {}, the actual error is about {:.4}",
+            error_rate,
+            MAX_HLL_PRECISION,
+            hll_error(precision)
+        );
+    }
+}
+
+/// 统计每个 taxon 的 distinct k-mer 的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KmerCounting {
+    /// 保存每个 k-mer, 内存随 distinct k-mer 数增长
+    Exact,
+    /// HyperLogLog++ 估计, 参数为精度
+    Approx(u8),
+}
+
+/// -K 的默认计数方式: 开启 exact_counting feature 时精确计数, 否则为默认精度的 HyperLogLog
+impl Default for KmerCounting {
     fn default() -> Self {
-        let kmers: HashSet<u64> = HashSet::new();
-        ReadCounts::new(kmers)
+        if cfg!(feature = "exact_counting") {
+            KmerCounting::Exact
+        } else {
+            KmerCounting::Approx(DEFAULT_HLL_PRECISION)
+        }
     }
 }
 
-#[cfg(not(feature = "exact_counting"))]
-pub type ReadCounter = ReadCounts<HyperLogLogPlus<u64, KBuildHasher>>;
+/// 一个 taxon 的 distinct k-mer 集合
+#[derive(Debug, Clone, Default)]
+pub enum KmerSet {
+    /// 还没有加入 k-mer, 与其他集合合并时采用对方的计数方式
+    #[default]
+    Empty,
+    Exact(HashSet<u64>),
+    Approx(HyperLogLogPlus<u64, KBuildHasher>),
+}
+
+impl KmerSet {
+    pub fn new(counting: KmerCounting) -> Self {
+        match counting {
+            KmerCounting::Exact => KmerSet::Exact(HashSet::new()),
+            KmerCounting::Approx(precision) => KmerSet::Approx(
+                HyperLogLogPlus::new(
+                    precision.clamp(4, MAX_HLL_PRECISION),
+                    KBuildHasher::default(),
+                )
+                .unwrap(),
+            ),
+        }
+    }
+}
+
+impl Unionable for KmerSet {
+    type Error = UnionError;
+
+    fn union(&mut self, other: &Self) -> Result<(), Self::Error> {
+        if let KmerSet::Empty = self {
+            *self = other.clone();
+            return Ok(());
+        }
+        match (self, other) {
+            (_, KmerSet::Empty) => Ok(()),
+            (KmerSet::Exact(kmers), KmerSet::Exact(other)) => Unionable::union(kmers, other),
+            (KmerSet::Approx(hllp), KmerSet::Approx(other)) => Unionable::union(hllp, other),
+            // 精确集合可以并入估计值, 反之不行
+            (KmerSet::Approx(hllp), KmerSet::Exact(other)) => {
+                other.iter().for_each(|&kmer| hllp.add_kmer(fmix64(kmer)));
+                Ok(())
+            }
+            (KmerSet::Exact(_), KmerSet::Approx(_)) => Err(UnionError),
+            (KmerSet::Empty, _) => unreachable!(),
+        }
+    }
+
+    fn distinct_count(&mut self) -> usize {
+        match self {
+            KmerSet::Empty => 0,
+            KmerSet::Exact(kmers) => kmers.distinct_count(),
+            KmerSet::Approx(hllp) => hllp.distinct_count(),
+        }
+    }
+
+    fn add_kmer(&mut self, kmer: u64) {
+        match self {
+            // 没有指定计数方式时按 -K 的默认方式计数
+            KmerSet::Empty => {
+                *self = KmerSet::new(KmerCounting::default());
+                self.add_kmer(kmer);
+            }
+            KmerSet::Exact(kmers) => kmers.add_kmer(kmer),
+            // KBuildHasher 直接使用输入作为 hash, 这里的 k-mer 是哈希表位置和压缩 key 组成的键, 需要先打散
+            KmerSet::Approx(hllp) => hllp.add_kmer(fmix64(kmer)),
+        }
+    }
+}
+
+// 定义 READCOUNTER 类型, 新建时不带 k-mer, 计数方式由第一次加入的 k-mer 或合并的计数决定
+pub type ReadCounter = ReadCounts<KmerSet>;
 
-#[cfg(not(feature = "exact_counting"))]
 impl Default for ReadCounter {
     fn default() -> Self {
-        let kmers: HyperLogLogPlus<u64, KBuildHasher> =
-            HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
-        ReadCounts::with_capacity(kmers, 0, 0)
+        ReadCounts::with_capacity(KmerSet::default(), 0, 0)
     }
 }
 
-#[cfg(not(feature = "exact_counting"))]
 impl ReadCounter {
     pub fn new(n_reads: u64, n_kmers: u64) -> Self {
-        let kmers: HyperLogLogPlus<u64, KBuildHasher> =
-            HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
-        ReadCounts::with_capacity(kmers, n_reads, n_kmers)
+        ReadCounts::with_capacity(KmerSet::default(), n_reads, n_kmers)
+    }
+
+    /// 按 counting 统计 distinct k-mer 的空计数
+    pub fn with_counting(counting: KmerCounting) -> Self {
+        ReadCounts::with_capacity(KmerSet::new(counting), 0, 0)
     }
 }
 
 pub type TaxonCounters = HashMap<u64, ReadCounter>;
 pub type TaxonCountersDash = DashMap<u64, ReadCounter>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hll_precision_meets_error_rate_until_clamped() {
+        let precision = hll_precision(0.005);
+        assert_eq!(precision, 16);
+        assert!(hll_error(precision) <= 0.005);
+        assert!(hll_error(precision - 1) > 0.005);
+
+        let precision = hll_precision(1e-4);
+        assert_eq!(precision, MAX_HLL_PRECISION);
+        assert!(hll_error(precision) > 1e-4);
+    }
+
+    #[test]
+    fn empty_counter_takes_the_merged_counting() {
+        let mut exact = ReadCounter::with_counting(KmerCounting::Exact);
+        (0..100).for_each(|kmer| exact.add_kmer(kmer));
+        let mut approx = ReadCounter::with_counting(KmerCounting::Approx(16));
+        (0..100).for_each(|kmer| approx.add_kmer(kmer));
+
+        let mut total = ReadCounter::default();
+        total.merge(&exact).unwrap();
+        assert!(matches!(total.kmers, KmerSet::Exact(_)));
+        assert_eq!(total.distinct_kmer_count(), 100);
+        // 精确计数不能并入估计值
+        assert!(total.merge(&approx).is_err());
+
+        let mut total = ReadCounter::default();
+        total.merge(&approx).unwrap();
+        total.merge(&exact).unwrap();
+        total.merge(&ReadCounter::default()).unwrap();
+        assert!(matches!(total.kmers, KmerSet::Approx(_)));
+        assert_eq!(total.distinct_kmer_count(), 100);
+        assert_eq!(ReadCounter::new(5, 0).distinct_kmer_count(), 0);
+    }
+}
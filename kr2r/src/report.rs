@@ -1,5 +1,6 @@
 use crate::readcounts::{ReadCounter, TaxonCounters};
 use crate::taxonomy::Taxonomy;
+use crate::Kr2Result;
 use std::collections::HashMap;
 
 use std::fs::File;
@@ -20,6 +21,15 @@ pub fn get_clade_counts(taxonomy: &Taxonomy, call_counts: &HashMap<u64, u64>) ->
     clade_counts
 }
 
+/// hit_counts 中落在 taxon 子树内 (包括 taxon 本身) 的命中数之和
+pub fn subtree_hits(taxonomy: &Taxonomy, hit_counts: &HashMap<u32, u64>, taxon: u32) -> u64 {
+    hit_counts
+        .iter()
+        .filter(|(&hit, _)| taxonomy.is_a_ancestor_of_b(taxon, hit))
+        .map(|(_, &count)| count)
+        .sum()
+}
+
 pub fn get_clade_counters(taxonomy: &Taxonomy, call_counters: &TaxonCounters) -> TaxonCounters {
     let mut clade_counters = TaxonCounters::new();
 
@@ -131,7 +141,7 @@ pub fn report_mpa_style<P: AsRef<Path>>(
     report_zeros: bool,
     taxonomy: &Taxonomy,
     call_counters: &HashMap<u64, ReadCounter>,
-) -> io::Result<()> {
+) -> Kr2Result<()> {
     let call_counts: HashMap<u64, u64> = call_counters
         .iter()
         .map(|(&taxid, counter)| (taxid, counter.read_count()))
@@ -149,7 +159,8 @@ pub fn report_mpa_style<P: AsRef<Path>>(
         taxonomy,
         &clade_counts,
         &mut taxonomy_names,
-    )
+    )?;
+    Ok(())
 }
 
 pub fn print_kraken_style_report_line(
@@ -162,7 +173,7 @@ pub fn print_kraken_style_report_line(
     taxid: u32,
     sci_name: &str,
     depth: usize,
-) -> io::Result<()> {
+) -> Kr2Result<()> {
     let pct = 100.0 * clade_counter.read_count() as f64 / total_seqs as f64;
     let pct_str = format!("{:6.2}", pct);
 
@@ -189,13 +200,14 @@ pub fn print_kraken_style_report_line(
         write!(file, "  ")?;
     }
 
-    writeln!(file, "{}", sci_name)
+    Ok(writeln!(file, "{}", sci_name)?)
 }
 
+/// clade 的 read 数小于 min_reads 的 taxon 及其子树不输出
 pub fn kraken_report_dfs(
     taxid: u64,
     file: &mut File,
-    report_zeros: bool,
+    min_reads: u64,
     report_kmer_data: bool,
     taxonomy: &Taxonomy,
     clade_counters: &mut HashMap<u64, ReadCounter>,
@@ -204,8 +216,8 @@ pub fn kraken_report_dfs(
     rank_code: char,
     rank_depth: i32,
     depth: usize,
-) -> io::Result<()> {
-    if !report_zeros && clade_counters.get(&taxid).map_or(0, |c| c.read_count()) == 0 {
+) -> Kr2Result<()> {
+    if clade_counters.get(&taxid).map_or(0, |c| c.read_count()) < min_reads {
         return Ok(());
     }
 
@@ -272,7 +284,7 @@ pub fn kraken_report_dfs(
         kraken_report_dfs(
             child_taxid,
             file,
-            report_zeros,
+            min_reads,
             report_kmer_data,
             taxonomy,
             clade_counters,
@@ -295,7 +307,33 @@ pub fn report_kraken_style<P: AsRef<Path>>(
     call_counters: &HashMap<u64, ReadCounter>,
     total_seqs: u64,
     total_unclassified: u64,
-) -> io::Result<()> {
+) -> Kr2Result<()> {
+    report_kraken_style_with_min_reads(
+        filename,
+        report_zeros,
+        report_kmer_data,
+        taxonomy,
+        call_counters,
+        total_seqs,
+        total_unclassified,
+        0,
+    )
+}
+
+/// 与 report_kraken_style 相同, 但省略 clade 的 read 数小于 min_reads 的 taxon, min_reads 为 0 时与 report_kraken_style 一致
+#[allow(clippy::too_many_arguments)]
+pub fn report_kraken_style_with_min_reads<P: AsRef<Path>>(
+    filename: P,
+    report_zeros: bool,
+    report_kmer_data: bool,
+    taxonomy: &Taxonomy,
+    call_counters: &HashMap<u64, ReadCounter>,
+    total_seqs: u64,
+    total_unclassified: u64,
+    min_reads: u64,
+) -> Kr2Result<()> {
+    // 不输出 0 计数的 taxon 时至少需要 1 条 read
+    let min_reads = min_reads.max(!report_zeros as u64);
     let mut clade_counters = get_clade_counters(taxonomy, call_counters);
 
     let mut file = File::create(filename)?;
@@ -321,7 +359,7 @@ pub fn report_kraken_style<P: AsRef<Path>>(
     kraken_report_dfs(
         1,
         &mut file,
-        report_zeros,
+        min_reads,
         report_kmer_data,
         taxonomy,
         &mut clade_counters,
@@ -332,3 +370,23 @@ pub fn report_kraken_style<P: AsRef<Path>>(
         0,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxonomy::test_taxonomy;
+
+    #[test]
+    fn subtree_hits_sums_descendants() {
+        // 1 ─┬─ 2 ─┬─ 4
+        //    │     └─ 5
+        //    └─ 3
+        let taxo = test_taxonomy(&[(0, 1), (1, 10), (1, 20), (2, 100), (2, 101)]);
+        let hit_counts = HashMap::from([(1, 1), (2, 2), (3, 4), (4, 8), (5, 16)]);
+        assert_eq!(subtree_hits(&taxo, &hit_counts, 1), 31);
+        assert_eq!(subtree_hits(&taxo, &hit_counts, 2), 26);
+        assert_eq!(subtree_hits(&taxo, &hit_counts, 4), 8);
+        assert_eq!(subtree_hits(&taxo, &hit_counts, 0), 0);
+        assert_eq!(subtree_hits(&taxo, &HashMap::new(), 1), 0);
+    }
+}
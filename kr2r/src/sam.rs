@@ -0,0 +1,246 @@
+use crate::classify::primary_call;
+use crate::rank_split::{next_record, record_id};
+use crate::report::subtree_hits;
+use crate::taxonomy::Taxonomy;
+use crate::utils::open_file;
+use crate::Kr2Result;
+use seqkmer::Compression;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+/// classify 的输出格式
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Kraken 2 风格的 output_{i}.txt
+    Kraken,
+    /// 另外写出 output_{i}.sam, 每条 read 一条未比对的 SAM 记录
+    Sam,
+}
+
+/// 一条 read 的分类结果, taxid 为外部 taxid, 未分类为 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamCall {
+    pub taxid: u64,
+    pub confidence: f64,
+}
+
+/// 按 hit 列表计算置信度: 命中分类结果子树的 k-mer 数 / 全部 k-mer 数, 与 --confidence-threshold 的含义一致
+fn call_confidence(call: u32, hit_list: &str, taxonomy: &Taxonomy) -> f64 {
+    let mut total = 0u64;
+    let mut hit_counts: HashMap<u32, u64> = HashMap::new();
+    for token in hit_list.split_whitespace() {
+        let Some((taxid, count)) = token.split_once(':') else {
+            continue;
+        };
+        let (Ok(taxid), Ok(count)) = (taxid.parse::<u64>(), count.parse::<u64>()) else {
+            continue;
+        };
+        total += count;
+        let hit = taxonomy.get_internal_id_opt(taxid).unwrap_or(0);
+        *hit_counts.entry(hit).or_default() += count;
+    }
+    let clade = subtree_hits(taxonomy, &hit_counts, call);
+    if total == 0 {
+        0.0
+    } else {
+        clade as f64 / total as f64
+    }
+}
+
+/// 读取 resolve 输出的 kraken 结果, 返回序列 id 到分类结果的映射
+/// 同一条 read 有多行结果时 (--ambiguous-taxid-policy report-all) 取主要分类结果 (见 primary_call)
+pub fn read_sam_calls<P: AsRef<Path>>(
+    path: P,
+    taxonomy: &Taxonomy,
+) -> Kr2Result<HashMap<String, SamCall>> {
+    let reader = BufReader::new(open_file(path)?);
+    let mut calls: HashMap<String, (u32, SamCall)> = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            continue;
+        }
+        let taxid = fields[2].trim().parse::<u64>().unwrap_or(0);
+        let mut call = taxonomy.get_internal_id_opt(taxid).unwrap_or(0);
+        if let Some((previous, _)) = calls.get(fields[1]) {
+            call = primary_call(taxonomy, &[*previous, call]);
+        }
+        let confidence = match fields.get(4) {
+            Some(hit_list) if call != 0 => call_confidence(call, hit_list, taxonomy),
+            _ => 0.0,
+        };
+        let taxid = taxonomy
+            .nodes
+            .get(call as usize)
+            .map_or(0, |node| node.external_id);
+        calls.insert(fields[1].to_string(), (call, SamCall { taxid, confidence }));
+    }
+    Ok(calls
+        .into_iter()
+        .map(|(seq_id, (_, call))| (seq_id, call))
+        .collect())
+}
+
+/// 写出 SAM 头, `@CO` 记录命令行和数据库路径
+pub fn write_sam_header<W: Write>(
+    writer: &mut W,
+    command_line: &str,
+    database: &Path,
+) -> Kr2Result<()> {
+    writeln!(writer, "@HD\tVN:1.6\tSO:unsorted")?;
+    writeln!(writer, "@CO\tcommand: {}", command_line)?;
+    writeln!(writer, "@CO\tdatabase: {}", database.display())?;
+    Ok(())
+}
+
+/// 把一条原始记录拆分为 (id, 序列, 质量值), FASTA 的多行序列会被拼接, 没有质量值时为 `*`
+fn sam_fields(record: &[u8], fastq: bool) -> (String, Vec<u8>, Vec<u8>) {
+    let mut lines = record
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let id = record_id(lines.next().unwrap_or(b">"));
+    if fastq {
+        let seq = lines.next().unwrap_or_default().to_vec();
+        let qual = lines.nth(1).unwrap_or_default().to_vec();
+        (id, seq, qual)
+    } else {
+        (id, lines.flatten().copied().collect(), b"*".to_vec())
+    }
+}
+
+/// SAM FLAG: 未分类为 0x4, 双端数据设置 0x1 和 0x40/0x80, 两条 mate 的分类结果相同, 未分类时同时设置 0x8
+fn sam_flag(classified: bool, mate: Option<usize>) -> u16 {
+    let mut flag = if classified { 0 } else { 0x4 };
+    if let Some(mate) = mate {
+        flag |= 0x1 | if mate == 0 { 0x40 } else { 0x80 };
+        if !classified {
+            flag |= 0x8;
+        }
+    }
+    flag
+}
+
+fn write_sam_record<W: Write>(
+    writer: &mut W,
+    record: &[u8],
+    fastq: bool,
+    mate: Option<usize>,
+    calls: &HashMap<String, SamCall>,
+) -> Result<()> {
+    let (id, seq, qual) = sam_fields(record, fastq);
+    // 没有 minimizer 的 read 不出现在 kraken 结果中, 按未分类处理
+    let call = calls.get(&id).copied().unwrap_or(SamCall {
+        taxid: 0,
+        confidence: 0.0,
+    });
+    let seq: &[u8] = if seq.is_empty() { b"*" } else { &seq };
+    let qual: &[u8] = if qual.is_empty() { b"*" } else { &qual };
+    write!(
+        writer,
+        "{}\t{}\t*\t0\t255\t*\t*\t0\t0\t",
+        id,
+        sam_flag(call.taxid != 0, mate)
+    )?;
+    writer.write_all(seq)?;
+    writer.write_all(b"\t")?;
+    writer.write_all(qual)?;
+    writeln!(writer, "\tXT:i:{}\tXC:f:{:.4}", call.taxid, call.confidence)
+}
+
+fn read_error(path: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "{}: paired input files have different numbers of reads",
+            path
+        ),
+    )
+}
+
+/// 把一个样本的 read 按 kraken 结果写为 SAM 记录
+/// input_files 为两个文件时按双端数据处理, interleaved 为 true 时单个文件中的记录两两成对
+pub fn write_sample_sam<W: Write>(
+    writer: &mut W,
+    input_files: &[String],
+    interleaved: bool,
+    compression: Compression,
+    calls: &HashMap<String, SamCall>,
+) -> Kr2Result<()> {
+    let mut readers = input_files
+        .iter()
+        .map(|path| {
+            let mut reader = seqkmer::open_sequence_reader_with(path.as_ref(), compression)?;
+            let mut line = Vec::new();
+            reader.read_until(b'\n', &mut line)?;
+            Ok((reader, line))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    match readers.as_mut_slice() {
+        [(reader, line)] => {
+            let mut index = 0;
+            while let Some((record, fastq)) = next_record(reader, line)? {
+                let mate = interleaved.then_some(index % 2);
+                write_sam_record(writer, &record, fastq, mate, calls)?;
+                index += 1;
+            }
+        }
+        [(reader1, line1), (reader2, line2)] => loop {
+            match (next_record(reader1, line1)?, next_record(reader2, line2)?) {
+                (Some((record1, fastq1)), Some((record2, fastq2))) => {
+                    write_sam_record(writer, &record1, fastq1, Some(0), calls)?;
+                    write_sam_record(writer, &record2, fastq2, Some(1), calls)?;
+                }
+                (None, None) => break,
+                (Some(_), None) => return Err(read_error(&input_files[1]).into()),
+                (None, Some(_)) => return Err(read_error(&input_files[0]).into()),
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "SAM output expects one input file or a pair of files per sample",
+            )
+            .into())
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxonomy::test_taxonomy;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn multi_line_reads_use_primary_call() {
+        // 1 ─┬─ 2 ─┬─ 4
+        //    │     └─ 5
+        //    └─ 3
+        let taxonomy = test_taxonomy(&[(0, 1), (1, 10), (1, 20), (2, 100), (2, 101)]);
+        let output = test_dir("sam_multi_line_calls").join("output_1.txt");
+        std::fs::write(
+            &output,
+            "C\tr1\t100\t4\t100:1 101:1 20:2\nC\tr1\t101\t4\t100:1 101:1 20:2\nU\tr2\t0\t4\t0:4\n",
+        )
+        .unwrap();
+        let calls = read_sam_calls(&output, &taxonomy).unwrap();
+        assert_eq!(
+            calls["r1"],
+            SamCall {
+                taxid: 10,
+                confidence: 0.5
+            }
+        );
+        assert_eq!(
+            calls["r2"],
+            SamCall {
+                taxid: 0,
+                confidence: 0.0
+            }
+        );
+    }
+}
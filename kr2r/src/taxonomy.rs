@@ -1,19 +1,26 @@
+use crate::error::{Kr2Error, Kr2Result};
 use crate::utils::open_file;
-use std::collections::{HashMap, HashSet, VecDeque};
+use byteorder::{ByteOrder, LittleEndian};
+use memmap2::Mmap;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
 use std::path::Path;
+use std::sync::OnceLock;
 
 /// 解析 ncbi 文件的 taxonomy nodes 文件
 pub fn parse_nodes_file<P: AsRef<Path>>(
     nodes_filename: P,
-) -> Result<(
+) -> Kr2Result<(
     HashMap<u64, u64>,
     HashMap<u64, HashSet<u64>>,
     HashMap<u64, String>,
     HashSet<String>,
 )> {
+    let nodes_filename = nodes_filename.as_ref();
     let nodes_file = open_file(nodes_filename)?;
     let reader = BufReader::new(nodes_file);
 
@@ -22,7 +29,7 @@ pub fn parse_nodes_file<P: AsRef<Path>>(
     let mut rank_map = HashMap::new();
     let mut known_ranks = HashSet::new();
 
-    for line in reader.lines() {
+    for (line_no, line) in reader.lines().enumerate() {
         let line = line?;
         if line.is_empty() || line.starts_with('#') {
             continue;
@@ -33,17 +40,21 @@ pub fn parse_nodes_file<P: AsRef<Path>>(
             continue;
         }
 
+        let invalid = |column: &str, value: &str| {
+            Kr2Error::malformed_taxonomy(
+                nodes_filename,
+                format!("line {}: invalid {} `{}`", line_no + 1, column, value),
+            )
+        };
         let node_id = fields[0]
             .parse::<u64>()
-            .map_err(|_| Error::new(ErrorKind::InvalidData, "node_id"))?;
+            .map_err(|_| invalid("node_id", fields[0]))?;
 
-        let parent_id = if node_id == 1 {
-            0
-        } else {
-            fields[1]
-                .parse::<u64>()
-                .map_err(|_| Error::new(ErrorKind::InvalidData, "parent_id"))?
-        };
+        // 父节点为自身的节点是根节点, 它的父节点记为 0
+        let parent_id = fields[1]
+            .parse::<u64>()
+            .map_err(|_| invalid("parent_id", fields[1]))?;
+        let parent_id = if parent_id == node_id { 0 } else { parent_id };
 
         let rank = fields[2].to_string();
 
@@ -60,7 +71,7 @@ pub fn parse_nodes_file<P: AsRef<Path>>(
 }
 
 /// 解析 ncbi 文件的 taxonomy names 文件
-pub fn parse_names_file<P: AsRef<Path>>(names_filename: P) -> Result<HashMap<u64, String>> {
+pub fn parse_names_file<P: AsRef<Path>>(names_filename: P) -> Kr2Result<HashMap<u64, String>> {
     let names_file = open_file(names_filename)?;
     let reader = BufReader::new(names_file);
 
@@ -92,8 +103,40 @@ pub fn parse_names_file<P: AsRef<Path>>(names_filename: P) -> Result<HashMap<u64
     Ok(name_map)
 }
 
+/// 两条路径的公共前缀长度, 每次比较 8 个元素并用掩码找到第一个不同的位置, 比较部分没有分支, 编译器可以向量化
+fn common_prefix_len(a: &[u32], b: &[u32]) -> usize {
+    const LANES: usize = 8;
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+    let mut prefix = 0;
+    for (chunk_a, chunk_b) in a.chunks_exact(LANES).zip(b.chunks_exact(LANES)) {
+        let mut mismatch = 0u32;
+        for lane in 0..LANES {
+            mismatch |= ((chunk_a[lane] != chunk_b[lane]) as u32) << lane;
+        }
+        if mismatch != 0 {
+            return prefix + mismatch.trailing_zeros() as usize;
+        }
+        prefix += LANES;
+    }
+    prefix
+        + a[prefix..]
+            .iter()
+            .zip(&b[prefix..])
+            .take_while(|(x, y)| x == y)
+            .count()
+}
+
+/// 从 offset 处读取以 '\0' 结尾的字符串
+fn str_at_offset(data: &[u8], offset: u64) -> Option<&str> {
+    let start = usize::try_from(offset).ok()?;
+    let tail = data.get(start..)?;
+    let end = tail.iter().position(|&c| c == b'\0')?;
+    std::str::from_utf8(&tail[..end]).ok()
+}
+
 /// 结构体定义
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TaxonomyNode {
     pub parent_id: u64,
     pub first_child: u64,
@@ -118,6 +161,100 @@ impl Default for TaxonomyNode {
     }
 }
 
+impl TaxonomyNode {
+    /// 序列化后每个节点占用的字节数
+    pub const SERIALIZED_SIZE: usize = 56;
+
+    /// 按小端序逐字段解析一个节点, buf 长度必须为 SERIALIZED_SIZE
+    pub fn from_le_bytes(buf: &[u8]) -> Self {
+        Self {
+            parent_id: LittleEndian::read_u64(&buf[0..8]),
+            first_child: LittleEndian::read_u64(&buf[8..16]),
+            child_count: LittleEndian::read_u64(&buf[16..24]),
+            name_offset: LittleEndian::read_u64(&buf[24..32]),
+            rank_offset: LittleEndian::read_u64(&buf[32..40]),
+            external_id: LittleEndian::read_u64(&buf[40..48]),
+            godparent_id: LittleEndian::read_u64(&buf[48..56]),
+        }
+    }
+}
+
+/// 自定义分隔文本 taxonomy 文件的列配置, 列号从 0 开始
+#[derive(Debug, Clone)]
+pub struct CustomTsvConfig {
+    pub taxid_col: usize,
+    pub parent_col: usize,
+    pub name_col: usize,
+    /// 为 None 时所有节点的 rank 都是 "no rank"
+    pub rank_col: Option<usize>,
+    pub separator: char,
+    /// 第一行是否为表头
+    pub has_header: bool,
+}
+
+impl Default for CustomTsvConfig {
+    fn default() -> Self {
+        Self {
+            taxid_col: 0,
+            parent_col: 1,
+            name_col: 2,
+            rank_col: Some(3),
+            separator: '\t',
+            has_header: false,
+        }
+    }
+}
+
+/// 解析 ncbi 文件的 taxonomy merged 文件, 返回旧 taxid 到新 taxid 的映射
+pub fn parse_merged_file<P: AsRef<Path>>(merged_filename: P) -> Kr2Result<HashMap<u64, u64>> {
+    let merged_file = open_file(merged_filename)?;
+    let reader = BufReader::new(merged_file);
+
+    let mut merged_ids = HashMap::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        // 忽略空行或注释行
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end_matches(['\t', '|', '\n']);
+        let fields: Vec<_> = line.split("\t|\t").collect();
+        if fields.len() < 2 {
+            continue;
+        }
+        let old_id = fields[0].parse::<u64>().unwrap_or(0);
+        let new_id = fields[1].parse::<u64>().unwrap_or(0);
+        if old_id != 0 && new_id != 0 {
+            merged_ids.insert(old_id, new_id);
+        }
+    }
+
+    Ok(merged_ids)
+}
+
+/// 解析 ncbi 文件的 taxonomy delnodes 文件, 返回已删除的 taxid
+pub fn parse_delnodes_file<P: AsRef<Path>>(delnodes_filename: P) -> Kr2Result<HashSet<u64>> {
+    let delnodes_file = open_file(delnodes_filename)?;
+    let reader = BufReader::new(delnodes_file);
+
+    let mut deleted_ids = HashSet::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        // 忽略空行或注释行
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end_matches(['\t', '|', '\n']);
+        if let Ok(taxid) = line.trim().parse::<u64>() {
+            deleted_ids.insert(taxid);
+        }
+    }
+
+    Ok(deleted_ids)
+}
+
 // NCBITaxonomy 类型定义
 pub struct NCBITaxonomy {
     parent_map: HashMap<u64, u64>,
@@ -126,17 +263,103 @@ pub struct NCBITaxonomy {
     child_map: HashMap<u64, HashSet<u64>>,
     marked_nodes: HashSet<u64>,
     known_ranks: HashSet<String>,
+    /// merged.dmp 中已合并的旧 taxid 到新 taxid 的映射
+    merged_ids: HashMap<u64, u64>,
+    /// mark_node 中被重定向过的旧 taxid
+    redirected_ids: BTreeMap<u64, u64>,
+    /// delnodes.dmp 中已删除的 taxid
+    deleted_ids: HashSet<u64>,
+    /// mark_node 中请求过的已删除 taxid
+    requested_deleted_ids: BTreeSet<u64>,
 }
 
 impl NCBITaxonomy {
     // 构造函数等实现
-    pub fn from_ncbi<P: AsRef<Path>>(nodes_filename: P, names_filename: P) -> Result<Self> {
-        let mut marked_nodes = HashSet::new();
+    pub fn from_ncbi<P: AsRef<Path>>(nodes_filename: P, names_filename: P) -> Kr2Result<Self> {
         let (parent_map, child_map, rank_map, known_ranks) = parse_nodes_file(nodes_filename)?;
 
         let name_map = parse_names_file(names_filename)?;
 
-        marked_nodes.insert(1); // 标记根节点
+        let mut ncbi = NCBITaxonomy {
+            parent_map,
+            name_map,
+            rank_map,
+            child_map,
+            known_ranks,
+            marked_nodes: HashSet::new(),
+            merged_ids: HashMap::new(),
+            redirected_ids: BTreeMap::new(),
+            deleted_ids: HashSet::new(),
+            requested_deleted_ids: BTreeSet::new(),
+        };
+        let root = ncbi.root_taxid();
+        ncbi.marked_nodes.insert(root); // 标记根节点
+        Ok(ncbi)
+    }
+
+    /// 解析 `taxonkit list -n -r` 的输出, 每行为 `taxid [rank] name`, 缩进深度表示树的层级
+    /// 顶层节点挂到根节点 1 下, 所有节点都会被标记
+    pub fn from_taxonkit_list<R: BufRead>(reader: R) -> Kr2Result<Self> {
+        let mut parent_map = HashMap::new();
+        let mut child_map: HashMap<u64, HashSet<u64>> = HashMap::new();
+        let mut name_map = HashMap::new();
+        let mut rank_map = HashMap::new();
+        let mut known_ranks = HashSet::new();
+
+        // (缩进宽度, taxid), 栈顶为当前行最近的祖先候选
+        let mut stack: Vec<(usize, u64)> = Vec::new();
+
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line?;
+            let content = line.trim_start();
+            if content.trim().is_empty() {
+                continue;
+            }
+            let indent = line.len() - content.len();
+
+            let (taxid, rest) = content
+                .split_once(char::is_whitespace)
+                .unwrap_or((content, ""));
+            let taxid = taxid.parse::<u64>().map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid taxid at line {}: {}", line_no + 1, line),
+                )
+            })?;
+
+            let rest = rest.trim();
+            let (rank, name) = match rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                Some((rank, name)) => (rank.trim(), name.trim()),
+                None => ("no rank", rest),
+            };
+
+            while stack.last().is_some_and(|&(depth, _)| depth >= indent) {
+                stack.pop();
+            }
+            let parent_id = match stack.last() {
+                Some(&(_, parent)) => parent,
+                None if taxid == 1 => 0,
+                None => 1,
+            };
+            stack.push((indent, taxid));
+
+            parent_map.insert(taxid, parent_id);
+            child_map.entry(parent_id).or_default().insert(taxid);
+            name_map.insert(taxid, name.to_string());
+            rank_map.insert(taxid, rank.to_string());
+            known_ranks.insert(rank.to_string());
+        }
+
+        // 输出中不含根节点时补充一个
+        if let std::collections::hash_map::Entry::Vacant(entry) = parent_map.entry(1) {
+            entry.insert(0);
+            child_map.entry(0).or_default().insert(1);
+            name_map.insert(1, "root".to_string());
+            rank_map.insert(1, "no rank".to_string());
+            known_ranks.insert("no rank".to_string());
+        }
+
+        let marked_nodes = parent_map.keys().copied().collect();
 
         Ok(NCBITaxonomy {
             parent_map,
@@ -145,11 +368,376 @@ impl NCBITaxonomy {
             child_map,
             known_ranks,
             marked_nodes,
+            merged_ids: HashMap::new(),
+            redirected_ids: BTreeMap::new(),
+            deleted_ids: HashSet::new(),
+            requested_deleted_ids: BTreeSet::new(),
         })
     }
 
-    pub fn mark_node(&mut self, taxid: u64) {
+    /// 按 config 指定的列解析分隔文本格式的 taxonomy, 所有节点都会被标记
+    /// parent 为 0 或等于自身的节点视为顶层节点, 顶层节点 (taxid 1 除外) 挂到根节点 1 下
+    pub fn from_custom_tsv<P: AsRef<Path>>(path: P, config: CustomTsvConfig) -> Kr2Result<Self> {
+        let reader = BufReader::new(open_file(&path)?);
+
+        let mut parent_map = HashMap::new();
+        let mut child_map: HashMap<u64, HashSet<u64>> = HashMap::new();
+        let mut name_map = HashMap::new();
+        let mut rank_map = HashMap::new();
+        let mut known_ranks = HashSet::new();
+
+        let skip = if config.has_header { 1 } else { 0 };
+        for (line_no, line) in reader.lines().enumerate().skip(skip) {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(config.separator).map(|f| f.trim()).collect();
+            let field = |col: usize| {
+                fields.get(col).copied().ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("missing column {} at line {}", col, line_no + 1),
+                    )
+                })
+            };
+            let parse_id = |col: usize| {
+                field(col)?.parse::<u64>().map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("invalid taxid in column {} at line {}", col, line_no + 1),
+                    )
+                })
+            };
+
+            let taxid = parse_id(config.taxid_col)?;
+            let parent = parse_id(config.parent_col)?;
+            let parent_id = match parent {
+                _ if taxid == 1 => 0,
+                0 => 1,
+                p if p == taxid => 1,
+                p => p,
+            };
+            let name = field(config.name_col)?.to_string();
+            let rank = match config.rank_col {
+                Some(col) => field(col)?.to_string(),
+                None => "no rank".to_string(),
+            };
+
+            parent_map.insert(taxid, parent_id);
+            child_map.entry(parent_id).or_default().insert(taxid);
+            name_map.insert(taxid, name);
+            known_ranks.insert(rank.clone());
+            rank_map.insert(taxid, rank);
+        }
+
+        // 文件中不含根节点时补充一个
+        if let std::collections::hash_map::Entry::Vacant(entry) = parent_map.entry(1) {
+            entry.insert(0);
+            child_map.entry(0).or_default().insert(1);
+            name_map.insert(1, "root".to_string());
+            rank_map.insert(1, "no rank".to_string());
+            known_ranks.insert("no rank".to_string());
+        }
+
+        let marked_nodes = parent_map.keys().copied().collect();
+
+        Ok(NCBITaxonomy {
+            parent_map,
+            name_map,
+            rank_map,
+            child_map,
+            known_ranks,
+            marked_nodes,
+            merged_ids: HashMap::new(),
+            redirected_ids: BTreeMap::new(),
+            deleted_ids: HashSet::new(),
+            requested_deleted_ids: BTreeSet::new(),
+        })
+    }
+
+    /// 解析带表头的四列 TSV 文件: taxid, parent_taxid, rank, name
+    /// taxid 等于 parent_taxid 或 parent_taxid 为 0 的节点是根节点, 必须恰好有一个且整个图是一棵树
+    /// 根节点的 taxid 不是 1 时在其上补充 taxid 为 1 的 root 节点
+    pub fn from_tsv<P: AsRef<Path>>(tsv_file: P) -> Kr2Result<NCBITaxonomy> {
+        const COLUMNS: [&str; 4] = ["taxid", "parent_taxid", "rank", "name"];
+        let reader = BufReader::new(open_file(&tsv_file)?);
+
+        let mut parent_map = HashMap::new();
+        let mut child_map: HashMap<u64, HashSet<u64>> = HashMap::new();
+        let mut name_map = HashMap::new();
+        let mut rank_map = HashMap::new();
+        let mut known_ranks = HashSet::new();
+        let mut roots = Vec::new();
+
+        for (line_no, line) in reader.lines().enumerate().skip(1) {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').map(|f| f.trim()).collect();
+            let field = |col: usize| {
+                fields.get(col).copied().ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("line {}: missing column `{}`", line_no + 1, COLUMNS[col]),
+                    )
+                })
+            };
+            let parse_id = |col: usize| {
+                let value = field(col)?;
+                value.parse::<u64>().map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "line {}: invalid `{}` value `{}`",
+                            line_no + 1,
+                            COLUMNS[col],
+                            value
+                        ),
+                    )
+                })
+            };
+
+            let taxid = parse_id(0)?;
+            let parent_id = parse_id(1)?;
+            if taxid == 0 || parent_map.contains_key(&taxid) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "line {}: invalid or duplicate `taxid` {}",
+                        line_no + 1,
+                        taxid
+                    ),
+                )
+                .into());
+            }
+            let rank = field(2)?.to_string();
+            let name = field(3)?.to_string();
+
+            if parent_id == 0 || parent_id == taxid {
+                roots.push(taxid);
+            } else {
+                child_map.entry(parent_id).or_default().insert(taxid);
+            }
+            parent_map.insert(taxid, parent_id);
+            name_map.insert(taxid, name);
+            known_ranks.insert(rank.clone());
+            rank_map.insert(taxid, rank);
+        }
+
+        let root = match roots[..] {
+            [root] => root,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("expected exactly one root node, found {}", roots.len()),
+                )
+                .into())
+            }
+        };
+        if let Some((taxid, parent_id)) = parent_map
+            .iter()
+            .find(|&(&taxid, parent_id)| taxid != root && !parent_map.contains_key(parent_id))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("parent {} of taxid {} not found", parent_id, taxid),
+            )
+            .into());
+        }
+        // 所有父节点都存在时, 从根节点无法到达的节点一定在环中
+        let mut reachable = 0;
+        let mut queue = VecDeque::from([root]);
+        while let Some(taxid) = queue.pop_front() {
+            reachable += 1;
+            queue.extend(child_map.get(&taxid).into_iter().flatten());
+        }
+        if reachable != parent_map.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "taxonomy is not a tree: {} nodes are part of a cycle",
+                    parent_map.len() - reachable
+                ),
+            )
+            .into());
+        }
+
+        if root == 1 {
+            parent_map.insert(1, 0);
+        } else if parent_map.contains_key(&1) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("taxid 1 is reserved for the root, but the root is {}", root),
+            )
+            .into());
+        } else {
+            parent_map.insert(root, 1);
+            child_map.entry(1).or_default().insert(root);
+            parent_map.insert(1, 0);
+            name_map.insert(1, "root".to_string());
+            rank_map.insert(1, "no rank".to_string());
+            known_ranks.insert("no rank".to_string());
+        }
+        child_map.entry(0).or_default().insert(1);
+
+        let marked_nodes = parent_map.keys().copied().collect();
+
+        Ok(NCBITaxonomy {
+            parent_map,
+            name_map,
+            rank_map,
+            child_map,
+            known_ranks,
+            marked_nodes,
+            merged_ids: HashMap::new(),
+            redirected_ids: BTreeMap::new(),
+            deleted_ids: HashSet::new(),
+            requested_deleted_ids: BTreeSet::new(),
+        })
+    }
+
+    /// 解析 GreenGenes 的 taxonomy 文件, 每行为 `OTU_ID\tk__Bacteria; p__Proteobacteria; ...`
+    /// 去掉 rank 前缀后按 lineage 建树, 从 2 开始依次分配 taxid, 名称为空的层级 (如 `g__`) 及其之后的部分被忽略
+    pub fn from_greengenes<P: AsRef<Path>>(path: P) -> Kr2Result<Self> {
+        Ok(Self::from_prefixed_lineages(path, greengenes_rank)?)
+    }
+
+    /// 解析 GTDB 的 taxonomy 文件, 每行为 `accession\td__Bacteria;p__Proteobacteria;...`
+    /// 与 from_greengenes 相同按 lineage 建树并分配合成 taxid, `d__` 对应 rank domain
+    pub fn from_gtdb<P: AsRef<Path>>(gtdb_taxonomy_file: P) -> Kr2Result<NCBITaxonomy> {
+        Ok(Self::from_prefixed_lineages(gtdb_taxonomy_file, gtdb_rank)?)
+    }
+
+    /// 解析第二列为 `x__name` 形式 lineage 的文件, rank_of 把前缀代码映射为 rank
+    fn from_prefixed_lineages<P: AsRef<Path>>(
+        path: P,
+        rank_of: fn(&str) -> &'static str,
+    ) -> Result<Self> {
+        let reader = BufReader::new(open_file(&path)?);
+
+        let mut parent_map = HashMap::from([(1, 0)]);
+        let mut child_map: HashMap<u64, HashSet<u64>> = HashMap::from([(0, HashSet::from([1]))]);
+        let mut name_map = HashMap::from([(1, "root".to_string())]);
+        let mut rank_map = HashMap::from([(1, "no rank".to_string())]);
+        let mut known_ranks = HashSet::from(["no rank".to_string()]);
+        // 以完整的 lineage 前缀区分同名节点
+        let mut lineage_ids: HashMap<String, u64> = HashMap::new();
+        let mut next_taxid = 2;
+
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (_, lineage) = line.split_once('\t').ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("missing lineage column at line {}", line_no + 1),
+                )
+            })?;
+
+            let mut parent_id = 1;
+            let mut prefix = String::new();
+            for level in lineage.split(';').map(|level| level.trim()) {
+                let (rank, name) = match level.split_once("__") {
+                    Some((code, name)) => (rank_of(code), name.trim()),
+                    None => ("no rank", level),
+                };
+                if name.is_empty() {
+                    break;
+                }
+
+                prefix.push_str(level);
+                prefix.push(';');
+                let taxid = *lineage_ids.entry(prefix.clone()).or_insert_with(|| {
+                    let taxid = next_taxid;
+                    next_taxid += 1;
+                    parent_map.insert(taxid, parent_id);
+                    child_map.entry(parent_id).or_default().insert(taxid);
+                    name_map.insert(taxid, name.to_string());
+                    rank_map.insert(taxid, rank.to_string());
+                    known_ranks.insert(rank.to_string());
+                    taxid
+                });
+                parent_id = taxid;
+            }
+        }
+
+        let marked_nodes = parent_map.keys().copied().collect();
+
+        Ok(NCBITaxonomy {
+            parent_map,
+            name_map,
+            rank_map,
+            child_map,
+            known_ranks,
+            marked_nodes,
+            merged_ids: HashMap::new(),
+            redirected_ids: BTreeMap::new(),
+            deleted_ids: HashSet::new(),
+            requested_deleted_ids: BTreeSet::new(),
+        })
+    }
+
+    /// 外部 taxid 对应的学名
+    pub fn get_name(&self, taxid: u64) -> Option<&str> {
+        self.name_map.get(&taxid).map(String::as_str)
+    }
+
+    /// 外部 taxid 对应的 rank
+    pub fn get_rank(&self, taxid: u64) -> Option<&str> {
+        self.rank_map.get(&taxid).map(String::as_str)
+    }
+
+    /// taxonomy 中是否存在该外部 taxid
+    pub fn contains_taxid(&self, taxid: u64) -> bool {
+        self.parent_map.contains_key(&taxid)
+    }
+
+    /// 加载 merged.dmp, 之后 mark_node 会把已合并的旧 taxid 重定向到新 taxid
+    pub fn load_merged<P: AsRef<Path>>(&mut self, merged_dmp: P) -> Kr2Result<()> {
+        self.merged_ids = parse_merged_file(merged_dmp)?;
+        Ok(())
+    }
+
+    /// taxid 当前对应的 taxid, 不在 parent_map 中但被合并过的 taxid 沿 merged_ids 重定向
+    pub fn current_taxid(&self, taxid: u64) -> u64 {
         let mut current_taxid = taxid;
+        // 限制步数, 避免 merged.dmp 中的环导致死循环
+        for _ in 0..self.merged_ids.len() {
+            if self.parent_map.contains_key(&current_taxid) {
+                break;
+            }
+            match self.merged_ids.get(&current_taxid) {
+                Some(&new_taxid) => current_taxid = new_taxid,
+                None => break,
+            }
+        }
+        current_taxid
+    }
+
+    /// 加载 delnodes.dmp, 之后 mark_node 遇到已删除的 taxid 会跳过并在转换时给出警告
+    pub fn load_deleted<P: AsRef<Path>>(&mut self, delnodes_dmp: P) -> Kr2Result<()> {
+        self.deleted_ids = parse_delnodes_file(delnodes_dmp)?;
+        Ok(())
+    }
+
+    /// mark_node 中请求过的已删除 taxid, 这些 taxid 对应的序列不会出现在数据库中
+    pub fn requested_deleted_ids(&self) -> &BTreeSet<u64> {
+        &self.requested_deleted_ids
+    }
+
+    pub fn mark_node(&mut self, taxid: u64) {
+        if !self.parent_map.contains_key(&taxid) && self.deleted_ids.contains(&taxid) {
+            self.requested_deleted_ids.insert(taxid);
+            return;
+        }
+        let mut current_taxid = self.current_taxid(taxid);
+        if current_taxid != taxid {
+            self.redirected_ids.insert(taxid, current_taxid);
+        }
         while !self.marked_nodes.contains(&current_taxid) {
             self.marked_nodes.insert(current_taxid);
             if let Some(&parent_id) = self.parent_map.get(&current_taxid) {
@@ -176,7 +764,42 @@ impl NCBITaxonomy {
         (rank_offsets, rank_data)
     }
 
+    /// 根节点的外部 taxid: parent_map 中父节点为 0 或自身的节点, 有多个时取最小的, 没有时为 1
+    pub fn root_taxid(&self) -> u64 {
+        self.parent_map
+            .iter()
+            .filter(|&(&taxid, &parent_id)| parent_id == 0 || parent_id == taxid)
+            .map(|(&taxid, _)| taxid)
+            .min()
+            .unwrap_or(1)
+    }
+
     pub fn convert_to_kraken_taxonomy(&self) -> Taxonomy {
+        if !self.requested_deleted_ids.is_empty() {
+            let deleted: Vec<String> = self
+                .requested_deleted_ids
+                .iter()
+                .map(|taxid| taxid.to_string())
+                .collect();
+            eprintln!(
+                "warning: {} deleted taxids skipped, their sequences will not be in the database: {}",
+                deleted.len(),
+                deleted.join(", ")
+            );
+        }
+        if !self.redirected_ids.is_empty() {
+            let redirected: Vec<String> = self
+                .redirected_ids
+                .iter()
+                .map(|(old_id, new_id)| format!("{} -> {}", old_id, new_id))
+                .collect();
+            eprintln!(
+                "warning: {} merged taxids redirected: {}",
+                redirected.len(),
+                redirected.join(", ")
+            );
+        }
+
         let mut taxo = Taxonomy::default();
         // 预分配内存
         taxo.nodes.reserve(self.marked_nodes.len() + 1);
@@ -185,11 +808,11 @@ impl NCBITaxonomy {
         let mut name_data = String::new();
         let (rank_offsets, rank_data) = self.get_rank_offset_data();
 
+        let root = self.root_taxid();
         let mut bfs_queue = VecDeque::new();
-        bfs_queue.push_back(1);
+        bfs_queue.push_back(root);
         let mut external_id_map = HashMap::new();
         external_id_map.insert(0, 0);
-        external_id_map.insert(1, 1);
         let mut internal_node_id = 0;
 
         while let Some(external_node_id) = bfs_queue.pop_front() {
@@ -197,10 +820,12 @@ impl NCBITaxonomy {
             external_id_map.insert(external_node_id, internal_node_id);
 
             let mut node = TaxonomyNode::default();
-            node.parent_id = external_id_map
-                .get(&self.parent_map[&external_node_id])
-                .unwrap()
-                .clone();
+            // 根节点的父节点为 0 号哨兵节点, 其余节点的父节点在 BFS 中已经出现过
+            node.parent_id = if external_node_id == root {
+                0
+            } else {
+                external_id_map[&self.parent_map[&external_node_id]]
+            };
             node.external_id = external_node_id;
             node.rank_offset = *rank_offsets.get(&self.rank_map[&external_node_id]).unwrap();
             node.name_offset = name_data.len() as u64;
@@ -232,24 +857,122 @@ impl NCBITaxonomy {
     }
 }
 
+/// taxonomy 文件格式版本, 由 write_to_disk_versioned 写入
+pub const TAXONOMY_VERSION: u32 = 1;
+
+/// PathCache 按节点 ID 分片的 HashMap 个数
+const PATH_CACHE_SHARDS: usize = 64;
+
+/// 每个节点从根节点开始的路径, 按节点 ID 分片保存在多个 HashMap 中, 构建时各分片可以并行插入
+/// 查询接口与 HashMap<u32, Vec<u32>> 相同
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathCache {
+    shards: Vec<HashMap<u32, Vec<u32>>>,
+}
+
+impl Default for PathCache {
+    fn default() -> Self {
+        Self {
+            shards: (0..PATH_CACHE_SHARDS).map(|_| HashMap::new()).collect(),
+        }
+    }
+}
+
+impl PathCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn shard_of(id: u32) -> usize {
+        id as usize % PATH_CACHE_SHARDS
+    }
+
+    pub fn get(&self, id: &u32) -> Option<&Vec<u32>> {
+        self.shards[Self::shard_of(*id)].get(id)
+    }
+
+    pub fn contains_key(&self, id: &u32) -> bool {
+        self.shards[Self::shard_of(*id)].contains_key(id)
+    }
+
+    pub fn insert(&mut self, id: u32, path: Vec<u32>) -> Option<Vec<u32>> {
+        self.shards[Self::shard_of(id)].insert(id, path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(HashMap::is_empty)
+    }
+
+    pub fn clear(&mut self) {
+        self.shards.iter_mut().for_each(HashMap::clear);
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &u32> {
+        self.iter().map(|(id, _)| id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u32, &Vec<u32>)> {
+        self.shards.iter().flatten()
+    }
+}
+
+impl std::ops::Index<&u32> for PathCache {
+    type Output = Vec<u32>;
+
+    fn index(&self, id: &u32) -> &Vec<u32> {
+        &self.shards[Self::shard_of(*id)][id]
+    }
+}
+
+impl<'a> IntoIterator for &'a PathCache {
+    type Item = (&'a u32, &'a Vec<u32>);
+    type IntoIter = std::iter::Flatten<std::slice::Iter<'a, HashMap<u32, Vec<u32>>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.shards.iter().flatten()
+    }
+}
+
 // Taxonomy 类型定义
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Taxonomy {
-    pub path_cache: HashMap<u32, Vec<u32>>,
+    pub path_cache: PathCache,
     pub nodes: Vec<TaxonomyNode>,
     pub name_data: Vec<u8>, // 字符串数据以 Vec<u8> 存储
     pub rank_data: Vec<u8>, // 字符串数据以 Vec<u8> 存储
     external_to_internal_id_map: HashMap<u64, u32>,
+    // rank 名称到 rank_data 偏移的映射, 首次使用时构建
+    rank_index_map: OnceLock<HashMap<String, u64>>,
+    // rank 名称到该 rank 所有节点的映射, 首次使用时构建
+    rank_nodes_map: OnceLock<HashMap<String, Vec<u32>>>,
+}
+
+/// 只比较节点, 名称, rank 数据和外部 id 映射, path_cache 和按需构建的索引由这些数据决定, 不参与比较
+impl PartialEq for Taxonomy {
+    fn eq(&self, other: &Self) -> bool {
+        self.nodes == other.nodes
+            && self.name_data == other.name_data
+            && self.rank_data == other.rank_data
+            && self.external_to_internal_id_map == other.external_to_internal_id_map
+    }
 }
 
+impl Eq for Taxonomy {}
+
 impl Default for Taxonomy {
     fn default() -> Self {
         Taxonomy {
-            path_cache: HashMap::new(),
+            path_cache: PathCache::new(),
             nodes: Vec::new(),
             name_data: Vec::new(),
             rank_data: Vec::new(),
             external_to_internal_id_map: HashMap::new(),
+            rank_index_map: OnceLock::new(),
+            rank_nodes_map: OnceLock::new(),
         }
     }
 }
@@ -257,29 +980,89 @@ impl Default for Taxonomy {
 impl Taxonomy {
     const MAGIC: &'static [u8] = b"K2TAXDAT"; // 替换为实际的 magic bytes
 
-    pub fn from_file<P: AsRef<Path> + Debug>(filename: P) -> Result<Taxonomy> {
+    // 带版本号的文件格式, magic 之后是 4 字节的版本号
+    const VERSIONED_MAGIC: &'static [u8] = b"K2TAXVER";
+    // write_to_disk_with_checksum 追加在文件末尾的 SHA-256 长度
+    const CHECKSUM_LEN: usize = 32;
+
+    /// 根据 magic 判断文件格式, 返回文件头中版本号的字节数
+    fn version_field_len(magic: &[u8]) -> Option<usize> {
+        if magic == Self::MAGIC {
+            Some(0)
+        } else if magic == Self::VERSIONED_MAGIC {
+            Some(4)
+        } else {
+            None
+        }
+    }
+
+    fn check_version(version: u32) -> Kr2Result<()> {
+        if version == 0 || version > TAXONOMY_VERSION {
+            return Err(Kr2Error::DatabaseVersionMismatch {
+                found: version,
+                expected: TAXONOMY_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// 旧格式没有可靠的 godparent_id, 也没有记录哪些节点有 k-mer, 读取后重新推导:
+    /// kraken 分类树只保留被序列引用的节点及其祖先, 按所有节点都有 k-mer 处理, 即 godparent 为父节点,
+    /// 用 build 重建数据库后会写入按实际 k-mer 推导的值
+    fn migrate_legacy_nodes(&mut self) {
+        let all_nodes: HashSet<u32> = (1..self.nodes.len() as u32).collect();
+        self.assign_godparents(&all_nodes);
+    }
+
+    pub fn from_file<P: AsRef<Path> + Debug>(filename: P) -> Kr2Result<Taxonomy> {
         let mut file = open_file(&filename)?;
 
         let mut magic = vec![0; Self::MAGIC.len()];
         file.read_exact(&mut magic)?;
-        if magic != Self::MAGIC {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Malformed taxonomy file {:?}", &filename),
-            ));
+        let versioned = match Self::version_field_len(&magic) {
+            Some(len) => len > 0,
+            None => {
+                return Err(Kr2Error::malformed_taxonomy(
+                    filename.as_ref(),
+                    "unknown magic",
+                ))
+            }
+        };
+        if versioned {
+            let mut version = [0; 4];
+            file.read_exact(&mut version)?;
+            Self::check_version(u32::from_le_bytes(version))?;
         }
 
         let mut buffer = [0; 24];
         file.read_exact(&mut buffer)?;
-        let (node_count, name_data_len, rank_data_len) =
-            unsafe { std::mem::transmute::<[u8; 24], (u64, u64, u64)>(buffer) };
+        let node_count = LittleEndian::read_u64(&buffer[0..8]);
+        let name_data_len = LittleEndian::read_u64(&buffer[8..16]);
+        let rank_data_len = LittleEndian::read_u64(&buffer[16..24]);
+
+        // 文件长度必须与头部记录的各部分长度完全一致
+        let header_len = Self::MAGIC.len() as u64 + if versioned { 4 } else { 0 } + 24;
+        let expected_len = node_count
+            .checked_mul(TaxonomyNode::SERIALIZED_SIZE as u64)
+            .and_then(|len| len.checked_add(name_data_len))
+            .and_then(|len| len.checked_add(rank_data_len))
+            .and_then(|len| len.checked_add(header_len));
+        let file_len = file.metadata()?.len();
+        if expected_len != Some(file_len) {
+            return Err(Kr2Error::malformed_taxonomy(
+                filename.as_ref(),
+                format!(
+                    "file is {} bytes, but its header describes {} nodes, {} bytes of names and {} bytes of ranks",
+                    file_len, node_count, name_data_len, rank_data_len
+                ),
+            ));
+        }
 
         let mut nodes = Vec::with_capacity(node_count as usize);
+        let mut buffer = [0; TaxonomyNode::SERIALIZED_SIZE];
         for _ in 0..node_count {
-            let mut buffer = [0; 56];
             file.read_exact(&mut buffer)?;
-            let node = unsafe { std::mem::transmute::<[u8; 56], TaxonomyNode>(buffer) };
-            nodes.push(node);
+            nodes.push(TaxonomyNode::from_le_bytes(&buffer));
         }
 
         let mut name_data = vec![0; name_data_len as usize];
@@ -295,22 +1078,157 @@ impl Taxonomy {
         }
 
         let mut taxo = Taxonomy {
-            path_cache: HashMap::new(),
+            path_cache: PathCache::new(),
             nodes,
             name_data,
             rank_data,
             external_to_internal_id_map,
+            rank_index_map: OnceLock::new(),
+            rank_nodes_map: OnceLock::new(),
         };
+        if !versioned {
+            taxo.migrate_legacy_nodes();
+        }
         taxo.build_path_cache();
         Ok(taxo)
     }
 
-    pub fn _is_a_ancestor_of_b(&self, a: u32, b: u32) -> bool {
-        if a == 0 || b == 0 {
-            return false;
-        }
+    /// 与 from_file 相同, 但通过内存映射读取文件, 并用 threads 个线程并行解析节点数据
+    pub fn from_file_parallel<P: AsRef<Path> + Debug>(
+        filename: P,
+        threads: usize,
+    ) -> Kr2Result<Taxonomy> {
+        let file = open_file(&filename)?;
+        let mmap = unsafe { Mmap::map(&file)? };
 
-        let mut current = b;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(std::io::Error::other)?;
+        let source = format!("{:?}", filename);
+        Ok(pool.install(|| Self::from_bytes(&mmap, &source))?)
+    }
+
+    /// 内存映射读取 write_to_disk_with_checksum 写入的文件, 先校验末尾的 SHA-256 再解析
+    pub fn from_file_mmap_verified<P: AsRef<Path> + Debug>(filename: P) -> Kr2Result<Taxonomy> {
+        let file = open_file(&filename)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let content_len = mmap.len().checked_sub(Self::CHECKSUM_LEN).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Taxonomy file {:?} is too short to hold a checksum",
+                    &filename
+                ),
+            )
+        })?;
+        let (content, checksum) = mmap.split_at(content_len);
+        if Sha256::digest(content).as_slice() != checksum {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Checksum mismatch in taxonomy file {:?}", &filename),
+            )
+            .into());
+        }
+
+        Ok(Self::from_bytes(content, &format!("{:?}", filename))?)
+    }
+
+    /// 读取压缩的 taxonomy 文件, 按文件头的 magic 识别 zstd 和 gzip, 其他情况按未压缩文件解析
+    pub fn from_binary_compressed<P: AsRef<Path>>(path: P) -> Kr2Result<Taxonomy> {
+        const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+        const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+        let mut compressed = Vec::new();
+        open_file(&path)?.read_to_end(&mut compressed)?;
+
+        let data = if compressed.starts_with(&ZSTD_MAGIC) {
+            zstd::decode_all(compressed.as_slice())?
+        } else if compressed.starts_with(&GZIP_MAGIC) {
+            let mut data = Vec::new();
+            flate2::read::MultiGzDecoder::new(compressed.as_slice()).read_to_end(&mut data)?;
+            data
+        } else {
+            compressed
+        };
+
+        Ok(Self::from_bytes(&data, &format!("{:?}", path.as_ref()))?)
+    }
+
+    /// 从完整的文件内容解析 taxonomy, 节点数据并行解码, source 用于错误信息
+    fn from_bytes(data: &[u8], source: &str) -> Result<Taxonomy> {
+        let malformed = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Malformed taxonomy file {}", source),
+            )
+        };
+
+        let magic_len = Self::MAGIC.len();
+        let version_len = data
+            .get(..magic_len)
+            .and_then(Self::version_field_len)
+            .ok_or_else(malformed)?;
+        let header_len = magic_len + version_len + 24;
+        if data.len() < header_len {
+            return Err(malformed());
+        }
+        if version_len > 0 {
+            let version = LittleEndian::read_u32(&data[magic_len..magic_len + version_len]);
+            Self::check_version(version)?;
+        }
+        let header = &data[magic_len + version_len..header_len];
+        let node_count = LittleEndian::read_u64(&header[0..8]) as usize;
+        let name_data_len = LittleEndian::read_u64(&header[8..16]) as usize;
+        let rank_data_len = LittleEndian::read_u64(&header[16..24]) as usize;
+
+        let nodes_end = node_count
+            .checked_mul(TaxonomyNode::SERIALIZED_SIZE)
+            .and_then(|len| len.checked_add(header_len))
+            .ok_or_else(malformed)?;
+        let name_end = nodes_end.checked_add(name_data_len).ok_or_else(malformed)?;
+        let rank_end = name_end.checked_add(rank_data_len).ok_or_else(malformed)?;
+        if data.len() < rank_end {
+            return Err(malformed());
+        }
+
+        let nodes: Vec<TaxonomyNode> = data[header_len..nodes_end]
+            .par_chunks_exact(TaxonomyNode::SERIALIZED_SIZE)
+            .map(TaxonomyNode::from_le_bytes)
+            .collect();
+
+        let name_data = data[nodes_end..name_end].to_vec();
+        let rank_data = data[name_end..rank_end].to_vec();
+
+        let external_to_internal_id_map = nodes
+            .iter()
+            .enumerate()
+            .map(|(internal_id, node)| (node.external_id, internal_id as u32))
+            .collect();
+
+        let mut taxo = Taxonomy {
+            path_cache: PathCache::new(),
+            nodes,
+            name_data,
+            rank_data,
+            external_to_internal_id_map,
+            rank_index_map: OnceLock::new(),
+            rank_nodes_map: OnceLock::new(),
+        };
+        if version_len == 0 {
+            taxo.migrate_legacy_nodes();
+        }
+        taxo.build_path_cache();
+        Ok(taxo)
+    }
+
+    pub fn _is_a_ancestor_of_b(&self, a: u32, b: u32) -> bool {
+        if a == 0 || b == 0 {
+            return false;
+        }
+
+        let mut current = b;
 
         while current > a {
             current = match self.nodes.get(current as usize) {
@@ -346,17 +1264,112 @@ impl Taxonomy {
         let path_a = self.path_cache.get(&a).unwrap_or(&default);
         let path_b = self.path_cache.get(&b).unwrap_or(&default);
 
-        let mut i = 0;
-        while i < path_a.len() && i < path_b.len() && path_a[i] == path_b[i] {
-            i += 1;
+        match common_prefix_len(path_a, path_b) {
+            0 => 0,
+            // 返回最后一个共同的祖先
+            i => path_a[i - 1],
+        }
+    }
+
+    /// 逐对计算一组节点的 LCA, 0 会被忽略, 空集合返回 0, 单个元素原样返回
+    pub fn lca_of_set(&self, ids: &[u32]) -> u32 {
+        let root = self.root();
+        let mut result = 0;
+        for &id in ids {
+            result = self.lca(result, id);
+            // 已经到达根节点, 不会再变化
+            if result == root {
+                break;
+            }
+        }
+        result
+    }
+
+    /// 以外部 taxid 计算 LCA, 不在分类树中的 taxid 被忽略, 结果为 0 表示没有有效的 taxid
+    pub fn lca_of_external_ids(&self, external_ids: &[u64]) -> u64 {
+        let internal_ids: Vec<u32> = external_ids
+            .iter()
+            .filter_map(|&external_id| self.get_internal_id_opt(external_id))
+            .collect();
+        self.nodes
+            .get(self.lca_of_set(&internal_ids) as usize)
+            .map_or(0, |node| node.external_id)
+    }
+
+    /// 并行计算每个集合的 LCA, 结果顺序与 hit_sets 一致
+    pub fn find_lca_set_parallel(&self, hit_sets: &[Vec<u32>]) -> Vec<u32> {
+        hit_sets
+            .par_iter()
+            .map(|ids| self.lca_of_set(ids))
+            .collect()
+    }
+
+    /// 批量计算 LCA, 结果顺序与 pairs 一致, 与逐对调用 lca 的结果相同
+    /// 先把 pairs 中出现的每个节点的根路径复制到一段连续内存中 (每个节点只查一次 path_cache),
+    /// 再对每对路径按 8 个一组比较公共前缀 (见 common_prefix_len)
+    pub fn batch_lca(&self, pairs: &[(u32, u32)]) -> Vec<u32> {
+        let mut ids: Vec<u32> = pairs.iter().flat_map(|&(a, b)| [a, b]).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        // 节点平均出现不到 4 次时复制路径得不偿失, 直接逐对查 path_cache
+        if ids.len() > pairs.len() / 2 {
+            return pairs.iter().map(|&(a, b)| self.lca(a, b)).collect();
         }
 
-        if i == 0 {
-            return 0;
+        // ids[i] 的路径为 flat[offsets[i]..offsets[i + 1]], 不在 path_cache 中的节点与 lca 一样视为 [0]
+        let mut flat: Vec<u32> = Vec::new();
+        let mut offsets: Vec<usize> = Vec::with_capacity(ids.len() + 1);
+        offsets.push(0);
+        for id in &ids {
+            match self.path_cache.get(id) {
+                Some(path) => flat.extend_from_slice(path),
+                None => flat.push(0),
+            }
+            offsets.push(flat.len());
         }
+        let path = |id: u32| {
+            let i = ids.binary_search(&id).unwrap();
+            &flat[offsets[i]..offsets[i + 1]]
+        };
+
+        pairs
+            .iter()
+            .map(|&(a, b)| {
+                if a == 0 || b == 0 || a == b {
+                    return if a != 0 { a } else { b };
+                }
+                let path_a = path(a);
+                match common_prefix_len(path_a, path(b)) {
+                    0 => 0,
+                    len => path_a[len - 1],
+                }
+            })
+            .collect()
+    }
+
+    /// 返回 (lca, a 到 lca 的路径, b 到 lca 的路径)
+    /// 路径按叶子到根的顺序排列, 不包含 lca 本身
+    pub fn lca_path(&self, a: u32, b: u32) -> (u32, Vec<u32>, Vec<u32>) {
+        let lca = self.lca(a, b);
+        (
+            lca,
+            self.path_to_ancestor(a, lca),
+            self.path_to_ancestor(b, lca),
+        )
+    }
 
-        // 返回最后一个共同的祖先
-        *path_a.get(i - 1).unwrap_or(&0)
+    /// node_id 到 ancestor 之间的节点 (不含 ancestor), 遇到 0 或不存在的节点时停止
+    fn path_to_ancestor(&self, node_id: u32, ancestor: u32) -> Vec<u32> {
+        let mut path = Vec::new();
+        let mut current = node_id;
+        while current != ancestor && path.len() < self.nodes.len() {
+            let Some(node) = self.nodes.get(current as usize).filter(|_| current != 0) else {
+                break;
+            };
+            path.push(current);
+            current = node.parent_id as u32;
+        }
+        path
     }
 
     pub fn lowest_common_ancestor(&self, mut a: u32, mut b: u32) -> u32 {
@@ -365,66 +1378,776 @@ impl Taxonomy {
             return if a != 0 { a } else { b };
         }
 
-        // 遍历节点直到找到共同的祖先
-        while a != b {
+        let parent = |id: u32| {
+            self.nodes
+                .get(id as usize)
+                .map_or(0, |node| node.parent_id as u32)
+        };
+
+        // 遍历节点直到找到共同的祖先, 最多走 node_count 步
+        for _ in 0..self.nodes.len() {
+            if a == b {
+                return a;
+            }
+            let (climbed, next) = if a > b {
+                (a, parent(a))
+            } else {
+                (b, parent(b))
+            };
+            // 到达哨兵节点或自身为父节点的根, 说明两个节点不在同一棵树中
+            if next == 0 || next == climbed {
+                return 0;
+            }
             if a > b {
-                a = self
-                    .nodes
-                    .get(a as usize)
-                    .map_or(0, |node| node.parent_id as u32);
+                a = next;
             } else {
-                b = self
-                    .nodes
-                    .get(b as usize)
-                    .map_or(0, |node| node.parent_id as u32);
+                b = next;
             }
         }
 
-        a
+        0
     }
 
+    /// 从根节点开始逐层构建: 上一层的路径全部确定后, 这一层的节点并行复制父节点的路径, 再按分片并行插入
     pub fn build_path_cache(&mut self) {
-        let mut cache: HashMap<u32, Vec<u32>> = HashMap::new();
-        let root_external_id = 1u64;
-        if let Some(&root_internal_id) = self.external_to_internal_id_map.get(&root_external_id) {
-            // 开始从根节点遍历
-            self.build_path_for_node(root_internal_id, &mut cache, Vec::new());
+        let mut cache = PathCache::new();
+        if let Some(root_internal_id) = self.find_root() {
+            cache.insert(root_internal_id, vec![root_internal_id]);
+            let mut level = vec![root_internal_id];
+            while !level.is_empty() {
+                let nodes = &self.nodes;
+                let parents = &cache;
+                let next: Vec<(u32, Vec<u32>)> = level
+                    .par_iter()
+                    .flat_map_iter(|&parent_id| {
+                        let node = &nodes[parent_id as usize];
+                        let path = &parents[&parent_id];
+                        let first_child_id = node.first_child as u32;
+                        let child_count = node.child_count as u32;
+                        // 这里假设子节点的ID是连续的
+                        (first_child_id..first_child_id + child_count)
+                            // 越界或重复访问 (分类树中存在环) 的节点直接跳过
+                            .filter(|&child| {
+                                (child as usize) < nodes.len() && !parents.contains_key(&child)
+                            })
+                            .map(move |child| {
+                                let mut child_path = Vec::with_capacity(path.len() + 1);
+                                child_path.extend_from_slice(path);
+                                child_path.push(child);
+                                (child, child_path)
+                            })
+                    })
+                    .collect();
+
+                // 同一节点总在同一分片中, 分片内保持 next 中的顺序
+                // 下一层按分片依次排列, 分片数固定, 结果与线程数无关
+                let mut buckets: Vec<Vec<(u32, Vec<u32>)>> =
+                    (0..PATH_CACHE_SHARDS).map(|_| Vec::new()).collect();
+                for (node_id, path) in next {
+                    buckets[PathCache::shard_of(node_id)].push((node_id, path));
+                }
+                level = cache
+                    .shards
+                    .par_iter_mut()
+                    .zip(buckets)
+                    .flat_map_iter(|(shard, bucket)| {
+                        bucket.into_iter().filter_map(move |(node_id, path)| {
+                            // 同一层中被多个节点引用时只取第一个
+                            match shard.entry(node_id) {
+                                std::collections::hash_map::Entry::Vacant(entry) => {
+                                    entry.insert(path);
+                                    Some(node_id)
+                                }
+                                std::collections::hash_map::Entry::Occupied(_) => None,
+                            }
+                        })
+                    })
+                    .collect();
+            }
         }
         self.path_cache = cache;
     }
 
-    fn build_path_for_node(
-        &self,
-        node_id: u32,
-        path_cache: &mut HashMap<u32, Vec<u32>>,
-        mut current_path: Vec<u32>,
-    ) {
-        current_path.push(node_id); // 将当前节点添加到路径中
-                                    // 存储当前节点的路径
-        path_cache.insert(node_id, current_path.clone());
-
-        // 获取当前节点的信息
-        let node = &self.nodes[node_id as usize];
-        let first_child_id = node.first_child as u32;
-        let child_count = node.child_count as u32;
+    /// 根节点的内部 id, 没有根节点时为 0
+    pub fn root(&self) -> u32 {
+        self.find_root().unwrap_or(0)
+    }
 
-        // 遍历所有子节点
-        for i in 0..child_count {
-            let child_internal_id = first_child_id + i; // 这里假设子节点的ID是连续的
-            self.build_path_for_node(child_internal_id, path_cache, current_path.clone());
+    /// 根节点: 父节点为 0 或自身的节点, 优先取内部 id 1, 其次是外部 taxid 为 1 的节点, 最后取第一个满足条件的节点
+    fn find_root(&self) -> Option<u32> {
+        let is_root = |id: u32| {
+            self.nodes
+                .get(id as usize)
+                .is_some_and(|node| node.parent_id == 0 || node.parent_id == id as u64)
+        };
+        if is_root(1) {
+            return Some(1);
+        }
+        if let Some(&root_internal_id) = self.external_to_internal_id_map.get(&1) {
+            return Some(root_internal_id);
         }
+        (1..self.nodes.len() as u32).find(|&id| is_root(id))
     }
 
     pub fn node_count(&self) -> usize {
         self.nodes.len()
     }
 
-    // get_internal_id 函数的优化
+    /// rank 名称到其在 rank_data 中偏移的映射, 与 NCBITaxonomy::get_rank_offset_data 对应
+    pub fn get_rank_index_map(&self) -> HashMap<String, u64> {
+        self.rank_index_map
+            .get_or_init(|| {
+                let mut rank_offsets = HashMap::new();
+                let mut offset = 0;
+                for rank in self.rank_data.split(|&b| b == b'\0') {
+                    if offset >= self.rank_data.len() {
+                        break;
+                    }
+                    if let Ok(rank) = std::str::from_utf8(rank) {
+                        rank_offsets.insert(rank.to_string(), offset as u64);
+                    }
+                    offset += rank.len() + 1;
+                }
+                rank_offsets
+            })
+            .clone()
+    }
+
+    /// 遍历 rank 为 rank 的所有节点, 返回 (内部 id, 节点)
+    pub fn iter_rank<'a>(
+        &'a self,
+        rank: &'a str,
+    ) -> impl Iterator<Item = (u32, &'a TaxonomyNode)> + 'a {
+        self.nodes
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(move |(_, node)| str_at_offset(&self.rank_data, node.rank_offset) == Some(rank))
+            .map(|(internal_id, node)| (internal_id as u32, node))
+    }
+
+    /// rank 为 rank 的所有节点的内部 id, 首次调用时为所有 rank 建立索引
+    pub fn nodes_at_rank_cached(&self, rank: &str) -> &[u32] {
+        self.rank_nodes_map
+            .get_or_init(|| {
+                let mut rank_nodes: HashMap<String, Vec<u32>> = HashMap::new();
+                for (internal_id, node) in self.nodes.iter().enumerate().skip(1) {
+                    if let Some(rank) = str_at_offset(&self.rank_data, node.rank_offset) {
+                        rank_nodes
+                            .entry(rank.to_string())
+                            .or_default()
+                            .push(internal_id as u32);
+                    }
+                }
+                rank_nodes
+            })
+            .get(rank)
+            .map_or(&[], |nodes| nodes.as_slice())
+    }
+
+    /// 所有 rank 名称, 按字典序排列
+    pub fn known_ranks(&self) -> Vec<String> {
+        let mut ranks: Vec<String> = self.get_rank_index_map().into_keys().collect();
+        ranks.sort_unstable();
+        ranks
+    }
+
+    /// 为每个节点设置 godparent_id: 祖先中第一个在数据库里有 k-mer 的节点, 不存在时为 0
+    /// nodes_with_kmers 为哈希表中出现过的内部 taxid, 需要在数据库构建完成后调用
+    pub fn assign_godparents(&mut self, nodes_with_kmers: &HashSet<u32>) {
+        let godparents: Vec<u64> = (0..self.nodes.len() as u32)
+            .map(|internal_id| {
+                self.ancestors(internal_id)
+                    .skip(1)
+                    .find(|id| nodes_with_kmers.contains(id))
+                    .map_or(0, |id| id as u64)
+            })
+            .collect();
+        for (node, godparent_id) in self.nodes.iter_mut().zip(godparents) {
+            node.godparent_id = godparent_id;
+        }
+    }
+
+    /// 每个 rank 下的节点数, 跳过 0 号哨兵节点
+    pub fn count_by_rank(&self) -> HashMap<String, usize> {
+        let mut rank_counts: HashMap<String, usize> = HashMap::new();
+        for node in self.nodes.iter().skip(1) {
+            let rank = str_at_offset(&self.rank_data, node.rank_offset).unwrap_or("");
+            match rank_counts.get_mut(rank) {
+                Some(count) => *count += 1,
+                None => {
+                    rank_counts.insert(rank.to_string(), 1);
+                }
+            }
+        }
+        rank_counts
+    }
+
+    /// rank_data 中排序去重后的 rank 名称
+    pub fn rank_names(&self) -> Vec<&str> {
+        let mut ranks: Vec<&str> = self
+            .rank_data
+            .split(|&b| b == b'\0')
+            .filter(|rank| !rank.is_empty())
+            .filter_map(|rank| std::str::from_utf8(rank).ok())
+            .collect();
+        ranks.sort_unstable();
+        ranks.dedup();
+        ranks
+    }
+
+    /// 节点到根节点的距离, 根节点的深度为 0
+    fn node_depth(&self, internal_id: u32) -> usize {
+        let mut depth = 0;
+        let mut current = internal_id;
+        while let Some(node) = self.nodes.get(current as usize) {
+            if node.parent_id == 0 || depth >= self.nodes.len() {
+                break;
+            }
+            current = node.parent_id as u32;
+            depth += 1;
+        }
+        depth
+    }
+
+    /// 外部 taxid 对应的内部 id, taxid 不在分类树中时返回 0
+    #[deprecated(
+        note = "returns 0 both for a missing taxid and for the sentinel node, use get_internal_id_opt"
+    )]
     pub fn get_internal_id(&self, external_id: u64) -> u32 {
-        *self
-            .external_to_internal_id_map
-            .get(&external_id)
-            .unwrap_or(&0)
+        self.get_internal_id_opt(external_id).unwrap_or(0)
+    }
+
+    /// 外部 taxid 对应的内部 id, taxid 不在分类树中时返回 None
+    pub fn get_internal_id_opt(&self, external_id: u64) -> Option<u32> {
+        self.external_to_internal_id_map.get(&external_id).copied()
+    }
+
+    /// 节点的学名, id 越界或偏移异常时返回 None
+    pub fn get_name(&self, internal_id: u32) -> Option<&str> {
+        let node = self.nodes.get(internal_id as usize)?;
+        str_at_offset(&self.name_data, node.name_offset)
+    }
+
+    /// 节点的 rank, id 越界或偏移异常时返回 None
+    pub fn get_rank(&self, internal_id: u32) -> Option<&str> {
+        let node = self.nodes.get(internal_id as usize)?;
+        str_at_offset(&self.rank_data, node.rank_offset)
+    }
+
+    /// 按外部 taxid 查询学名, taxid 不存在时返回 None
+    pub fn get_name_by_external_id(&self, external_id: u64) -> Option<&str> {
+        match self.get_internal_id_opt(external_id)? {
+            0 => None,
+            internal_id => self.get_name(internal_id),
+        }
+    }
+
+    /// 从该节点自身开始依次向上遍历到根节点, 遇到哨兵节点 0 或越界 id 时停止
+    /// 最多走 node_count 步, 分类树中存在环时也不会死循环
+    pub fn ancestors(&self, internal_id: u32) -> impl Iterator<Item = u32> + '_ {
+        let first =
+            (internal_id != 0 && (internal_id as usize) < self.nodes.len()).then_some(internal_id);
+        std::iter::successors(first, move |&id| {
+            let parent_id = self.nodes[id as usize].parent_id;
+            (parent_id != 0 && parent_id != id as u64 && (parent_id as usize) < self.nodes.len())
+                .then_some(parent_id as u32)
+        })
+        .take(self.nodes.len())
+    }
+
+    /// 与 ancestors 相同, 但输入输出都是外部 taxid, 不在分类树中的 taxid 得到空迭代器
+    pub fn ancestors_external(&self, external_id: u64) -> impl Iterator<Item = u64> + '_ {
+        self.get_internal_id_opt(external_id)
+            .into_iter()
+            .flat_map(move |id| self.ancestors(id))
+            .map(move |id| self.nodes[id as usize].external_id)
+    }
+
+    /// 该节点或其祖先中 rank 为 rank 的最近节点, 不存在时返回 None
+    pub fn node_at_rank(&self, internal_id: u32, rank: &str) -> Option<u32> {
+        self.path_to_ancestor(internal_id, 0)
+            .into_iter()
+            .find(|&id| self.get_rank(id) == Some(rank))
+    }
+
+    /// 从根节点下一级到该节点的学名, 以 sep 连接, 例如 `Bacteria > ... > Pseudomonas aeruginosa`
+    pub fn node_path_string(&self, internal_id: u32, sep: &str) -> Option<String> {
+        self.node_path_string_by(internal_id, sep, |_| true)
+    }
+
+    /// 与 node_path_string 相同, 但只保留 rank 在 ranks 中的节点
+    pub fn node_path_string_at_ranks(
+        &self,
+        internal_id: u32,
+        ranks: &[&str],
+        sep: &str,
+    ) -> Option<String> {
+        self.node_path_string_by(internal_id, sep, |rank| ranks.contains(&rank))
+    }
+
+    fn node_path_string_by<F>(&self, internal_id: u32, sep: &str, keep: F) -> Option<String>
+    where
+        F: Fn(&str) -> bool,
+    {
+        if internal_id == 0 || internal_id as usize >= self.nodes.len() {
+            return None;
+        }
+
+        // 根节点本身不出现在路径中, 除非查询的就是根节点
+        let mut path = self.path_to_ancestor(internal_id, self.root());
+        if path.is_empty() {
+            path.push(internal_id);
+        }
+
+        let mut names = Vec::with_capacity(path.len());
+        for &id in path.iter().rev() {
+            if keep(self.get_rank(id)?) {
+                names.push(self.get_name(id)?);
+            }
+        }
+        Some(names.join(sep))
+    }
+
+    /// internal_id 为根的子树中的所有节点, 按 BFS 顺序排列
+    fn subtree_nodes(&self, internal_id: u32) -> Vec<u32> {
+        let mut result = Vec::new();
+        let mut queue = VecDeque::from([internal_id]);
+        while let Some(id) = queue.pop_front() {
+            let Some(node) = self.nodes.get(id as usize) else {
+                continue;
+            };
+            result.push(id);
+            let first_child = node.first_child as u32;
+            queue.extend(first_child..first_child + node.child_count as u32);
+        }
+        result
+    }
+
+    /// internal_id 子树中除自身以外的所有节点, 按 BFS 顺序惰性遍历
+    pub fn descendants(&self, internal_id: u32) -> impl Iterator<Item = u32> + '_ {
+        let mut queue = VecDeque::new();
+        let push_children = move |queue: &mut VecDeque<u32>, id: u32| {
+            if let Some(node) = self.nodes.get(id as usize) {
+                let first_child = node.first_child as u32;
+                queue.extend(first_child..first_child + node.child_count as u32);
+            }
+        };
+        if internal_id != 0 {
+            push_children(&mut queue, internal_id);
+        }
+        std::iter::from_fn(move || {
+            while let Some(id) = queue.pop_front() {
+                if (id as usize) < self.nodes.len() {
+                    push_children(&mut queue, id);
+                    return Some(id);
+                }
+            }
+            None
+        })
+    }
+
+    /// internal_id 子树中的节点数, 不含自身
+    pub fn subtree_size(&self, internal_id: u32) -> usize {
+        self.descendants(internal_id).count()
+    }
+
+    /// 把 subtree 中以 subtree_root 为根的子树嫁接到本树的 attach_to 节点下, 节点的名称和 rank 保持不变
+    /// 为了让每个节点的子节点连续存放, 嫁接后所有节点按 BFS 顺序重新分配内部 id,
+    /// 返回本树原有节点的旧内部 id 到新内部 id 的映射, 新增节点数为嫁接前后 node_count 之差
+    /// godparent 随节点一起重新编号, 嫁接节点的 godparent 不在嫁接的子树中时置为 0
+    /// id 无效或外部 id 与已有节点冲突时返回 InvalidInput 错误, 本树保持不变
+    pub fn add_subtree(
+        &mut self,
+        subtree: &Taxonomy,
+        subtree_root: u32,
+        attach_to: u32,
+    ) -> Kr2Result<HashMap<u32, u32>> {
+        if subtree_root == 0 || subtree_root as usize >= subtree.nodes.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid subtree root {}", subtree_root),
+            )
+            .into());
+        }
+        if attach_to == 0 || attach_to as usize >= self.nodes.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid attach point {}", attach_to),
+            )
+            .into());
+        }
+
+        let grafted = subtree.subtree_nodes(subtree_root);
+        let existing: HashSet<u64> = self.nodes.iter().map(|node| node.external_id).collect();
+        if let Some(&conflict) = grafted
+            .iter()
+            .find(|&&id| existing.contains(&subtree.nodes[id as usize].external_id))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "external id {} already exists",
+                    subtree.nodes[conflict as usize].external_id
+                ),
+            )
+            .into());
+        }
+
+        // 嫁接的节点先追加到本树末尾, 子节点关系由 children_of 给出, 再统一按 BFS 重建
+        let appended_from = self.nodes.len() as u32;
+        let temp_ids: HashMap<u32, u32> = grafted
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| (id, appended_from + i as u32))
+            .collect();
+        let mut combined = self.clone();
+        for &id in &grafted {
+            let src = &subtree.nodes[id as usize];
+            let name_offset = combined.name_data.len() as u64;
+            combined
+                .name_data
+                .extend_from_slice(subtree.get_name(id).unwrap_or("").as_bytes());
+            combined.name_data.push(b'\0');
+            let rank_offset = combined.rank_data.len() as u64;
+            combined
+                .rank_data
+                .extend_from_slice(subtree.get_rank(id).unwrap_or("").as_bytes());
+            combined.rank_data.push(b'\0');
+
+            let parent_id = if id == subtree_root {
+                attach_to
+            } else {
+                temp_ids[&(src.parent_id as u32)]
+            };
+            combined.nodes.push(TaxonomyNode {
+                parent_id: parent_id as u64,
+                first_child: 0,
+                child_count: 0,
+                name_offset,
+                rank_offset,
+                external_id: src.external_id,
+                godparent_id: temp_ids
+                    .get(&(src.godparent_id as u32))
+                    .map_or(0, |&temp_id| temp_id as u64),
+            });
+        }
+
+        let (taxo, new_ids) = combined.rebuild_tree_with_ids(1, |id| {
+            let (tree, node_id) = if id >= appended_from {
+                (subtree, grafted[(id - appended_from) as usize])
+            } else {
+                (&*self, id)
+            };
+            let node = &tree.nodes[node_id as usize];
+            let first_child = node.first_child as u32;
+            let mut children: Vec<u32> = (first_child..first_child + node.child_count as u32)
+                .map(|child| {
+                    if id >= appended_from {
+                        temp_ids[&child]
+                    } else {
+                        child
+                    }
+                })
+                .collect();
+            if id == attach_to {
+                children.push(temp_ids[&subtree_root]);
+            }
+            children
+        });
+        let id_map = (1..appended_from)
+            .filter_map(|id| new_ids.get(&id).map(|&new_id| (id, new_id)))
+            .collect();
+        *self = taxo;
+
+        Ok(id_map)
+    }
+
+    /// 复制以 root_internal_id 为根的子树, 得到独立的 Taxonomy
+    /// 节点按 BFS 顺序从 1 重新编号, 新的根节点的父节点为 0, name_data/rank_data 只保留用到的字符串
+    /// 外部 taxid 保持不变, godparent 不在子树中时置为 0
+    pub fn clone_subtree(&self, root_internal_id: u32) -> Taxonomy {
+        if root_internal_id == 0 || root_internal_id as usize >= self.nodes.len() {
+            return self.rebuild_tree(0, |_| Vec::new());
+        }
+        self.rebuild_tree(root_internal_id, |id| {
+            let node = &self.nodes[id as usize];
+            let first_child = node.first_child as u32;
+            (first_child..first_child + node.child_count as u32).collect()
+        })
+    }
+
+    /// 从 root 开始按 children_of 给出的子节点 BFS 重建一棵独立的树, root 为 0 时只包含哨兵节点
+    /// 节点从 1 重新编号, name_data/rank_data 只保留用到的字符串, godparent 不在新树中时置为 0
+    fn rebuild_tree<F: Fn(u32) -> Vec<u32>>(&self, root: u32, children_of: F) -> Taxonomy {
+        self.rebuild_tree_with_ids(root, children_of).0
+    }
+
+    /// 与 rebuild_tree 相同, 同时返回原内部 id 到新内部 id 的映射
+    fn rebuild_tree_with_ids<F: Fn(u32) -> Vec<u32>>(
+        &self,
+        root: u32,
+        children_of: F,
+    ) -> (Taxonomy, HashMap<u32, u32>) {
+        let mut taxo = Taxonomy::default();
+        taxo.nodes.push(TaxonomyNode::default());
+        if root == 0 {
+            return (taxo, HashMap::new());
+        }
+
+        // (原内部 id, 新的父节点 id, 子节点)
+        let mut order: Vec<(u32, u32, Vec<u32>)> = Vec::new();
+        let mut queue = VecDeque::from([(root, 0)]);
+        while let Some((id, parent_id)) = queue.pop_front() {
+            let children = children_of(id);
+            let new_id = order.len() as u32 + 1;
+            queue.extend(children.iter().map(|&child| (child, new_id)));
+            order.push((id, parent_id, children));
+        }
+        let new_ids: HashMap<u32, u32> = order
+            .iter()
+            .enumerate()
+            .map(|(i, &(id, _, _))| (id, i as u32 + 1))
+            .collect();
+
+        // 与 convert_to_kraken_taxonomy 一致, rank 按字典序排列
+        let mut ranks: Vec<&str> = order
+            .iter()
+            .map(|&(id, _, _)| self.get_rank(id).unwrap_or(""))
+            .collect();
+        ranks.sort_unstable();
+        ranks.dedup();
+        let mut rank_offsets: HashMap<&str, u64> = HashMap::new();
+        for rank in ranks {
+            rank_offsets.insert(rank, taxo.rank_data.len() as u64);
+            taxo.rank_data.extend_from_slice(rank.as_bytes());
+            taxo.rank_data.push(b'\0');
+        }
+
+        // BFS 顺序下每个节点的子节点紧接着前面节点的子节点排列
+        let mut next_child = 2;
+        for (id, parent_id, children) in &order {
+            let src = &self.nodes[*id as usize];
+            let name = self.get_name(*id).unwrap_or("");
+            let rank = self.get_rank(*id).unwrap_or("");
+
+            let name_offset = taxo.name_data.len() as u64;
+            taxo.name_data.extend_from_slice(name.as_bytes());
+            taxo.name_data.push(b'\0');
+
+            let godparent_id = new_ids
+                .get(&(src.godparent_id as u32))
+                .map_or(0, |&new_id| new_id as u64);
+
+            taxo.nodes.push(TaxonomyNode {
+                parent_id: *parent_id as u64,
+                first_child: next_child,
+                child_count: children.len() as u64,
+                name_offset,
+                rank_offset: rank_offsets[rank],
+                external_id: src.external_id,
+                godparent_id,
+            });
+            next_child += children.len() as u64;
+        }
+
+        taxo.generate_external_to_internal_id_map();
+        taxo.build_path_cache();
+        (taxo, new_ids)
+    }
+
+    /// 只保留 rank 在 ranks 中的节点和根节点, 其余节点被移除, 它们的子节点挂到最近的保留祖先下
+    /// keep_no_rank 为 true 时同时保留 rank 为 "no rank" 的节点, ranks 为空时返回错误
+    pub fn prune_to_ranks(
+        &self,
+        ranks: &[&str],
+        keep_no_rank: bool,
+    ) -> std::result::Result<Taxonomy, String> {
+        if ranks.is_empty() {
+            return Err("no ranks to keep".to_string());
+        }
+        if self.nodes.len() < 2 {
+            return Err("taxonomy has no root node".to_string());
+        }
+
+        let keep = |id: u32| match self.get_rank(id) {
+            Some("no rank") if keep_no_rank => true,
+            Some(rank) => ranks.contains(&rank),
+            None => false,
+        };
+
+        // 节点按 BFS 顺序排列, 父节点总是先于子节点处理
+        let mut survivor = vec![0u32; self.nodes.len()];
+        survivor[1] = 1;
+        let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+        for internal_id in 2..self.nodes.len() as u32 {
+            let parent_survivor = survivor[self.nodes[internal_id as usize].parent_id as usize];
+            if keep(internal_id) {
+                survivor[internal_id as usize] = internal_id;
+                children
+                    .entry(parent_survivor)
+                    .or_default()
+                    .push(internal_id);
+            } else {
+                survivor[internal_id as usize] = parent_survivor;
+            }
+        }
+
+        Ok(self.rebuild_tree(1, |id| children.get(&id).cloned().unwrap_or_default()))
+    }
+
+    /// 提取以 root_internal_id 为根的子树, 结果可以直接 write_to_disk 后再用 from_file 读取
+    /// 对根节点调用时原样复制整个 taxonomy, 结果与原 taxonomy 相等, 不会像 clone_subtree 那样重排 rank_data
+    /// 其余节点与 clone_subtree 相同, 按 BFS 重新编号并只保留子树用到的字符串
+    pub fn extract_subtree(&self, root_internal_id: u32) -> Taxonomy {
+        if root_internal_id != 0 && root_internal_id == self.root() {
+            return self.clone();
+        }
+        self.clone_subtree(root_internal_id)
+    }
+
+    /// 检查节点结构是否自洽: 父节点排在子节点之前, 子节点范围与 parent_id 对应, 字符串偏移有效,
+    /// 且外部 id 映射与 nodes 一致, 返回发现的第一个问题
+    pub fn check_integrity(&self) -> std::result::Result<(), String> {
+        if self.nodes.is_empty() {
+            return Err("taxonomy has no sentinel node".to_string());
+        }
+        for (internal_id, node) in self.nodes.iter().enumerate().skip(1) {
+            let parent_id = node.parent_id as usize;
+            if internal_id == 1 {
+                if parent_id != 0 {
+                    return Err(format!("root node has parent {}", parent_id));
+                }
+            } else if parent_id == 0 || parent_id >= internal_id {
+                return Err(format!(
+                    "node {} has invalid parent {}",
+                    internal_id, parent_id
+                ));
+            }
+
+            let first_child = node.first_child as usize;
+            let children_end = first_child + node.child_count as usize;
+            if node.child_count > 0
+                && (first_child <= internal_id || children_end > self.nodes.len())
+            {
+                return Err(format!(
+                    "node {} has children {}..{} out of range",
+                    internal_id, first_child, children_end
+                ));
+            }
+            for child in first_child..children_end {
+                if self.nodes[child].parent_id as usize != internal_id {
+                    return Err(format!(
+                        "child {} of node {} has parent {}",
+                        child, internal_id, self.nodes[child].parent_id
+                    ));
+                }
+            }
+
+            if self.get_name(internal_id as u32).is_none()
+                || self.get_rank(internal_id as u32).is_none()
+            {
+                return Err(format!(
+                    "node {} has invalid name or rank offset",
+                    internal_id
+                ));
+            }
+        }
+
+        let mismatched = self.hash_consistency_check();
+        if let Some(external_id) = mismatched.first() {
+            return Err(format!(
+                "external id {} is not mapped to its node",
+                external_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// 检查节点索引、字符串偏移、external_to_internal_id_map 和 path_cache 是否自洽
+    /// 与 check_integrity 不同, 这里不要求 BFS 顺序, 而是收集所有问题, 返回为空表示没有发现问题
+    pub fn validate(&self) -> Kr2Result<Vec<String>> {
+        if self.nodes.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "taxonomy has no sentinel node").into());
+        }
+
+        let node_count = self.nodes.len();
+        let mut issues = Vec::new();
+        for (internal_id, node) in self.nodes.iter().enumerate().skip(1) {
+            if node.parent_id as usize >= node_count {
+                issues.push(format!(
+                    "node {}: parent_id {} out of range (node count {})",
+                    internal_id, node.parent_id, node_count
+                ));
+            }
+            if node.child_count > 0 {
+                let last_child = node.first_child as usize + node.child_count as usize - 1;
+                if node.first_child as usize >= node_count || last_child >= node_count {
+                    issues.push(format!(
+                        "node {}: children {}..={} out of range (node count {})",
+                        internal_id, node.first_child, last_child, node_count
+                    ));
+                }
+            }
+            if str_at_offset(&self.name_data, node.name_offset).is_none() {
+                issues.push(format!(
+                    "node {}: name_offset {} is not a null-terminated string in name_data ({} bytes)",
+                    internal_id,
+                    node.name_offset,
+                    self.name_data.len()
+                ));
+            }
+            if str_at_offset(&self.rank_data, node.rank_offset).is_none() {
+                issues.push(format!(
+                    "node {}: rank_offset {} is not a null-terminated string in rank_data ({} bytes)",
+                    internal_id,
+                    node.rank_offset,
+                    self.rank_data.len()
+                ));
+            }
+            if !self
+                .external_to_internal_id_map
+                .contains_key(&node.external_id)
+            {
+                issues.push(format!(
+                    "node {}: external id {} missing from external_to_internal_id_map",
+                    internal_id, node.external_id
+                ));
+            }
+        }
+
+        let mut cached: Vec<&u32> = self.path_cache.keys().collect();
+        cached.sort_unstable();
+        for &internal_id in cached {
+            let path = &self.path_cache[&internal_id];
+            if internal_id as usize >= node_count {
+                issues.push(format!(
+                    "path_cache: entry for nonexistent node {}",
+                    internal_id
+                ));
+            } else if let Some(&id) = path.iter().find(|&&id| id as usize >= node_count) {
+                issues.push(format!(
+                    "path_cache: path of node {} references nonexistent node {}",
+                    internal_id, id
+                ));
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// 检查 external_to_internal_id_map 与 nodes 是否一致
+    /// 返回映射缺失或指向错误内部 id 的 external_id, 为空表示完全一致
+    pub fn hash_consistency_check(&self) -> Vec<u64> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(internal_id, node)| {
+                self.external_to_internal_id_map.get(&node.external_id)
+                    != Some(&(*internal_id as u32))
+            })
+            .map(|(_, node)| node.external_id)
+            .collect()
     }
 
     pub fn generate_external_to_internal_id_map(&mut self) {
@@ -437,12 +2160,52 @@ impl Taxonomy {
         }
     }
 
-    pub fn write_to_disk<P: AsRef<Path>>(&self, filename: P) -> Result<()> {
+    /// 以不带版本号的旧格式写入, godparent_id 照常写入节点, 但旧格式读取时会重新推导它,
+    /// 需要保留 godparent 时使用 write_to_disk_versioned
+    pub fn write_to_disk<P: AsRef<Path>>(&self, filename: P) -> Kr2Result<()> {
         let mut file = File::create(filename)?;
 
         // Write file magic
         file.write_all(Taxonomy::MAGIC)?;
+        Ok(self.write_body(&mut file)?)
+    }
+
+    /// 与 write_to_disk 相同, 但在 magic 之后写入 4 字节的格式版本号
+    pub fn write_to_disk_versioned<P: AsRef<Path>>(&self, filename: P) -> Kr2Result<()> {
+        let mut file = File::create(filename)?;
+        self.write_to_writer(&mut file)
+    }
+
+    /// 以带版本号的格式写入任意 writer
+    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> Kr2Result<()> {
+        writer.write_all(Taxonomy::VERSIONED_MAGIC)?;
+        writer.write_all(&TAXONOMY_VERSION.to_le_bytes())?;
+        Ok(self.write_body(writer)?)
+    }
+
+    /// 以 zstd 压缩写入带版本号的格式
+    /// 节点字段多为高位是 0 的 u64, 压缩效果明显: data/ 构建的测试库 level 3 时由 2073 字节压缩到 669 字节
+    pub fn save_binary_compressed<P: AsRef<Path>>(&self, path: P, level: i32) -> Kr2Result<()> {
+        let file = File::create(path)?;
+        let mut encoder = zstd::Encoder::new(BufWriter::new(file), level)?;
+        self.write_to_writer(&mut encoder)?;
+        Ok(encoder.finish()?.flush()?)
+    }
+
+    /// 以带版本号的格式写入, 并在文件末尾追加整个文件内容的 SHA-256
+    pub fn write_to_disk_with_checksum<P: AsRef<Path>>(&self, filename: P) -> Kr2Result<()> {
+        let mut content = Vec::new();
+        content.write_all(Taxonomy::VERSIONED_MAGIC)?;
+        content.write_all(&TAXONOMY_VERSION.to_le_bytes())?;
+        self.write_body(&mut content)?;
+
+        let mut file = File::create(filename)?;
+        file.write_all(&content)?;
+        file.write_all(Sha256::digest(&content).as_slice())?;
+        Ok(())
+    }
 
+    fn write_body<W: Write>(&self, file: &mut W) -> Result<()> {
         // Write node count, name data length, and rank data length
         let node_count = self.nodes.len() as u64;
         let name_data_len = self.name_data.len() as u64;
@@ -468,4 +2231,2223 @@ impl Taxonomy {
 
         Ok(())
     }
+
+    /// 导出 MEGAN6 summary 格式, counts 的键为内部 taxid, 0 表示未分类
+    pub fn export_megan6<W: Write>(
+        &self,
+        counts: &HashMap<u32, u64>,
+        writer: &mut W,
+    ) -> Kr2Result<()> {
+        let total_reads: u64 = counts.values().sum();
+
+        writeln!(writer, "@ContentType\tSummary4")?;
+        writeln!(writer, "@Names\tkun_peng")?;
+        writeln!(writer, "@Reads\t{}", total_reads)?;
+        writeln!(writer, "@Assignments\tTaxonomy")?;
+
+        let mut assignments: Vec<(i64, u64)> = counts
+            .iter()
+            .filter(|(_, &count)| count > 0)
+            .filter_map(|(&taxid, &count)| match taxid {
+                // MEGAN 使用 -1 表示 "Not assigned"
+                0 => Some((-1, count)),
+                _ => self
+                    .nodes
+                    .get(taxid as usize)
+                    .map(|node| (node.external_id as i64, count)),
+            })
+            .collect();
+        assignments.sort_unstable();
+
+        for (external_id, count) in assignments {
+            writeln!(writer, "TAX\t{}\t{}", external_id, count)?;
+        }
+
+        Ok(())
+    }
+
+    /// child_count 的分布, 键为子节点数, 值为具有该子节点数的节点个数
+    pub fn degree_distribution(&self) -> HashMap<u64, usize> {
+        let mut distribution = HashMap::new();
+        // 跳过 0 号哨兵节点
+        for node in self.nodes.iter().skip(1) {
+            *distribution.entry(node.child_count).or_insert(0) += 1;
+        }
+        distribution
+    }
+
+    /// 非叶子节点的平均子节点数, 没有非叶子节点时返回 0
+    pub fn avg_branching_factor(&self) -> f64 {
+        let (internal_nodes, children) = self
+            .nodes
+            .iter()
+            .skip(1)
+            .filter(|node| node.child_count > 0)
+            .fold((0u64, 0u64), |(count, sum), node| {
+                (count + 1, sum + node.child_count)
+            });
+        if internal_nodes == 0 {
+            0.0
+        } else {
+            children as f64 / internal_nodes as f64
+        }
+    }
+
+    /// 所有节点中最大的子节点数
+    pub fn max_branching_factor(&self) -> u64 {
+        self.nodes
+            .iter()
+            .skip(1)
+            .map(|node| node.child_count)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 默认的主要 rank 顺序, 从高到低
+    pub fn rank_order() -> &'static [&'static str] {
+        &[
+            "superkingdom",
+            "kingdom",
+            "phylum",
+            "class",
+            "order",
+            "family",
+            "genus",
+            "species",
+        ]
+    }
+
+    /// 按 rank_order 查找 rank 与所在位置冲突的节点, 见 find_misplaced_nodes_with_order
+    pub fn find_misplaced_nodes(&self) -> Vec<(u32, String, String)> {
+        self.find_misplaced_nodes_with_order(Self::rank_order())
+    }
+
+    /// 查找 rank 不低于其最近的有序祖先的节点, 例如 class 节点之下的 phylum 节点
+    /// 返回 (内部 id, 实际 rank, 期望 rank), 期望 rank 为该祖先 rank 在 order 中的下一级
+    /// rank 不在 order 中的节点不参与检查
+    pub fn find_misplaced_nodes_with_order(&self, order: &[&str]) -> Vec<(u32, String, String)> {
+        let rank_level = |id: u32| {
+            let rank = self.get_rank(id)?;
+            order.iter().position(|&r| r == rank)
+        };
+
+        let mut misplaced = Vec::new();
+        for internal_id in 1..self.nodes.len() as u32 {
+            let Some(level) = rank_level(internal_id) else {
+                continue;
+            };
+            let ancestor_level = self
+                .path_to_ancestor(internal_id, 0)
+                .into_iter()
+                .skip(1)
+                .find_map(rank_level);
+            if let Some(ancestor_level) = ancestor_level {
+                if level <= ancestor_level {
+                    let expected = order.get(ancestor_level + 1).copied().unwrap_or("no rank");
+                    misplaced.push((internal_id, order[level].to_string(), expected.to_string()));
+                }
+            }
+        }
+        misplaced
+    }
+
+    /// 以每层 1.0 的枝长计算 read 在 root 子树上的简约得分, 即各 taxid 的 read 数乘以其到 root 的深度之和
+    /// 不在 root 子树中或不存在的 taxid 被忽略
+    pub fn compute_parsimony_score(&self, counts: &HashMap<u32, u64>, root: u32) -> f64 {
+        counts
+            .iter()
+            .filter_map(|(&taxid, &count)| {
+                let path = self.path_to_ancestor(taxid, root);
+                let reaches_root = match path.last() {
+                    Some(&last) => self
+                        .nodes
+                        .get(last as usize)
+                        .is_some_and(|node| node.parent_id as u32 == root),
+                    None => taxid == root,
+                };
+                reaches_root.then_some(path.len() as f64 * count as f64)
+            })
+            .sum()
+    }
+
+    /// 导出纯文本的 taxonomy 摘要, 各部分之间以空行分隔
+    pub fn export_summary<W: Write>(&self, writer: &mut W) -> Kr2Result<()> {
+        let mut leaf_count = 0;
+        let mut depth_counts: BTreeMap<usize, usize> = BTreeMap::new();
+
+        // 跳过 0 号哨兵节点
+        for (internal_id, node) in self.nodes.iter().enumerate().skip(1) {
+            if node.child_count == 0 {
+                leaf_count += 1;
+            }
+            *depth_counts
+                .entry(self.node_depth(internal_id as u32))
+                .or_insert(0) += 1;
+        }
+
+        writeln!(writer, "node count\t{}", self.node_count())?;
+        writeln!(writer, "leaf count\t{}", leaf_count)?;
+
+        writeln!(writer)?;
+        writeln!(writer, "depth\tnodes")?;
+        for (depth, count) in &depth_counts {
+            writeln!(writer, "{}\t{}", depth, count)?;
+        }
+
+        let mut ranks: Vec<(String, usize)> = self.count_by_rank().into_iter().collect();
+        ranks.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        writeln!(writer)?;
+        writeln!(writer, "rank\tnodes")?;
+        for (rank, count) in ranks.into_iter().take(10) {
+            writeln!(writer, "{}\t{}", rank, count)?;
+        }
+
+        Ok(())
+    }
+
+    /// MetaPhlAn 格式的 clade 字符串, 例如 `k__Bacteria|p__Proteobacteria|...|s__Escherichia_coli`
+    /// 只保留标准 rank 的祖先节点, 没有任何标准 rank 的祖先时返回 None
+    pub fn metaphlan_clade_string(&self, internal_id: u32) -> Option<String> {
+        if internal_id == 0 || internal_id as usize >= self.nodes.len() {
+            return None;
+        }
+
+        let mut clades = Vec::new();
+        for &id in self.path_to_ancestor(internal_id, 0).iter().rev() {
+            if let Some(prefix) = metaphlan_rank_prefix(self.get_rank(id)?) {
+                clades.push(format!(
+                    "{}{}",
+                    prefix,
+                    self.get_name(id)?.replace(' ', "_")
+                ));
+            }
+        }
+
+        if clades.is_empty() {
+            None
+        } else {
+            Some(clades.join("|"))
+        }
+    }
+
+    /// 为每个叶子节点导出一行 MetaPhlAn3 taxonomy 字符串
+    pub fn export_metaphlan3_taxonomy<W: Write>(&self, writer: &mut W) -> Kr2Result<()> {
+        for (internal_id, node) in self.nodes.iter().enumerate().skip(1) {
+            if node.child_count != 0 {
+                continue;
+            }
+            if let Some(clade) = self.metaphlan_clade_string(internal_id as u32) {
+                writeln!(writer, "{}", clade)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 整棵树的 Newick 字符串, 见 to_newick_from
+    pub fn to_newick(&self) -> String {
+        self.to_newick_from(self.root())
+    }
+
+    /// 以 root_internal_id 为根的子树的 Newick 字符串, 以 `;` 结尾
+    /// 内部节点以学名标注, 叶子节点为 `学名_外部taxid`, 没有学名时使用外部 taxid
+    pub fn to_newick_from(&self, root_internal_id: u32) -> String {
+        let mut newick = String::new();
+        if root_internal_id != 0 && (root_internal_id as usize) < self.nodes.len() {
+            self.write_newick_node(root_internal_id, &mut newick);
+        }
+        newick.push(';');
+        newick
+    }
+
+    fn write_newick_node(&self, internal_id: u32, newick: &mut String) {
+        let node = &self.nodes[internal_id as usize];
+        let first_child = node.first_child as u32;
+        let children = first_child..first_child + node.child_count as u32;
+
+        if !children.is_empty() {
+            newick.push('(');
+            for (i, child) in children.enumerate() {
+                if i > 0 {
+                    newick.push(',');
+                }
+                self.write_newick_node(child, newick);
+            }
+            newick.push(')');
+        }
+
+        let label = match self.get_name(internal_id).filter(|name| !name.is_empty()) {
+            Some(name) if node.child_count == 0 => format!("{}_{}", name, node.external_id),
+            Some(name) => name.to_string(),
+            None => node.external_id.to_string(),
+        };
+        newick.push_str(&newick_label(&label));
+    }
+
+    /// 导出 iTOL 的柱状图 (DATASET_SIMPLEBAR) 注释文件, 每个叶子节点一行, 以外部 taxid 标识
+    /// counts 为内部 taxid 到 read 数的映射, 不在 counts 中的叶子记为 0
+    pub fn export_itol_dataset<W: Write>(
+        &self,
+        counts: &HashMap<u32, u64>,
+        dataset_name: &str,
+        writer: &mut W,
+    ) -> Kr2Result<()> {
+        writeln!(writer, "DATASET_SIMPLEBAR")?;
+        writeln!(writer, "SEPARATOR TAB")?;
+        writeln!(writer, "DATASET_LABEL\t{}", dataset_name)?;
+        writeln!(writer, "COLOR\t#ff0000")?;
+        writeln!(writer, "DATA")?;
+        for (internal_id, node) in self.nodes.iter().enumerate().skip(1) {
+            if node.child_count != 0 {
+                continue;
+            }
+            let count = counts.get(&(internal_id as u32)).copied().unwrap_or(0);
+            writeln!(writer, "{}\t{}", node.external_id, count)?;
+        }
+        Ok(())
+    }
+
+    /// 导出 GraphSON 3.0 格式的图, 每个节点 (不含 0 号哨兵节点) 一个 vertex, 每对父子关系一条 parent_of 边
+    /// vertex 的 id 为外部 taxid, 边从父节点指向子节点
+    pub fn export_graphson<W: Write>(&self, writer: &mut W) -> Kr2Result<()> {
+        use serde_json::json;
+
+        let int64 = |value: u64| json!({"@type": "g:Int64", "@value": value});
+        let property = |id: u64, label: &str, value: serde_json::Value| {
+            json!([{
+                "@type": "g:VertexProperty",
+                "@value": {"id": int64(id), "label": label, "value": value}
+            }])
+        };
+
+        let mut vertices = Vec::with_capacity(self.nodes.len());
+        let mut edges = Vec::with_capacity(self.nodes.len());
+        for (internal_id, node) in self.nodes.iter().enumerate().skip(1) {
+            let internal_id = internal_id as u32;
+            // 属性 id 在整个图中唯一
+            let property_id = node.external_id * 3;
+            vertices.push(json!({
+                "@type": "g:Vertex",
+                "@value": {
+                    "id": int64(node.external_id),
+                    "label": "taxon",
+                    "properties": {
+                        "name": property(property_id, "name", self.get_name(internal_id).unwrap_or("").into()),
+                        "rank": property(property_id + 1, "rank", self.get_rank(internal_id).unwrap_or("").into()),
+                        "external_id": property(property_id + 2, "external_id", int64(node.external_id)),
+                    }
+                }
+            }));
+
+            if node.parent_id == 0 {
+                continue;
+            }
+            let parent_external_id = self.nodes[node.parent_id as usize].external_id;
+            edges.push(json!({
+                "@type": "g:Edge",
+                "@value": {
+                    "id": int64(edges.len() as u64),
+                    "label": "parent_of",
+                    "outV": int64(parent_external_id),
+                    "outVLabel": "taxon",
+                    "inV": int64(node.external_id),
+                    "inVLabel": "taxon",
+                }
+            }));
+        }
+
+        let graph = json!({"vertices": vertices, "edges": edges});
+        serde_json::to_writer(&mut *writer, &graph).map_err(Error::other)?;
+        Ok(writeln!(writer)?)
+    }
+
+    /// 生成带 lineage 的 FASTA 标题行, 如 `>original_id k__Bacteria;p__Firmicutes;...`
+    /// 每个 rank 一项, 名称中的空格替换为 `_`, 祖先中没有该 rank 时名称留空, external_id 不存在时只保留 original_id
+    pub fn reformat_fasta_header(
+        &self,
+        external_id: u64,
+        original_id: &str,
+        ranks: &[&str],
+    ) -> String {
+        let internal_id = match self.get_internal_id_opt(external_id) {
+            Some(internal_id) if internal_id != 0 => internal_id,
+            _ => return format!(">{}", original_id),
+        };
+
+        let lineage: Vec<String> = ranks
+            .iter()
+            .map(|&rank| {
+                let prefix = metaphlan_rank_prefix(rank)
+                    .map_or_else(|| format!("{}__", rank), |prefix| prefix.to_string());
+                let name = self
+                    .node_at_rank(internal_id, rank)
+                    .and_then(|id| self.get_name(id))
+                    .unwrap_or("");
+                format!("{}{}", prefix, name.replace(' ', "_"))
+            })
+            .collect();
+        format!(">{} {}", original_id, lineage.join(";"))
+    }
+
+    /// 把 FASTA 文件中能在 seqid_map (序列 id 到外部 taxid) 中找到的标题行替换为带 lineage 的标题
+    /// 其余内容原样输出, 返回替换的标题数
+    pub fn reformat_fasta_file<R: BufRead, W: Write>(
+        &self,
+        seqid_map: &HashMap<String, u64>,
+        reader: R,
+        mut writer: W,
+        ranks: &[&str],
+    ) -> Kr2Result<usize> {
+        let mut reformatted = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let header = line
+                .strip_prefix('>')
+                .and_then(|header| header.split_whitespace().next())
+                .and_then(|seqid| seqid_map.get(seqid).map(|&taxid| (seqid, taxid)));
+            match header {
+                Some((seqid, taxid)) => {
+                    writeln!(
+                        writer,
+                        "{}",
+                        self.reformat_fasta_header(taxid, seqid, ranks)
+                    )?;
+                    reformatted += 1;
+                }
+                None => writeln!(writer, "{}", line)?,
+            }
+        }
+        writer.flush()?;
+        Ok(reformatted)
+    }
+
+    /// 导出 anvi'o 的 taxonomy 表, seqid_map 为序列 id 到内部 taxid 的映射, 按序列 id 排序输出
+    /// 祖先中不存在的 rank 对应的列留空
+    pub fn export_anvio_taxonomy<W: Write>(
+        &self,
+        seqid_map: &HashMap<String, u32>,
+        writer: &mut W,
+    ) -> Kr2Result<()> {
+        const COLUMNS: [&str; 7] = [
+            "t_domain",
+            "t_phylum",
+            "t_class",
+            "t_order",
+            "t_family",
+            "t_genus",
+            "t_species",
+        ];
+        writeln!(writer, "seq_id\t{}", COLUMNS.join("\t"))?;
+
+        let mut seqids: Vec<(&String, &u32)> = seqid_map.iter().collect();
+        seqids.sort_unstable();
+
+        for (seqid, &internal_id) in seqids {
+            let mut row = [""; COLUMNS.len()];
+            if internal_id != 0 && (internal_id as usize) < self.nodes.len() {
+                for id in self.path_to_ancestor(internal_id, 0) {
+                    let column = match self.get_rank(id) {
+                        Some("superkingdom" | "kingdom" | "domain") => 0,
+                        Some("phylum") => 1,
+                        Some("class") => 2,
+                        Some("order") => 3,
+                        Some("family") => 4,
+                        Some("genus") => 5,
+                        Some("species") => 6,
+                        _ => continue,
+                    };
+                    // 从叶子向根遍历, 保留离叶子最近的同 rank 节点
+                    if row[column].is_empty() {
+                        row[column] = self.get_name(id).unwrap_or("");
+                    }
+                }
+            }
+            writeln!(writer, "{}\t{}", seqid, row.join("\t"))?;
+        }
+
+        Ok(())
+    }
+
+    /// 从根节点下一级到该节点的 (rank, 学名) 序列, id 越界时返回空
+    pub fn get_rank_sequence(&self, internal_id: u32) -> Vec<(&str, &str)> {
+        if internal_id == 0 || internal_id as usize >= self.nodes.len() {
+            return Vec::new();
+        }
+        let mut path = self.path_to_ancestor(internal_id, self.root());
+        if path.is_empty() {
+            path.push(internal_id);
+        }
+        path.iter()
+            .rev()
+            .filter_map(|&id| Some((self.get_rank(id)?, self.get_name(id)?)))
+            .collect()
+    }
+
+    /// 与 get_rank_sequence 相同的 (rank, 学名) 序列, 但不跳过异常节点:
+    /// 缺少学名时用外部 taxid 代替, 缺少 rank 时记为 "no rank"
+    pub fn lineage_with_ranks(&self, internal_id: u32) -> Vec<(String, String)> {
+        if internal_id == 0 || internal_id as usize >= self.nodes.len() {
+            return Vec::new();
+        }
+        let mut path = self.path_to_ancestor(internal_id, self.root());
+        if path.is_empty() {
+            path.push(internal_id);
+        }
+        path.iter()
+            .rev()
+            .map(|&id| {
+                let rank = self.get_rank(id).unwrap_or("no rank").to_string();
+                let name = match self.get_name(id) {
+                    Some(name) if !name.is_empty() => name.to_string(),
+                    _ => self.nodes[id as usize].external_id.to_string(),
+                };
+                (rank, name)
+            })
+            .collect()
+    }
+
+    /// 从根节点下一级到该节点的学名, 以 separator 连接, skip_no_rank 为 true 时跳过 rank 为 "no rank" 的节点
+    pub fn lineage_string(&self, internal_id: u32, separator: &str, skip_no_rank: bool) -> String {
+        self.lineage_with_ranks(internal_id)
+            .into_iter()
+            .filter(|(rank, _)| !(skip_no_rank && rank == "no rank"))
+            .map(|(_, name)| name)
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    /// 导出 QIIME2 taxonomy artifact 兼容的 JSON
+    /// `data` 为序列 id 到 `k__...; p__...; ...` 形式 lineage 的映射, `index` 为序号到序列 id 的映射
+    /// 没有标准 rank 祖先的序列记为 `Unassigned`
+    pub fn export_qiime2_json<W: Write>(
+        &self,
+        seqid_to_internal: &HashMap<String, u32>,
+        writer: &mut W,
+    ) -> Kr2Result<()> {
+        let mut seqids: Vec<(&String, &u32)> = seqid_to_internal.iter().collect();
+        seqids.sort_unstable();
+
+        let mut index = serde_json::Map::new();
+        let mut data = serde_json::Map::new();
+        for (i, (seqid, &internal_id)) in seqids.into_iter().enumerate() {
+            let ranks: Vec<String> = self
+                .get_rank_sequence(internal_id)
+                .into_iter()
+                .filter_map(|(rank, name)| {
+                    metaphlan_rank_prefix(rank).map(|prefix| format!("{}{}", prefix, name))
+                })
+                .collect();
+            let lineage = if ranks.is_empty() {
+                "Unassigned".to_string()
+            } else {
+                ranks.join("; ")
+            };
+            index.insert(i.to_string(), seqid.clone().into());
+            data.insert(seqid.clone(), lineage.into());
+        }
+
+        let mut root = serde_json::Map::new();
+        root.insert("index".to_string(), index.into());
+        root.insert("data".to_string(), data.into());
+        serde_json::to_writer_pretty(&mut *writer, &root).map_err(Error::other)?;
+        Ok(writeln!(writer)?)
+    }
+}
+
+/// 两组 read 计数之间的非加权 UniFrac 距离, 每个非根节点代表它到父节点的一条枝
+/// 距离为只被一组覆盖的枝数除以被任一组覆盖的枝数, 两组都为空时返回 0
+pub fn compute_unifrac_distance(
+    a: &HashMap<u32, u64>,
+    b: &HashMap<u32, u64>,
+    taxonomy: &Taxonomy,
+) -> f64 {
+    let root = taxonomy.root();
+    let branches = |counts: &HashMap<u32, u64>| -> HashSet<u32> {
+        counts
+            .iter()
+            .filter(|(_, &count)| count > 0)
+            .flat_map(|(&taxid, _)| taxonomy.path_to_ancestor(taxid, root))
+            .collect()
+    };
+    let branches_a = branches(a);
+    let branches_b = branches(b);
+
+    let total = branches_a.union(&branches_b).count();
+    if total == 0 {
+        return 0.0;
+    }
+    let unique = branches_a.symmetric_difference(&branches_b).count();
+    unique as f64 / total as f64
+}
+
+/// Newick 标签, 含空白或 Newick 保留字符时用单引号括起, 内部的单引号写成两个
+fn newick_label(label: &str) -> String {
+    if label
+        .chars()
+        .any(|c| c.is_whitespace() || "()[]':;,".contains(c))
+    {
+        format!("'{}'", label.replace('\'', "''"))
+    } else {
+        label.to_string()
+    }
+}
+
+/// GreenGenes rank 前缀对应的 NCBI rank
+fn greengenes_rank(code: &str) -> &'static str {
+    match code {
+        "k" | "d" => "superkingdom",
+        "p" => "phylum",
+        "c" => "class",
+        "o" => "order",
+        "f" => "family",
+        "g" => "genus",
+        "s" => "species",
+        _ => "no rank",
+    }
+}
+
+/// GTDB lineage 前缀代码对应的 rank
+fn gtdb_rank(code: &str) -> &'static str {
+    match code {
+        "d" => "domain",
+        "p" => "phylum",
+        "c" => "class",
+        "o" => "order",
+        "f" => "family",
+        "g" => "genus",
+        "s" => "species",
+        _ => "no rank",
+    }
+}
+
+/// MetaPhlAn 使用的 rank 前缀, 非标准 rank 返回 None
+fn metaphlan_rank_prefix(rank: &str) -> Option<&'static str> {
+    match rank {
+        "superkingdom" | "kingdom" | "domain" => Some("k__"),
+        "phylum" => Some("p__"),
+        "class" => Some("c__"),
+        "order" => Some("o__"),
+        "family" => Some("f__"),
+        "genus" => Some("g__"),
+        "species" => Some("s__"),
+        "strain" => Some("t__"),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_io {
+    use crate::error::{Kr2Error, Kr2Result};
+    use parquet2::compression::CompressionOptions;
+    use parquet2::encoding::Encoding;
+    use parquet2::metadata::{Descriptor, FileMetaData, SchemaDescriptor};
+    use parquet2::page::{split_buffer, DataPage, DataPageHeader, DataPageHeaderV1, Page};
+    use parquet2::read::{decompress, get_page_iterator};
+    use parquet2::schema::types::{
+        ParquetType, PhysicalType, PrimitiveConvertedType, PrimitiveLogicalType,
+    };
+    use parquet2::schema::Repetition;
+    use parquet2::write::{
+        Compressor, DynIter, DynStreamingIterator, FileWriter, Version, WriteOptions,
+    };
+    use std::io::{Error, ErrorKind, Read, Seek, Write};
+
+    /// 导出的列, 依次为 (列名, 物理类型)
+    pub const COLUMNS: [(&str, PhysicalType); 6] = [
+        ("internal_id", PhysicalType::Int32),
+        ("external_id", PhysicalType::Int64),
+        ("parent_external_id", PhysicalType::Int64),
+        ("name", PhysicalType::ByteArray),
+        ("rank", PhysicalType::ByteArray),
+        ("child_count", PhysicalType::Int64),
+    ];
+
+    fn to_kr2_error(e: parquet2::error::Error) -> Kr2Error {
+        Error::new(ErrorKind::InvalidData, e).into()
+    }
+
+    pub fn schema() -> Kr2Result<SchemaDescriptor> {
+        let fields = COLUMNS
+            .iter()
+            .map(|(name, physical_type)| {
+                let (converted_type, logical_type) = match physical_type {
+                    PhysicalType::ByteArray => (
+                        Some(PrimitiveConvertedType::Utf8),
+                        Some(PrimitiveLogicalType::String),
+                    ),
+                    _ => (None, None),
+                };
+                ParquetType::try_from_primitive(
+                    name.to_string(),
+                    *physical_type,
+                    Repetition::Required,
+                    converted_type,
+                    logical_type,
+                    None,
+                )
+                .map_err(to_kr2_error)
+            })
+            .collect::<Kr2Result<Vec<_>>>()?;
+        Ok(SchemaDescriptor::new("taxonomy".to_string(), fields))
+    }
+
+    /// 所有列都是 required, 页中不含 definition/repetition levels, 只有 plain 编码的值
+    fn plain_page(values: Vec<u8>, num_values: usize, descriptor: &Descriptor) -> Page {
+        let header = DataPageHeaderV1 {
+            num_values: num_values as i32,
+            encoding: Encoding::Plain.into(),
+            definition_level_encoding: Encoding::Rle.into(),
+            repetition_level_encoding: Encoding::Rle.into(),
+            statistics: None,
+        };
+        Page::Data(DataPage::new(
+            DataPageHeader::V1(header),
+            values,
+            descriptor.clone(),
+            Some(num_values),
+        ))
+    }
+
+    /// columns 中每一项为一列 plain 编码后的数据
+    pub fn write_columns<W: Write>(
+        writer: W,
+        columns: Vec<Vec<u8>>,
+        num_rows: usize,
+    ) -> Kr2Result<()> {
+        let schema = schema()?;
+        let options = WriteOptions {
+            write_statistics: false,
+            version: Version::V1,
+        };
+        let pages: Vec<Page> = columns
+            .into_iter()
+            .zip(schema.columns())
+            .map(|(values, column)| plain_page(values, num_rows, &column.descriptor))
+            .collect();
+
+        let mut file_writer = FileWriter::new(writer, schema, options, None);
+        let columns = pages.into_iter().map(|page| {
+            Ok(DynStreamingIterator::new(Compressor::new_from_vec(
+                DynIter::new(std::iter::once(Ok(page))),
+                CompressionOptions::Uncompressed,
+                vec![],
+            )))
+        });
+        file_writer
+            .write(DynIter::new(columns))
+            .map_err(to_kr2_error)?;
+        file_writer.end(None).map_err(to_kr2_error)?;
+        Ok(())
+    }
+
+    /// 读取第 column 列所有 plain 编码的值, 按行组顺序拼接
+    pub fn read_column<R: Read + Seek>(
+        reader: &mut R,
+        metadata: &FileMetaData,
+        column: usize,
+    ) -> Kr2Result<Vec<u8>> {
+        let mut values = Vec::new();
+        for row_group in &metadata.row_groups {
+            let column_chunk = row_group.columns().get(column).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("missing parquet column {}", COLUMNS[column].0),
+                )
+            })?;
+            let pages = get_page_iterator(column_chunk, &mut *reader, None, vec![], usize::MAX)
+                .map_err(to_kr2_error)?;
+            for compressed_page in pages {
+                let mut buffer = vec![];
+                let page = decompress(compressed_page.map_err(to_kr2_error)?, &mut buffer)
+                    .map_err(to_kr2_error)?;
+                if let Page::Data(page) = page {
+                    if page.encoding() != Encoding::Plain {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            "only plain encoded parquet pages are supported",
+                        )
+                        .into());
+                    }
+                    let (_, _, page_values) = split_buffer(&page).map_err(to_kr2_error)?;
+                    values.extend_from_slice(page_values);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// 解析 plain 编码的 BYTE_ARRAY 列, 每个值前有 4 字节小端长度
+    pub fn decode_byte_arrays(mut data: &[u8]) -> Kr2Result<Vec<String>> {
+        let mut strings = Vec::new();
+        while !data.is_empty() {
+            let invalid = || Error::new(ErrorKind::InvalidData, "malformed parquet byte array");
+            let len_bytes: [u8; 4] = data.get(..4).ok_or_else(invalid)?.try_into().unwrap();
+            let len = u32::from_le_bytes(len_bytes) as usize;
+            let value = data.get(4..4 + len).ok_or_else(invalid)?;
+            strings.push(String::from_utf8(value.to_vec()).map_err(|_| invalid())?);
+            data = &data[4 + len..];
+        }
+        Ok(strings)
+    }
+
+    pub fn encode_byte_array(buf: &mut Vec<u8>, value: &str) {
+        buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl Taxonomy {
+    /// 以 Parquet 格式导出所有节点 (不含 0 号哨兵节点)
+    pub fn export_parquet<P: AsRef<Path>>(&self, path: P) -> Kr2Result<()> {
+        let mut internal_ids = Vec::new();
+        let mut external_ids = Vec::new();
+        let mut parent_external_ids = Vec::new();
+        let mut names = Vec::new();
+        let mut ranks = Vec::new();
+        let mut child_counts = Vec::new();
+
+        for (internal_id, node) in self.nodes.iter().enumerate().skip(1) {
+            let parent_external_id = self
+                .nodes
+                .get(node.parent_id as usize)
+                .map_or(0, |parent| parent.external_id);
+            internal_ids.extend_from_slice(&(internal_id as i32).to_le_bytes());
+            external_ids.extend_from_slice(&(node.external_id as i64).to_le_bytes());
+            parent_external_ids.extend_from_slice(&(parent_external_id as i64).to_le_bytes());
+            parquet_io::encode_byte_array(
+                &mut names,
+                self.get_name(internal_id as u32).unwrap_or(""),
+            );
+            parquet_io::encode_byte_array(
+                &mut ranks,
+                self.get_rank(internal_id as u32).unwrap_or(""),
+            );
+            child_counts.extend_from_slice(&(node.child_count as i64).to_le_bytes());
+        }
+
+        let file = File::create(path)?;
+        parquet_io::write_columns(
+            std::io::BufWriter::new(file),
+            vec![
+                internal_ids,
+                external_ids,
+                parent_external_ids,
+                names,
+                ranks,
+                child_counts,
+            ],
+            self.nodes.len().saturating_sub(1),
+        )
+    }
+
+    /// 读取 export_parquet 导出的文件, 节点需按 internal_id 的 BFS 顺序排列
+    pub fn from_parquet<P: AsRef<Path>>(path: P) -> Kr2Result<Taxonomy> {
+        let mut reader = BufReader::new(open_file(&path)?);
+        let metadata = parquet2::read::read_metadata(&mut reader)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let mut columns = Vec::with_capacity(parquet_io::COLUMNS.len());
+        for column in 0..parquet_io::COLUMNS.len() {
+            columns.push(parquet_io::read_column(&mut reader, &metadata, column)?);
+        }
+        let ints = |data: &[u8]| -> Vec<i64> {
+            data.chunks_exact(8)
+                .map(|c| i64::from_le_bytes(c.try_into().unwrap()))
+                .collect()
+        };
+        let internal_ids: Vec<i32> = columns[0]
+            .chunks_exact(4)
+            .map(|c| i32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        let external_ids = ints(&columns[1]);
+        let parent_external_ids = ints(&columns[2]);
+        let names = parquet_io::decode_byte_arrays(&columns[3])?;
+        let ranks = parquet_io::decode_byte_arrays(&columns[4])?;
+        let child_counts = ints(&columns[5]);
+
+        let row_count = internal_ids.len();
+        if [
+            external_ids.len(),
+            parent_external_ids.len(),
+            names.len(),
+            ranks.len(),
+            child_counts.len(),
+        ]
+        .iter()
+        .any(|&len| len != row_count)
+            || internal_ids
+                .iter()
+                .enumerate()
+                .any(|(i, &id)| id as usize != i + 1)
+        {
+            return Err(Kr2Error::malformed_taxonomy(
+                path.as_ref(),
+                "parquet columns are inconsistent",
+            ));
+        }
+
+        let records: Vec<NodeRecord> = (0..row_count)
+            .map(|i| NodeRecord {
+                external_id: external_ids[i] as u64,
+                parent_external_id: parent_external_ids[i] as u64,
+                rank: ranks[i].clone(),
+                name: names[i].clone(),
+                child_count: child_counts[i] as u64,
+            })
+            .collect();
+        Self::from_node_records(&records, &format!("{:?}", path.as_ref()))
+    }
+}
+
+/// 不含偏移量的节点记录, 用于 Parquet 和 JSON 的导入导出
+#[cfg(any(feature = "parquet", feature = "json-taxonomy"))]
+#[cfg_attr(
+    feature = "json-taxonomy",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+struct NodeRecord {
+    external_id: u64,
+    parent_external_id: u64,
+    rank: String,
+    name: String,
+    child_count: u64,
+}
+
+#[cfg(any(feature = "parquet", feature = "json-taxonomy"))]
+impl Taxonomy {
+    /// 按 BFS 顺序排列的节点记录 (不含 0 号哨兵节点) 重建 taxonomy, source 用于错误信息
+    fn from_node_records(records: &[NodeRecord], source: &str) -> Kr2Result<Taxonomy> {
+        let mut known_ranks: Vec<&str> =
+            records.iter().map(|record| record.rank.as_str()).collect();
+        known_ranks.sort_unstable();
+        known_ranks.dedup();
+        let mut rank_data = Vec::new();
+        let mut rank_offsets = HashMap::new();
+        for rank in known_ranks {
+            rank_offsets.insert(rank, rank_data.len() as u64);
+            rank_data.extend_from_slice(rank.as_bytes());
+            rank_data.push(b'\0');
+        }
+
+        let mut external_to_internal_id_map = HashMap::new();
+        external_to_internal_id_map.insert(0, 0);
+        for (i, record) in records.iter().enumerate() {
+            external_to_internal_id_map.insert(record.external_id, i as u32 + 1);
+        }
+
+        let mut nodes = Vec::with_capacity(records.len() + 1);
+        nodes.push(TaxonomyNode::default());
+        let mut name_data = Vec::new();
+        // BFS 顺序下, 节点的子节点紧接在之前所有节点的子节点之后
+        let mut next_child = 2u64;
+        for record in records {
+            let parent_id = *external_to_internal_id_map
+                .get(&record.parent_external_id)
+                .unwrap_or(&0) as u64;
+            nodes.push(TaxonomyNode {
+                parent_id,
+                first_child: next_child,
+                child_count: record.child_count,
+                name_offset: name_data.len() as u64,
+                rank_offset: rank_offsets[record.rank.as_str()],
+                external_id: record.external_id,
+                godparent_id: 0,
+            });
+            next_child += record.child_count;
+            name_data.extend_from_slice(record.name.as_bytes());
+            name_data.push(b'\0');
+        }
+
+        let mut taxo = Taxonomy {
+            path_cache: PathCache::new(),
+            nodes,
+            name_data,
+            rank_data,
+            external_to_internal_id_map,
+            rank_index_map: OnceLock::new(),
+            rank_nodes_map: OnceLock::new(),
+        };
+        taxo.check_integrity()
+            .map_err(|e| Kr2Error::malformed_taxonomy(source, e))?;
+        taxo.build_path_cache();
+        Ok(taxo)
+    }
+}
+
+#[cfg(feature = "json-taxonomy")]
+impl Taxonomy {
+    /// 以 JSON 数组导出所有节点 (不含 0 号哨兵节点), 按内部 id 即 BFS 顺序排列, name 和 rank 直接存为字符串
+    pub fn to_json_writer<W: Write>(&self, w: W) -> Kr2Result<()> {
+        let records: Vec<NodeRecord> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(internal_id, node)| NodeRecord {
+                external_id: node.external_id,
+                parent_external_id: self
+                    .nodes
+                    .get(node.parent_id as usize)
+                    .map_or(0, |parent| parent.external_id),
+                rank: self.get_rank(internal_id as u32).unwrap_or("").to_string(),
+                name: self.get_name(internal_id as u32).unwrap_or("").to_string(),
+                child_count: node.child_count,
+            })
+            .collect();
+        Ok(serde_json::to_writer_pretty(w, &records)?)
+    }
+
+    /// 读取 to_json_writer 导出的 JSON, 节点必须按 BFS 顺序排列
+    pub fn from_json_reader<R: Read>(r: R) -> Kr2Result<Taxonomy> {
+        let records: Vec<NodeRecord> =
+            serde_json::from_reader(r).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Self::from_node_records(&records, "json")
+    }
+}
+
+#[cfg(feature = "taxadb")]
+impl Taxonomy {
+    /// 导出 TaxaDB 使用的 SQLite 数据库, 已存在的 taxa 表会被替换
+    /// 与 NCBI 的约定一致, 根节点的父节点为其自身
+    pub fn export_taxadb<P: AsRef<Path>>(&self, path: P) -> Kr2Result<()> {
+        let mut conn = rusqlite::Connection::open(path).map_err(Error::other)?;
+        conn.execute_batch(
+            "DROP TABLE IF EXISTS taxa;
+             CREATE TABLE taxa (
+                 ncbi_taxon_id INTEGER PRIMARY KEY,
+                 parent_ncbi_taxon_id INTEGER NOT NULL,
+                 taxon_name TEXT NOT NULL,
+                 lineage_level TEXT NOT NULL
+             );",
+        )
+        .map_err(Error::other)?;
+
+        let tx = conn.transaction().map_err(Error::other)?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    "INSERT INTO taxa (ncbi_taxon_id, parent_ncbi_taxon_id, taxon_name, lineage_level)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )
+                .map_err(Error::other)?;
+
+            // 跳过 0 号哨兵节点
+            for (internal_id, node) in self.nodes.iter().enumerate().skip(1) {
+                let parent_external_id = match node.parent_id {
+                    0 => node.external_id,
+                    parent_id => self
+                        .nodes
+                        .get(parent_id as usize)
+                        .map_or(0, |parent| parent.external_id),
+                };
+                stmt.execute(rusqlite::params![
+                    node.external_id as i64,
+                    parent_external_id as i64,
+                    self.get_name(internal_id as u32).unwrap_or(""),
+                    self.get_rank(internal_id as u32).unwrap_or(""),
+                ])
+                .map_err(Error::other)?;
+            }
+        }
+        tx.commit().map_err(Error::other)?;
+
+        Ok(())
+    }
+}
+
+/// 由 (parent_id, external_id) 列表按 BFS 顺序构建分类树, 列表从 1 号根节点开始, 父节点为 0 表示根
+#[cfg(test)]
+pub(crate) fn test_taxonomy(nodes: &[(u32, u64)]) -> Taxonomy {
+    let mut taxo = Taxonomy::default();
+    taxo.nodes.push(TaxonomyNode::default());
+    for &(parent_id, external_id) in nodes {
+        taxo.nodes.push(TaxonomyNode {
+            parent_id: parent_id as u64,
+            external_id,
+            ..Default::default()
+        });
+    }
+    for id in (1..taxo.nodes.len()).rev() {
+        let parent_id = taxo.nodes[id].parent_id as usize;
+        if parent_id != 0 && parent_id != id {
+            taxo.nodes[parent_id].first_child = id as u64;
+            taxo.nodes[parent_id].child_count += 1;
+        }
+    }
+    taxo.generate_external_to_internal_id_map();
+    taxo.build_path_cache();
+    taxo
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1 ─┬─ 2 ─┬─ 4 ── 7
+    ///    │     └─ 5
+    ///    └─ 3 ── 6
+    fn small_taxonomy() -> Taxonomy {
+        test_taxonomy(&[
+            (0, 1),
+            (1, 10),
+            (1, 20),
+            (2, 100),
+            (2, 101),
+            (3, 200),
+            (4, 1000),
+        ])
+    }
+
+    /// NCBI 格式的分类树, 2 另有一个同义名:
+    /// 1 (no rank) ── 2 (superkingdom) ─┬─ 10 (genus) ─┬─ 100 (species)
+    ///                                  │              └─ 101 (species)
+    ///                                  └─ 20 (genus) ── 200 (species)
+    fn ncbi_fixture(name: &str) -> NCBITaxonomy {
+        let nodes = [
+            (1, 1, "no rank"),
+            (2, 1, "superkingdom"),
+            (10, 2, "genus"),
+            (20, 2, "genus"),
+            (100, 10, "species"),
+            (101, 10, "species"),
+            (200, 20, "species"),
+        ];
+        let names = [
+            (1, "root", "scientific name"),
+            (2, "Bacteria", "scientific name"),
+            (2, "eubacteria", "synonym"),
+            (10, "Alpha", "scientific name"),
+            (20, "Beta", "scientific name"),
+            (100, "Alpha one", "scientific name"),
+            (101, "Alpha two", "scientific name"),
+            (200, "Beta one", "scientific name"),
+        ];
+        write_ncbi(name, &nodes, &names)
+    }
+
+    /// 写出 nodes.dmp 和 names.dmp 并读取, nodes 为 (taxid, parent, rank), names 为 (taxid, 名称, 名称类型)
+    fn write_ncbi(
+        name: &str,
+        nodes: &[(u64, u64, &str)],
+        names: &[(u64, &str, &str)],
+    ) -> NCBITaxonomy {
+        let dir = crate::utils::test_dir(name);
+        let nodes_dmp: String = nodes
+            .iter()
+            .map(|(taxid, parent, rank)| format!("{}\t|\t{}\t|\t{}\t|\t\t|\n", taxid, parent, rank))
+            .collect();
+        let names_dmp: String = names
+            .iter()
+            .map(|(taxid, name, class)| format!("{}\t|\t{}\t|\t\t|\t{}\t|\n", taxid, name, class))
+            .collect();
+        std::fs::write(dir.join("nodes.dmp"), nodes_dmp).unwrap();
+        std::fs::write(dir.join("names.dmp"), names_dmp).unwrap();
+        NCBITaxonomy::from_ncbi(dir.join("nodes.dmp"), dir.join("names.dmp")).unwrap()
+    }
+
+    /// ncbi_fixture 标记所有叶子后转换得到的分类树, 与 generate_taxonomy 的流程相同
+    fn converted_taxonomy(name: &str) -> Taxonomy {
+        let mut ncbi = ncbi_fixture(name);
+        for taxid in [100, 101, 200] {
+            ncbi.mark_node(taxid);
+        }
+        let mut taxo = ncbi.convert_to_kraken_taxonomy();
+        taxo.generate_external_to_internal_id_map();
+        taxo.build_path_cache();
+        taxo
+    }
+
+    #[test]
+    fn from_file_parallel_matches_from_file() {
+        let dir = crate::utils::test_dir("from_file_parallel");
+        let taxo = small_taxonomy();
+        let legacy = dir.join("legacy.k2d");
+        let versioned = dir.join("versioned.k2d");
+        taxo.write_to_disk(&legacy).unwrap();
+        taxo.write_to_disk_versioned(&versioned).unwrap();
+
+        for path in [&legacy, &versioned] {
+            let sequential = Taxonomy::from_file(path).unwrap();
+            for threads in [1, 4] {
+                let parallel = Taxonomy::from_file_parallel(path, threads).unwrap();
+                assert!(parallel == sequential);
+                assert_eq!(parallel.path_cache, sequential.path_cache);
+            }
+        }
+
+        let truncated = dir.join("truncated.k2d");
+        let data = std::fs::read(&versioned).unwrap();
+        std::fs::write(&truncated, &data[..data.len() - 1]).unwrap();
+        assert!(Taxonomy::from_file(&truncated).is_err());
+        assert!(Taxonomy::from_file_parallel(&truncated, 4).is_err());
+    }
+
+    #[test]
+    fn root_is_not_hard_coded() {
+        // 内部 id 2 为根, 1 是它的子节点
+        let taxo = test_taxonomy(&[(2, 10), (0, 1), (1, 100)]);
+        assert_eq!(taxo.root(), 2);
+        assert_eq!(taxo.lca_of_set(&[1, 2]), 2);
+        assert_eq!(taxo.find_lca_set_parallel(&[vec![3, 1, 2]]), vec![2]);
+        let lineage: Vec<String> = taxo
+            .lineage_with_ranks(3)
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect();
+        assert_eq!(lineage, vec!["10", "100"]);
+        assert_eq!(taxo.to_newick(), "((100)10)1;");
+    }
+
+    #[test]
+    fn legacy_format_rederives_godparents() {
+        let dir = crate::utils::test_dir("legacy_godparents");
+        let mut taxo = small_taxonomy();
+        let legacy = dir.join("legacy.k2d");
+        taxo.write_to_disk(&legacy).unwrap();
+        let loaded = Taxonomy::from_file(&legacy).unwrap();
+        let godparents: Vec<u64> = loaded.nodes.iter().map(|node| node.godparent_id).collect();
+        assert_eq!(godparents, vec![0, 0, 1, 1, 2, 2, 3, 4]);
+
+        // 带版本号的格式保留写入的 godparent_id
+        taxo.assign_godparents(&HashSet::from([1, 6]));
+        let versioned = dir.join("versioned.k2d");
+        taxo.write_to_disk_versioned(&versioned).unwrap();
+        let loaded = Taxonomy::from_file(&versioned).unwrap();
+        let godparents: Vec<u64> = loaded.nodes.iter().map(|node| node.godparent_id).collect();
+        assert_eq!(godparents, vec![0, 0, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn legacy_layout_is_unchanged_with_godparents() {
+        let dir = crate::utils::test_dir("legacy_layout");
+        let mut taxo = small_taxonomy();
+        taxo.assign_godparents(&HashSet::from([1, 6]));
+        let path = dir.join("legacy.k2d");
+        taxo.write_to_disk(&path).unwrap();
+
+        let mut expected = Taxonomy::MAGIC.to_vec();
+        for len in [taxo.nodes.len(), 0, 0] {
+            expected.extend_from_slice(&(len as u64).to_le_bytes());
+        }
+        for node in &taxo.nodes {
+            for field in [
+                node.parent_id,
+                node.first_child,
+                node.child_count,
+                node.name_offset,
+                node.rank_offset,
+                node.external_id,
+                node.godparent_id,
+            ] {
+                expected.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn conversion_assigns_godparents() {
+        let mut taxo = converted_taxonomy("conversion_godparents");
+        let externals: Vec<u64> = taxo.nodes.iter().map(|node| node.external_id).collect();
+        assert_eq!(externals, vec![0, 1, 2, 10, 20, 100, 101, 200]);
+        let parents: Vec<u64> = taxo.nodes.iter().map(|node| node.parent_id).collect();
+        assert_eq!(parents, vec![0, 0, 1, 2, 2, 3, 3, 4]);
+
+        // 只有 2 (Bacteria) 和 10 (Alpha) 有 k-mer
+        let with_kmers: HashSet<u32> = [2, 10]
+            .iter()
+            .filter_map(|&taxid| taxo.get_internal_id_opt(taxid))
+            .collect();
+        assert_eq!(with_kmers.len(), 2);
+        taxo.assign_godparents(&with_kmers);
+        let godparents: Vec<u64> = taxo.nodes.iter().map(|node| node.godparent_id).collect();
+        assert_eq!(godparents, vec![0, 0, 0, 2, 2, 3, 3, 2]);
+    }
+
+    #[test]
+    fn conversion_takes_root_from_parent_map() {
+        // 根节点的 taxid 为 7, taxid 1 是普通的叶子
+        let mut ncbi = write_ncbi(
+            "conversion_root",
+            &[(7, 7, "no rank"), (3, 7, "genus"), (1, 3, "species")],
+            &[
+                (7, "cellular organisms", "scientific name"),
+                (3, "Gamma", "scientific name"),
+                (1, "Gamma one", "scientific name"),
+            ],
+        );
+        assert_eq!(ncbi.root_taxid(), 7);
+        ncbi.mark_node(1);
+        let mut taxo = ncbi.convert_to_kraken_taxonomy();
+        taxo.generate_external_to_internal_id_map();
+        taxo.build_path_cache();
+        assert!(taxo.check_integrity().is_ok());
+
+        let externals: Vec<u64> = taxo.nodes.iter().map(|node| node.external_id).collect();
+        assert_eq!(externals, vec![0, 7, 3, 1]);
+        assert_eq!(taxo.root(), 1);
+        assert_eq!(taxo.get_name(3), Some("Gamma one"));
+
+        taxo.assign_godparents(&HashSet::from([1, 2, 3]));
+        let godparents: Vec<u64> = taxo.nodes.iter().map(|node| node.godparent_id).collect();
+        assert_eq!(godparents, vec![0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn find_lca_set_parallel_matches_serial() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<Taxonomy>();
+
+        let taxo = small_taxonomy();
+        let hit_sets = vec![
+            vec![],
+            vec![0],
+            vec![7],
+            vec![7, 5],
+            vec![0, 7, 5, 0],
+            vec![7, 6],
+            vec![5, 4, 2],
+            vec![99, 7],
+        ];
+        let serial: Vec<u32> = hit_sets.iter().map(|ids| taxo.lca_of_set(ids)).collect();
+        assert_eq!(serial, vec![0, 0, 7, 2, 2, 1, 2, 0]);
+        assert_eq!(taxo.find_lca_set_parallel(&hit_sets), serial);
+    }
+
+    /// small_taxonomy 加上学名 `n<外部taxid>` 和 rank
+    fn named_taxonomy() -> Taxonomy {
+        let mut taxo = small_taxonomy();
+        let ranks = [
+            "no rank", "genus", "genus", "species", "species", "species", "strain",
+        ];
+        for id in 1..taxo.nodes.len() {
+            taxo.nodes[id].name_offset = taxo.name_data.len() as u64;
+            let name = format!("n{}", taxo.nodes[id].external_id);
+            taxo.name_data.extend_from_slice(name.as_bytes());
+            taxo.name_data.push(0);
+            taxo.nodes[id].rank_offset = taxo.rank_data.len() as u64;
+            taxo.rank_data.extend_from_slice(ranks[id - 1].as_bytes());
+            taxo.rank_data.push(0);
+        }
+        taxo
+    }
+
+    #[test]
+    fn clone_subtree_is_independent() {
+        let taxo = named_taxonomy();
+        let mut subtree = taxo.clone_subtree(2);
+        assert!(subtree.check_integrity().is_ok());
+        assert_eq!(subtree.node_count(), 5);
+        assert_eq!(subtree.nodes[1].parent_id, 0);
+        assert_eq!(subtree.nodes[1].external_id, 10);
+        let externals: Vec<u64> = subtree.nodes[1..].iter().map(|n| n.external_id).collect();
+        assert_eq!(externals, vec![10, 100, 101, 1000]);
+        assert_eq!(subtree.get_name_by_external_id(1000), Some("n1000"));
+        assert_eq!(
+            subtree.get_rank(subtree.get_internal_id_opt(1000).unwrap()),
+            Some("strain")
+        );
+        // 只保留子树引用的字符串
+        assert!(!subtree.name_data.windows(3).any(|w| w == b"n20"));
+
+        let leaf = subtree.get_internal_id_opt(1000).unwrap();
+        assert_eq!(
+            subtree.lca(leaf, subtree.get_internal_id_opt(101).unwrap()),
+            1
+        );
+        subtree.nodes[leaf as usize].external_id = 9999;
+        subtree.name_data[0] = b'x';
+        assert_eq!(taxo.nodes[7].external_id, 1000);
+        assert_eq!(taxo.get_name(1), Some("n1"));
+
+        assert_eq!(
+            taxo.clone_subtree(0).node_count(),
+            taxo.clone_subtree(99).node_count()
+        );
+    }
+
+    #[test]
+    fn add_subtree_renumbers_and_keeps_names_and_godparents() {
+        let mut taxo = named_taxonomy();
+        taxo.nodes[2].godparent_id = 7;
+        let mut donor = named_taxonomy();
+        for node in donor.nodes.iter_mut().skip(1) {
+            node.external_id += 5000;
+        }
+        donor.nodes[7].godparent_id = 4;
+        donor.generate_external_to_internal_id_map();
+
+        let id_map = taxo.add_subtree(&donor, 2, 3).unwrap();
+        assert!(taxo.check_integrity().is_ok());
+        assert_eq!(taxo.node_count(), 12);
+        let externals: Vec<u64> = taxo.nodes[1..].iter().map(|n| n.external_id).collect();
+        assert_eq!(
+            externals,
+            vec![1, 10, 20, 100, 101, 200, 5010, 1000, 5100, 5101, 6000]
+        );
+        // 挂在 3 下的子树插在 1000 之前, 只有原来的 7 被重新编号
+        assert_eq!(id_map.len(), 7);
+        assert_eq!(id_map[&7], 8);
+        assert!((1..7).all(|id| id_map[&id] == id));
+
+        assert_eq!(taxo.nodes[7].parent_id, 3);
+        assert_eq!(taxo.get_name_by_external_id(6000), Some("n1000"));
+        assert_eq!(taxo.get_name_by_external_id(1000), Some("n1000"));
+        assert_eq!(taxo.get_rank(11), Some("strain"));
+        assert_eq!(taxo.get_rank(7), Some("genus"));
+        assert_eq!(taxo.nodes[11].godparent_id, 9);
+        assert_eq!(taxo.nodes[2].godparent_id, 8);
+    }
+
+    #[test]
+    fn add_subtree_rejects_conflicts_and_bad_ids() {
+        let mut taxo = named_taxonomy();
+        let original = taxo.clone();
+        let donor = named_taxonomy();
+
+        let err = taxo.add_subtree(&donor, 4, 3).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("100"));
+        for (subtree_root, attach_to) in [(0, 3), (99, 3), (2, 0), (2, 99)] {
+            let err = taxo
+                .add_subtree(&donor, subtree_root, attach_to)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        assert_eq!(taxo, original);
+    }
+
+    #[test]
+    fn extract_subtree_keeps_root_unchanged() {
+        let taxo = named_taxonomy();
+        assert_eq!(taxo.extract_subtree(1), taxo);
+        // clone_subtree 会合并重复的 rank 字符串
+        assert_ne!(taxo.clone_subtree(1), taxo);
+
+        let subtree = taxo.extract_subtree(2);
+        assert_eq!(subtree, taxo.clone_subtree(2));
+        let path = crate::utils::test_dir("extract_subtree").join("taxo.k2d");
+        // 旧格式读取时会重新推导 godparent, 用带版本号的格式比较完整内容
+        subtree.write_to_disk_versioned(&path).unwrap();
+        let loaded = Taxonomy::from_file(&path).unwrap();
+        assert_eq!(loaded, subtree);
+        assert_eq!(loaded.get_name_by_external_id(1000), Some("n1000"));
+    }
+
+    #[test]
+    fn clone_equals_original_until_mutated() {
+        let taxo = named_taxonomy();
+        let mut copy = taxo.clone();
+        assert_eq!(copy, taxo);
+
+        // path_cache 由节点决定, 不参与比较
+        copy.path_cache.clear();
+        assert_eq!(copy, taxo);
+
+        copy.nodes[7].parent_id = 3;
+        assert_ne!(copy, taxo);
+        assert_eq!(taxo.nodes[7].parent_id, 4);
+
+        let mut copy = taxo.clone();
+        copy.name_data.push(b'x');
+        assert_ne!(copy, taxo);
+
+        let mut copy = taxo.clone();
+        copy.external_to_internal_id_map.insert(5000, 7);
+        assert_ne!(copy, taxo);
+        assert_ne!(taxo, small_taxonomy());
+    }
+
+    #[test]
+    fn node_bytes_are_little_endian() {
+        let mut buf = Vec::new();
+        for field in 1..=7u64 {
+            buf.extend_from_slice(&(field << 56 | field).to_le_bytes());
+        }
+        let node = TaxonomyNode::from_le_bytes(&buf);
+        assert_eq!(node.parent_id, 1 << 56 | 1);
+        assert_eq!(node.rank_offset, 5 << 56 | 5);
+        assert_eq!(node.godparent_id, 7 << 56 | 7);
+    }
+
+    /// 文件内容与主机字节序无关: 在小端和大端平台上写出的字节相同, 读回的分类树相同
+    #[test]
+    fn file_round_trip_is_host_endian_independent() {
+        let dir = crate::utils::test_dir("taxonomy_round_trip");
+        let taxo = named_taxonomy();
+        let path = dir.join("taxo.k2d");
+        taxo.write_to_disk_versioned(&path).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        let header_len = Taxonomy::VERSIONED_MAGIC.len() + 4 + 24;
+        assert_eq!(data[8..12], TAXONOMY_VERSION.to_le_bytes());
+        let node_count = u64::from_le_bytes(data[12..20].try_into().unwrap());
+        assert_eq!(node_count, taxo.nodes.len() as u64);
+        let leaf = header_len + 7 * TaxonomyNode::SERIALIZED_SIZE;
+        assert_eq!(data[leaf..leaf + 8], 4u64.to_le_bytes());
+        assert_eq!(data[leaf + 40..leaf + 48], 1000u64.to_le_bytes());
+
+        let loaded = Taxonomy::from_file(&path).unwrap();
+        assert_eq!(loaded, taxo);
+        assert_eq!(loaded.get_name(7), Some("n1000"));
+
+        // 长度与头部不一致时报错
+        std::fs::write(&path, [&data[..], b"x"].concat()).unwrap();
+        let err = Taxonomy::from_file(&path).unwrap_err().to_string();
+        assert!(err.contains("header describes"), "{}", err);
+    }
+
+    /// 五个节点的二叉树: 1 ─┬─ 2 ─┬─ 4
+    ///                     │     └─ 5
+    ///                     └─ 3
+    fn binary_taxonomy() -> Taxonomy {
+        test_taxonomy(&[(0, 1), (1, 2), (1, 3), (2, 4), (2, 5)])
+    }
+
+    #[test]
+    fn parsimony_score_weights_depth() {
+        let taxo = binary_taxonomy();
+        let counts = HashMap::from([(4, 2), (3, 1), (1, 5)]);
+        assert_eq!(taxo.compute_parsimony_score(&counts, 1), 5.0);
+        assert_eq!(taxo.compute_parsimony_score(&HashMap::new(), 1), 0.0);
+    }
+
+    #[test]
+    fn parsimony_score_ignores_unknown_taxids() {
+        let taxo = binary_taxonomy();
+        let counts = HashMap::from([(4, 2), (99, 7), (u32::MAX, 1)]);
+        assert_eq!(taxo.compute_parsimony_score(&counts, 1), 4.0);
+    }
+
+    #[test]
+    fn parsimony_score_ignores_taxids_outside_root() {
+        let taxo = binary_taxonomy();
+        // 3 和 1 不在 2 的子树中, 5 在子树中且深度为 1
+        let counts = HashMap::from([(3, 4), (1, 1), (5, 3), (2, 10)]);
+        assert_eq!(taxo.compute_parsimony_score(&counts, 2), 3.0);
+    }
+
+    #[test]
+    fn unifrac_distance_counts_unique_branches() {
+        let taxo = binary_taxonomy();
+        let a = HashMap::from([(4, 1)]);
+        let b = HashMap::from([(5, 3)]);
+        // 分支 {4, 2} 和 {5, 2}, 共 3 条, 其中 2 条只出现在一侧
+        assert!((compute_unifrac_distance(&a, &b, &taxo) - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(compute_unifrac_distance(&a, &a, &taxo), 0.0);
+        assert_eq!(
+            compute_unifrac_distance(&a, &HashMap::from([(3, 1)]), &taxo),
+            1.0
+        );
+        // 不存在的 taxid 和 read 数为 0 的 taxid 不贡献分支
+        let a_unknown = HashMap::from([(4, 1), (99, 1), (3, 0)]);
+        assert_eq!(compute_unifrac_distance(&a_unknown, &a, &taxo), 0.0);
+        assert_eq!(
+            compute_unifrac_distance(&HashMap::new(), &HashMap::new(), &taxo),
+            0.0
+        );
+    }
+
+    #[test]
+    fn lca_of_set_edge_cases() {
+        let taxo = small_taxonomy();
+        assert_eq!(taxo.lca_of_set(&[]), 0);
+        assert_eq!(taxo.lca_of_set(&[0, 0]), 0);
+        assert_eq!(taxo.lca_of_set(&[7]), 7);
+        assert_eq!(taxo.lca_of_set(&[0, 7, 0, 5]), 2);
+        assert_eq!(taxo.lca_of_set(&[7, 6, 5]), 1);
+        assert_eq!(taxo.lca_of_external_ids(&[1000, 101]), 10);
+        // 不在分类树中的 taxid 被忽略
+        assert_eq!(taxo.lca_of_external_ids(&[1000, 424242]), 1000);
+        assert_eq!(taxo.lca_of_external_ids(&[424242]), 0);
+    }
+
+    /// 根节点下是一条 DEPTH 层的链和 100k 个叶子, 共 100k 个以上节点
+    /// path_cache 为每个节点保存完整路径, 10 万层的纯链需要约 20 GB 内存, 所以链的深度较小,
+    /// 改为在很小的栈上构建: 递归实现在几千层时就会栈溢出
+    #[test]
+    fn deep_chain_builds_without_recursion() {
+        const DEPTH: u32 = 5_000;
+        const LEAVES: u32 = 100_000;
+        // 1 为根, 2 为链的第一个节点, 3..LEAVES + 3 为根的叶子, 之后是链上的其余节点
+        let chain_start = LEAVES + 3;
+        let mut parents = vec![(0, 1), (1, 2)];
+        parents.extend((0..LEAVES).map(|i| (1, 1_000_000 + i as u64)));
+        parents.push((2, 3));
+        parents.extend((1..DEPTH - 1).map(|i| (chain_start + i - 1, 3 + i as u64)));
+        // 根的 external_id 不是 1 也能找到
+        parents[0].1 = 7;
+
+        let taxo = std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || test_taxonomy(&parents))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(taxo.node_count() as u32, LEAVES + DEPTH + 2);
+        assert_eq!(taxo.path_cache.len() as u32, LEAVES + DEPTH + 1);
+        let deepest = taxo.node_count() as u32 - 1;
+        assert_eq!(taxo.path_cache[&deepest].len() as u32, DEPTH + 1);
+        assert_eq!(taxo.lca(deepest, 3), 1);
+        assert_eq!(taxo.lca(deepest, chain_start), chain_start);
+    }
+
+    #[test]
+    fn path_cache_does_not_depend_on_thread_count() {
+        // 节点 i 的父节点为 (i + 1) / 3, 共 1000 个节点, 分布在所有分片中
+        let mut parents = vec![(0, 1)];
+        parents.extend((2..=1000u32).map(|i| ((i + 1) / 3, i as u64)));
+        let build = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| test_taxonomy(&parents))
+        };
+        let taxo = build(1);
+        assert_eq!(taxo.path_cache.len(), 1000);
+        for (&id, path) in &taxo.path_cache {
+            assert_eq!(path.last(), Some(&id));
+            let parent_id = taxo.nodes[id as usize].parent_id as u32;
+            assert_eq!(path.iter().rev().nth(1).copied().unwrap_or(0), parent_id);
+        }
+        assert_eq!(build(4).path_cache, taxo.path_cache);
+    }
+
+    #[test]
+    fn lowest_common_ancestor_of_disconnected_components() {
+        // 两个互不相连的根节点
+        let taxo = test_taxonomy(&[(0, 1), (0, 2)]);
+        assert_eq!(taxo.lowest_common_ancestor(1, 2), 0);
+        assert_eq!(taxo.lowest_common_ancestor(2, 1), 0);
+        assert_eq!(taxo.lowest_common_ancestor(1, 1), 1);
+        assert_eq!(taxo.lowest_common_ancestor(0, 2), 2);
+
+        // 1 ── 2 和 3 ── 4, 3 的父节点是自身
+        let mut taxo = test_taxonomy(&[(0, 1), (1, 10), (0, 3), (3, 30)]);
+        taxo.nodes[3].parent_id = 3;
+        assert_eq!(taxo.lowest_common_ancestor(2, 4), 0);
+        assert_eq!(taxo.lowest_common_ancestor(4, 2), 0);
+        assert_eq!(taxo.lowest_common_ancestor(4, 3), 3);
+
+        let taxo = small_taxonomy();
+        for a in 0..taxo.node_count() as u32 {
+            for b in 0..taxo.node_count() as u32 {
+                assert_eq!(taxo.lowest_common_ancestor(a, b), taxo.lca(a, b));
+            }
+        }
+    }
+
+    #[test]
+    fn batch_lca_matches_lca() {
+        let taxo = small_taxonomy();
+        let ids = 0..=taxo.nodes.len() as u32;
+        let pairs: Vec<(u32, u32)> = ids
+            .clone()
+            .flat_map(|a| ids.clone().map(move |b| (a, b)))
+            .collect();
+        let expected: Vec<u32> = pairs.iter().map(|&(a, b)| taxo.lca(a, b)).collect();
+        assert_eq!(taxo.batch_lca(&pairs), expected);
+        assert_eq!(taxo.batch_lca(&[(7, 5), (7, 6), (5, 4)]), vec![2, 1, 2]);
+        assert!(taxo.batch_lca(&[]).is_empty());
+    }
+
+    #[test]
+    fn common_prefix_len_crosses_lanes() {
+        let a: Vec<u32> = (0..20).collect();
+        for len in 0..20 {
+            let mut b = a.clone();
+            b[len] = 99;
+            assert_eq!(common_prefix_len(&a, &b), len);
+            assert_eq!(common_prefix_len(&a[..len], &a), len);
+        }
+        assert_eq!(common_prefix_len(&a, &a), a.len());
+    }
+
+    #[test]
+    fn megan6_summary() {
+        let taxo = converted_taxonomy("megan6_summary");
+        let counts = HashMap::from([(0, 3), (5, 5), (7, 2), (3, 0)]);
+        let mut out = Vec::new();
+        taxo.export_megan6(&counts, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "@ContentType\tSummary4\n@Names\tkun_peng\n@Reads\t10\n@Assignments\tTaxonomy\n\
+             TAX\t-1\t3\nTAX\t100\t5\nTAX\t200\t2\n"
+        );
+    }
+
+    #[test]
+    fn taxonkit_list_follows_indentation() {
+        let input = "1 [no rank] root\n  2 [superkingdom] Bacteria\n    10 [genus] Alpha\n      100 [species] Alpha one\n\n    20 [genus] Beta\n  3 Unranked thing\n";
+        let ncbi = NCBITaxonomy::from_taxonkit_list(input.as_bytes()).unwrap();
+        let parents: BTreeMap<u64, u64> = ncbi.parent_map.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(
+            parents,
+            BTreeMap::from([(1, 0), (2, 1), (3, 1), (10, 2), (20, 2), (100, 10)])
+        );
+        assert_eq!(ncbi.get_name(100), Some("Alpha one"));
+        assert_eq!(ncbi.get_rank(100), Some("species"));
+        assert_eq!(ncbi.get_name(3), Some("Unranked thing"));
+        assert_eq!(ncbi.get_rank(3), Some("no rank"));
+        assert_eq!(ncbi.marked_nodes.len(), 6);
+
+        // 不含根节点时顶层节点挂到补充的根节点 1 下
+        let ncbi =
+            NCBITaxonomy::from_taxonkit_list("  2 [genus] X\n  3 [genus] Y\n".as_bytes()).unwrap();
+        assert_eq!(ncbi.parent_map[&2], 1);
+        assert_eq!(ncbi.parent_map[&3], 1);
+        assert_eq!(ncbi.parent_map[&1], 0);
+        assert_eq!(ncbi.get_name(1), Some("root"));
+    }
+
+    #[test]
+    fn taxonkit_list_rejects_bad_taxid() {
+        let input = "1 [no rank] root\n  x2 [genus] Broken\n";
+        let err = NCBITaxonomy::from_taxonkit_list(input.as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn custom_tsv_uses_configured_columns() {
+        let dir = crate::utils::test_dir("custom_tsv");
+        let config = CustomTsvConfig {
+            taxid_col: 2,
+            parent_col: 3,
+            name_col: 0,
+            rank_col: Some(1),
+            separator: ',',
+            has_header: true,
+        };
+        let path = dir.join("taxonomy.csv");
+        std::fs::write(
+            &path,
+            "name,rank,taxid,parent\nBacteria,superkingdom,2,0\n# comment\nAlpha,genus,10,2\nAlpha one,species,100,10\n",
+        )
+        .unwrap();
+        let ncbi = NCBITaxonomy::from_custom_tsv(&path, config.clone()).unwrap();
+        let parents: BTreeMap<u64, u64> = ncbi.parent_map.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(
+            parents,
+            BTreeMap::from([(1, 0), (2, 1), (10, 2), (100, 10)])
+        );
+        assert_eq!(ncbi.get_name(100), Some("Alpha one"));
+        assert_eq!(ncbi.get_rank(10), Some("genus"));
+
+        std::fs::write(&path, "name,rank,taxid,parent\nAlpha,genus,10\n").unwrap();
+        let err = NCBITaxonomy::from_custom_tsv(&path, config.clone())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("missing column 3 at line 2"));
+
+        std::fs::write(&path, "name,rank,taxid,parent\nAlpha,genus,x10,2\n").unwrap();
+        let err = NCBITaxonomy::from_custom_tsv(&path, config).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .contains("invalid taxid in column 2 at line 2"));
+    }
+
+    #[test]
+    fn mmap_verified_detects_corruption() {
+        let dir = crate::utils::test_dir("mmap_verified");
+        let taxo = named_taxonomy();
+        let path = dir.join("taxo.k2d");
+        taxo.write_to_disk_with_checksum(&path).unwrap();
+        assert_eq!(Taxonomy::from_file_mmap_verified(&path).unwrap(), taxo);
+
+        let data = std::fs::read(&path).unwrap();
+        // 分别破坏末尾的校验和和节点数据
+        for index in [data.len() - 1, Taxonomy::VERSIONED_MAGIC.len() + 40] {
+            let mut corrupted = data.clone();
+            corrupted[index] ^= 0x01;
+            std::fs::write(&path, &corrupted).unwrap();
+            let err = Taxonomy::from_file_mmap_verified(&path).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(err.to_string().contains("Checksum mismatch"));
+        }
+
+        std::fs::write(&path, &data[..Taxonomy::CHECKSUM_LEN - 1]).unwrap();
+        let err = Taxonomy::from_file_mmap_verified(&path).unwrap_err();
+        assert!(err.to_string().contains("too short"));
+    }
+
+    #[test]
+    fn greengenes_lineages_stop_at_empty_levels() {
+        let dir = crate::utils::test_dir("greengenes");
+        let path = dir.join("taxonomy.txt");
+        std::fs::write(
+            &path,
+            "OTU1\tk__Bacteria; p__Proteobacteria; c__; o__; f__; g__; s__\n\
+             OTU2\tk__Bacteria; p__Proteobacteria; c__Gamma; o__Entero\n\
+             OTU3\tk__Bacteria; p__Firmicutes\n\
+             OTU4\tk__Archaea; p__Proteobacteria\n",
+        )
+        .unwrap();
+        let ncbi = NCBITaxonomy::from_greengenes(&path).unwrap();
+        let parents: BTreeMap<u64, u64> = ncbi.parent_map.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(
+            parents,
+            BTreeMap::from([
+                (1, 0),
+                (2, 1),
+                (3, 2),
+                (4, 3),
+                (5, 4),
+                (6, 2),
+                (7, 1),
+                (8, 7)
+            ])
+        );
+        assert_eq!(ncbi.get_name(2), Some("Bacteria"));
+        assert_eq!(ncbi.get_rank(2), Some("superkingdom"));
+        assert_eq!(ncbi.get_name(5), Some("Entero"));
+        assert_eq!(ncbi.get_rank(5), Some("order"));
+        // 同名节点按完整 lineage 区分
+        assert_eq!(ncbi.get_name(8), Some("Proteobacteria"));
+        assert_eq!(ncbi.get_rank(8), Some("phylum"));
+        assert_eq!(ncbi.marked_nodes.len(), 8);
+
+        std::fs::write(&path, "OTU1 k__Bacteria\n").unwrap();
+        let err = NCBITaxonomy::from_greengenes(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("missing lineage column at line 1"));
+    }
+
+    #[test]
+    fn compressed_binary_round_trip() {
+        let dir = crate::utils::test_dir("compressed_binary");
+        let taxo = named_taxonomy();
+        let path = dir.join("taxo.k2d.zst");
+        taxo.save_binary_compressed(&path, 3).unwrap();
+        assert_eq!(Taxonomy::from_binary_compressed(&path).unwrap(), taxo);
+
+        // gzip 和未压缩的文件也能读取
+        let mut plain = Vec::new();
+        taxo.write_to_writer(&mut plain).unwrap();
+        let gz_path = dir.join("taxo.k2d.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(&gz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&plain).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(Taxonomy::from_binary_compressed(&gz_path).unwrap(), taxo);
+        let plain_path = dir.join("taxo.k2d");
+        std::fs::write(&plain_path, &plain).unwrap();
+        assert_eq!(Taxonomy::from_binary_compressed(&plain_path).unwrap(), taxo);
+    }
+
+    #[test]
+    fn itol_dataset_lists_leaves() {
+        let taxo = converted_taxonomy("itol_dataset");
+        // 非叶子节点 2 的计数不输出
+        let counts = HashMap::from([(5, 4), (7, 1), (2, 9)]);
+        let mut out = Vec::new();
+        taxo.export_itol_dataset(&counts, "sample", &mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "DATASET_SIMPLEBAR\nSEPARATOR TAB\nDATASET_LABEL\tsample\nCOLOR\t#ff0000\nDATA\n\
+             100\t4\n101\t0\n200\t1\n"
+        );
+    }
+
+    #[test]
+    fn graphson_has_one_vertex_per_node() {
+        let taxo = converted_taxonomy("graphson");
+        let mut out = Vec::new();
+        taxo.export_graphson(&mut out).unwrap();
+        let graph: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let vertices = graph["vertices"].as_array().unwrap();
+        let edges = graph["edges"].as_array().unwrap();
+        assert_eq!(vertices.len(), 7);
+        assert_eq!(edges.len(), 6);
+
+        let vertex = &vertices[4]["@value"];
+        assert_eq!(vertex["id"]["@value"], 100);
+        assert_eq!(
+            vertex["properties"]["name"][0]["@value"]["value"],
+            "Alpha one"
+        );
+        assert_eq!(
+            vertex["properties"]["rank"][0]["@value"]["value"],
+            "species"
+        );
+
+        let pairs: Vec<(u64, u64)> = edges
+            .iter()
+            .map(|edge| {
+                let edge = &edge["@value"];
+                assert_eq!(edge["label"], "parent_of");
+                (
+                    edge["outV"]["@value"].as_u64().unwrap(),
+                    edge["inV"]["@value"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![(1, 2), (2, 10), (2, 20), (10, 100), (10, 101), (20, 200)]
+        );
+    }
+
+    #[test]
+    fn misplaced_nodes_report_only_the_misranked_node() {
+        let mut taxo = converted_taxonomy("misplaced_nodes");
+        assert!(taxo.find_misplaced_nodes().is_empty());
+
+        // 101 (Alpha two) 改为与父节点相同的 genus
+        taxo.nodes[6].rank_offset = taxo.nodes[3].rank_offset;
+        assert_eq!(
+            taxo.find_misplaced_nodes(),
+            vec![(6, "genus".to_string(), "species".to_string())]
+        );
+        // rank 不在 order 中的节点不参与检查
+        assert!(taxo
+            .find_misplaced_nodes_with_order(&["superkingdom", "species"])
+            .is_empty());
+    }
+
+    #[test]
+    fn summary_output() {
+        let taxo = converted_taxonomy("summary_output");
+        let mut out = Vec::new();
+        taxo.export_summary(&mut out).unwrap();
+        // node count 包含 0 号哨兵节点, 与 node_count 一致
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "node count\t8\nleaf count\t3\n\n\
+             depth\tnodes\n0\t1\n1\t1\n2\t2\n3\t3\n\n\
+             rank\tnodes\nspecies\t3\ngenus\t2\nno rank\t1\nsuperkingdom\t1\n"
+        );
+    }
+
+    #[test]
+    fn metaphlan3_taxonomy_output() {
+        let taxo = converted_taxonomy("metaphlan3_output");
+        let mut out = Vec::new();
+        taxo.export_metaphlan3_taxonomy(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "k__Bacteria|g__Alpha|s__Alpha_one\n\
+             k__Bacteria|g__Alpha|s__Alpha_two\n\
+             k__Bacteria|g__Beta|s__Beta_one\n"
+        );
+        // 根节点没有标准 rank
+        assert_eq!(taxo.metaphlan_clade_string(1), None);
+        assert_eq!(taxo.metaphlan_clade_string(0), None);
+    }
+
+    #[test]
+    fn anvio_taxonomy_output() {
+        let taxo = converted_taxonomy("anvio_output");
+        let seqid_map: HashMap<String, u32> =
+            [("s2", 7), ("s1", 5), ("s3", 3), ("s0", 0), ("s9", 99)]
+                .into_iter()
+                .map(|(seqid, id)| (seqid.to_string(), id))
+                .collect();
+        let mut out = Vec::new();
+        taxo.export_anvio_taxonomy(&seqid_map, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "seq_id\tt_domain\tt_phylum\tt_class\tt_order\tt_family\tt_genus\tt_species\n\
+             s0\t\t\t\t\t\t\t\n\
+             s1\tBacteria\t\t\t\t\tAlpha\tAlpha one\n\
+             s2\tBacteria\t\t\t\t\tBeta\tBeta one\n\
+             s3\tBacteria\t\t\t\t\tAlpha\t\n\
+             s9\t\t\t\t\t\t\t\n"
+        );
+    }
+
+    #[test]
+    fn qiime2_json_output() {
+        let taxo = converted_taxonomy("qiime2_output");
+        let seqid_map: HashMap<String, u32> = [("b", 7), ("a", 5), ("c", 1)]
+            .into_iter()
+            .map(|(seqid, id)| (seqid.to_string(), id))
+            .collect();
+        let mut out = Vec::new();
+        taxo.export_qiime2_json(&seqid_map, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "index": {"0": "a", "1": "b", "2": "c"},
+                "data": {
+                    "a": "k__Bacteria; g__Alpha; s__Alpha one",
+                    "b": "k__Bacteria; g__Beta; s__Beta one",
+                    "c": "Unassigned",
+                }
+            })
+        );
+        assert!(out.ends_with(b"}\n"));
+    }
+
+    #[test]
+    fn fasta_headers_carry_lineage() {
+        let taxo = converted_taxonomy("fasta_headers");
+        let ranks = ["superkingdom", "phylum", "genus", "species"];
+        assert_eq!(
+            taxo.reformat_fasta_header(100, "seq1", &ranks),
+            ">seq1 k__Bacteria;p__;g__Alpha;s__Alpha_one"
+        );
+        // 非标准 rank 以 rank 名作为前缀
+        assert_eq!(
+            taxo.reformat_fasta_header(200, "seq2", &["clade", "species"]),
+            ">seq2 clade__;s__Beta_one"
+        );
+        assert_eq!(taxo.reformat_fasta_header(999, "seq3", &ranks), ">seq3");
+        assert_eq!(taxo.reformat_fasta_header(0, "seq4", &ranks), ">seq4");
+
+        let seqid_map = HashMap::from([("seq1".to_string(), 100), ("seq2".to_string(), 999)]);
+        let input = ">seq1 description\nACGT\n>seq2\nGG\n>other\nTT\n";
+        let mut out = Vec::new();
+        let reformatted = taxo
+            .reformat_fasta_file(
+                &seqid_map,
+                input.as_bytes(),
+                &mut out,
+                &["genus", "species"],
+            )
+            .unwrap();
+        assert_eq!(reformatted, 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            ">seq1 g__Alpha;s__Alpha_one\nACGT\n>seq2\nGG\n>other\nTT\n"
+        );
+    }
+
+    #[test]
+    fn lca_path_lists_nodes_below_the_lca() {
+        let taxo = small_taxonomy();
+        assert_eq!(taxo.lca_path(7, 5), (2, vec![7, 4], vec![5]));
+        assert_eq!(taxo.lca_path(7, 6), (1, vec![7, 4, 2], vec![6, 3]));
+        assert_eq!(taxo.lca_path(4, 7), (4, vec![], vec![7]));
+        assert_eq!(taxo.lca_path(7, 7), (7, vec![], vec![]));
+        // 0 表示没有分类, lca 为另一个节点
+        assert_eq!(taxo.lca_path(0, 5), (5, vec![], vec![]));
+        // 不存在的节点没有公共祖先, 另一个节点的路径一直到根节点
+        assert_eq!(taxo.lca_path(99, 5), (0, vec![], vec![5, 2, 1]));
+    }
+
+    #[test]
+    fn rank_index_map_matches_rank_data() {
+        let taxo = converted_taxonomy("rank_index_map");
+        let map = taxo.get_rank_index_map();
+        let expected: HashMap<String, u64> = [
+            ("genus", 0),
+            ("no rank", 6),
+            ("species", 14),
+            ("superkingdom", 22),
+        ]
+        .into_iter()
+        .map(|(rank, offset)| (rank.to_string(), offset))
+        .collect();
+        assert_eq!(map, expected);
+        for id in 1..taxo.node_count() as u32 {
+            let rank = taxo.get_rank(id).unwrap();
+            assert_eq!(map[rank], taxo.nodes[id as usize].rank_offset);
+        }
+        assert_eq!(map.get("strain"), None);
+        assert_eq!(
+            taxo.known_ranks(),
+            vec!["genus", "no rank", "species", "superkingdom"]
+        );
+
+        assert!(Taxonomy::default().get_rank_index_map().is_empty());
+        assert!(Taxonomy::default().known_ranks().is_empty());
+    }
+
+    #[test]
+    fn node_path_string_skips_root() {
+        let taxo = converted_taxonomy("node_path_string");
+        assert_eq!(
+            taxo.node_path_string(5, " > ").as_deref(),
+            Some("Bacteria > Alpha > Alpha one")
+        );
+        assert_eq!(taxo.node_path_string(1, " > ").as_deref(), Some("root"));
+        assert_eq!(taxo.node_path_string(0, " > "), None);
+        assert_eq!(taxo.node_path_string(99, " > "), None);
+
+        assert_eq!(
+            taxo.node_path_string_at_ranks(7, &["superkingdom", "species"], "|")
+                .as_deref(),
+            Some("Bacteria|Beta one")
+        );
+        // 路径中没有该 rank 时得到空字符串
+        assert_eq!(
+            taxo.node_path_string_at_ranks(7, &["strain"], "|")
+                .as_deref(),
+            Some("")
+        );
+        assert_eq!(taxo.node_path_string_at_ranks(99, &["genus"], "|"), None);
+    }
+
+    #[test]
+    fn degree_statistics() {
+        let taxo = small_taxonomy();
+        assert_eq!(
+            taxo.degree_distribution(),
+            HashMap::from([(0, 3), (1, 2), (2, 2)])
+        );
+        assert_eq!(taxo.avg_branching_factor(), 1.5);
+        assert_eq!(taxo.max_branching_factor(), 2);
+
+        // 只有根节点时没有非叶子节点
+        let single = test_taxonomy(&[(0, 1)]);
+        assert_eq!(single.degree_distribution(), HashMap::from([(0, 1)]));
+        assert_eq!(single.avg_branching_factor(), 0.0);
+        assert_eq!(single.max_branching_factor(), 0);
+
+        let empty = Taxonomy::default();
+        assert!(empty.degree_distribution().is_empty());
+        assert_eq!(empty.avg_branching_factor(), 0.0);
+        assert_eq!(empty.max_branching_factor(), 0);
+    }
+
+    #[test]
+    fn hash_consistency_check_reports_bad_mappings() {
+        let mut taxo = small_taxonomy();
+        assert!(taxo.hash_consistency_check().is_empty());
+
+        taxo.external_to_internal_id_map.remove(&100);
+        taxo.external_to_internal_id_map.insert(1000, 3);
+        let mut bad = taxo.hash_consistency_check();
+        bad.sort_unstable();
+        assert_eq!(bad, vec![100, 1000]);
+
+        // 哨兵节点也要求 0 映射到 0
+        taxo.external_to_internal_id_map.remove(&0);
+        assert!(taxo.hash_consistency_check().contains(&0));
+
+        taxo.generate_external_to_internal_id_map();
+        assert!(taxo.hash_consistency_check().is_empty());
+    }
+
+    #[test]
+    fn iter_rank_matches_cached_index() {
+        let taxo = converted_taxonomy("iter_rank");
+        let species: Vec<u64> = taxo
+            .iter_rank("species")
+            .map(|(_, node)| node.external_id)
+            .collect();
+        assert_eq!(species, vec![100, 101, 200]);
+        assert_eq!(taxo.nodes_at_rank_cached("species"), &[5, 6, 7]);
+        assert_eq!(taxo.nodes_at_rank_cached("genus"), &[3, 4]);
+        for rank in taxo.known_ranks() {
+            let ids: Vec<u32> = taxo.iter_rank(&rank).map(|(id, _)| id).collect();
+            assert_eq!(taxo.nodes_at_rank_cached(&rank), ids.as_slice());
+        }
+
+        // 未知 rank 和哨兵节点的空 rank 都没有节点
+        assert_eq!(taxo.iter_rank("strain").count(), 0);
+        assert!(taxo.nodes_at_rank_cached("strain").is_empty());
+        assert_eq!(taxo.iter_rank("").count(), 0);
+    }
+
+    #[test]
+    fn ncbi_lookups_by_external_taxid() {
+        let nodes = [
+            (1, 1, "no rank"),
+            (2, 1, "superkingdom"),
+            (20, 2, "genus"),
+            (300, 20, "species"),
+        ];
+        // 300 只有同义名, 没有学名
+        let names = [
+            (1, "root", "scientific name"),
+            (2, "Bacteria", "scientific name"),
+            (2, "eubacteria", "synonym"),
+            (20, "Beta", "scientific name"),
+            (300, "Beta nameless", "synonym"),
+        ];
+        let ncbi = write_ncbi("ncbi_lookups", &nodes, &names);
+        assert_eq!(ncbi.get_name(2), Some("Bacteria"));
+        assert_eq!(ncbi.get_rank(2), Some("superkingdom"));
+        assert!(ncbi.contains_taxid(1));
+
+        assert_eq!(ncbi.get_name(300), None);
+        assert_eq!(ncbi.get_rank(300), Some("species"));
+        assert!(ncbi.contains_taxid(300));
+
+        for taxid in [0, 999] {
+            assert_eq!(ncbi.get_name(taxid), None);
+            assert_eq!(ncbi.get_rank(taxid), None);
+            assert!(!ncbi.contains_taxid(taxid));
+        }
+    }
+
+    #[cfg(feature = "json-taxonomy")]
+    #[test]
+    fn json_round_trip() {
+        let taxo = converted_taxonomy("json_round_trip");
+        let mut json = Vec::new();
+        taxo.to_json_writer(&mut json).unwrap();
+        let loaded = Taxonomy::from_json_reader(&json[..]).unwrap();
+        assert_eq!(loaded, taxo);
+        assert_eq!(loaded.get_name_by_external_id(200), Some("Beta one"));
+
+        let err = Taxonomy::from_json_reader(&b"[{\"external_id\": 1}]"[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_round_trip() {
+        let dir = crate::utils::test_dir("parquet_round_trip");
+        let taxo = converted_taxonomy("parquet_round_trip_taxonomy");
+        let path = dir.join("taxonomy.parquet");
+        taxo.export_parquet(&path).unwrap();
+        let loaded = Taxonomy::from_parquet(&path).unwrap();
+        assert_eq!(loaded, taxo);
+        assert_eq!(loaded.get_name_by_external_id(101), Some("Alpha two"));
+
+        // 截断的文件报错而不是 panic
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() / 2]).unwrap();
+        assert!(Taxonomy::from_parquet(&path).is_err());
+    }
+
+    #[cfg(feature = "taxadb")]
+    #[test]
+    fn taxadb_export() {
+        let dir = crate::utils::test_dir("taxadb_export");
+        let taxo = converted_taxonomy("taxadb_export_taxonomy");
+        let path = dir.join("taxadb.sqlite");
+        // 第二次导出替换已有的表
+        taxo.export_taxadb(&path).unwrap();
+        taxo.export_taxadb(&path).unwrap();
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT ncbi_taxon_id, parent_ncbi_taxon_id, taxon_name, lineage_level FROM taxa ORDER BY ncbi_taxon_id")
+            .unwrap();
+        let rows: Vec<(i64, i64, String, String)> = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        let expected = [
+            (1, 1, "root", "no rank"),
+            (2, 1, "Bacteria", "superkingdom"),
+            (10, 2, "Alpha", "genus"),
+            (20, 2, "Beta", "genus"),
+            (100, 10, "Alpha one", "species"),
+            (101, 10, "Alpha two", "species"),
+            (200, 20, "Beta one", "species"),
+        ];
+        let expected: Vec<(i64, i64, String, String)> = expected
+            .iter()
+            .map(|&(taxid, parent, name, rank)| (taxid, parent, name.to_string(), rank.to_string()))
+            .collect();
+        assert_eq!(rows, expected);
+    }
 }
@@ -1,11 +1,13 @@
+use crate::Kr2Result;
 use std::collections::HashMap;
 use std::fs::{self, create_dir_all, File, OpenOptions};
 use std::io::{self, BufRead, BufReader, BufWriter, Result, Write};
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use walkdir::WalkDir;
 
 /// 读取 seqid2taxid.map 文件。为了裁剪 ncbi 的 taxonomy 树
-pub fn read_id_to_taxon_map<P: AsRef<Path>>(filename: P) -> Result<HashMap<String, u64>> {
+pub fn read_id_to_taxon_map<P: AsRef<Path>>(filename: P) -> Kr2Result<HashMap<String, u64>> {
     let file = open_file(filename)?;
     let reader = BufReader::new(file);
     let mut id_map = HashMap::new();
@@ -88,7 +90,7 @@ pub fn find_library_fna_files<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
     find_files(path, "library", ".fna")
 }
 
-pub fn summary_prelim_map_files<P: AsRef<Path>>(data_dir: P) -> Result<PathBuf> {
+pub fn summary_prelim_map_files<P: AsRef<Path>>(data_dir: P) -> Kr2Result<PathBuf> {
     let lib_path = data_dir.as_ref().join("library");
 
     let prelim_map_files: Vec<PathBuf> = WalkDir::new(&lib_path)
@@ -118,7 +120,10 @@ pub fn summary_prelim_map_files<P: AsRef<Path>>(data_dir: P) -> Result<PathBuf>
     Ok(summary_file_path)
 }
 
-pub fn create_seqid2taxid_file<P: AsRef<Path>>(prelim_map_file: P, output_file: P) -> Result<()> {
+pub fn create_seqid2taxid_file<P: AsRef<Path>>(
+    prelim_map_file: P,
+    output_file: P,
+) -> Kr2Result<()> {
     let file = open_file(prelim_map_file)?;
     let reader = BufReader::new(file);
     let mut output = File::create(output_file).unwrap();
@@ -225,7 +230,7 @@ pub fn find_and_sort_files(
     directory: &Path,
     prefix: &str,
     suffix: &str,
-) -> io::Result<Vec<PathBuf>> {
+) -> Kr2Result<Vec<PathBuf>> {
     // 构建正则表达式以匹配文件名中的数字
     let pattern = format!(r"{}_(\d+){}", prefix, suffix);
     let re = Regex::new(&pattern).unwrap();
@@ -264,7 +269,8 @@ pub fn find_and_sort_files(
             return Err(io::Error::new(
                 io::ErrorKind::NotFound,
                 "File numbers are not continuous starting from 1.",
-            ));
+            )
+            .into());
         }
     }
 
@@ -272,18 +278,19 @@ pub fn find_and_sort_files(
     Ok(sorted_entries.into_iter().map(|(path, _)| path).collect())
 }
 
-pub fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
-    File::open(&path).map_err(|e| {
+pub fn open_file<P: AsRef<Path>>(path: P) -> Kr2Result<File> {
+    let file = File::open(&path).map_err(|e| {
         if e.kind() == io::ErrorKind::NotFound {
             io::Error::new(e.kind(), format!("File not found: {:?}", path.as_ref()))
         } else {
             e
         }
-    })
+    })?;
+    Ok(file)
 }
 
 /// 获取最新的文件序号
-pub fn get_lastest_file_index(file_path: &PathBuf) -> Result<usize> {
+pub fn get_lastest_file_index(file_path: &PathBuf) -> Kr2Result<usize> {
     let file_content = fs::read_to_string(&file_path)?;
     // 如果文件内容为空，则默认最大值为0
     let index = if file_content.is_empty() {
@@ -298,3 +305,46 @@ pub fn get_lastest_file_index(file_path: &PathBuf) -> Result<usize> {
     };
     Ok(index)
 }
+
+/// 时间对应的 Unix 时间戳 (秒), 早于 1970 年时为 0
+pub fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as i64
+}
+
+/// 当前 UTC 时间, ISO 8601 格式
+pub fn utc_now() -> String {
+    format_utc(unix_secs(SystemTime::now()))
+}
+
+/// 把 Unix 时间戳格式化为 ISO 8601 格式的 UTC 时间
+pub fn format_utc(secs: i64) -> String {
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // 公历日期换算, 见 https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// 测试用的临时目录, 每次调用时清空
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kr2r_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,286 @@
+use crate::bloom::BLOOM_FILE;
+use crate::compact_hash::HashConfig;
+use crate::utils::{find_and_sort_files, open_file};
+use crate::Kr2Result;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Result, Write};
+use std::path::{Path, PathBuf};
+
+/// 数据库目录中保存各文件 SHA-256 的文件, 格式与 `sha256sum` 相同
+pub const CHECKSUM_FILE: &str = "database.sha256";
+
+/// 哈希表分页文件头: 分页序号和容量, 各 8 字节
+const HASH_HEADER_SIZE: u64 = 16;
+const CELL_SIZE: u64 = std::mem::size_of::<u32>() as u64;
+
+/// 读满 buffer 或读到文件末尾, 返回读到的字节数
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// 需要校验的数据库文件: taxo.k2d, opts.k2d, hash_config.k2d, 所有哈希表分页和可选的 database.k2bloom
+pub fn database_files(k2d_dir: &Path) -> Kr2Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = ["taxo.k2d", "opts.k2d", "hash_config.k2d"]
+        .iter()
+        .map(|name| k2d_dir.join(name))
+        .collect();
+    files.extend(find_and_sort_files(k2d_dir, "hash", ".k2d")?);
+    let bloom_file = k2d_dir.join(BLOOM_FILE);
+    if bloom_file.exists() {
+        files.push(bloom_file);
+    }
+    Ok(files)
+}
+
+/// 以十六进制字符串返回文件的 SHA-256
+pub fn file_sha256<P: AsRef<Path>>(path: P) -> Kr2Result<String> {
+    let mut reader = BufReader::new(open_file(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// 所有数据库文件的 `sha256  文件名` 行, 即 database.sha256 的内容
+fn checksum_listing(k2d_dir: &Path) -> Result<String> {
+    let mut listing = String::new();
+    for file in database_files(k2d_dir)? {
+        let name = file.file_name().unwrap().to_string_lossy().to_string();
+        listing.push_str(&format!("{}  {}\n", file_sha256(&file)?, name));
+    }
+    Ok(listing)
+}
+
+/// 计算所有数据库文件的 SHA-256 并写入 k2d_dir 下的 database.sha256
+pub fn write_checksum_file(k2d_dir: &Path) -> Kr2Result<PathBuf> {
+    let path = k2d_dir.join(CHECKSUM_FILE);
+    let mut writer = BufWriter::new(File::create(&path)?);
+    writer.write_all(checksum_listing(k2d_dir)?.as_bytes())?;
+    writer.flush()?;
+    Ok(path)
+}
+
+/// database.sha256 是否仍然对应当前的数据库文件:
+/// 大小与按当前文件名生成的清单相同, 并且没有数据库文件在它之后被修改
+fn checksum_file_is_current(k2d_dir: &Path, checksum_file: &Path) -> Kr2Result<bool> {
+    let Ok(metadata) = std::fs::metadata(checksum_file) else {
+        return Ok(false);
+    };
+    let written = metadata.modified()?;
+    // 每行为 64 位十六进制的 SHA-256, 两个空格, 文件名和换行符
+    let mut expected_size = 0;
+    for file in database_files(k2d_dir)? {
+        if std::fs::metadata(&file)?.modified()? > written {
+            return Ok(false);
+        }
+        expected_size += 64 + 2 + file.file_name().unwrap().len() as u64 + 1;
+    }
+    Ok(metadata.len() == expected_size)
+}
+
+/// 整个数据库的 SHA-256, 即 database.sha256 的 SHA-256
+/// database.sha256 与当前数据库文件一致时直接使用, 否则重新计算各个数据库文件
+pub fn database_sha256(k2d_dir: &Path) -> Kr2Result<String> {
+    let checksum_file = k2d_dir.join(CHECKSUM_FILE);
+    if checksum_file_is_current(k2d_dir, &checksum_file)? {
+        return file_sha256(checksum_file);
+    }
+    if checksum_file.exists() {
+        eprintln!(
+            "warning: {:?} is out of date, computing the SHA-256 of every database file",
+            checksum_file
+        );
+    }
+    let digest = Sha256::digest(checksum_listing(k2d_dir)?.as_bytes());
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// 按 database.sha256 校验数据库文件, 返回发现的问题
+pub fn verify_checksums(k2d_dir: &Path, checksum_file: &Path) -> Kr2Result<Vec<String>> {
+    let reader = BufReader::new(open_file(checksum_file)?);
+    let mut issues = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((expected, name)) = line.split_once("  ") else {
+            issues.push(format!("{:?}: invalid line `{}`", checksum_file, line));
+            continue;
+        };
+        let file = k2d_dir.join(name.trim());
+        if !file.exists() {
+            issues.push(format!("{:?}: missing", file));
+            continue;
+        }
+        let actual = file_sha256(&file)?;
+        if actual != expected.trim() {
+            issues.push(format!(
+                "{:?}: SHA-256 mismatch (expected {}, got {})",
+                file, expected, actual
+            ));
+        }
+    }
+    Ok(issues)
+}
+
+/// 哈希表的检查结果
+#[derive(Debug, Default)]
+pub struct HashTableReport {
+    pub issues: Vec<String>,
+    /// 非空格子数
+    pub occupied_cells: u64,
+    /// 哈希表中出现但分类树中不存在的内部 taxid
+    pub missing_taxids: BTreeSet<u32>,
+}
+
+/// 检查每个哈希表分页没有被截断, 分页容量之和等于 hash_config.k2d 中的容量,
+/// 并找出格子中引用的、分类树中不存在的 taxid (node_count 为分类树的节点数, taxid 0 也视为不存在)
+pub fn verify_hash_tables(k2d_dir: &Path, node_count: usize) -> Kr2Result<HashTableReport> {
+    let config = HashConfig::from_hash_header(k2d_dir.join("hash_config.k2d"))?;
+    let hash_files = find_and_sort_files(k2d_dir, "hash", ".k2d")?;
+    let mut report = HashTableReport::default();
+    if hash_files.len() != config.partition {
+        report.issues.push(format!(
+            "found {} hash table files, hash_config.k2d expects {}",
+            hash_files.len(),
+            config.partition
+        ));
+    }
+
+    let mut total_capacity = 0;
+    for hash_file in &hash_files {
+        let file_size = hash_file.metadata()?.len();
+        let mut reader = BufReader::new(open_file(hash_file)?);
+        let mut header = [0u8; HASH_HEADER_SIZE as usize];
+        if file_size < HASH_HEADER_SIZE || reader.read_exact(&mut header).is_err() {
+            report.issues.push(format!(
+                "{:?}: truncated header ({} bytes)",
+                hash_file, file_size
+            ));
+            continue;
+        }
+        let capacity = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        total_capacity += capacity;
+        let expected_size = HASH_HEADER_SIZE + capacity * CELL_SIZE;
+        if file_size != expected_size {
+            report.issues.push(format!(
+                "{:?}: {} bytes, expected {} (header + {} cells * {} bytes)",
+                hash_file, file_size, expected_size, capacity, CELL_SIZE
+            ));
+        }
+
+        let mut buffer = vec![0u8; 1 << 20];
+        loop {
+            let bytes_read = read_full(&mut reader, &mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            for cell in buffer[..bytes_read].chunks_exact(CELL_SIZE as usize) {
+                let value = u32::from_le_bytes(cell.try_into().unwrap());
+                if value == 0 {
+                    continue;
+                }
+                report.occupied_cells += 1;
+                let taxid = value & config.value_mask as u32;
+                if taxid == 0 || taxid as usize >= node_count {
+                    report.missing_taxids.insert(taxid);
+                }
+            }
+        }
+    }
+    if total_capacity != config.capacity as u64 {
+        report.issues.push(format!(
+            "hash table files hold {} cells, hash_config.k2d expects {}",
+            total_capacity, config.capacity
+        ));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+    use std::time::{Duration, SystemTime};
+
+    /// 只含数据库文件名的目录, 内容不需要是合法的数据库
+    fn fake_database(name: &str) -> PathBuf {
+        let dir = test_dir(name);
+        for name in ["taxo.k2d", "opts.k2d", "hash_config.k2d", "hash_1.k2d"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+        dir
+    }
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn current_checksum_file_is_used() {
+        let dir = fake_database("checksum_current");
+        let checksum_file = write_checksum_file(&dir).unwrap();
+        assert_eq!(
+            database_sha256(&dir).unwrap(),
+            file_sha256(&checksum_file).unwrap()
+        );
+        // 直接使用 database.sha256 的内容, 即使其中的 SHA-256 是错的
+        let listing = std::fs::read_to_string(&checksum_file).unwrap();
+        std::fs::write(&checksum_file, listing.replacen('0', "1", 1)).unwrap();
+        assert_eq!(
+            database_sha256(&dir).unwrap(),
+            file_sha256(&checksum_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn stale_checksum_file_is_recomputed() {
+        let dir = fake_database("checksum_stale");
+        let checksum_file = write_checksum_file(&dir).unwrap();
+        let expected = file_sha256(&checksum_file).unwrap();
+
+        // 数据库文件在 database.sha256 之后被修改
+        std::fs::write(dir.join("opts.k2d"), "changed").unwrap();
+        set_modified(
+            &dir.join("opts.k2d"),
+            SystemTime::now() + Duration::from_secs(60),
+        );
+        let recomputed = database_sha256(&dir).unwrap();
+        assert_ne!(recomputed, expected);
+        assert_eq!(file_sha256(&checksum_file).unwrap(), expected);
+
+        // 新增的数据库文件比 database.sha256 旧, 但清单的大小不再一致
+        let dir = fake_database("checksum_new_file");
+        let checksum_file = write_checksum_file(&dir).unwrap();
+        let expected = file_sha256(&checksum_file).unwrap();
+        std::fs::write(dir.join("hash_2.k2d"), "hash_2.k2d").unwrap();
+        set_modified(&dir.join("hash_2.k2d"), SystemTime::UNIX_EPOCH);
+        assert_ne!(database_sha256(&dir).unwrap(), expected);
+        write_checksum_file(&dir).unwrap();
+        assert_eq!(
+            database_sha256(&dir).unwrap(),
+            file_sha256(&checksum_file).unwrap()
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::feat::char_to_value;
+use crate::reader::Reader;
+use crate::seq::Base;
+use std::io::Result;
+
+/// DUST 默认阈值, 与 dustmasker 的 level 20 一致
+pub const DEFAULT_DUST_THRESHOLD: f64 = 20.0;
+/// DUST 窗口长度 (碱基数)
+pub const DUST_WINDOW: usize = 64;
+
+/// 三联体编码, 含非 ACGT 碱基时返回 None
+#[inline]
+fn triplet(bases: &[u8], end: usize) -> Option<usize> {
+    let mut code = 0;
+    for &base in &bases[end - 2..=end] {
+        code = (code << 2) | char_to_value(base)? as usize;
+    }
+    Some(code)
+}
+
+/// DUST 低复杂度检测, 返回与 seq 等长的屏蔽标记
+/// 在长度为 DUST_WINDOW 的滑动窗口内统计重叠三联体的出现次数 c_t,
+/// 窗口得分为 sum(c_t * (c_t - 1) / 2) / (l - 1), l 为窗口内有效三联体数,
+/// 得分超过 threshold 的窗口内所有碱基都被屏蔽. 换行符不计入碱基, 也不会被屏蔽
+pub fn dust_mask(seq: &[u8], threshold: f64) -> Vec<bool> {
+    let mut mask = vec![false; seq.len()];
+    let positions: Vec<usize> = (0..seq.len())
+        .filter(|&i| seq[i] != b'\n' && seq[i] != b'\r')
+        .collect();
+    let bases: Vec<u8> = positions.iter().map(|&i| seq[i]).collect();
+    let n = bases.len();
+    if n < 3 {
+        return mask;
+    }
+
+    let window = DUST_WINDOW.min(n);
+    let mut counts = [0usize; 64];
+    // sum(c_t * (c_t - 1) / 2)
+    let mut pairs = 0usize;
+    let mut valid = 0usize;
+    for code in (2..window).filter_map(|end| triplet(&bases, end)) {
+        pairs += counts[code];
+        counts[code] += 1;
+        valid += 1;
+    }
+
+    let mut masked_end = 0;
+    let mut start = 0;
+    loop {
+        if valid > 1 && pairs as f64 / (valid - 1) as f64 > threshold {
+            for &pos in &positions[masked_end.max(start)..start + window] {
+                mask[pos] = true;
+            }
+            masked_end = start + window;
+        }
+        if start + window >= n {
+            break;
+        }
+        // 窗口右移一位: 移出以 start + 2 结尾的三联体, 加入以 start + window 结尾的三联体
+        if let Some(code) = triplet(&bases, start + 2) {
+            counts[code] -= 1;
+            pairs -= counts[code];
+            valid -= 1;
+        }
+        if let Some(code) = triplet(&bases, start + window) {
+            pairs += counts[code];
+            counts[code] += 1;
+            valid += 1;
+        }
+        start += 1;
+    }
+    mask
+}
+
+/// 把 DUST 屏蔽的碱基替换为 N, 使其与 N 一样不参与 minimizer 窗口
+pub fn apply_dust_mask(seq: &mut [u8], threshold: f64) {
+    let mask = dust_mask(seq, threshold);
+    for (base, masked) in seq.iter_mut().zip(mask) {
+        if masked {
+            *base = b'N';
+        }
+    }
+}
+
+/// 对读取的每条序列做 DUST 低复杂度屏蔽
+pub struct DustReader<R: Reader> {
+    inner: R,
+    threshold: f64,
+}
+
+impl<R: Reader> DustReader<R> {
+    pub fn new(inner: R, threshold: f64) -> Self {
+        Self { inner, threshold }
+    }
+}
+
+impl<R: Reader> Reader for DustReader<R> {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        let mut seqs = self.inner.next()?;
+        if let Some(seqs) = seqs.as_mut() {
+            for seq in seqs.iter_mut() {
+                seq.body
+                    .apply_mut(|body| apply_dust_mask(body, self.threshold));
+            }
+        }
+        Ok(seqs)
+    }
+}
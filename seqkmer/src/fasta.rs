@@ -1,4 +1,4 @@
-use crate::reader::{dyn_reader, trim_end, Reader, BUFSIZE};
+use crate::reader::{dyn_reader, trim_end, Compression, Reader, BUFSIZE};
 use crate::seq::{Base, SeqFormat, SeqHeader};
 use crate::utils::OptionPair;
 use std::io::{BufRead, BufReader, Read, Result};
@@ -99,7 +99,7 @@ where
             let first_space_index = s
                 .as_bytes()
                 .iter()
-                .position(|&c| c == b' ')
+                .position(|c| c.is_ascii_whitespace())
                 .unwrap_or(s.len());
 
             // 直接从原始切片创建第一个单词的切片
@@ -123,7 +123,16 @@ where
 impl FastaReader<Box<dyn Read + Send>> {
     #[inline]
     pub fn from_path<P: AsRef<Path>>(path: P, file_index: usize) -> Result<Self> {
-        let reader = dyn_reader(path)?;
+        Self::from_path_with(path, file_index, Compression::Auto)
+    }
+
+    /// 与 from_path 相同, 但按 compression 指定的格式解压
+    pub fn from_path_with<P: AsRef<Path>>(
+        path: P,
+        file_index: usize,
+        compression: Compression,
+    ) -> Result<Self> {
+        let reader = dyn_reader(path, compression)?;
         Ok(Self::new(reader, file_index))
     }
 }
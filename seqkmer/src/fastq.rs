@@ -1,12 +1,16 @@
-use crate::reader::{dyn_reader, trim_end, trim_pair_info, Reader, BUFSIZE};
+use crate::reader::{dyn_reader, trim_end, trim_pair_info, Compression, Reader, BUFSIZE};
 use crate::seq::{Base, SeqFormat, SeqHeader};
+use crate::trim::{QualityEncoding, QualityTrimmer};
 use crate::utils::OptionPair;
-use std::io::{BufRead, BufReader, Read, Result};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result};
 use std::path::Path;
 
 struct QReader<R: Read + Send> {
     reader: BufReader<R>,
     quality_score: i32,
+    encoding: QualityEncoding,
+    /// 读取后先按质量值裁剪 3' 端
+    trimmer: Option<QualityTrimmer>,
 
     header: Vec<u8>,
     seq: Vec<u8>,
@@ -27,6 +31,8 @@ where
             plus: Vec::new(),
             quals: Vec::new(),
             quality_score,
+            encoding: QualityEncoding::Phred33,
+            trimmer: None,
         }
     }
 
@@ -54,11 +60,25 @@ where
         if self.reader.read_until(b'\n', &mut self.quals)? == 0 {
             return Ok(None);
         }
-        trim_end(&mut self.quals);
+        // 质量值中 `@` 是合法字符, 只去掉换行符
+        while let Some(b'\n' | b'\r') = self.quals.last() {
+            self.quals.pop();
+        }
+
+        // 长度不一致的记录由 check_lengths 报告, 不做裁剪
+        if let Some(trimmer) = self
+            .trimmer
+            .as_ref()
+            .filter(|_| self.seq.len() == self.quals.len())
+        {
+            let len = trimmer.trim(&self.seq, &self.quals).0.len();
+            self.seq.truncate(len);
+            self.quals.truncate(len);
+        }
 
         if self.quality_score > 0 {
             for (base, &qscore) in self.seq.iter_mut().zip(self.quals.iter()) {
-                if (qscore as i32 - '!' as i32) < self.quality_score {
+                if (self.encoding.score(qscore) as i32) < self.quality_score {
                     *base = b'x';
                 }
             }
@@ -66,10 +86,36 @@ where
 
         Ok(Some(()))
     }
+
+    /// 检查当前记录的序列与质量值长度是否一致, 需在裁剪和屏蔽之前的原始长度上检查
+    fn check_lengths(&self) -> Result<()> {
+        if self.seq.len() == self.quals.len() {
+            return Ok(());
+        }
+        let header = String::from_utf8_lossy(&self.header);
+        let id = header
+            .trim_start_matches('@')
+            .split_ascii_whitespace()
+            .next()
+            .unwrap_or("");
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "FASTQ record `{}`: sequence length {} does not match quality length {}",
+                id,
+                self.seq.len(),
+                self.quals.len()
+            ),
+        ))
+    }
 }
 
 pub struct FastqReader<R: Read + Send> {
     inner: OptionPair<QReader<R>>,
+    /// 单个文件中 R1/R2 交替排列, 每次读取相邻的两条记录
+    interleaved: bool,
+    /// 跳过而不是拒绝序列与质量值长度不一致的记录
+    lenient: bool,
     file_index: usize,
     reads_index: usize,
     // 批量读取
@@ -103,19 +149,55 @@ where
         };
         Self {
             inner,
+            interleaved: false,
+            lenient: false,
             file_index,
             reads_index: 0,
             batch_size,
         }
     }
 
+    /// 把单文件输入作为交替排列的双端数据读取, 每条结果为 OptionPair::Pair
+    /// 每对 mate 的 id 去掉 /1 /2 后必须一致, 否则读取时返回错误
+    pub fn interleaved(mut self) -> Self {
+        self.interleaved = true;
+        self
+    }
+
+    /// 序列与质量值长度不一致的记录输出警告后跳过, 而不是返回错误
+    /// 双端数据中任意一条 mate 不合法时整对跳过
+    pub fn lenient(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+
+    /// 设置质量值编码, 同时用于 quality_score 屏蔽和裁剪
+    pub fn quality_encoding(mut self, encoding: QualityEncoding) -> Self {
+        self.inner.apply_mut(|reader| {
+            reader.encoding = encoding;
+            if let Some(trimmer) = reader.trimmer.as_mut() {
+                trimmer.encoding = encoding;
+            }
+        });
+        self
+    }
+
+    /// 读取每条 read 后按质量值裁剪 3' 端, 双端数据的两条 mate 分别裁剪
+    /// trimmer 的质量值编码会被 quality_encoding 设置的编码覆盖
+    pub fn trimmer(mut self, trimmer: QualityTrimmer) -> Self {
+        self.inner.apply_mut(|reader| {
+            reader.trimmer = Some(trimmer.clone().with_encoding(reader.encoding));
+        });
+        self
+    }
+
     fn create_seq_header(reader: &QReader<R>, file_index: usize, reads_index: usize) -> SeqHeader {
         let seq_id = unsafe {
             let s = std::str::from_utf8_unchecked(&reader.header[1..]);
             let first_space_index = s
                 .as_bytes()
                 .iter()
-                .position(|&c| c == b' ')
+                .position(|c| c.is_ascii_whitespace())
                 .unwrap_or(s.len());
 
             // 直接从原始切片创建第一个单词的切片
@@ -129,38 +211,100 @@ where
         }
     }
 
+    /// 读取一条记录并检查长度
+    /// 返回 Some(false) 表示长度不一致且处于 lenient 模式, 调用者应跳过这条记录
+    fn read_checked(reader: &mut QReader<R>, lenient: bool) -> Result<Option<bool>> {
+        if reader.read_next()?.is_none() {
+            return Ok(None);
+        }
+        match reader.check_lengths() {
+            Ok(()) => Ok(Some(true)),
+            Err(e) if lenient => {
+                eprintln!("warning: {}, record skipped", e);
+                Ok(Some(false))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     pub fn read_next(&mut self) -> Result<Option<Base<Vec<u8>>>> {
-        match &mut self.inner {
-            OptionPair::Single(reader) => {
-                if reader.read_next()?.is_none() {
-                    return Ok(None);
-                }
+        let lenient = self.lenient;
+        loop {
+            match &mut self.inner {
+                OptionPair::Single(reader) if self.interleaved => {
+                    let Some(valid1) = Self::read_checked(reader, lenient)? else {
+                        return Ok(None);
+                    };
+                    let pair_index = self.reads_index + 1;
+                    let seq_header = Self::create_seq_header(reader, self.file_index, pair_index);
+                    let seq1 = reader.seq.to_owned();
 
-                self.reads_index += 1;
+                    let Some(valid2) = Self::read_checked(reader, lenient)? else {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "interleaved record {} (`{}`) has no mate",
+                                pair_index, seq_header.id
+                            ),
+                        ));
+                    };
+                    let mate_header = Self::create_seq_header(reader, self.file_index, pair_index);
+                    if mate_header.id != seq_header.id {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "interleaved record {}: mate ids do not match: `{}` and `{}`",
+                                pair_index, seq_header.id, mate_header.id
+                            ),
+                        ));
+                    }
+                    // 任意一条 mate 不合法时整对跳过
+                    if !(valid1 && valid2) {
+                        continue;
+                    }
 
-                let seq_header =
-                    Self::create_seq_header(&reader, self.file_index, self.reads_index);
-                Ok(Some(Base::new(
-                    seq_header,
-                    OptionPair::Single(reader.seq.to_owned()),
-                )))
-            }
-            OptionPair::Pair(reader1, reader2) => {
-                if reader1.read_next()?.is_none() {
-                    return Ok(None);
+                    self.reads_index = pair_index;
+                    return Ok(Some(Base::new(
+                        seq_header,
+                        OptionPair::Pair(seq1, reader.seq.to_owned()),
+                    )));
                 }
-                if reader2.read_next()?.is_none() {
-                    return Ok(None);
+                OptionPair::Single(reader) => {
+                    match Self::read_checked(reader, lenient)? {
+                        None => return Ok(None),
+                        Some(false) => continue,
+                        Some(true) => {}
+                    }
+
+                    self.reads_index += 1;
+
+                    let seq_header =
+                        Self::create_seq_header(&reader, self.file_index, self.reads_index);
+                    return Ok(Some(Base::new(
+                        seq_header,
+                        OptionPair::Single(reader.seq.to_owned()),
+                    )));
                 }
+                OptionPair::Pair(reader1, reader2) => {
+                    let Some(valid1) = Self::read_checked(reader1, lenient)? else {
+                        return Ok(None);
+                    };
+                    let Some(valid2) = Self::read_checked(reader2, lenient)? else {
+                        return Ok(None);
+                    };
+                    if !(valid1 && valid2) {
+                        continue;
+                    }
 
-                self.reads_index += 1;
-                let seq_header =
-                    Self::create_seq_header(&reader1, self.file_index, self.reads_index);
+                    self.reads_index += 1;
+                    let seq_header =
+                        Self::create_seq_header(&reader1, self.file_index, self.reads_index);
 
-                Ok(Some(Base::new(
-                    seq_header,
-                    OptionPair::Pair(reader1.seq.to_owned(), reader2.seq.to_owned()),
-                )))
+                    return Ok(Some(Base::new(
+                        seq_header,
+                        OptionPair::Pair(reader1.seq.to_owned(), reader2.seq.to_owned()),
+                    )));
+                }
             }
         }
     }
@@ -173,7 +317,17 @@ impl FastqReader<Box<dyn Read + Send>> {
         file_index: usize,
         quality_score: i32,
     ) -> Result<Self> {
-        let readers = paths.map(|path| dyn_reader(path))?;
+        Self::from_path_with(paths, file_index, quality_score, Compression::Auto)
+    }
+
+    /// 与 from_path 相同, 但按 compression 指定的格式解压
+    pub fn from_path_with<P: AsRef<Path>>(
+        paths: OptionPair<P>,
+        file_index: usize,
+        quality_score: i32,
+        compression: Compression,
+    ) -> Result<Self> {
+        let readers = paths.map(|path| dyn_reader(path, compression))?;
         Ok(Self::new(readers, file_index, quality_score))
     }
 }
@@ -190,3 +344,51 @@ where
         Ok(Some(seqs).filter(|v| !v.is_empty()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MALFORMED: &[u8] =
+        b"@ok1\nACGT\n+\nIIII\n@bad desc\nACGTACGT\n+\nIIII\n@ok2\nGGCC\n+\nIIII\n";
+
+    fn read_ids<R: Read + Send>(mut reader: FastqReader<R>) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        while let Some(seq) = reader.read_next()? {
+            ids.push(seq.header.id);
+        }
+        Ok(ids)
+    }
+
+    #[test]
+    fn mismatched_quality_length_is_an_error() {
+        let reader = FastqReader::new(OptionPair::Single(MALFORMED), 1, 0);
+        let err = read_ids(reader).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let msg = err.to_string();
+        assert!(msg.contains("`bad`"), "{}", msg);
+        assert!(msg.contains("sequence length 8"), "{}", msg);
+        assert!(msg.contains("quality length 4"), "{}", msg);
+    }
+
+    #[test]
+    fn lenient_skips_mismatched_record() {
+        let reader = FastqReader::new(OptionPair::Single(MALFORMED), 1, 0).lenient();
+        assert_eq!(read_ids(reader).unwrap(), ["ok1", "ok2"]);
+    }
+
+    #[test]
+    fn lenient_skips_whole_pair() {
+        let r1: &[u8] = b"@a/1\nACGT\n+\nIIII\n@b/1\nACGT\n+\nIIII\n";
+        let r2: &[u8] = b"@a/2\nACGT\n+\nIII\n@b/2\nTTGG\n+\nIIII\n";
+        let reader = FastqReader::new(OptionPair::Pair(r1, r2), 1, 0).lenient();
+        assert_eq!(read_ids(reader).unwrap(), ["b"]);
+    }
+
+    #[test]
+    fn trailing_at_is_a_quality_character() {
+        let data: &[u8] = b"@r\nACGT\n+\nIII@\n";
+        let reader = FastqReader::new(OptionPair::Single(data), 1, 0);
+        assert_eq!(read_ids(reader).unwrap(), ["r"]);
+    }
+}
@@ -1,9 +1,10 @@
 use crate::fasta::FastaReader;
 use crate::fastq::FastqReader;
-use crate::reader::{detect_file_format, Reader};
+use crate::reader::{detect_file_format, Compression, Reader};
 use crate::seq::{Base, SeqFormat};
+use crate::trim::{QualityEncoding, QualityTrimmer};
 use crate::utils::OptionPair;
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
 use std::path::Path;
 
 pub struct FastxReader<R: Reader> {
@@ -21,23 +22,70 @@ impl<R: Reader> Reader for FastxReader<R> {
         self.inner.next()
     }
 }
+/// 打开序列文件的选项
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    pub compression: Compression,
+    /// 单个 FASTQ 文件中相邻的两条记录作为一对 mate 读取
+    pub interleaved: bool,
+    /// FASTQ 质量值编码
+    pub quality_encoding: QualityEncoding,
+    /// 按质量值裁剪 FASTQ read 的 3' 端, FASTA 没有质量值, 不做裁剪
+    pub trimmer: Option<QualityTrimmer>,
+    /// 跳过序列与质量值长度不一致的 FASTQ 记录, 而不是返回错误
+    pub lenient: bool,
+}
+
 impl FastxReader<Box<dyn Reader + Send>> {
     pub fn from_paths<P: AsRef<Path>>(
         paths: OptionPair<P>,
         file_index: usize,
         quality_score: i32,
     ) -> Result<Self> {
-        let file_format = paths.map(|path: &P| detect_file_format(path));
+        Self::from_paths_with(paths, file_index, quality_score, &ReadOptions::default())
+    }
+
+    /// 与 from_paths 相同, 但按 options 解压, 读取和裁剪
+    pub fn from_paths_with<P: AsRef<Path>>(
+        paths: OptionPair<P>,
+        file_index: usize,
+        quality_score: i32,
+        options: &ReadOptions,
+    ) -> Result<Self> {
+        let compression = options.compression;
+        let file_format = paths.map(|path: &P| detect_file_format(path, compression));
+        let fastq_reader = |paths: OptionPair<P>| -> Result<Box<dyn Reader + Send>> {
+            let mut reader =
+                FastqReader::from_path_with(paths, file_index, quality_score, compression)?
+                    .quality_encoding(options.quality_encoding);
+            if let Some(trimmer) = &options.trimmer {
+                reader = reader.trimmer(trimmer.clone());
+            }
+            if options.interleaved {
+                reader = reader.interleaved();
+            }
+            if options.lenient {
+                reader = reader.lenient();
+            }
+            Ok(Box::new(reader))
+        };
 
         match file_format? {
+            OptionPair::Single(SeqFormat::Fasta) if options.interleaved => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "interleaved paired-end input must be FASTQ",
+            )),
             OptionPair::Single(SeqFormat::Fasta) => {
-                let reader = FastaReader::from_path(paths.single().unwrap().as_ref(), file_index)?;
+                let reader = FastaReader::from_path_with(
+                    paths.single().unwrap().as_ref(),
+                    file_index,
+                    compression,
+                )?;
                 Ok(Self::new(Box::new(reader) as Box<dyn Reader + Send>))
             }
             OptionPair::Single(SeqFormat::Fastq)
             | OptionPair::Pair(SeqFormat::Fastq, SeqFormat::Fastq) => {
-                let reader = FastqReader::from_path(paths, file_index, quality_score)?;
-                Ok(Self::new(Box::new(reader) as Box<dyn Reader + Send>))
+                Ok(Self::new(fastq_reader(paths)?))
             }
             _ => panic!("Unsupported file format combination"),
         }
@@ -128,7 +128,7 @@ const M2: u64 = 0xc4ceb9fe1a85ec53;
 /// # Examples
 ///
 /// ```
-/// # use kr2r::fmix64;
+/// # use seqkmer::fmix64;
 /// let key: u64 = 123;
 /// let hash = fmix64(key);
 /// assert_eq!(hash, 9208534749291869864);
@@ -153,6 +153,8 @@ pub struct Meros {
     pub spaced_seed_mask: u64,
     pub toggle_mask: u64,
     pub min_clear_hash_value: Option<u64>,
+    /// 跳过含有 N (或其他非 ACGT 字符) 的窗口; 为 false 时这些字符按 A 计算
+    pub mask_n_bases: bool,
 }
 
 impl Meros {
@@ -174,6 +176,7 @@ impl Meros {
             spaced_seed_mask: spaced_seed_mask.unwrap_or(DEFAULT_SPACED_SEED_MASK),
             toggle_mask: toggle_mask.unwrap_or(DEFAULT_TOGGLE_MASK) & mask,
             min_clear_hash_value,
+            mask_n_bases: true,
         }
     }
 
@@ -197,6 +200,7 @@ impl Default for Meros {
             spaced_seed_mask: DEFAULT_SPACED_SEED_MASK,
             toggle_mask: DEFAULT_TOGGLE_MASK & mask,
             min_clear_hash_value: None,
+            mask_n_bases: true,
         }
     }
 }
@@ -0,0 +1,62 @@
+use crate::reader::Reader;
+use crate::seq::Base;
+use std::io::Result;
+
+/// 序列长度, FASTA 序列中的换行符不计入
+#[inline]
+fn seq_len(seq: &[u8]) -> usize {
+    seq.iter().filter(|&&b| b != b'\n' && b != b'\r').count()
+}
+
+/// 按长度过滤 read, 长度不在 [min_length, max_length] 范围内的 read 直接丢弃
+/// 双端数据分别检查两条 mate, 任意一条不满足时整对丢弃
+pub struct LengthFilter<R: Reader> {
+    inner: R,
+    min_length: usize,
+    max_length: Option<usize>,
+    filtered: usize,
+}
+
+impl<R: Reader> LengthFilter<R> {
+    pub fn new(inner: R, min_length: usize, max_length: Option<usize>) -> Self {
+        Self {
+            inner,
+            min_length,
+            max_length,
+            filtered: 0,
+        }
+    }
+
+    /// 已丢弃的 read 数量, 双端数据一对计为一条
+    pub fn filtered(&self) -> usize {
+        self.filtered
+    }
+
+    fn accepts(&self, body: &[u8]) -> bool {
+        let len = seq_len(body);
+        len >= self.min_length && self.max_length.is_none_or(|max| len <= max)
+    }
+}
+
+impl<R: Reader> Reader for LengthFilter<R> {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        // 整批都被过滤时继续读取下一批, 避免提前返回空批次
+        while let Some(batch) = self.inner.next()? {
+            let total = batch.len();
+            let seqs: Vec<Base<Vec<u8>>> = batch
+                .into_iter()
+                .filter_map(|seq| {
+                    let header = seq.header;
+                    seq.body
+                        .filter_map_strict(|body| self.accepts(&body).then_some(body))
+                        .map(|body| Base::new(header, body))
+                })
+                .collect();
+            self.filtered += total - seqs.len();
+            if !seqs.is_empty() {
+                return Ok(Some(seqs));
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,110 @@
+use crate::mmscanner::minimizer_iter;
+use crate::Meros;
+use std::collections::HashMap;
+
+/// 统计多条序列中 minimizer 出现的次数
+#[derive(Debug, Clone, Default)]
+pub struct KmerCount {
+    inner: HashMap<u64, u32>,
+}
+
+impl KmerCount {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 累加一条序列中所有 minimizer 的出现次数
+    pub fn add_sequence(&mut self, seq: &[u8], meros: &Meros) {
+        for (_, minimizer) in minimizer_iter(seq, meros) {
+            let count = self.inner.entry(minimizer).or_insert(0);
+            *count = count.saturating_add(1);
+        }
+    }
+
+    /// minimizer 的出现次数, 未出现时为 0
+    pub fn get(&self, kmer: u64) -> u32 {
+        self.inner.get(&kmer).copied().unwrap_or(0)
+    }
+
+    /// 不同 minimizer 的个数
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// 出现次数最多的 k 个 minimizer, 按次数降序排列, 次数相同时按 minimizer 升序
+    pub fn top_k(&self, k: usize) -> Vec<(u64, u32)> {
+        let mut counts: Vec<(u64, u32)> = self.inner.iter().map(|(&k, &v)| (k, v)).collect();
+        counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(k);
+        counts
+    }
+
+    /// 合并另一个计数结果, 用于并行统计后汇总
+    pub fn merge(&mut self, other: &KmerCount) {
+        for (&kmer, &count) in &other.inner {
+            let total = self.inner.entry(kmer).or_insert(0);
+            *total = total.saturating_add(count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEQ_A: &[u8] = b"ACGTTGCAACGGCATTCAGT";
+    const SEQ_B: &[u8] = b"TTGCAACGGCTTAGCAGGAT";
+
+    #[test]
+    fn counts_every_minimizer_occurrence() {
+        let meros = Meros::new(5, 3, None, None, None);
+        let mut expected: HashMap<u64, u32> = HashMap::new();
+        for (_, minimizer) in minimizer_iter(SEQ_A, &meros) {
+            *expected.entry(minimizer).or_insert(0) += 1;
+        }
+        assert!(!expected.is_empty());
+
+        let mut counts = KmerCount::new();
+        assert!(counts.is_empty());
+        counts.add_sequence(SEQ_A, &meros);
+        assert_eq!(counts.len(), expected.len());
+        for (&minimizer, &count) in &expected {
+            assert_eq!(counts.get(minimizer), count);
+        }
+        assert_eq!(counts.get(u64::MAX), 0);
+
+        counts.add_sequence(SEQ_A, &meros);
+        for (&minimizer, &count) in &expected {
+            assert_eq!(counts.get(minimizer), count * 2);
+        }
+    }
+
+    #[test]
+    fn merge_matches_sequential_counting() {
+        let meros = Meros::new(5, 3, None, None, None);
+        let mut sequential = KmerCount::new();
+        sequential.add_sequence(SEQ_A, &meros);
+        sequential.add_sequence(SEQ_B, &meros);
+
+        let mut merged = KmerCount::new();
+        merged.add_sequence(SEQ_A, &meros);
+        let mut other = KmerCount::new();
+        other.add_sequence(SEQ_B, &meros);
+        merged.merge(&other);
+
+        assert_eq!(merged.top_k(usize::MAX), sequential.top_k(usize::MAX));
+    }
+
+    #[test]
+    fn top_k_orders_by_count_then_minimizer() {
+        let mut counts = KmerCount::new();
+        counts.inner = HashMap::from([(7, 2), (3, 5), (5, 2), (9, 1)]);
+        assert_eq!(counts.top_k(3), vec![(3, 5), (5, 2), (7, 2)]);
+        assert!(counts.top_k(0).is_empty());
+        assert_eq!(counts.top_k(10).len(), 4);
+    }
+}
@@ -1,20 +1,36 @@
+mod dust;
 mod fasta;
 mod fastq;
 mod fastx;
 mod feat;
+mod filter;
+mod kmer_count;
+mod minimizer_set;
 mod mmscanner;
 mod parallel;
 mod reader;
+mod revcomp;
 mod seq;
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+mod simd;
+mod trim;
 mod utils;
+mod window;
 
+pub use dust::{apply_dust_mask, dust_mask, DustReader, DEFAULT_DUST_THRESHOLD, DUST_WINDOW};
 pub use fasta::*;
 pub use fastq::*;
 pub use fastx::*;
 pub use feat::constants::*;
 pub use feat::*;
+pub use filter::LengthFilter;
+pub use kmer_count::KmerCount;
+pub use minimizer_set::MinimizerSet;
 pub use mmscanner::MinimizerIterator;
 pub use parallel::*;
 pub use reader::*;
+pub use revcomp::{canonical, RevComp};
 pub use seq::*;
+pub use trim::{QualityEncoding, QualityTrimmer, TrimStrategy};
 pub use utils::OptionPair;
+pub use window::WindowIterator;
@@ -0,0 +1,285 @@
+use crate::mmscanner::minimizer_iter;
+use crate::Meros;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+
+const MAGIC: &[u8] = b"MMSET001";
+
+/// 每个块中的 minimizer 个数
+const BLOCK_LEN: usize = 64;
+
+/// 数据库中出现过的所有 minimizer
+/// 排序后每 BLOCK_LEN 个分为一块, 块内记录与块中最小值的差, 按块内最大差值所需的位数紧密排列在 bits 中
+#[derive(Debug, Clone, Default)]
+pub struct MinimizerSet {
+    /// 每块的最小值
+    bases: Vec<u64>,
+    /// 每块中每个差值的位数
+    widths: Vec<u8>,
+    /// 每块在 bits 中的起始位置 (位)
+    offsets: Vec<u64>,
+    bits: Vec<u64>,
+    len: usize,
+}
+
+impl MinimizerSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 收集所有序列的 minimizer, 每条序列可以使用不同的 Meros
+    pub fn from_sequences<'a, I: Iterator<Item = (Vec<u8>, &'a Meros)>>(seqs: I) -> Self {
+        let mut values = Vec::new();
+        for (seq, meros) in seqs {
+            values.extend(minimizer_iter(&seq, meros).map(|(_, minimizer)| minimizer));
+        }
+        values.sort_unstable();
+        values.dedup();
+        Self::from_sorted(&values)
+    }
+
+    /// 由严格递增的 minimizer 构建
+    fn from_sorted(values: &[u64]) -> Self {
+        let mut set = Self {
+            len: values.len(),
+            ..Self::default()
+        };
+        let mut bit_len = 0u64;
+        for block in values.chunks(BLOCK_LEN) {
+            let base = block[0];
+            let max_delta = block[block.len() - 1] - base;
+            let width = (u64::BITS - max_delta.leading_zeros()) as u8;
+            set.bases.push(base);
+            set.widths.push(width);
+            set.offsets.push(bit_len);
+            let offset = bit_len;
+            bit_len += width as u64 * block.len() as u64;
+            set.bits.resize(bit_len.div_ceil(64) as usize, 0);
+            for (i, &value) in block.iter().enumerate() {
+                set.write_bits(offset + i as u64 * width as u64, width, value - base);
+            }
+        }
+        set
+    }
+
+    fn write_bits(&mut self, pos: u64, width: u8, value: u64) {
+        if width == 0 {
+            return;
+        }
+        let (word, shift) = ((pos / 64) as usize, (pos % 64) as u32);
+        self.bits[word] |= value << shift;
+        if shift + width as u32 > 64 {
+            self.bits[word + 1] |= value >> (64 - shift);
+        }
+    }
+
+    fn read_bits(&self, pos: u64, width: u8) -> u64 {
+        if width == 0 {
+            return 0;
+        }
+        let (word, shift) = ((pos / 64) as usize, (pos % 64) as u32);
+        let mut value = self.bits[word] >> shift;
+        if shift + width as u32 > 64 {
+            value |= self.bits[word + 1] << (64 - shift);
+        }
+        if width < 64 {
+            value &= (1u64 << width) - 1;
+        }
+        value
+    }
+
+    /// 第 block 块的第 i 个 minimizer
+    fn get(&self, block: usize, i: usize) -> u64 {
+        let width = self.widths[block];
+        self.bases[block] + self.read_bits(self.offsets[block] + i as u64 * width as u64, width)
+    }
+
+    fn block_len(&self, block: usize) -> usize {
+        (self.len - block * BLOCK_LEN).min(BLOCK_LEN)
+    }
+
+    pub fn contains(&self, kmer: u64) -> bool {
+        // 最后一个最小值不大于 kmer 的块
+        let block = match self.bases.partition_point(|&base| base <= kmer) {
+            0 => return false,
+            n => n - 1,
+        };
+        let (mut lo, mut hi) = (0, self.block_len(block));
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.get(block, mid).cmp(&kmer) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return true,
+            }
+        }
+        false
+    }
+
+    /// 按从小到大的顺序遍历所有 minimizer
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.bases.len())
+            .flat_map(move |block| (0..self.block_len(block)).map(move |i| self.get(block, i)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 写入文件: magic, 8 字节个数, 之后是排序后相邻 minimizer 的差值, 以 LEB128 变长编码
+    /// 集合越大相邻差值越小, 编码后占用的字节越少
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.len as u64).to_le_bytes())?;
+
+        let mut prev = 0;
+        let mut buf = Vec::with_capacity(10);
+        for value in self.iter() {
+            let mut delta = value - prev;
+            prev = value;
+            buf.clear();
+            loop {
+                let byte = (delta & 0x7F) as u8;
+                delta >>= 7;
+                if delta == 0 {
+                    buf.push(byte);
+                    break;
+                }
+                buf.push(byte | 0x80);
+            }
+            writer.write_all(&buf)?;
+        }
+        writer.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid("not a minimizer set file"));
+        }
+        let mut count = [0; 8];
+        reader.read_exact(&mut count)?;
+        let count = u64::from_le_bytes(count);
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        // 每个差值至少占 1 个字节, 先检查个数再分配内存
+        if count > data.len() as u64 {
+            return Err(invalid("truncated minimizer set file"));
+        }
+
+        let mut values = Vec::with_capacity(count as usize);
+        let mut bytes = data.into_iter();
+        let mut prev: u64 = 0;
+        for i in 0..count {
+            let mut delta: u64 = 0;
+            let mut shift = 0;
+            loop {
+                let byte = bytes
+                    .next()
+                    .ok_or_else(|| invalid("truncated minimizer set file"))?;
+                if shift >= 64 {
+                    return Err(invalid("malformed minimizer set file"));
+                }
+                delta |= ((byte & 0x7F) as u64) << shift;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+                shift += 7;
+            }
+            // 除第一个值外差值必须大于 0, 否则不是严格递增的
+            if i > 0 && delta == 0 {
+                return Err(invalid("malformed minimizer set file"));
+            }
+            prev = prev
+                .checked_add(delta)
+                .ok_or_else(|| invalid("malformed minimizer set file"))?;
+            values.push(prev);
+        }
+        Ok(Self::from_sorted(&values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feat::fmix64;
+
+    fn test_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("seqkmer_{}_{}", name, std::process::id()))
+    }
+
+    /// 稀疏的随机值加上一段连续的值和两端的极值
+    fn sample_values() -> Vec<u64> {
+        let mut values: Vec<u64> = (1..5000u64).map(fmix64).collect();
+        values.extend(1_000_000..1_000_300);
+        values.extend([0, u64::MAX]);
+        values.sort_unstable();
+        values.dedup();
+        values
+    }
+
+    #[test]
+    fn contains_exactly_the_packed_values() {
+        let values = sample_values();
+        let set = MinimizerSet::from_sorted(&values);
+        assert_eq!(set.len(), values.len());
+        assert_eq!(set.iter().collect::<Vec<_>>(), values);
+        for &value in &values {
+            assert!(set.contains(value));
+            if value > 0 && values.binary_search(&(value - 1)).is_err() {
+                assert!(!set.contains(value - 1));
+            }
+            if value < u64::MAX && values.binary_search(&(value + 1)).is_err() {
+                assert!(!set.contains(value + 1));
+            }
+        }
+        assert!(!MinimizerSet::new().contains(0));
+    }
+
+    #[test]
+    fn dense_values_use_few_bits() {
+        let values: Vec<u64> = (0..64 * 100).map(|i| (1 << 40) | (i * 3)).collect();
+        let set = MinimizerSet::from_sorted(&values);
+        // 块内差值最大 189, 每个值 8 位
+        assert!(set.widths.iter().all(|&width| width == 8));
+        assert_eq!(set.bits.len(), values.len() / 8);
+        assert!(values.iter().all(|&value| set.contains(value)));
+        assert!(!set.contains((1 << 40) | 1));
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = test_path("minimizer_set_round_trip");
+        let set = MinimizerSet::from_sorted(&sample_values());
+        set.save(&path).unwrap();
+        let loaded = MinimizerSet::load(&path).unwrap();
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            set.iter().collect::<Vec<_>>()
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn load_rejects_count_larger_than_data() {
+        let path = test_path("minimizer_set_huge_count");
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        data.extend_from_slice(&[1, 2, 3]);
+        std::fs::write(&path, data).unwrap();
+        let err = MinimizerSet::load(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let _ = std::fs::remove_file(&path);
+    }
+}
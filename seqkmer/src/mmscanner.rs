@@ -1,11 +1,20 @@
+// simd 路径不使用逐字符扫描的 Cursor 和 to_candidate_lmer
+#![cfg_attr(
+    all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"),
+    allow(dead_code, unused_imports)
+)]
 // kraken 2 使用的是murmur_hash3 算法的 fmix64作为 hash
 use crate::seq::Base;
 use crate::utils::OptionPair;
 use crate::{
     canonical_representation, char_to_value, fmix64 as murmur_hash3, Meros, BITS_PER_CHAR,
 };
+use std::cell::RefCell;
 use std::collections::VecDeque;
 
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+use crate::simd::{self, Scan};
+
 #[inline]
 fn to_candidate_lmer(meros: &Meros, lmer: u64) -> u64 {
     let mut canonical_lmer = canonical_representation(lmer, meros.l_mer);
@@ -30,6 +39,11 @@ impl MinimizerData {
     }
 }
 
+thread_local! {
+    /// 逐字符扫描时 MinimizerWindow 的队列; 每个线程同一时刻只扫描一条序列, 扫描结束后放回给下一条复用
+    static WINDOW_BUF: RefCell<VecDeque<MinimizerData>> = const { RefCell::new(VecDeque::new()) };
+}
+
 pub struct MinimizerWindow {
     queue: VecDeque<MinimizerData>,
     queue_pos: usize,
@@ -42,7 +56,8 @@ pub struct MinimizerWindow {
 impl MinimizerWindow {
     fn new(capacity: usize) -> Self {
         Self {
-            queue: VecDeque::with_capacity(capacity),
+            // 开始扫描时才取出线程内复用的队列, 一批 read 的迭代器会同时创建
+            queue: VecDeque::new(),
             capacity,
             count: 0,
             queue_pos: 0,
@@ -97,6 +112,34 @@ impl MinimizerWindow {
         self.queue_pos = 0;
         self.queue.clear();
     }
+
+    #[inline]
+    fn acquire(&mut self) {
+        if self.queue.capacity() == 0 {
+            self.queue = WINDOW_BUF.with(|buf| buf.take());
+        }
+    }
+
+    /// 扫描结束后把队列放回线程内, 线程退出时不再放回
+    fn release(&mut self) {
+        if self.queue.capacity() == 0 {
+            return;
+        }
+        let mut queue = std::mem::take(&mut self.queue);
+        queue.clear();
+        let _ = WINDOW_BUF.try_with(|buf| {
+            let mut buf = buf.borrow_mut();
+            if buf.capacity() < queue.capacity() {
+                *buf = queue;
+            }
+        });
+    }
+}
+
+impl Drop for MinimizerWindow {
+    fn drop(&mut self) {
+        self.release();
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -136,6 +179,8 @@ impl Cursor {
     }
 }
 
+/// 逐个输出序列的 minimizer hash
+/// 默认含有 N (或任何非 ACGT 字符) 的窗口会被跳过, 从该字符之后重新开始累积; Meros::mask_n_bases 为 false 时这些字符按 A 计算
 pub struct MinimizerIterator<'a> {
     cursor: Cursor,
     window: MinimizerWindow,
@@ -144,6 +189,13 @@ pub struct MinimizerIterator<'a> {
     pos: usize,
     end: usize,
     pub size: usize,
+    /// 第一次调用 next 时一次扫描整条序列
+    #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+    scan: Option<Scan>,
+    #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+    candidate_pos: usize,
+    #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+    break_pos: usize,
 }
 
 impl<'a> MinimizerIterator<'a> {
@@ -156,9 +208,20 @@ impl<'a> MinimizerIterator<'a> {
             pos: 0,
             size: 0,
             end: seq.len(),
+            #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+            scan: None,
+            #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+            candidate_pos: 0,
+            #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+            break_pos: 0,
         }
     }
 
+    /// 为一段序列创建 minimizer 迭代器, 与 read 分类时的扫描方式相同
+    pub fn from_seq(seq: &'a [u8], meros: &'a Meros) -> Self {
+        minimizer_iter(seq, meros)
+    }
+
     fn clear_state(&mut self) {
         self.cursor.clear();
         self.window.clear();
@@ -172,7 +235,52 @@ impl<'a> MinimizerIterator<'a> {
 impl<'a> Iterator for MinimizerIterator<'a> {
     type Item = (usize, u64);
 
+    #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
     fn next(&mut self) -> Option<Self::Item> {
+        let meros = self.meros;
+        let capacity = self.window.capacity;
+        let scan = self
+            .scan
+            .get_or_insert_with(|| simd::scan(self.seq, meros, capacity));
+        let candidates = match scan {
+            Scan::Hashes(hashes) => {
+                let Some(&hash) = hashes.get(self.size) else {
+                    scan.release();
+                    return None;
+                };
+                self.size += 1;
+                return Some((self.size, hash));
+            }
+            Scan::Candidates(candidates) => candidates,
+        };
+        self.window.acquire();
+        while self.candidate_pos < candidates.lmers.len() {
+            let pos = self.candidate_pos;
+            self.candidate_pos += 1;
+            // 与逐字符扫描遇到 N 时相同, 清空窗口
+            if candidates.breaks.get(self.break_pos) == Some(&pos) {
+                self.window.clear();
+                self.break_pos += 1;
+            }
+            if let Some(minimizer) = self.window.next(candidates.lmers[pos]) {
+                self.size += 1;
+                return Some((self.size, murmur_hash3(minimizer ^ meros.toggle_mask)));
+            }
+        }
+        scan.release();
+        self.window.release();
+        None
+    }
+
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2")))]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_scalar()
+    }
+}
+
+impl MinimizerIterator<'_> {
+    /// 逐字符扫描, 未启用 simd 时的实现, 启用时作为 AVX2 扫描的对照
+    fn next_scalar(&mut self) -> Option<(usize, u64)> {
         // self.sequence
         //     .iter()
         //     .filter_map(|&ch| {
@@ -194,19 +302,22 @@ impl<'a> Iterator for MinimizerIterator<'a> {
         //         }
         //     })
         //     .next()
+        self.window.acquire();
         while self.pos < self.end {
             let ch = self.seq[self.pos];
             self.pos += 1;
             if ch == b'\n' || ch == b'\r' {
                 continue;
             } else {
-                let data = match char_to_value(ch) {
+                let code = char_to_value(ch).or((!self.meros.mask_n_bases).then_some(0));
+                let data = match code {
                     Some(code) => self.cursor.next_lmer(code).and_then(|lmer| {
                         let candidate_lmer = to_candidate_lmer(&self.meros, lmer);
                         self.window
                             .next(candidate_lmer)
                             .map(|minimizer| murmur_hash3(minimizer ^ self.meros.toggle_mask))
                     }),
+                    // N 等非 ACGT 字符: 清空 l-mer 和窗口, 跨越它的 k-mer 都不会输出
                     None => {
                         self.clear_state();
                         None
@@ -218,6 +329,7 @@ impl<'a> Iterator for MinimizerIterator<'a> {
                 }
             }
         }
+        self.window.release();
         None
     }
 }
@@ -265,15 +377,18 @@ impl<'a> Base<MinimizerIterator<'a>> {
     }
 }
 
+/// 为单条序列创建 minimizer 迭代器
+pub(crate) fn minimizer_iter<'a>(seq: &'a [u8], meros: &'a Meros) -> MinimizerIterator<'a> {
+    let cursor = Cursor::new(meros.l_mer, meros.mask);
+    let window = MinimizerWindow::new(meros.window_size());
+    MinimizerIterator::new(seq, cursor, window, meros)
+}
+
 pub fn scan_sequence<'a>(
     sequence: &'a Base<Vec<u8>>,
     meros: &'a Meros,
 ) -> Base<MinimizerIterator<'a>> {
-    let func = |seq: &'a Vec<u8>| {
-        let cursor = Cursor::new(meros.l_mer, meros.mask);
-        let window = MinimizerWindow::new(meros.window_size());
-        MinimizerIterator::new(seq, cursor, window, meros)
-    };
+    let func = |seq: &'a Vec<u8>| minimizer_iter(seq, meros);
 
     match &sequence.body {
         OptionPair::Pair(seq1, seq2) => Base::new(
@@ -285,3 +400,90 @@ pub fn scan_sequence<'a>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minimizers(seq: &[u8], meros: &Meros) -> Vec<u64> {
+        minimizer_iter(seq, meros).map(|(_, hash)| hash).collect()
+    }
+
+    #[test]
+    fn n_bases_mask_spanning_windows() {
+        let meros = Meros::new(5, 3, None, None, None);
+        // 两段都短于 k, 所有窗口都跨过 N
+        assert!(minimizers(b"ACGTNACGT", &meros).is_empty());
+
+        let (left, right) = (&b"ACGTTGCAAC"[..], &b"GGCATTCAGT"[..]);
+        let joined = [left, b"N", right].concat();
+        let mut expected = minimizers(left, &meros);
+        expected.extend(minimizers(right, &meros));
+        assert_eq!(minimizers(&joined, &meros), expected);
+    }
+
+    #[test]
+    fn unmasked_n_bases_count_as_a() {
+        let meros = Meros {
+            mask_n_bases: false,
+            ..Meros::new(5, 3, None, None, None)
+        };
+        let expected = minimizers(b"ACGTAACGT", &meros);
+        assert!(!expected.is_empty());
+        assert_eq!(minimizers(b"ACGTNACGT", &meros), expected);
+        assert_eq!(minimizers(b"ACGTnACGT", &meros), expected);
+    }
+
+    /// 在随机序列上比较 AVX2 扫描与逐字符扫描, 覆盖含 N/换行符的序列, 超过 32 的窗口和 l < 4
+    #[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx2"))]
+    #[test]
+    fn simd_matches_scalar_on_random_sequences() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let shapes = [
+            (35, 31),
+            (31, 25),
+            (25, 21),
+            (15, 12),
+            (5, 3),
+            (8, 4),
+            (60, 20),
+        ];
+        let mut state = 0u64;
+        let mut random = || {
+            state += 1;
+            murmur_hash3(state)
+        };
+        for round in 0..3000 {
+            let (k, l) = shapes[round % shapes.len()];
+            let spaced_seed_mask = (round % 3 == 0).then_some(0);
+            let meros = Meros {
+                mask_n_bases: round % 5 != 0,
+                ..Meros::new(k, l, spaced_seed_mask, None, None)
+            };
+            // 一半的序列只含 ACGT, 走不需要处理断点的路径
+            let alphabet: &[u8] = if round % 2 == 0 {
+                b"ACGTacgt"
+            } else {
+                b"ACGTACGTACGTacgtNn\n"
+            };
+            let len = (random() % 300) as usize;
+            let seq: Vec<u8> = (0..len)
+                .map(|_| alphabet[(random() % alphabet.len() as u64) as usize])
+                .collect();
+
+            let simd: Vec<_> = minimizer_iter(&seq, &meros).collect();
+            let mut iter = minimizer_iter(&seq, &meros);
+            let scalar: Vec<_> = std::iter::from_fn(|| iter.next_scalar()).collect();
+            assert_eq!(
+                simd,
+                scalar,
+                "k={} l={} seq={}",
+                k,
+                l,
+                String::from_utf8_lossy(&seq)
+            );
+        }
+    }
+}
@@ -5,7 +5,7 @@ use crate::{detect_file_format, FastaReader, FastqReader, Meros};
 use crossbeam_channel::{bounded, Receiver};
 use scoped_threadpool::Pool;
 use std::io::Result;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 pub struct ParallelResult<P>
 where
@@ -32,7 +32,7 @@ pub fn create_reader(
     // let mut files_iter = file_pair.iter();
     let paths = crate::OptionPair::from_slice(file_pair);
 
-    match detect_file_format(&file_pair[0])? {
+    match detect_file_format(&file_pair[0], crate::Compression::Auto)? {
         SeqFormat::Fastq => Ok(Box::new(FastqReader::from_path(paths, file_index, score)?)),
         SeqFormat::Fasta => Ok(Box::new(FastaReader::from_path(&file_pair[0], file_index)?)),
     }
@@ -61,12 +61,20 @@ where
     let mut pool = Pool::new(n_threads as u32);
 
     let mut parallel_result = ParallelResult { recv: done_recv };
+    // 读取出错时停止读取, 错误在所有线程结束后返回给调用者
+    let reader_error = Mutex::new(None);
 
     pool.scoped(|pool_scope| {
+        let reader_error = &reader_error;
         // 生产者线程
-        pool_scope.execute(move || {
-            while let Ok(Some(seqs)) = reader.next() {
-                sender.send(seqs).expect("Failed to send sequences");
+        pool_scope.execute(move || loop {
+            match reader.next() {
+                Ok(Some(seqs)) => sender.send(seqs).expect("Failed to send sequences"),
+                Ok(None) => break,
+                Err(e) => {
+                    *reader_error.lock().unwrap() = Some(e);
+                    break;
+                }
             }
         });
 
@@ -96,5 +104,8 @@ where
         pool_scope.join_all();
     });
 
-    Ok(())
+    match reader_error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
 }
@@ -1,25 +1,114 @@
 use crate::seq::{Base, SeqFormat};
 use crate::utils::OptionPair;
-use flate2::read::GzDecoder;
+use flate2::read::MultiGzDecoder;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, Read, Result, Seek};
 use std::path::Path;
+use std::str::FromStr;
 
-pub(crate) fn dyn_reader<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read + Send>> {
+/// 序列文件的压缩格式, Auto 根据 magic bytes 检测, 文件太短无法判断时按扩展名判断
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    Auto,
+    Gzip,
+    Zstd,
+    None,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Compression::Auto),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            "none" => Ok(Compression::None),
+            _ => Err(format!(
+                "unknown compression `{}`, expected one of auto, gzip, zstd, none",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Compression::Auto => "auto",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::None => "none",
+        };
+        f.write_str(name)
+    }
+}
+
+pub(crate) const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+pub(crate) const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// 打开序列文件, 按 magic bytes 自动解压 gzip 和 zstd
+pub fn open_sequence_reader(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    open_sequence_reader_with(path, Compression::Auto)
+}
+
+/// 按指定的压缩格式打开序列文件, 用于扩展名或内容会误导自动检测的情况
+/// gzip 使用 MultiGzDecoder 以支持多个 gzip 成员拼接的文件 (如 `cat a.gz b.gz` 或 bgzip 输出)
+pub fn open_sequence_reader_with(
+    path: &Path,
+    compression: Compression,
+) -> Result<Box<dyn BufRead + Send>> {
     let mut file = open_file(path)?;
-    if is_gzipped(&mut file)? {
-        let decoder = GzDecoder::new(file);
-        Ok(Box::new(decoder))
-    } else {
-        Ok(Box::new(file))
+    let compression = match compression {
+        Compression::Auto => detect_compression(&mut file, path)?,
+        compression => compression,
+    };
+    match compression {
+        Compression::Gzip => Ok(Box::new(BufReader::new(MultiGzDecoder::new(file)))),
+        Compression::Zstd => Ok(Box::new(BufReader::new(zstd::Decoder::new(file)?))),
+        Compression::Auto | Compression::None => Ok(Box::new(BufReader::new(file))),
     }
 }
 
-pub(crate) fn is_gzipped(file: &mut File) -> Result<bool> {
-    let mut buffer = [0; 2];
-    file.read_exact(&mut buffer)?;
+pub(crate) fn dyn_reader<P: AsRef<Path>>(
+    path: P,
+    compression: Compression,
+) -> Result<Box<dyn Read + Send>> {
+    Ok(Box::new(open_sequence_reader_with(
+        path.as_ref(),
+        compression,
+    )?))
+}
+
+/// 根据文件开头的 magic bytes 判断压缩格式, 读指针会被重置到文件开头
+fn detect_compression(file: &mut File, path: &Path) -> Result<Compression> {
+    let mut buffer = [0; 4];
+    let mut len = 0;
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
     file.rewind()?; // 重置文件指针到开头
-    Ok(buffer == [0x1F, 0x8B])
+
+    if buffer[..len].starts_with(&GZIP_MAGIC) {
+        return Ok(Compression::Gzip);
+    }
+    if buffer[..len].starts_with(&ZSTD_MAGIC) {
+        return Ok(Compression::Zstd);
+    }
+    if len < GZIP_MAGIC.len() {
+        let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        if ext.eq_ignore_ascii_case("gz") {
+            return Ok(Compression::Gzip);
+        }
+        if ext.eq_ignore_ascii_case("zst") {
+            return Ok(Compression::Zstd);
+        }
+    }
+    Ok(Compression::None)
 }
 
 pub fn trim_pair_info(id: &str) -> String {
@@ -43,37 +132,17 @@ pub fn open_file<P: AsRef<Path>>(path: P) -> Result<File> {
     })
 }
 
-pub(crate) fn detect_file_format<P: AsRef<Path>>(path: P) -> io::Result<SeqFormat> {
-    // let mut file = open_file(path)?;
-    let read1: Box<dyn io::Read + Send> = dyn_reader(path)?;
-    let reader = BufReader::new(read1);
-    let mut lines = reader.lines();
-
-    if let Some(first_line) = lines.next() {
-        let line = first_line?;
-
-        if line.starts_with('>') {
-            return Ok(SeqFormat::Fasta);
-        } else if line.starts_with('@') {
-            let _ = lines.next();
-            if let Some(third_line) = lines.next() {
-                let line: String = third_line?;
-                if line.starts_with('+') {
-                    return Ok(SeqFormat::Fastq);
-                }
-            }
-        } else {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Unrecognized fasta(fastq) file format",
-            ));
-        }
-    }
-
-    Err(io::Error::new(
-        io::ErrorKind::Other,
-        "Unrecognized fasta(fastq) file format",
-    ))
+/// 按文件内容判断序列格式, 不依赖扩展名
+pub(crate) fn detect_file_format<P: AsRef<Path>>(
+    path: P,
+    compression: Compression,
+) -> io::Result<SeqFormat> {
+    let path = path.as_ref();
+    let format = match compression {
+        Compression::Auto => SeqFormat::detect(&mut open_file(path)?),
+        compression => SeqFormat::peek(&mut open_sequence_reader_with(path, compression)?),
+    };
+    format.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
 }
 
 pub(crate) fn trim_end(buffer: &mut Vec<u8>) {
@@ -200,3 +269,91 @@ impl OptionPair<SpaceDist> {
         self.apply_mut(|sd| sd.fill_tail_with_zeros());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fastx::FastxReader;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    const FASTQ_1: &[u8] = b"@r1/1\nACGTACGT\n+\nIIIIIIII\n@r2/1\nGGGGCCCC\n+\nIIIIIIII\n";
+    const FASTQ_2: &[u8] = b"@r1/2\nTTTTAAAA\n+\nIIIIIIII\n@r2/2\nCATGCATG\n+\nIIIIIIII\n";
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("seqkmer_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read_all(path: &Path, compression: Compression) -> Vec<u8> {
+        let mut data = Vec::new();
+        open_sequence_reader_with(path, compression)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    /// 读出所有 read 的 (id, 每个 mate 的序列)
+    fn read_pairs(paths: OptionPair<PathBuf>) -> Vec<(String, Vec<Vec<u8>>)> {
+        let mut reader = FastxReader::from_paths(paths, 1, 0).unwrap();
+        let mut reads = Vec::new();
+        while let Some(batch) = reader.next().unwrap() {
+            reads.extend(
+                batch
+                    .into_iter()
+                    .map(|base| (base.header.id, base.body.iter().cloned().collect())),
+            );
+        }
+        reads
+    }
+
+    #[test]
+    fn gzip_is_detected_by_magic() {
+        let dir = test_dir("gzip_magic");
+        // 扩展名不是 .gz 也按内容解压
+        let path = dir.join("reads.fq");
+        std::fs::write(&path, gzip(FASTQ_1)).unwrap();
+        assert_eq!(read_all(&path, Compression::Auto), FASTQ_1);
+        assert_eq!(read_all(&path, Compression::Gzip), FASTQ_1);
+
+        // 多个 gzip 成员拼接的文件
+        let concatenated = dir.join("concatenated.fq.gz");
+        std::fs::write(&concatenated, [gzip(FASTQ_1), gzip(FASTQ_2)].concat()).unwrap();
+        assert_eq!(
+            read_all(&concatenated, Compression::Auto),
+            [FASTQ_1, FASTQ_2].concat()
+        );
+
+        let plain = dir.join("plain.fq");
+        std::fs::write(&plain, FASTQ_1).unwrap();
+        assert_eq!(read_all(&plain, Compression::Auto), FASTQ_1);
+    }
+
+    #[test]
+    fn gzip_pairs_read_like_plain_pairs() {
+        let dir = test_dir("gzip_pairs");
+        let plain = OptionPair::Pair(dir.join("r_1.fq"), dir.join("r_2.fq"));
+        let gz = OptionPair::Pair(dir.join("r_1.fastq.gz"), dir.join("r_2.fastq.gz"));
+        for (paths, compress) in [(&plain, false), (&gz, true)] {
+            for (path, data) in paths.iter().zip([FASTQ_1, FASTQ_2]) {
+                let data = if compress { gzip(data) } else { data.to_vec() };
+                std::fs::write(path, data).unwrap();
+            }
+        }
+
+        let reads = read_pairs(gz);
+        assert_eq!(reads.len(), 2);
+        assert_eq!(reads[0].1, vec![b"ACGTACGT".to_vec(), b"TTTTAAAA".to_vec()]);
+        assert_eq!(reads, read_pairs(plain));
+    }
+}
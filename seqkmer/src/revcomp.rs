@@ -0,0 +1,84 @@
+use std::borrow::Cow;
+
+/// 碱基互补, 保留大小写, 非 ACGT 字符 (如 N) 保持不变
+#[inline]
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'T' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'a' => b't',
+        b't' => b'a',
+        b'c' => b'g',
+        b'g' => b'c',
+        _ => base,
+    }
+}
+
+/// DNA 序列的反向互补
+pub trait RevComp {
+    fn rev_comp(&self) -> Vec<u8>;
+
+    /// 反向互补序列按字典序是否比原序列小
+    fn is_rev_comp_smaller(&self) -> bool;
+}
+
+impl RevComp for &[u8] {
+    fn rev_comp(&self) -> Vec<u8> {
+        self.iter().rev().map(|&base| complement(base)).collect()
+    }
+
+    fn is_rev_comp_smaller(&self) -> bool {
+        // 逐位比较, 不必构造完整的反向互补序列
+        let rev = self.iter().rev().map(|&base| complement(base));
+        rev.lt(self.iter().copied())
+    }
+}
+
+impl RevComp for Vec<u8> {
+    fn rev_comp(&self) -> Vec<u8> {
+        self.as_slice().rev_comp()
+    }
+
+    fn is_rev_comp_smaller(&self) -> bool {
+        self.as_slice().is_rev_comp_smaller()
+    }
+}
+
+/// 序列与其反向互补中字典序较小的一个, 原序列较小时不复制
+pub fn canonical(seq: &[u8]) -> Cow<'_, [u8]> {
+    if seq.is_rev_comp_smaller() {
+        Cow::Owned(seq.rev_comp())
+    } else {
+        Cow::Borrowed(seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_n_and_case() {
+        assert_eq!((&b"ACGTN"[..]).rev_comp(), b"NACGT");
+        assert_eq!((&b"acgTn"[..]).rev_comp(), b"nAcgt");
+        assert_eq!(b"AACCGGTTx".to_vec().rev_comp(), b"xAACCGGTT");
+        assert!((&b""[..]).rev_comp().is_empty());
+
+        let seq = b"GATTACAnNacgt".to_vec();
+        assert_eq!(seq.rev_comp().rev_comp(), seq);
+    }
+
+    #[test]
+    fn canonical_picks_the_smaller_strand() {
+        assert!((&b"TTT"[..]).is_rev_comp_smaller());
+        assert!(!(&b"AAA"[..]).is_rev_comp_smaller());
+        // 回文序列与反向互补相同
+        assert!(!b"ACGT".to_vec().is_rev_comp_smaller());
+
+        assert!(matches!(canonical(b"TTG"), Cow::Owned(ref seq) if seq == b"CAA"));
+        assert!(matches!(canonical(b"CAA"), Cow::Borrowed(b"CAA")));
+        assert!(matches!(canonical(b"NNT"), Cow::Owned(ref seq) if seq == b"ANN"));
+    }
+}
@@ -1,4 +1,7 @@
+use crate::reader::{GZIP_MAGIC, ZSTD_MAGIC};
 use crate::utils::OptionPair;
+use flate2::read::MultiGzDecoder;
+use std::io::{self, Read, Seek, SeekFrom};
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 pub enum SeqFormat {
@@ -6,6 +9,66 @@ pub enum SeqFormat {
     Fastq,
 }
 
+impl SeqFormat {
+    /// 根据内容判断序列格式: `>` 为 FASTA, `@` 为 FASTQ
+    /// gzip 和 zstd 压缩的内容先解压再判断, 返回前读指针会被重置到调用时的位置
+    pub fn detect<R: Read + Seek>(reader: &mut R) -> io::Result<SeqFormat> {
+        let start = reader.stream_position()?;
+        let mut magic = [0; 4];
+        let len = read_up_to(reader, &mut magic)?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        let format = if magic[..len].starts_with(&GZIP_MAGIC) {
+            Self::peek(&mut MultiGzDecoder::new(&mut *reader))
+        } else if magic[..len].starts_with(&ZSTD_MAGIC) {
+            Self::peek(&mut zstd::Decoder::new(&mut *reader)?)
+        } else {
+            Self::peek(reader)
+        };
+        reader.seek(SeekFrom::Start(start))?;
+        format
+    }
+
+    /// 跳过开头的空白, 按第一个字符判断格式, 会消耗读取的内容
+    pub(crate) fn peek<R: Read>(reader: &mut R) -> io::Result<SeqFormat> {
+        let mut byte = [0; 1];
+        loop {
+            if reader.read(&mut byte)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "cannot detect sequence format: input is empty",
+                ));
+            }
+            match byte[0] {
+                b'>' => return Ok(SeqFormat::Fasta),
+                b'@' => return Ok(SeqFormat::Fastq),
+                b if b.is_ascii_whitespace() => continue,
+                b => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "cannot detect sequence format: first byte is {:#04x}, expected `>` (FASTA) or `@` (FASTQ)",
+                            b
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// 读取至多 buffer.len() 个字节, 返回实际读取的长度
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match reader.read(&mut buffer[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SeqHeader {
     pub id: String,
@@ -0,0 +1,452 @@
+// AVX2 实现的 minimizer 扫描: 先批量把字符编码为 2 bit, 再 4 个位置一组同时滚动计算 l-mer, 求 canonical 并应用 spaced seed 和 toggle 掩码,
+// 窗口不大时用向量化的滑动最小值代替单调队列, 一次算出整条序列的 minimizer hash
+// 结果与逐字符扫描完全相同, 只在 `--features simd` 且目标平台启用 avx2 (如 `-C target-cpu=native`) 时编译
+use crate::{fmix64 as murmur_hash3, Meros};
+use std::arch::x86_64::*;
+use std::cell::RefCell;
+
+/// 非 ACGT 字符, 清空 l-mer 和窗口
+const INVALID: u8 = 4;
+/// 换行符, 直接跳过
+const SKIP: u8 = 5;
+
+/// 编码结果前面补的 0, 使每个位置都能向前读 3 个字符
+const PAD: usize = 3;
+
+/// 使用向量化滑动最小值的最大窗口, 更大的窗口仍用单调队列
+const MAX_WINDOW: usize = 32;
+
+/// 按 4 位掩码把选中的 u64 依次移到前面, 用作 _mm256_permutevar8x32_epi32 的下标
+const COMPRESS: [[i32; 8]; 16] = compress_table();
+
+const fn compress_table() -> [[i32; 8]; 16] {
+    let mut table = [[0; 8]; 16];
+    let mut bits = 0;
+    while bits < 16 {
+        let (mut lane, mut len) = (0, 0);
+        while lane < 4 {
+            if bits >> lane & 1 == 1 {
+                table[bits][2 * len] = 2 * lane;
+                table[bits][2 * len + 1] = 2 * lane + 1;
+                len += 1;
+            }
+            lane += 1;
+        }
+        bits += 1;
+    }
+    table
+}
+
+/// 一条序列的所有候选 l-mer, 顺序与逐字符扫描时相同
+#[derive(Debug, Default)]
+pub(crate) struct Candidates {
+    pub lmers: Vec<u64>,
+    /// 在这些下标的候选之前需要清空窗口 (两者之间有非 ACGT 字符), 升序
+    pub breaks: Vec<usize>,
+}
+
+/// 扫描结果: 窗口不大时直接得到所有 minimizer hash, 否则只给出候选 l-mer, 由 MinimizerWindow 逐个处理
+pub(crate) enum Scan {
+    Hashes(Vec<u64>),
+    Candidates(Candidates),
+}
+
+impl Scan {
+    /// 把结果占用的缓冲区放回线程内, 给下一条序列的扫描复用
+    pub fn release(&mut self) {
+        let buf = match self {
+            Scan::Hashes(hashes) => std::mem::take(hashes),
+            Scan::Candidates(candidates) => std::mem::take(&mut candidates.lmers),
+        };
+        if buf.capacity() == 0 {
+            return;
+        }
+        let _ = KMER_BUF.try_with(|slot| {
+            let mut slot = slot.borrow_mut();
+            if slot.capacity() < buf.capacity() {
+                *slot = buf;
+            }
+        });
+    }
+}
+
+impl Drop for Scan {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// 每个线程复用的缓冲区, 避免每条 read 分配
+#[derive(Default)]
+struct Scratch {
+    codes: Vec<u8>,
+    appended: Vec<u8>,
+    complements: Vec<u8>,
+    candidates: Candidates,
+    revcoms: Vec<u64>,
+    window_min: Vec<u64>,
+}
+
+thread_local! {
+    static SCRATCH: RefCell<Scratch> = RefCell::new(Scratch::default());
+    /// 扫描结果 (minimizer hash 或候选 l-mer) 的缓冲区, 迭代器用完后由 Scan::release 放回
+    static KMER_BUF: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+fn take_kmer_buf(len: usize) -> Vec<u64> {
+    let mut buf = KMER_BUF.with(|slot| slot.take());
+    buf.clear();
+    buf.reserve(len);
+    buf
+}
+
+/// 每次编码 32 个字符: A/C/G/T (不区分大小写) 为 0..=3, 换行符为 SKIP, 其他为 INVALID
+/// 结果从 codes[PAD] 开始, 前后补 0; 返回是否所有字符都是 ACGT
+fn encode(seq: &[u8], codes: &mut Vec<u8>) -> bool {
+    let blocks = seq.len().div_ceil(32);
+    codes.clear();
+    codes.resize(PAD + blocks * 32 + 32, 0);
+    let mut all_valid = true;
+    // 安全性: 编译期已确认 avx2 可用, 最后不满 32 个字符的块先复制出来再读, 写入不超过 codes 的长度
+    unsafe {
+        let lower = _mm256_set1_epi8(0x20);
+        let two_bits = _mm256_set1_epi8(0x03);
+        let invalid = _mm256_set1_epi8(INVALID as i8);
+        let skip = _mm256_set1_epi8(SKIP as i8);
+        let letters = [b'a', b'c', b'g', b't'].map(|ch| _mm256_set1_epi8(ch as i8));
+        let newline = _mm256_set1_epi8(b'\n' as i8);
+        let carriage = _mm256_set1_epi8(b'\r' as i8);
+        let mut last = [0u8; 32];
+        for block in 0..blocks {
+            let start = block * 32;
+            let len = (seq.len() - start).min(32);
+            let chars = if len == 32 {
+                _mm256_loadu_si256(seq.as_ptr().add(start) as *const __m256i)
+            } else {
+                last[..len].copy_from_slice(&seq[start..]);
+                _mm256_loadu_si256(last.as_ptr() as *const __m256i)
+            };
+            // ((c >> 1) ^ (c >> 2)) & 3: A=0, C=1, G=2, T=3, 小写相同; 16 位移位带入的高位会被掩掉
+            let code = _mm256_and_si256(
+                _mm256_xor_si256(_mm256_srli_epi16(chars, 1), _mm256_srli_epi16(chars, 2)),
+                two_bits,
+            );
+            let folded = _mm256_or_si256(chars, lower);
+            let valid = letters.iter().fold(_mm256_setzero_si256(), |acc, &letter| {
+                _mm256_or_si256(acc, _mm256_cmpeq_epi8(folded, letter))
+            });
+            let is_newline = _mm256_or_si256(
+                _mm256_cmpeq_epi8(chars, newline),
+                _mm256_cmpeq_epi8(chars, carriage),
+            );
+            let other = _mm256_blendv_epi8(invalid, skip, is_newline);
+            let result = _mm256_blendv_epi8(other, code, valid);
+            _mm256_storeu_si256(codes.as_mut_ptr().add(PAD + start) as *mut __m256i, result);
+            // 补齐的字符不参与判断
+            let padding = u32::MAX.checked_shl(len as u32).unwrap_or(0);
+            all_valid &= (_mm256_movemask_epi8(valid) as u32 | padding) == u32::MAX;
+        }
+    }
+    codes[PAD + seq.len()..].fill(0);
+    all_valid
+}
+
+/// 4 个 u64 的最小值; 候选 l-mer 不超过 31 个字符, 小于 2^62, 可以直接用有符号比较
+#[inline]
+unsafe fn min_epi64(a: __m256i, b: __m256i) -> __m256i {
+    _mm256_blendv_epi8(a, b, _mm256_cmpgt_epi64(a, b))
+}
+
+/// 每个位置与前 3 个字符打包为一个字节: appended[i] 为第 i - 3..=i 个字符, complements[i] 为它们的反向互补
+/// codes 从 codes[PAD] 开始, 第 i 个位置的 4 个字符正好是 codes[i..i + 4]; 每个字符只占低 2 位, 16 位移位不会越过字节
+fn pack_groups(codes: &[u8], len: usize, appended: &mut Vec<u8>, complements: &mut Vec<u8>) {
+    let blocks = len.div_ceil(32);
+    appended.clear();
+    appended.resize(blocks * 32, 0);
+    complements.clear();
+    complements.resize(blocks * 32, 0);
+    // 安全性: 编译期已确认 avx2 可用, encode 在 codes 末尾补了至少 32 个字节
+    unsafe {
+        let ones = _mm256_set1_epi8(-1);
+        for block in 0..blocks {
+            let i = block * 32;
+            let [c0, c1, c2, c3] = [0, 1, 2, 3]
+                .map(|offset| _mm256_loadu_si256(codes.as_ptr().add(i + offset) as *const __m256i));
+            let forward = _mm256_or_si256(
+                _mm256_or_si256(_mm256_slli_epi16(c0, 6), _mm256_slli_epi16(c1, 4)),
+                _mm256_or_si256(_mm256_slli_epi16(c2, 2), c3),
+            );
+            let reverse = _mm256_or_si256(
+                _mm256_or_si256(_mm256_slli_epi16(c3, 6), _mm256_slli_epi16(c2, 4)),
+                _mm256_or_si256(_mm256_slli_epi16(c1, 2), c0),
+            );
+            _mm256_storeu_si256(appended.as_mut_ptr().add(i) as *mut __m256i, forward);
+            _mm256_storeu_si256(
+                complements.as_mut_ptr().add(i) as *mut __m256i,
+                _mm256_xor_si256(reverse, ones),
+            );
+        }
+    }
+}
+
+/// 所有字符都是 ACGT 时的候选 l-mer, lmers[i] 为以第 i 个字符结尾的 l-mer, 只有 i >= l_mer - 1 的有意义
+/// 第 i 个 l-mer 由第 i - 4 个追加 4 个字符得到, 4 条互不依赖的链正好放进一个向量
+fn roll_valid(
+    appended: &[u8],
+    complements: &[u8],
+    len: usize,
+    meros: &Meros,
+    lmers: &mut Vec<u64>,
+) {
+    let spaced_seed_mask = if meros.spaced_seed_mask > 0 {
+        meros.spaced_seed_mask
+    } else {
+        u64::MAX
+    };
+    let blocks = len.div_ceil(4);
+    lmers.clear();
+    lmers.resize(blocks * 4, 0);
+    // 安全性: 编译期已确认 avx2 可用, pack_groups 按 32 个位置对齐, 不短于 blocks * 4
+    unsafe {
+        let mask = _mm256_set1_epi64x(meros.mask as i64);
+        let spaced = _mm256_set1_epi64x(spaced_seed_mask as i64);
+        let toggle = _mm256_set1_epi64x(meros.toggle_mask as i64);
+        let top_shift = _mm_cvtsi64_si128(2 * (meros.l_mer as i64 - 4));
+        let mut forward = _mm256_setzero_si256();
+        let mut revcom = _mm256_setzero_si256();
+        for block in 0..blocks {
+            let i = block * 4;
+            let widen = |groups: &[u8]| {
+                let bytes = (groups.as_ptr().add(i) as *const i32).read_unaligned();
+                _mm256_cvtepu8_epi64(_mm_cvtsi32_si128(bytes))
+            };
+            forward = _mm256_and_si256(
+                _mm256_or_si256(_mm256_slli_epi64(forward, 8), widen(appended)),
+                mask,
+            );
+            revcom = _mm256_or_si256(
+                _mm256_srli_epi64(revcom, 8),
+                _mm256_sll_epi64(widen(complements), top_shift),
+            );
+            let candidate =
+                _mm256_xor_si256(_mm256_and_si256(min_epi64(forward, revcom), spaced), toggle);
+            _mm256_storeu_si256(lmers.as_mut_ptr().add(i) as *mut __m256i, candidate);
+        }
+    }
+    lmers.truncate(len);
+}
+
+/// 正向 l-mer 和反向互补各追加一个字符, 反向互补随字符滚动更新, 不需要对每个 l-mer 做位翻转
+#[inline(always)]
+fn roll(forward: &mut u64, revcom: &mut u64, code: u8, mask: u64, top_shift: usize) {
+    let code = code as u64;
+    *forward = ((*forward << 2) | code) & mask;
+    *revcom = (*revcom >> 2) | ((3 ^ code) << top_shift);
+}
+
+/// 含有换行符或非 ACGT 字符时逐字符计算, 遇到非 ACGT 字符时重新累积并记录窗口的清空位置
+/// mask_n_bases 为 false 时非 ACGT 字符按 A 计算, 不清空窗口
+fn roll_with_breaks(
+    codes: &[u8],
+    l_mer: usize,
+    mask: u64,
+    mask_n_bases: bool,
+    candidates: &mut Candidates,
+    revcoms: &mut Vec<u64>,
+) {
+    let top_shift = 2 * (l_mer - 1);
+    candidates.lmers.clear();
+    candidates.breaks.clear();
+    revcoms.clear();
+    let (mut forward, mut revcom, mut count) = (0, 0, 0);
+    let mut pending_break = false;
+    for &code in codes {
+        match code {
+            SKIP => continue,
+            INVALID if mask_n_bases => {
+                forward = 0;
+                revcom = 0;
+                count = 0;
+                pending_break = true;
+            }
+            code => {
+                let code = if code == INVALID { 0 } else { code };
+                roll(&mut forward, &mut revcom, code, mask, top_shift);
+                count += 1;
+                if count >= l_mer {
+                    if pending_break {
+                        candidates.breaks.push(candidates.lmers.len());
+                        pending_break = false;
+                    }
+                    candidates.lmers.push(forward);
+                    revcoms.push(revcom);
+                }
+            }
+        }
+    }
+}
+
+/// 4 个一组求 min(正向, 反向互补), 再应用 spaced seed 和 toggle 掩码, 结果写回 lmers
+fn apply_masks(lmers: &mut [u64], revcoms: &[u64], meros: &Meros) {
+    let spaced_seed_mask = if meros.spaced_seed_mask > 0 {
+        meros.spaced_seed_mask
+    } else {
+        u64::MAX
+    };
+    let lanes = lmers.len() / 4;
+    // 安全性: 编译期已确认 avx2 可用, 两个切片长度相同
+    unsafe {
+        let spaced = _mm256_set1_epi64x(spaced_seed_mask as i64);
+        let toggle = _mm256_set1_epi64x(meros.toggle_mask as i64);
+        for lane in 0..lanes {
+            let forward = _mm256_loadu_si256(lmers.as_ptr().add(lane * 4) as *const __m256i);
+            let revcom = _mm256_loadu_si256(revcoms.as_ptr().add(lane * 4) as *const __m256i);
+            let canonical = min_epi64(forward, revcom);
+            let candidate = _mm256_xor_si256(_mm256_and_si256(canonical, spaced), toggle);
+            _mm256_storeu_si256(lmers.as_mut_ptr().add(lane * 4) as *mut __m256i, candidate);
+        }
+    }
+    for i in lanes * 4..lmers.len() {
+        lmers[i] = (lmers[i].min(revcoms[i]) & spaced_seed_mask) ^ meros.toggle_mask;
+    }
+}
+
+/// 一段不含 N 的候选 l-mer 依次送入容量为 capacity 的 MinimizerWindow 时输出的 minimizer
+/// 第 i 个候选的窗口为 [i - capacity, i], 其最小值为 m_i; i == capacity 时输出 m_i,
+/// 之后在 c_i 小于上一个窗口的最小值 (队列被清空) 或上一个窗口的最小值恰好移出窗口时输出 m_i
+fn segment_minimizers(
+    lmers: &[u64],
+    capacity: usize,
+    window_min: &mut Vec<u64>,
+    out: &mut Vec<u64>,
+) {
+    // MinimizerWindow 对容量 1 不做比较, 直接输出每个候选
+    if capacity == 1 {
+        out.extend_from_slice(lmers);
+        return;
+    }
+    if lmers.len() <= capacity {
+        return;
+    }
+    // window_min[j] 为 lmers[j..=j + capacity] 的最小值
+    let n = lmers.len() - capacity;
+    window_min.clear();
+    window_min.resize(n, 0);
+    let blocks = n / 4;
+    // 安全性: 编译期已确认 avx2 可用, 读取的最后一个候选为 lmers[blocks * 4 - 1 + capacity]
+    unsafe {
+        for block in 0..blocks {
+            let j = block * 4;
+            let mut min = _mm256_loadu_si256(lmers.as_ptr().add(j) as *const __m256i);
+            for d in 1..capacity + 1 {
+                let lmer = _mm256_loadu_si256(lmers.as_ptr().add(j + d) as *const __m256i);
+                min = min_epi64(min, lmer);
+            }
+            _mm256_storeu_si256(window_min.as_mut_ptr().add(j) as *mut __m256i, min);
+        }
+    }
+    for j in blocks * 4..n {
+        window_min[j] = lmers[j..=j + capacity]
+            .iter()
+            .copied()
+            .fold(u64::MAX, u64::min);
+    }
+
+    // 是否输出难以预测, 无分支地写入后再截断; len 始终不超过 j
+    let start = out.len();
+    out.resize(start + n, 0);
+    let emitted = &mut out[start..];
+    emitted[0] = window_min[0];
+    let mut len = 1;
+    let mut j = 1;
+    // 安全性: 编译期已确认 avx2 可用, j + 3 < n, 读取的最后一个候选为 lmers[j + 3 + capacity], 写入不超过 emitted[j + 3]
+    unsafe {
+        while j + 4 <= n {
+            let previous = _mm256_loadu_si256(window_min.as_ptr().add(j - 1) as *const __m256i);
+            let entering = _mm256_loadu_si256(lmers.as_ptr().add(j + capacity) as *const __m256i);
+            let leaving = _mm256_loadu_si256(lmers.as_ptr().add(j - 1) as *const __m256i);
+            let emit = _mm256_or_si256(
+                _mm256_cmpgt_epi64(previous, entering),
+                _mm256_cmpeq_epi64(leaving, previous),
+            );
+            let bits = _mm256_movemask_pd(_mm256_castsi256_pd(emit)) as usize;
+            let current = _mm256_loadu_si256(window_min.as_ptr().add(j) as *const __m256i);
+            let indices = _mm256_loadu_si256(COMPRESS[bits].as_ptr() as *const __m256i);
+            _mm256_storeu_si256(
+                emitted.as_mut_ptr().add(len) as *mut __m256i,
+                _mm256_permutevar8x32_epi32(current, indices),
+            );
+            len += bits.count_ones() as usize;
+            j += 4;
+        }
+    }
+    for j in j..n {
+        let previous = window_min[j - 1];
+        emitted[len] = window_min[j];
+        len += ((lmers[j + capacity] < previous) | (lmers[j - 1] == previous)) as usize;
+    }
+    out.truncate(start + len);
+}
+
+/// 扫描整条序列, capacity 为 MinimizerWindow 的容量 (k - l)
+pub(crate) fn scan(seq: &[u8], meros: &Meros, capacity: usize) -> Scan {
+    SCRATCH.with(|scratch| {
+        let scratch = &mut *scratch.borrow_mut();
+        let candidates = &mut scratch.candidates;
+        // 4 条链各自每次追加 4 个字符, 要求 l-mer 至少有 4 个字符
+        let start = if encode(seq, &mut scratch.codes) && meros.l_mer >= 4 {
+            pack_groups(
+                &scratch.codes,
+                seq.len(),
+                &mut scratch.appended,
+                &mut scratch.complements,
+            );
+            roll_valid(
+                &scratch.appended,
+                &scratch.complements,
+                seq.len(),
+                meros,
+                &mut candidates.lmers,
+            );
+            candidates.breaks.clear();
+            meros.l_mer - 1
+        } else {
+            roll_with_breaks(
+                &scratch.codes[PAD..PAD + seq.len()],
+                meros.l_mer,
+                meros.mask,
+                meros.mask_n_bases,
+                candidates,
+                &mut scratch.revcoms,
+            );
+            apply_masks(&mut candidates.lmers, &scratch.revcoms, meros);
+            0
+        };
+        let lmers = candidates.lmers.get(start..).unwrap_or_default();
+
+        if capacity > MAX_WINDOW {
+            let mut buf = take_kmer_buf(lmers.len());
+            buf.extend_from_slice(lmers);
+            return Scan::Candidates(Candidates {
+                lmers: buf,
+                breaks: candidates.breaks.clone(),
+            });
+        }
+        let mut hashes = take_kmer_buf(lmers.len());
+        let mut segment_start = 0;
+        let end = lmers.len();
+        for &stop in candidates.breaks.iter().chain(std::iter::once(&end)) {
+            segment_minimizers(
+                &lmers[segment_start..stop],
+                capacity,
+                &mut scratch.window_min,
+                &mut hashes,
+            );
+            segment_start = stop;
+        }
+        for hash in hashes.iter_mut() {
+            *hash = murmur_hash3(*hash ^ meros.toggle_mask);
+        }
+        Scan::Hashes(hashes)
+    })
+}
@@ -0,0 +1,217 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// 质量裁剪方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// 从 5' 端开始滑动窗口, 窗口平均质量低于阈值时从窗口起点截断
+    SlidingWindow(usize),
+    /// 去掉 3' 端连续的低质量碱基
+    Trailing,
+    /// BWA 的裁剪算法, 从 3' 端累加 (阈值 - 质量), 在累加和最大处截断
+    BWA,
+}
+
+/// FASTQ 质量值的编码方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QualityEncoding {
+    #[default]
+    Phred33,
+    Phred64,
+}
+
+impl QualityEncoding {
+    /// 质量值 0 对应的字符
+    pub fn offset(&self) -> u8 {
+        match self {
+            QualityEncoding::Phred33 => b'!',
+            QualityEncoding::Phred64 => b'@',
+        }
+    }
+
+    /// 把质量字符转换为 Phred 分数
+    #[inline]
+    pub fn score(&self, qual: u8) -> u8 {
+        qual.saturating_sub(self.offset())
+    }
+}
+
+impl FromStr for QualityEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "phred33" => Ok(QualityEncoding::Phred33),
+            "phred64" => Ok(QualityEncoding::Phred64),
+            _ => Err(format!(
+                "unknown quality encoding `{}`, expected phred33 or phred64",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for QualityEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QualityEncoding::Phred33 => f.write_str("phred33"),
+            QualityEncoding::Phred64 => f.write_str("phred64"),
+        }
+    }
+}
+
+/// 按质量值裁剪 read 的 3' 端, 质量值默认按 Phred33 编码
+#[derive(Debug, Clone)]
+pub struct QualityTrimmer {
+    pub strategy: TrimStrategy,
+    pub min_quality: u8,
+    /// 裁剪后短于此长度的 read 返回空序列
+    pub min_length: usize,
+    pub encoding: QualityEncoding,
+}
+
+impl QualityTrimmer {
+    pub fn new(strategy: TrimStrategy, min_quality: u8, min_length: usize) -> Self {
+        Self {
+            strategy,
+            min_quality,
+            min_length,
+            encoding: QualityEncoding::Phred33,
+        }
+    }
+
+    pub fn with_encoding(mut self, encoding: QualityEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// 返回裁剪后的序列和质量值
+    pub fn trim<'a>(&self, seq: &'a [u8], qual: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        let len = seq.len().min(qual.len());
+        let scores: Vec<i64> = qual[..len]
+            .iter()
+            .map(|&q| self.encoding.score(q) as i64)
+            .collect();
+
+        let end = match self.strategy {
+            TrimStrategy::SlidingWindow(window) => self.sliding_window_end(&scores, window),
+            TrimStrategy::Trailing => self.trailing_end(&scores),
+            TrimStrategy::BWA => self.bwa_end(&scores),
+        };
+
+        if end < self.min_length {
+            (&seq[..0], &qual[..0])
+        } else {
+            (&seq[..end], &qual[..end])
+        }
+    }
+
+    fn sliding_window_end(&self, scores: &[i64], window: usize) -> usize {
+        let window = window.clamp(1, scores.len().max(1));
+        let threshold = self.min_quality as i64 * window as i64;
+        let mut sum: i64 = scores.iter().take(window).sum();
+        if scores.len() < window {
+            // 序列比窗口短时按整条序列的平均质量判断
+            return if sum * (window as i64) < threshold * scores.len() as i64 {
+                0
+            } else {
+                scores.len()
+            };
+        }
+
+        for start in 0..=scores.len() - window {
+            if start > 0 {
+                sum += scores[start + window - 1] - scores[start - 1];
+            }
+            if sum < threshold {
+                return start;
+            }
+        }
+        scores.len()
+    }
+
+    fn trailing_end(&self, scores: &[i64]) -> usize {
+        scores
+            .iter()
+            .rposition(|&q| q >= self.min_quality as i64)
+            .map_or(0, |pos| pos + 1)
+    }
+
+    fn bwa_end(&self, scores: &[i64]) -> usize {
+        let threshold = self.min_quality as i64;
+        let mut sum = 0;
+        let mut max_sum = 0;
+        let mut end = scores.len();
+        for (i, &q) in scores.iter().enumerate().rev() {
+            sum += threshold - q;
+            if sum < 0 {
+                break;
+            }
+            if sum > max_sum {
+                max_sum = sum;
+                end = i;
+            }
+        }
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEQ: &[u8] = b"ACGTACGT";
+
+    /// 按 Phred33 把分数转换为质量字符
+    fn qual(scores: &[u8]) -> Vec<u8> {
+        scores.iter().map(|&score| score + b'!').collect()
+    }
+
+    fn trimmed_len(trimmer: &QualityTrimmer, scores: &[u8]) -> usize {
+        let qual = qual(scores);
+        let (seq, trimmed_qual) = trimmer.trim(&SEQ[..scores.len()], &qual);
+        assert_eq!(seq.len(), trimmed_qual.len());
+        seq.len()
+    }
+
+    #[test]
+    fn sliding_window_cuts_at_first_low_window() {
+        let trimmer = QualityTrimmer::new(TrimStrategy::SlidingWindow(3), 20, 0);
+        // 从位置 3 开始的窗口 (30, 10, 10) 平均质量低于 20
+        assert_eq!(trimmed_len(&trimmer, &[30, 30, 30, 30, 10, 10, 30, 30]), 3);
+        assert_eq!(trimmed_len(&trimmer, &[30; 8]), 8);
+        assert_eq!(trimmed_len(&trimmer, &[10; 8]), 0);
+    }
+
+    #[test]
+    fn trailing_removes_low_quality_tail() {
+        let trimmer = QualityTrimmer::new(TrimStrategy::Trailing, 20, 0);
+        assert_eq!(trimmed_len(&trimmer, &[30, 10, 30, 10, 5]), 3);
+        assert_eq!(trimmed_len(&trimmer, &[30; 5]), 5);
+        assert_eq!(trimmed_len(&trimmer, &[5; 5]), 0);
+    }
+
+    #[test]
+    fn bwa_cuts_at_maximum_sum() {
+        let trimmer = QualityTrimmer::new(TrimStrategy::BWA, 20, 0);
+        // 从 3' 端累加 (20 - q): 15, 10, 20, 10, 0, -10, 在累加和为 20 的位置 3 截断
+        assert_eq!(trimmed_len(&trimmer, &[30, 30, 30, 10, 25, 5]), 3);
+        assert_eq!(trimmed_len(&trimmer, &[30; 6]), 6);
+    }
+
+    #[test]
+    fn short_reads_and_encodings() {
+        let trimmer = QualityTrimmer::new(TrimStrategy::Trailing, 20, 4);
+        assert_eq!(trimmed_len(&trimmer, &[30, 10, 30, 10, 5]), 0);
+        assert_eq!(trimmed_len(&trimmer, &[30, 30, 30, 30, 5]), 4);
+
+        let trimmer = QualityTrimmer::new(TrimStrategy::Trailing, 20, 0)
+            .with_encoding(QualityEncoding::Phred64);
+        let phred64: Vec<u8> = [30u8, 30, 5].iter().map(|&score| score + b'@').collect();
+        assert_eq!(trimmer.trim(b"ACG", &phred64).0, b"AC");
+
+        // 质量值比序列短时只考虑有质量值的部分
+        let trimmer = QualityTrimmer::new(TrimStrategy::Trailing, 20, 0);
+        assert_eq!(trimmer.trim(b"ACGT", &qual(&[30, 30])).0, b"AC");
+    }
+}
@@ -1,3 +1,6 @@
+use std::iter::Once;
+use std::option;
+
 #[derive(Debug, Clone)]
 pub enum OptionPair<T> {
     Single(T),
@@ -5,6 +8,7 @@ pub enum OptionPair<T> {
 }
 
 impl<T> OptionPair<T> {
+    /// 单端数据时返回其引用, 双端数据返回 None
     pub fn single(&self) -> Option<&T> {
         match self {
             OptionPair::Single(value) => Some(value),
@@ -12,6 +16,66 @@ impl<T> OptionPair<T> {
         }
     }
 
+    /// 第一条序列 (单端数据即唯一的序列)
+    pub fn get_seq1(&self) -> &T {
+        match self {
+            OptionPair::Single(t) | OptionPair::Pair(t, _) => t,
+        }
+    }
+
+    /// 第二条序列, 单端数据返回 None
+    pub fn get_seq2(&self) -> Option<&T> {
+        match self {
+            OptionPair::Single(_) => None,
+            OptionPair::Pair(_, t) => Some(t),
+        }
+    }
+
+    /// 依次遍历一条或两条序列
+    pub fn get_seqs(&self) -> impl Iterator<Item = &T> + '_ {
+        self.iter()
+    }
+
+    /// 依次遍历一个或两个元素
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.into_iter()
+    }
+
+    /// 对每个元素调用 f, 任意一个返回 None 时整体返回 None
+    pub fn filter_map_strict<U, F>(self, mut f: F) -> Option<OptionPair<U>>
+    where
+        F: FnMut(T) -> Option<U>,
+    {
+        match self {
+            OptionPair::Single(t) => f(t).map(OptionPair::Single),
+            OptionPair::Pair(t1, t2) => Some(OptionPair::Pair(f(t1)?, f(t2)?)),
+        }
+    }
+
+    /// 对每个元素调用 f 并去掉返回 None 的元素, 双端数据可能变为单端, 全部被去掉时返回 None
+    pub fn filter_map_lenient<U, F>(self, mut f: F) -> Option<OptionPair<U>>
+    where
+        F: FnMut(T) -> Option<U>,
+    {
+        match self {
+            OptionPair::Single(t) => f(t).map(OptionPair::Single),
+            OptionPair::Pair(t1, t2) => match (f(t1), f(t2)) {
+                (Some(u1), Some(u2)) => Some(OptionPair::Pair(u1, u2)),
+                (Some(u), None) | (None, Some(u)) => Some(OptionPair::Single(u)),
+                (None, None) => None,
+            },
+        }
+    }
+
+    /// 元素个数, 单端为 1, 双端为 2
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        match self {
+            OptionPair::Single(_) => 1,
+            OptionPair::Pair(_, _) => 2,
+        }
+    }
+
     // 它接受一个泛型闭包 F，并返回一个新的 OptionPair<U>
     pub fn map<U, E, F>(&self, mut f: F) -> Result<OptionPair<U>, E>
     where
@@ -86,6 +150,31 @@ impl<T> OptionPair<T> {
     }
 }
 
+impl<T> OptionPair<T> {
+    /// 把两个结构相同的 OptionPair 逐个元素组合, 一个单端一个双端时返回 None
+    pub fn zip<U>(self, other: OptionPair<U>) -> Option<OptionPair<(T, U)>> {
+        match (self, other) {
+            (OptionPair::Single(t), OptionPair::Single(u)) => Some(OptionPair::Single((t, u))),
+            (OptionPair::Pair(t1, t2), OptionPair::Pair(u1, u2)) => {
+                Some(OptionPair::Pair((t1, u1), (t2, u2)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<A, B> OptionPair<(A, B)> {
+    /// zip 的逆操作, 拆分为两个结构相同的 OptionPair
+    pub fn unzip(self) -> (OptionPair<A>, OptionPair<B>) {
+        match self {
+            OptionPair::Single((a, b)) => (OptionPair::Single(a), OptionPair::Single(b)),
+            OptionPair::Pair((a1, b1), (a2, b2)) => {
+                (OptionPair::Pair(a1, a2), OptionPair::Pair(b1, b2))
+            }
+        }
+    }
+}
+
 impl<T: Clone> OptionPair<T> {
     pub fn from_slice(slice: &[T]) -> OptionPair<T> {
         match slice {
@@ -104,3 +193,25 @@ impl<T> From<(T, Option<T>)> for OptionPair<T> {
         }
     }
 }
+
+impl<T> IntoIterator for OptionPair<T> {
+    type Item = T;
+    type IntoIter = std::iter::Chain<Once<T>, option::IntoIter<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let (first, second) = match self {
+            OptionPair::Single(t) => (t, None),
+            OptionPair::Pair(t1, t2) => (t1, Some(t2)),
+        };
+        std::iter::once(first).chain(second)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a OptionPair<T> {
+    type Item = &'a T;
+    type IntoIter = std::iter::Chain<Once<&'a T>, option::IntoIter<&'a T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        std::iter::once(self.get_seq1()).chain(self.get_seq2())
+    }
+}
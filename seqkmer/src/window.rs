@@ -0,0 +1,95 @@
+/// 按固定长度滑动窗口遍历序列
+/// 提供质量值时, 低于 min_qual 的碱基视为被屏蔽 (等同于 N), 包含这类碱基的窗口会被跳过
+pub struct WindowIterator<'a> {
+    seq: &'a [u8],
+    qual: Option<&'a [u8]>,
+    win_len: usize,
+    min_qual: u8,
+    pos: usize,
+    /// [pos, clean_end) 范围内的碱基已确认未被屏蔽
+    clean_end: usize,
+}
+
+impl<'a> WindowIterator<'a> {
+    pub fn new(seq: &'a [u8], qual: Option<&'a [u8]>, win_len: usize, min_qual: u8) -> Self {
+        Self {
+            seq,
+            qual,
+            win_len,
+            min_qual,
+            pos: 0,
+            clean_end: 0,
+        }
+    }
+
+    /// 质量值按 Phred33 编码
+    #[inline]
+    fn is_masked(&self, idx: usize) -> bool {
+        self.qual
+            .and_then(|qual| qual.get(idx))
+            .is_some_and(|&q| q.saturating_sub(b'!') < self.min_qual)
+    }
+}
+
+impl<'a> Iterator for WindowIterator<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.win_len == 0 {
+            return None;
+        }
+
+        while self.pos + self.win_len <= self.seq.len() {
+            let end = self.pos + self.win_len;
+            self.clean_end = self.clean_end.max(self.pos);
+            while self.clean_end < end && !self.is_masked(self.clean_end) {
+                self.clean_end += 1;
+            }
+
+            if self.clean_end == end {
+                let window = &self.seq[self.pos..end];
+                self.pos += 1;
+                return Some(window);
+            }
+            // 窗口内有被屏蔽的碱基, 下一个窗口从它之后开始
+            self.pos = self.clean_end + 1;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn windows(seq: &[u8], qual: Option<&[u8]>, win_len: usize, min_qual: u8) -> Vec<Vec<u8>> {
+        WindowIterator::new(seq, qual, win_len, min_qual)
+            .map(|window| window.to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn slides_over_the_sequence() {
+        assert_eq!(
+            windows(b"ACGTA", None, 3, 0),
+            [b"ACG".to_vec(), b"CGT".to_vec(), b"GTA".to_vec()]
+        );
+        assert_eq!(windows(b"ACGTA", None, 5, 0), [b"ACGTA".to_vec()]);
+        assert!(windows(b"ACGTA", None, 6, 0).is_empty());
+        assert!(windows(b"ACGTA", None, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn skips_windows_with_low_quality_bases() {
+        // '#' 的 Phred33 质量为 2, 低于阈值 10
+        let seq = b"ACGTACG";
+        let qual = b"IIII#II";
+        assert_eq!(
+            windows(seq, Some(qual), 3, 10),
+            [b"ACG".to_vec(), b"CGT".to_vec()]
+        );
+        assert_eq!(windows(seq, Some(qual), 2, 10).len(), 4);
+        // 阈值不高于质量值时不屏蔽
+        assert_eq!(windows(seq, Some(qual), 3, 2).len(), 5);
+    }
+}